@@ -0,0 +1,243 @@
+use agents::adapter::{bitget, latoken, lbank, xt};
+use agents::frame::Frame;
+use arb_core::events::{
+    DepthUpdateEvent, Event, LatokenStreamMessage, LatokenTradeEvent, StreamMessage, TradeEvent,
+    XtEvent, XtStreamMessage,
+};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use serde_json::Value;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct CountingAlloc;
+
+static ALLOCS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCS.fetch_add(1, Ordering::SeqCst);
+        System.alloc(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static A: CountingAlloc = CountingAlloc;
+
+const BINANCE: &str = r#"{"stream":"btcusdt@trade","data":{"e":"trade","E":123,"s":"BTCUSDT","t":1,"p":"0.001","q":"100","b":1,"a":2,"T":123,"m":true,"M":true}}"#;
+const BITGET: &str = r#"{"action":"update","arg":{"instType":"SPOT","channel":"trade","instId":"BTCUSDT"},"data":[{"ts":"1695709835822","price":"26293.4","size":"0.0013","side":"buy","tradeId":"1000000000"}],"ts":1695709835829}"#;
+const LBANK: &str = r#"{"depth":{"asks":[[0.0252,0.5833],[0.025215,4.377],[0.02522,1.1]],"bids":[[0.025135,3.962],[0.025134,3.46],[0.02513,0.8]]},"count":100,"type":"depth","pair":"eth_btc","SERVER":"V2","TS":"2019-06-28T17:49:22.722"}"#;
+const LATOKEN: &str = r#"{"topic":"trade","symbol":"BTC-USDT","data":{"t":1695709835822,"p":"26293.4","q":"0.0013","m":true,"id":42}}"#;
+const XT: &str = r#"{"topic":"btc_usdt@trade","data":[{"i":42,"p":"26293.4","q":"0.0013","T":1695709835822,"m":true}]}"#;
+
+/// Allocations made by `f`.
+fn count<T>(f: impl FnOnce() -> T) -> usize {
+    ALLOCS.store(0, Ordering::SeqCst);
+    black_box(f());
+    ALLOCS.load(Ordering::SeqCst)
+}
+
+fn owned(s: &str) -> Cow<'static, str> {
+    Cow::Owned(s.to_string())
+}
+
+/// The websocket hands adapters an owned `String`, which Binance parsed in
+/// place with fresh `simd_json` scratch buffers into owned strings.
+fn binance_today() -> usize {
+    let mut bytes = BINANCE.as_bytes().to_vec();
+    count(|| {
+        simd_json::serde::from_slice::<StreamMessage<'_>>(&mut bytes)
+            .unwrap()
+            .into_owned()
+    })
+}
+
+/// Bitget went through a `serde_json::Value` before building the event.
+fn bitget_today() -> usize {
+    count(|| {
+        let v: Value = serde_json::from_str(BITGET).unwrap();
+        let inst_id = v["arg"]["instId"].as_str().unwrap().to_string();
+        let data = &v["data"][0];
+        let ts = data["ts"]
+            .as_str()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0);
+        let event = TradeEvent {
+            event_time: ts,
+            symbol: inst_id.clone(),
+            trade_id: data["tradeId"]
+                .as_str()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0),
+            price: owned(data["price"].as_str().unwrap_or("0")),
+            quantity: owned(data["size"].as_str().unwrap_or("0")),
+            buyer_order_id: 0,
+            seller_order_id: 0,
+            trade_time: ts,
+            buyer_is_maker: data["side"].as_str() == Some("sell"),
+            best_match: true,
+        };
+        StreamMessage {
+            stream: format!("{inst_id}@trade"),
+            data: Event::Trade(event),
+        }
+    })
+}
+
+/// LBank also went through a `serde_json::Value`.
+fn lbank_today() -> usize {
+    count(|| {
+        let v: Value = serde_json::from_str(LBANK).unwrap();
+        let pair = v["pair"].as_str().unwrap().to_string();
+        let levels = |side: &Value| {
+            side.as_array()
+                .unwrap()
+                .iter()
+                .map(|l| {
+                    let price = l[0].as_f64().unwrap_or_default().to_string();
+                    let qty = l[1].as_f64().unwrap_or_default().to_string();
+                    [Cow::Owned(price), Cow::Owned(qty)]
+                })
+                .collect()
+        };
+        let event = DepthUpdateEvent {
+            event_time: 0,
+            symbol: pair.clone(),
+            first_update_id: 0,
+            final_update_id: 0,
+            previous_final_update_id: 0,
+            bids: levels(&v["depth"]["bids"]),
+            asks: levels(&v["depth"]["asks"]),
+        };
+        StreamMessage {
+            stream: format!("{pair}@depth"),
+            data: Event::DepthUpdate(event),
+        }
+    })
+}
+
+/// LATOKEN parsed the envelope with `data` as a `Value`, then converted that.
+fn latoken_today() -> usize {
+    count(|| {
+        let msg: LatokenStreamMessage<'_> = serde_json::from_str(LATOKEN).unwrap();
+        let t: LatokenTradeEvent<'_> = serde_json::from_value(msg.data).unwrap();
+        let event = TradeEvent {
+            event_time: t.timestamp,
+            symbol: msg.symbol.clone(),
+            trade_id: t.id.unwrap_or(0),
+            price: Cow::Owned(t.price.into_owned()),
+            quantity: Cow::Owned(t.quantity.into_owned()),
+            buyer_order_id: 0,
+            seller_order_id: 0,
+            trade_time: t.timestamp,
+            buyer_is_maker: t.maker.unwrap_or(false),
+            best_match: true,
+        };
+        StreamMessage {
+            stream: format!("{}@trade", msg.symbol),
+            data: Event::Trade(event),
+        }
+    })
+}
+
+/// XT parsed with `serde_json` into the untagged `XtEvent`, which buffers
+/// `data` before picking a variant.
+fn xt_today() -> usize {
+    count(|| {
+        let msg: XtStreamMessage<'_> = serde_json::from_str(XT).unwrap();
+        let (symbol, _) = msg.topic.split_once('@').unwrap();
+        let XtEvent::Trade(trades) = msg.data else {
+            unreachable!()
+        };
+        trades
+            .into_iter()
+            .map(|t| {
+                let event = TradeEvent {
+                    event_time: t.trade_time,
+                    symbol: symbol.to_string(),
+                    trade_id: t.i.unwrap_or(0),
+                    price: Cow::Owned(t.price.into_owned()),
+                    quantity: Cow::Owned(t.quantity.into_owned()),
+                    buyer_order_id: 0,
+                    seller_order_id: 0,
+                    trade_time: t.trade_time,
+                    buyer_is_maker: t.buyer_is_maker.unwrap_or(false),
+                    best_match: true,
+                };
+                (
+                    symbol.to_string(),
+                    StreamMessage {
+                        stream: format!("{symbol}@trade"),
+                        data: Event::Trade(event),
+                    },
+                )
+            })
+            .collect::<Vec<_>>()
+    })
+}
+
+/// Binance parses the websocket's `String` in place into a borrowed event,
+/// and copies its strings out only to send it on.
+fn binance_pooled(frame: &mut Frame) -> usize {
+    let text = BINANCE.to_string();
+    count(|| {
+        frame
+            .parse_in_place::<StreamMessage<'_>>(text)
+            .unwrap()
+            .into_owned()
+    })
+}
+
+fn bitget_pooled(frame: &mut Frame) -> usize {
+    count(|| bitget::parse_message(frame, BITGET).unwrap())
+}
+
+fn lbank_pooled(frame: &mut Frame) -> usize {
+    count(|| lbank::parse_message(frame, LBANK).unwrap())
+}
+
+fn latoken_pooled(frame: &mut Frame) -> usize {
+    count(|| latoken::parse_message(frame, LATOKEN).unwrap())
+}
+
+fn xt_pooled(frame: &mut Frame) -> usize {
+    count(|| xt::parse_message(frame, XT).unwrap())
+}
+
+type Case = (&'static str, fn() -> usize, fn(&mut Frame) -> usize);
+
+const CASES: &[Case] = &[
+    ("binance_trade", binance_today, binance_pooled),
+    ("bitget_trade", bitget_today, bitget_pooled),
+    ("lbank_depth", lbank_today, lbank_pooled),
+    ("latoken_trade", latoken_today, latoken_pooled),
+    ("xt_trade", xt_today, xt_pooled),
+];
+
+/// Each benchmark returns the allocations of one message, and the counts
+/// are printed once up front for comparison.
+fn bench_frame_alloc(c: &mut Criterion) {
+    let mut frame = Frame::new();
+    for (name, today, pooled) in CASES {
+        // The first pooled parse may grow the buffers.
+        pooled(&mut frame);
+        println!(
+            "{name}: {} allocations per message today, {} pooled",
+            today(),
+            pooled(&mut frame)
+        );
+    }
+
+    let mut group = c.benchmark_group("frame_alloc");
+    for (name, today, pooled) in CASES {
+        group.bench_function(format!("{name}/today"), |b| b.iter(today));
+        group.bench_function(format!("{name}/pooled"), |b| b.iter(|| pooled(&mut frame)));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_frame_alloc);
+criterion_main!(benches);
@@ -0,0 +1,113 @@
+use agents::ChannelRegistry;
+use arb_core::channel_policy::Transport;
+use arb_core::events::StreamMessage;
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+use tokio::sync::Notify;
+
+const BUFFER: usize = 1024;
+const MESSAGES: usize = 10_000;
+const BATCH: usize = 64;
+
+fn trade(id: usize) -> StreamMessage<'static> {
+    let json = format!(
+        r#"{{"stream":"btcusdt@trade","data":{{"e":"trade","E":1,"s":"BTCUSDT","t":{id},"p":"1","q":"1","b":1,"a":2,"T":1,"m":true,"M":true}}}}"#
+    );
+    let mut bytes = json.into_bytes();
+    simd_json::serde::from_slice::<StreamMessage<'_>>(&mut bytes)
+        .unwrap()
+        .into_owned()
+}
+
+fn registry(transport: Transport) -> ChannelRegistry {
+    ChannelRegistry::new(BUFFER)
+        .with_transport(transport)
+        .unwrap()
+}
+
+/// Time to move `MESSAGES` trades from an adapter task to a consumer
+/// draining them in batches, on another worker thread.
+async fn transfer(transport: Transport, messages: Vec<StreamMessage<'static>>) -> Duration {
+    let (tx, rx) = registry(transport).get_or_create("Bench:BTCUSDT");
+    let mut rx = rx.unwrap();
+    let start = Instant::now();
+    let sender = tokio::spawn(async move {
+        for msg in messages {
+            tx.send(msg).await.unwrap();
+        }
+    });
+    let mut batch = Vec::with_capacity(BATCH);
+    let mut received = 0;
+    while received < MESSAGES {
+        received += rx.recv_many(&mut batch, BATCH).await;
+        batch.clear();
+    }
+    let elapsed = start.elapsed();
+    sender.await.unwrap();
+    elapsed
+}
+
+fn bench_throughput(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("transport_throughput");
+    group.throughput(Throughput::Elements(MESSAGES as u64));
+    for (name, transport) in [("queue", Transport::Queue), ("ring", Transport::Ring)] {
+        group.bench_function(name, |b| {
+            b.to_async(&rt).iter_custom(|iters| async move {
+                let mut total = Duration::ZERO;
+                for _ in 0..iters {
+                    let messages = (0..MESSAGES).map(trade).collect();
+                    total += transfer(transport, messages).await;
+                }
+                total
+            })
+        });
+    }
+    group.finish();
+}
+
+/// Round trip of a single trade to an idle consumer on another worker
+/// thread, which acknowledges it through a `Notify`. Both transports pay
+/// the same acknowledgement.
+fn bench_latency(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("transport_latency");
+    for (name, transport) in [("queue", Transport::Queue), ("ring", Transport::Ring)] {
+        let (tx, rx) = rt.block_on(async { registry(transport).get_or_create("Bench:BTCUSDT") });
+        let mut rx = rx.unwrap();
+        let ack = Arc::new(Notify::new());
+        let consumer = rt.spawn({
+            let ack = ack.clone();
+            async move {
+                while rx.recv().await.is_some() {
+                    ack.notify_one();
+                }
+            }
+        });
+        group.bench_function(name, |b| {
+            b.to_async(&rt).iter_custom(|iters| {
+                let tx = tx.clone();
+                let ack = ack.clone();
+                async move {
+                    let mut total = Duration::ZERO;
+                    for id in 0..iters as usize {
+                        let msg = trade(id);
+                        let start = Instant::now();
+                        tx.send(msg).await.unwrap();
+                        ack.notified().await;
+                        total += start.elapsed();
+                    }
+                    total
+                }
+            })
+        });
+        drop(tx);
+        rt.block_on(consumer).unwrap();
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_throughput, bench_latency);
+criterion_main!(benches);
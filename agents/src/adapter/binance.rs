@@ -12,12 +12,10 @@ use rand::Rng;
 use reqwest::{Client, StatusCode};
 use rustls::ClientConfig;
 use serde_json::Value;
-use simd_json::serde::from_slice;
 use std::{env, future::Future, sync::Arc};
 use tokio::{
     io::{AsyncRead, AsyncWrite},
     net::TcpStream,
-    sync::mpsc,
     time::{interval, sleep, timeout, Duration, Instant, MissedTickBehavior},
 };
 use tokio_socks::tcp::Socks5Stream;
@@ -37,7 +35,8 @@ use core::events::{DepthUpdateEvent, Event, StreamMessage};
 use core::rate_limit::TokenBucket;
 
 use super::ExchangeAdapter;
-use crate::{registry, ChannelRegistry, TaskSet};
+use crate::{frame::Frame, instruments, registry, ChannelRegistry, StreamReceiver, TaskSet};
+use canonical::symbol::{ContractSpec, Instrument, VenueType};
 use futures::future::BoxFuture;
 use std::sync::Once;
 /// Configuration for a single Binance exchange endpoint.
@@ -84,6 +83,13 @@ pub const BINANCE_EXCHANGES: &[BinanceConfig] = &[
 
 /// Retrieve all trading symbols for an exchange using its `exchangeInfo` endpoint.
 pub async fn fetch_symbols(client: &Client, info_url: &str) -> Result<Vec<String>> {
+    Ok(instruments::symbols(
+        &fetch_instruments(client, info_url).await?,
+    ))
+}
+
+/// Retrieve metadata for all trading instruments from `exchangeInfo`.
+pub async fn fetch_instruments(client: &Client, info_url: &str) -> Result<Vec<Instrument>> {
     let resp = client.get(info_url).send().await?.error_for_status()?;
     let data: Value = resp.json().await?;
     let symbols = data
@@ -91,25 +97,53 @@ pub async fn fetch_symbols(client: &Client, info_url: &str) -> Result<Vec<String
         .and_then(|v| v.as_array())
         .ok_or_else(|| anyhow!("missing symbols array"))?;
 
-    let mut result: Vec<String> = symbols
+    Ok(symbols
         .iter()
-        .filter_map(|s| {
-            if s.get("status")
+        .filter(|s| {
+            // Coin-margined delivery contracts report `contractStatus`.
+            s.get("status")
+                .or_else(|| s.get("contractStatus"))
                 .and_then(|v| v.as_str())
                 .map(|st| st == "TRADING")
                 .unwrap_or(false)
-            {
-                s.get("symbol")
-                    .and_then(|v| v.as_str())
-                    .map(|v| v.to_string())
-            } else {
-                None
-            }
         })
-        .collect();
-    result.sort();
-    result.dedup();
-    Ok(result)
+        .filter_map(|s| {
+            let symbol = s.get("symbol").and_then(|v| v.as_str())?;
+            Some(instruments::instrument("binance", symbol, binance_spec(s)))
+        })
+        .collect())
+}
+
+fn binance_spec(s: &Value) -> ContractSpec {
+    let filter = |kind: &str, key: &str| {
+        s.get("filters")
+            .and_then(|v| v.as_array())
+            .and_then(|fs| {
+                fs.iter()
+                    .find(|f| f.get("filterType").and_then(|v| v.as_str()) == Some(kind))
+            })
+            .and_then(|f| instruments::positive(f, key))
+    };
+    let contract_type = s.get("contractType").and_then(|v| v.as_str());
+    let venue = if contract_type.is_some() {
+        VenueType::Futures
+    } else {
+        VenueType::Spot
+    };
+    let expiry = match contract_type {
+        Some("PERPETUAL") | Some("") | None => None,
+        Some(_) => s.get("deliveryDate").and_then(|v| v.as_u64()),
+    };
+    ContractSpec {
+        venue,
+        base: instruments::text(s, "baseAsset"),
+        quote: instruments::text(s, "quoteAsset"),
+        settle: instruments::text(s, "marginAsset"),
+        lot_step: filter("LOT_SIZE", "stepSize"),
+        price_step: filter("PRICE_FILTER", "tickSize"),
+        contract_size: instruments::positive(s, "contractSize"),
+        expiry,
+    }
 }
 
 static REGISTER: Once = Once::new();
@@ -127,21 +161,26 @@ pub fn register() {
                           task_set: TaskSet,
                           channels: ChannelRegistry,
                           tls_config: Arc<ClientConfig>|
-                          -> BoxFuture<
-                        'static,
-                        Result<Vec<mpsc::Receiver<StreamMessage<'static>>>>,
-                    > {
+                          -> BoxFuture<'static, Result<Vec<StreamReceiver>>> {
                         let cfg = cfg_ref;
                         let initial_symbols = exchange_cfg.symbols.clone();
                         Box::pin(async move {
-                            let mut symbols = initial_symbols;
-                            if symbols.is_empty() {
-                                symbols = fetch_symbols(&client, cfg.info_url).await?;
-                            }
+                            let symbols = instruments::resolve_symbols(
+                                cfg.id,
+                                initial_symbols,
+                                {
+                                    let client = client.clone();
+                                    move || {
+                                        let client = client.clone();
+                                        async move { fetch_instruments(&client, cfg.info_url).await }
+                                    }
+                                },
+                            )
+                            .await?;
 
                             let mut receivers = Vec::new();
                             for symbol in &symbols {
-                                let key = format!("{name}:{symbol}", name = cfg.name, symbol = symbol);
+                                let key = format!("{id}:{symbol}", id = cfg.id, symbol = symbol);
                                 let (_, rx) = channels.get_or_create(&key);
                                 if let Some(rx) = rx {
                                     receivers.push(rx);
@@ -238,7 +277,7 @@ impl BinanceAdapter {
             .context("parsing WebSocket URL")?;
 
         let proxy = self.proxy_url.clone();
-        let name = self.cfg.name.to_string();
+        let id = self.cfg.id.to_string();
         let task_set = self.task_set.clone();
         let books = self.orderbooks.clone();
         let channels = self.channels.clone();
@@ -269,7 +308,7 @@ impl BinanceAdapter {
                                 .map(|(ws_stream, _)| ws_stream)
                             }
                         },
-                        name.clone(),
+                        id.clone(),
                         url,
                         chunk_len,
                         ws_bucket,
@@ -290,7 +329,7 @@ impl BinanceAdapter {
                             let tls = tls_config.clone();
                             async move { connect_via_socks5(url, &proxy_addr, tls).await }
                         },
-                        name.clone(),
+                        id.clone(),
                         url,
                         chunk_len,
                         ws_bucket,
@@ -312,7 +351,7 @@ impl BinanceAdapter {
 impl ExchangeAdapter for BinanceAdapter {
     async fn subscribe(&mut self) -> Result<()> {
         for symbol in &self.symbols {
-            let key = format!("{name}:{symbol}", name = self.cfg.name, symbol = symbol);
+            let key = format!("{id}:{symbol}", id = self.cfg.id, symbol = symbol);
             // Ensure a channel exists for each subscribed symbol
             self.channels.get_or_create(&key);
         }
@@ -653,7 +692,7 @@ fn lag_ns_from_event_time(ev_time: u64, now: std::time::SystemTime) -> u128 {
 }
 
 fn log_and_metric_event(
-    event: &StreamMessage<'static>,
+    event: &StreamMessage<'_>,
     bytes: &[u8],
     exchange: &str,
 ) -> (String, Instant, Span) {
@@ -735,6 +774,7 @@ async fn update_order_book(
 #[allow(clippy::too_many_arguments)]
 pub async fn process_text_message(
     text: String,
+    frame: &mut Frame,
     books: &Arc<DashMap<String, OrderBook>>,
     channels: &ChannelRegistry,
     client: &Client,
@@ -742,16 +782,29 @@ pub async fn process_text_message(
     exchange: &str,
     http_bucket: &Arc<TokenBucket>,
 ) -> Result<()> {
-    let mut bytes = text.into_bytes();
-    let event = match from_slice::<StreamMessage<'static>>(&mut bytes) {
+    // Parsed in place, so the event borrows its strings from the frame's
+    // text until it is handed to the channel.
+    let event = match frame.parse_in_place::<StreamMessage<'_>>(text) {
         Ok(event) => event,
         Err(e) => {
             tracing::error!("failed to parse message: {}", e);
             return Ok(());
         }
     };
+    // An unknown event borrows nothing, so the text it came from can be
+    // logged with it; `handle_stream_event` ignores it for the others.
+    let (event, raw) = match event.data {
+        Event::Unknown => {
+            let event = StreamMessage {
+                stream: event.stream,
+                data: Event::Unknown,
+            };
+            (event, frame.text())
+        }
+        _ => (event, &[][..]),
+    };
 
-    let (symbol, pipeline_start, span) = log_and_metric_event(&event, &bytes, exchange);
+    let (symbol, pipeline_start, span) = log_and_metric_event(&event, raw, exchange);
     let _enter = span.enter();
 
     if let Event::DepthUpdate(ref update) = event.data {
@@ -760,7 +813,7 @@ pub async fn process_text_message(
 
     let key = format!("{exchange}:{symbol}");
     if let Some(tx) = channels.get(&key) {
-        if let Err(e) = tx.send(event) {
+        if let Err(e) = tx.send(event.into_owned()).await {
             tracing::warn!("failed to send event: {}", e);
         }
     } else {
@@ -790,6 +843,7 @@ where
     let mut ping_interval = interval(Duration::from_secs(30));
     ping_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
     let mut last_pong = Instant::now();
+    let mut frame = Frame::new();
 
     loop {
         tokio::select! {
@@ -814,6 +868,7 @@ where
                     Ok(Message::Text(text)) => {
                         process_text_message(
                             text,
+                            &mut frame,
                             &books,
                             &channels,
                             &client,
@@ -8,13 +8,13 @@ use reqwest::Client;
 use serde_json::Value;
 use std::borrow::Cow;
 use std::sync::{Arc, Once};
-use tokio::sync::mpsc;
 use tokio::time::{interval, sleep, Duration};
 use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
 use tracing::{error, info, warn};
 
 use super::ExchangeAdapter;
-use crate::{registry, ChannelRegistry, TaskSet};
+use crate::{instruments, registry, ChannelRegistry, StreamReceiver, TaskSet};
+use canonical::symbol::{ContractSpec, Instrument, VenueType};
 
 #[derive(Clone, Copy)]
 pub enum MarketType {
@@ -47,7 +47,7 @@ pub const BINGX_EXCHANGES: &[BingxConfig] = &[
     },
 ];
 
-async fn fetch_spot_symbols(info_url: &str) -> Result<Vec<String>> {
+async fn fetch_spot_instruments(info_url: &str) -> Result<Vec<Instrument>> {
     let resp = Client::new()
         .get(info_url)
         .send()
@@ -59,24 +59,25 @@ async fn fetch_spot_symbols(info_url: &str) -> Result<Vec<String>> {
         .and_then(|d| d.get("symbols"))
         .and_then(|v| v.as_array())
         .ok_or_else(|| anyhow!("missing symbols array"))?;
-    let mut result: Vec<String> = symbols
+    Ok(symbols
         .iter()
         .filter_map(|s| {
             if s.get("status").and_then(|v| v.as_i64()).unwrap_or(0) == 0 {
-                s.get("symbol")
-                    .and_then(|v| v.as_str())
-                    .map(|v| v.to_string())
+                let sym = s.get("symbol").and_then(|v| v.as_str())?;
+                let spec = ContractSpec {
+                    lot_step: instruments::positive(s, "stepSize"),
+                    price_step: instruments::positive(s, "tickSize"),
+                    ..instruments::pair_spec(VenueType::Spot, sym, '-')
+                };
+                Some(instruments::instrument("bingx", sym, spec))
             } else {
                 None
             }
         })
-        .collect();
-    result.sort();
-    result.dedup();
-    Ok(result)
+        .collect())
 }
 
-async fn fetch_swap_symbols(info_url: &str) -> Result<Vec<String>> {
+async fn fetch_swap_instruments(info_url: &str) -> Result<Vec<Instrument>> {
     let client = Client::new();
     let mut page = 1;
     let mut result = Vec::new();
@@ -94,21 +95,25 @@ async fn fetch_swap_symbols(info_url: &str) -> Result<Vec<String>> {
         for s in arr {
             if s.get("status").and_then(|v| v.as_i64()).unwrap_or(1) == 1 {
                 if let Some(sym) = s.get("symbol").and_then(|v| v.as_str()) {
-                    result.push(sym.to_string());
+                    let spec = ContractSpec {
+                        lot_step: instruments::decimals(s, "quantityPrecision"),
+                        price_step: instruments::decimals(s, "pricePrecision"),
+                        contract_size: instruments::positive(s, "size"),
+                        ..instruments::pair_spec(VenueType::Futures, sym, '-')
+                    };
+                    result.push(instruments::instrument("bingx", sym, spec));
                 }
             }
         }
         page += 1;
     }
-    result.sort();
-    result.dedup();
     Ok(result)
 }
 
-async fn fetch_symbols(cfg: &BingxConfig) -> Result<Vec<String>> {
+async fn fetch_instruments(cfg: &BingxConfig) -> Result<Vec<Instrument>> {
     match cfg.market {
-        MarketType::Spot => fetch_spot_symbols(cfg.info_url).await,
-        MarketType::Swap => fetch_swap_symbols(cfg.info_url).await,
+        MarketType::Spot => fetch_spot_instruments(cfg.info_url).await,
+        MarketType::Swap => fetch_swap_instruments(cfg.info_url).await,
     }
 }
 
@@ -127,20 +132,19 @@ pub fn register() {
                           task_set: TaskSet,
                           channels: ChannelRegistry,
                           _tls: Arc<rustls::ClientConfig>|
-                          -> BoxFuture<
-                        'static,
-                        Result<Vec<mpsc::Receiver<core::events::StreamMessage<'static>>>>,
-                    > {
+                          -> BoxFuture<'static, Result<Vec<StreamReceiver>>> {
                         let cfg = cfg_ref;
                         let initial_symbols = exchange_cfg.symbols.clone();
                         Box::pin(async move {
-                            let mut symbols = initial_symbols;
-                            if symbols.is_empty() {
-                                symbols = fetch_symbols(cfg).await?;
-                            }
+                            let symbols = instruments::resolve_symbols(
+                                cfg.id,
+                                initial_symbols,
+                                move || fetch_instruments(cfg),
+                            )
+                            .await?;
                             let mut receivers = Vec::new();
                             for symbol in &symbols {
-                                let key = format!("{name}:{symbol}", name = cfg.name, symbol = symbol);
+                                let key = format!("{id}:{symbol}", id = cfg.id, symbol = symbol);
                                 let (_, rx) = channels.get_or_create(&key);
                                 if let Some(rx) = rx {
                                     receivers.push(rx);
@@ -251,6 +255,7 @@ fn map_message(msg: BingxStreamMessage<'_>) -> Option<core::events::StreamMessag
 
 #[async_trait]
 impl ExchangeAdapter for BingxAdapter {
+    #[allow(clippy::collapsible_match)]
     async fn subscribe(&mut self) -> Result<()> {
         let symbol_refs: Vec<&str> = self.symbols.iter().map(|s| s.as_str()).collect();
         let cfg = stream_config_for_exchange(self.cfg.name);
@@ -259,7 +264,7 @@ impl ExchangeAdapter for BingxAdapter {
         for chunk in chunks {
             let ws_url = self.cfg.ws_base.to_string();
             let channels = self.channels.clone();
-            let exch_name = self.cfg.name;
+            let exch_id = self.cfg.id;
             tokio::spawn(async move {
                 loop {
                     match connect_async(&ws_url).await {
@@ -291,9 +296,9 @@ impl ExchangeAdapter for BingxAdapter {
                                                             core::events::Event::DepthUpdate(ev) => &ev.symbol,
                                                             _ => unreachable!(),
                                                         };
-                                                        let key = format!("{exch_name}:{symbol_key}");
+                                                        let key = format!("{exch_id}:{symbol_key}");
                                                         let (tx, _) = channels.get_or_create(&key);
-                                                        if tx.send(event).is_err() {
+                                                        if tx.send(event).await.is_err() {
                                                             break;
                                                         }
                                                     }
@@ -4,6 +4,7 @@ use async_trait::async_trait;
 use core::events::StreamMessage;
 use futures::{future::BoxFuture, SinkExt, StreamExt};
 use reqwest::Client;
+use serde::Deserialize;
 use serde_json::Value;
 use std::{
     borrow::Cow,
@@ -13,7 +14,6 @@ use std::{
         Arc, Once,
     },
 };
-use tokio::sync::mpsc;
 use tokio::{
     signal,
     task::JoinHandle,
@@ -23,7 +23,10 @@ use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
 use tracing::{error, info, warn};
 
 use super::ExchangeAdapter;
-use crate::{registry, ChannelRegistry, StreamSender, TaskSet};
+use crate::{
+    frame::{Frame, MapOrSeq}, instruments, registry, ChannelRegistry, StreamReceiver, StreamSender, TaskSet,
+};
+use canonical::symbol::{ContractSpec, Instrument, VenueType};
 
 /// Configuration for the Bitget exchange.
 pub struct BitgetConfig {
@@ -41,6 +44,11 @@ pub const BITGET_EXCHANGES: &[BitgetConfig] = &[BitgetConfig {
 
 /// Retrieve all trading symbols across Bitget spot and futures markets.
 pub async fn fetch_symbols() -> Result<Vec<String>> {
+    Ok(instruments::symbols(&fetch_instruments().await?))
+}
+
+/// Retrieve metadata for all Bitget spot and futures instruments.
+pub async fn fetch_instruments() -> Result<Vec<Instrument>> {
     let client = Client::new();
     let mut result = Vec::new();
 
@@ -58,9 +66,16 @@ pub async fn fetch_symbols() -> Result<Vec<String>> {
     result.extend(arr.iter().filter_map(|s| {
         let status = s.get("status").and_then(|v| v.as_str()).unwrap_or("");
         if status.eq_ignore_ascii_case("online") {
-            s.get("symbol")
-                .and_then(|v| v.as_str())
-                .map(|v| v.to_string())
+            let symbol = s.get("symbol").and_then(|v| v.as_str())?;
+            let spec = ContractSpec {
+                venue: VenueType::Spot,
+                base: instruments::text(s, "baseCoin"),
+                quote: instruments::text(s, "quoteCoin"),
+                lot_step: instruments::decimals(s, "quantityScale"),
+                price_step: instruments::decimals(s, "priceScale"),
+                ..Default::default()
+            };
+            Some(instruments::instrument("bitget", symbol, spec))
         } else {
             None
         }
@@ -83,17 +98,30 @@ pub async fn fetch_symbols() -> Result<Vec<String>> {
         result.extend(arr.iter().filter_map(|s| {
             let status = s.get("symbolStatus").and_then(|v| v.as_str()).unwrap_or("");
             if status.eq_ignore_ascii_case("normal") {
-                s.get("symbol")
-                    .and_then(|v| v.as_str())
-                    .map(|v| v.to_string())
+                let symbol = s.get("symbol").and_then(|v| v.as_str())?;
+                let base = instruments::text(s, "baseCoin");
+                // Coin-margined (`dmcbl`) contracts settle in the base coin.
+                let settle = if *pt == "dmcbl" {
+                    base.clone()
+                } else {
+                    String::new()
+                };
+                let spec = ContractSpec {
+                    venue: VenueType::Futures,
+                    base,
+                    quote: instruments::text(s, "quoteCoin"),
+                    settle,
+                    lot_step: instruments::positive(s, "sizeMultiplier"),
+                    price_step: instruments::decimals(s, "pricePlace"),
+                    ..Default::default()
+                };
+                Some(instruments::instrument("bitget", symbol, spec))
             } else {
                 None
             }
         }));
     }
 
-    result.sort();
-    result.dedup();
     Ok(result)
 }
 
@@ -113,21 +141,20 @@ pub fn register() {
                           task_set: TaskSet,
                           channels: ChannelRegistry,
                           _tls_config: Arc<rustls::ClientConfig>|
-                          -> BoxFuture<
-                        'static,
-                        Result<Vec<mpsc::Receiver<StreamMessage<'static>>>>,
-                    > {
+                          -> BoxFuture<'static, Result<Vec<StreamReceiver>>> {
                         let cfg = cfg_ref;
                         let initial_symbols = exchange_cfg.symbols.clone();
                         Box::pin(async move {
-                            let mut symbols = initial_symbols;
-                            if symbols.is_empty() {
-                                symbols = fetch_symbols().await?;
-                            }
+                            let symbols = instruments::resolve_symbols(
+                                cfg.id,
+                                initial_symbols,
+                                fetch_instruments,
+                            )
+                            .await?;
 
                             let mut receivers = Vec::new();
                             for symbol in &symbols {
-                                let key = format!("{name}:{symbol}", name = cfg.name, symbol = symbol);
+                                let key = format!("{id}:{symbol}", id = cfg.id, symbol = symbol);
                                 let (_, rx) = channels.get_or_create(&key);
                                 if let Some(rx) = rx {
                                     receivers.push(rx);
@@ -194,6 +221,7 @@ impl BitgetAdapter {
 
 #[async_trait]
 impl super::ExchangeAdapter for BitgetAdapter {
+    #[allow(clippy::collapsible_match)]
     async fn subscribe(&mut self) -> Result<()> {
         for symbols in self.symbols.chunks(self.chunk_size) {
             let symbol_list = symbols.iter().map(|s| s.to_string()).collect::<Vec<_>>();
@@ -215,7 +243,7 @@ impl super::ExchangeAdapter for BitgetAdapter {
                                 args.push(serde_json::json!({"channel":"trade","instId":s}));
                                 args.push(serde_json::json!({"channel":"depth","instId":s}));
                                 args.push(serde_json::json!({"channel":"candle1m","instId":s}));
-                                let key = format!("{name}:{sym}", name = cfg.name, sym = s);
+                                let key = format!("{id}:{sym}", id = cfg.id, sym = s);
                                 if let Some(tx) = channels.get(&key) {
                                     senders.insert(s.clone(), tx);
                                 }
@@ -226,21 +254,16 @@ impl super::ExchangeAdapter for BitgetAdapter {
                             }
 
                             let mut hb = tokio::time::interval(Duration::from_secs(20));
+                            let mut frame = Frame::new();
                             loop {
                                 tokio::select! {
                                     msg = ws.next() => {
                                         match msg {
                                             Some(Ok(Message::Text(text))) => {
-                                                if let Ok(v) = serde_json::from_str::<Value>(&text) {
-                                                    if let Some(event) =
-                                                        parse_trade_message(&v)
-                                                            .or_else(|| parse_depth_message(&v))
-                                                            .or_else(|| parse_candle_message(&v))
-                                                    {
-                                                        if let Some(sym) = event.data.symbol() {
-                                                            if let Some(tx) = senders.get(sym) {
-                                                                if tx.send(event).is_err() { break; }
-                                                            }
+                                                if let Some(event) = parse_message(&mut frame, &text) {
+                                                    if let Some(sym) = event.data.symbol() {
+                                                        if let Some(tx) = senders.get(sym) {
+                                                            if tx.send(event).await.is_err() { break; }
                                                         }
                                                     }
                                                 }
@@ -297,41 +320,72 @@ impl super::ExchangeAdapter for BitgetAdapter {
     }
 }
 
-fn parse_trade_message(val: &Value) -> Option<StreamMessage<'static>> {
-    let arg = val.get("arg")?.as_object()?;
-    if arg.get("channel")?.as_str()? != "trade" {
-        return None;
+/// Push frame, e.g. `{"action":"update","arg":{"channel":"trade","instId":"BTCUSDT"},"data":[...]}`.
+#[derive(Deserialize)]
+struct BitgetFrame<'a> {
+    #[serde(borrow)]
+    arg: Option<BitgetArg<'a>>,
+    #[serde(borrow, default)]
+    data: Vec<BitgetRow<'a>>,
+}
+
+#[derive(Deserialize)]
+struct BitgetArg<'a> {
+    channel: &'a str,
+    #[serde(rename = "instId")]
+    inst_id: &'a str,
+}
+
+/// Entry of `data`: an object for trades and depth, and an array of
+/// `[ts, open, high, low, close, volume, ...]` for candles.
+type BitgetRow<'a> = MapOrSeq<BitgetFields<'a>, Vec<&'a str>>;
+
+#[derive(Deserialize)]
+struct BitgetFields<'a> {
+    ts: Option<&'a str>,
+    #[serde(alias = "px")]
+    price: Option<&'a str>,
+    #[serde(alias = "size")]
+    sz: Option<&'a str>,
+    side: Option<&'a str>,
+    #[serde(rename = "tradeId")]
+    trade_id: Option<&'a str>,
+    #[serde(default)]
+    bids: Vec<[&'a str; 2]>,
+    #[serde(default)]
+    asks: Vec<[&'a str; 2]>,
+}
+
+/// Parse a text frame through `frame` into a trade, depth or candle message.
+pub fn parse_message(frame: &mut Frame, text: &str) -> Option<StreamMessage<'static>> {
+    let msg: BitgetFrame<'_> = frame.parse(text.as_bytes()).ok()?;
+    let arg = msg.arg?;
+    let row = msg.data.into_iter().next()?;
+    match (arg.channel, row) {
+        ("trade", MapOrSeq::Map(data)) => Some(trade_message(arg.inst_id, data)),
+        ("depth", MapOrSeq::Map(data)) => Some(depth_message(arg.inst_id, data)),
+        (channel, MapOrSeq::Seq(data)) => {
+            let interval = channel.strip_prefix("candle")?;
+            candle_message(arg.inst_id, channel, interval, &data)
+        }
+        _ => None,
     }
-    let inst_id = arg.get("instId")?.as_str()?.to_string();
-    let data = val.get("data")?.as_array()?.first()?.as_object()?;
-    let ts = data
-        .get("ts")
-        .and_then(|v| v.as_str())
-        .and_then(|s| s.parse::<u64>().ok())
-        .unwrap_or(0);
-    let price = data
-        .get("price")
-        .or_else(|| data.get("px"))
-        .and_then(|v| v.as_str())
-        .unwrap_or("0");
-    let qty = data
-        .get("sz")
-        .or_else(|| data.get("size"))
-        .and_then(|v| v.as_str())
-        .unwrap_or("0");
-    let side = data.get("side").and_then(|v| v.as_str()).unwrap_or("");
-    let trade_id = data
-        .get("tradeId")
-        .and_then(|v| v.as_str())
-        .and_then(|s| s.parse::<u64>().ok())
-        .unwrap_or(0);
+}
+
+fn parse_u64(val: Option<&str>) -> u64 {
+    val.and_then(|s| s.parse().ok()).unwrap_or(0)
+}
+
+fn trade_message(inst_id: &str, data: BitgetFields<'_>) -> StreamMessage<'static> {
+    let ts = parse_u64(data.ts);
+    let side = data.side.unwrap_or("");
 
     let event = core::events::TradeEvent {
         event_time: ts,
-        symbol: inst_id.clone(),
-        trade_id,
-        price: Cow::Owned(price.to_string()),
-        quantity: Cow::Owned(qty.to_string()),
+        symbol: inst_id.to_string(),
+        trade_id: parse_u64(data.trade_id),
+        price: Cow::Owned(data.price.unwrap_or("0").to_string()),
+        quantity: Cow::Owned(data.sz.unwrap_or("0").to_string()),
         buyer_order_id: 0,
         seller_order_id: 0,
         trade_time: ts,
@@ -339,89 +393,57 @@ fn parse_trade_message(val: &Value) -> Option<StreamMessage<'static>> {
         best_match: true,
     };
 
-    Some(StreamMessage {
-        stream: Box::leak(format!("{inst_id}@trade").into_boxed_str()).into(),
+    StreamMessage {
+        stream: format!("{inst_id}@trade"),
         data: core::events::Event::Trade(event),
-    })
+    }
 }
 
-fn parse_depth_message(val: &Value) -> Option<StreamMessage<'static>> {
-    let arg = val.get("arg")?.as_object()?;
-    if arg.get("channel")?.as_str()? != "depth" {
-        return None;
-    }
-    let inst_id = arg.get("instId")?.as_str()?.to_string();
-    let data = val.get("data")?.as_array()?.first()?.as_object()?;
-    let ts = data
-        .get("ts")
-        .and_then(|v| v.as_str())
-        .and_then(|s| s.parse::<u64>().ok())
-        .unwrap_or(0);
-    let mut bids = Vec::new();
-    if let Some(arr) = data.get("bids").and_then(|v| v.as_array()) {
-        for level in arr.iter().filter_map(|l| l.as_array()) {
-            if level.len() >= 2 {
-                let price = level[0].as_str().unwrap_or("0").to_string();
-                let qty = level[1].as_str().unwrap_or("0").to_string();
-                bids.push([Cow::Owned(price), Cow::Owned(qty)]);
-            }
-        }
-    }
-    let mut asks = Vec::new();
-    if let Some(arr) = data.get("asks").and_then(|v| v.as_array()) {
-        for level in arr.iter().filter_map(|l| l.as_array()) {
-            if level.len() >= 2 {
-                let price = level[0].as_str().unwrap_or("0").to_string();
-                let qty = level[1].as_str().unwrap_or("0").to_string();
-                asks.push([Cow::Owned(price), Cow::Owned(qty)]);
-            }
-        }
-    }
+fn depth_message(inst_id: &str, data: BitgetFields<'_>) -> StreamMessage<'static> {
+    let levels = |levels: Vec<[&str; 2]>| {
+        levels
+            .into_iter()
+            .map(|[price, qty]| [Cow::Owned(price.to_string()), Cow::Owned(qty.to_string())])
+            .collect()
+    };
 
     let event = core::events::DepthUpdateEvent {
-        event_time: ts,
-        symbol: inst_id.clone(),
+        event_time: parse_u64(data.ts),
+        symbol: inst_id.to_string(),
         first_update_id: 0,
         final_update_id: 0,
         previous_final_update_id: 0,
-        bids,
-        asks,
+        bids: levels(data.bids),
+        asks: levels(data.asks),
     };
 
-    Some(StreamMessage {
-        stream: Box::leak(format!("{inst_id}@depth").into_boxed_str()).into(),
+    StreamMessage {
+        stream: format!("{inst_id}@depth"),
         data: core::events::Event::DepthUpdate(event),
-    })
+    }
 }
 
-fn parse_candle_message(val: &Value) -> Option<StreamMessage<'static>> {
-    let arg = val.get("arg")?.as_object()?;
-    let channel = arg.get("channel")?.as_str()?;
-    if !channel.starts_with("candle") {
-        return None;
-    }
-    let inst_id = arg.get("instId")?.as_str()?.to_string();
-    let interval = channel.strip_prefix("candle").unwrap_or("");
-    let data = val.get("data")?.as_array()?.first()?.as_array()?;
-    let ts = data.first()?.as_str()?.parse::<u64>().unwrap_or(0);
-    let open = data.get(1).and_then(|v| v.as_str()).unwrap_or("0");
-    let high = data.get(2).and_then(|v| v.as_str()).unwrap_or("0");
-    let low = data.get(3).and_then(|v| v.as_str()).unwrap_or("0");
-    let close = data.get(4).and_then(|v| v.as_str()).unwrap_or("0");
-    let vol = data.get(5).and_then(|v| v.as_str()).unwrap_or("0");
+fn candle_message(
+    inst_id: &str,
+    channel: &str,
+    interval: &str,
+    data: &[&str],
+) -> Option<StreamMessage<'static>> {
+    let ts = data.first()?.parse::<u64>().unwrap_or(0);
+    let field = |i: usize| Cow::Owned(data.get(i).copied().unwrap_or("0").to_string());
 
     let event = core::events::KlineEvent {
         event_time: ts,
-        symbol: inst_id.clone(),
+        symbol: inst_id.to_string(),
         kline: core::events::Kline {
             start_time: ts,
             close_time: ts,
             interval: interval.to_string(),
-            open: Cow::Owned(open.to_string()),
-            close: Cow::Owned(close.to_string()),
-            high: Cow::Owned(high.to_string()),
-            low: Cow::Owned(low.to_string()),
-            volume: Cow::Owned(vol.to_string()),
+            open: field(1),
+            close: field(4),
+            high: field(2),
+            low: field(3),
+            volume: field(5),
             trades: 0,
             is_closed: true,
             quote_volume: Cow::Owned("0".to_string()),
@@ -431,7 +453,7 @@ fn parse_candle_message(val: &Value) -> Option<StreamMessage<'static>> {
     };
 
     Some(StreamMessage {
-        stream: Box::leak(format!("{inst_id}@{channel}").into_boxed_str()).into(),
+        stream: format!("{inst_id}@{channel}"),
         data: core::events::Event::Kline(event),
     })
 }
@@ -12,7 +12,6 @@ use std::{
         Arc, Once,
     },
 };
-use tokio::sync::mpsc;
 use tokio::{
     signal,
     task::JoinHandle,
@@ -24,7 +23,8 @@ use tracing::{error, info, warn};
 use core::events::{Event, StreamMessage};
 
 use super::ExchangeAdapter;
-use crate::{registry, ChannelRegistry, TaskSet};
+use crate::{instruments, registry, ChannelRegistry, StreamReceiver, TaskSet};
+use canonical::symbol::{ContractSpec, Instrument, VenueType};
 
 /// Configuration for a single BitMart exchange endpoint.
 pub struct BitmartConfig {
@@ -53,8 +53,8 @@ pub const BITMART_EXCHANGES: &[BitmartConfig] = &[
     },
 ];
 
-/// Retrieve all spot trading symbols from BitMart.
-pub async fn fetch_spot_symbols(url: &str) -> Result<Vec<String>> {
+/// Retrieve metadata for all spot trading instruments from BitMart.
+pub async fn fetch_spot_instruments(url: &str) -> Result<Vec<Instrument>> {
     let resp = Client::new().get(url).send().await?.error_for_status()?;
     let data: Value = resp.json().await?;
     let arr = data
@@ -63,7 +63,7 @@ pub async fn fetch_spot_symbols(url: &str) -> Result<Vec<String>> {
         .and_then(|v| v.as_array())
         .ok_or_else(|| anyhow!("missing symbols array"))?;
 
-    let mut result: Vec<String> = arr
+    Ok(arr
         .iter()
         .filter_map(|s| {
             let tradable = s
@@ -72,21 +72,25 @@ pub async fn fetch_spot_symbols(url: &str) -> Result<Vec<String>> {
                 .map(|st| st.eq_ignore_ascii_case("trading"))
                 .unwrap_or(false);
             if tradable {
-                s.get("symbol")
-                    .and_then(|v| v.as_str())
-                    .map(|v| v.to_string())
+                let symbol = s.get("symbol").and_then(|v| v.as_str())?;
+                let spec = ContractSpec {
+                    venue: VenueType::Spot,
+                    base: instruments::text(s, "base_currency"),
+                    quote: instruments::text(s, "quote_currency"),
+                    lot_step: instruments::positive(s, "base_min_size"),
+                    price_step: instruments::decimals(s, "price_max_precision"),
+                    ..Default::default()
+                };
+                Some(instruments::instrument("bitmart", symbol, spec))
             } else {
                 None
             }
         })
-        .collect();
-    result.sort();
-    result.dedup();
-    Ok(result)
+        .collect())
 }
 
-/// Retrieve all contract trading symbols from BitMart.
-pub async fn fetch_contract_symbols(url: &str) -> Result<Vec<String>> {
+/// Retrieve metadata for all contract trading instruments from BitMart.
+pub async fn fetch_contract_instruments(url: &str) -> Result<Vec<Instrument>> {
     let resp = Client::new().get(url).send().await?.error_for_status()?;
     let data: Value = resp.json().await?;
     let arr = data
@@ -95,27 +99,49 @@ pub async fn fetch_contract_symbols(url: &str) -> Result<Vec<String>> {
         .and_then(|v| v.as_array())
         .ok_or_else(|| anyhow!("missing contracts array"))?;
 
-    let mut result: Vec<String> = arr
+    Ok(arr
         .iter()
         .filter_map(|s| {
-            s.get("symbol")
-                .and_then(|v| v.as_str())
-                .map(|v| v.to_string())
+            let symbol = s.get("symbol").and_then(|v| v.as_str())?;
+            let spec = ContractSpec {
+                venue: VenueType::Futures,
+                base: instruments::text(s, "base_currency"),
+                quote: instruments::text(s, "quote_currency"),
+                settle: instruments::text(s, "settle_coin"),
+                lot_step: instruments::positive(s, "vol_precision"),
+                price_step: instruments::positive(s, "price_precision"),
+                contract_size: instruments::positive(s, "contract_size"),
+                // Perpetual contracts report an expiry of zero.
+                expiry: s
+                    .get("expire_timestamp")
+                    .and_then(|v| v.as_u64())
+                    .filter(|ts| *ts > 0),
+            };
+            Some(instruments::instrument("bitmart", symbol, spec))
         })
-        .collect();
-    result.sort();
-    result.dedup();
-    Ok(result)
+        .collect())
+}
+
+/// Retrieve all spot trading symbols from BitMart.
+pub async fn fetch_spot_symbols(url: &str) -> Result<Vec<String>> {
+    Ok(instruments::symbols(&fetch_spot_instruments(url).await?))
+}
+
+/// Retrieve all contract trading symbols from BitMart.
+pub async fn fetch_contract_symbols(url: &str) -> Result<Vec<String>> {
+    Ok(instruments::symbols(&fetch_contract_instruments(url).await?))
 }
 
 /// Fetch and merge spot and contract symbols.
 pub async fn fetch_symbols(cfg: &BitmartConfig) -> Result<Vec<String>> {
-    let mut symbols = fetch_spot_symbols(cfg.spot_url).await?;
-    let mut contracts = fetch_contract_symbols(cfg.contract_url).await?;
-    symbols.append(&mut contracts);
-    symbols.sort();
-    symbols.dedup();
-    Ok(symbols)
+    Ok(instruments::symbols(&fetch_instruments(cfg).await?))
+}
+
+/// Fetch and merge spot and contract instrument metadata.
+pub async fn fetch_instruments(cfg: &BitmartConfig) -> Result<Vec<Instrument>> {
+    let mut instruments = fetch_spot_instruments(cfg.spot_url).await?;
+    instruments.append(&mut fetch_contract_instruments(cfg.contract_url).await?);
+    Ok(instruments)
 }
 
 fn parse_depth_side(side: Option<&Value>) -> Vec<[String; 2]> {
@@ -247,21 +273,20 @@ pub fn register() {
                           task_set: TaskSet,
                           channels: ChannelRegistry,
                           _tls_config: Arc<rustls::ClientConfig>|
-                          -> BoxFuture<
-                        'static,
-                        Result<Vec<mpsc::Receiver<core::events::StreamMessage<'static>>>>,
-                    > {
+                          -> BoxFuture<'static, Result<Vec<StreamReceiver>>> {
                         let cfg = cfg_ref;
                         let initial_symbols = exchange_cfg.symbols.clone();
                         Box::pin(async move {
-                            let mut symbols = initial_symbols;
-                            if symbols.is_empty() {
-                                symbols = fetch_symbols(cfg).await?;
-                            }
+                            let symbols = instruments::resolve_symbols(
+                                cfg.id,
+                                initial_symbols,
+                                move || fetch_instruments(cfg),
+                            )
+                            .await?;
 
                             let mut receivers = Vec::new();
                             for symbol in &symbols {
-                                let key = format!("{name}:{symbol}", name = cfg.name, symbol = symbol);
+                                let key = format!("{id}:{symbol}", id = cfg.id, symbol = symbol);
                                 let (_, rx) = channels.get_or_create(&key);
                                 if let Some(rx) = rx {
                                     receivers.push(rx);
@@ -330,6 +355,7 @@ impl BitmartAdapter {
 
 #[async_trait]
 impl ExchangeAdapter for BitmartAdapter {
+    #[allow(clippy::collapsible_match)]
     async fn subscribe(&mut self) -> Result<()> {
         for symbols in self.symbols.chunks(self.chunk_size) {
             let prefix = if self.cfg.id == "bitmart_spot" {
@@ -459,9 +485,9 @@ impl ExchangeAdapter for BitmartAdapter {
                                                                                 stream,
                                                                                 data: Event::DepthUpdate(update),
                                                                             };
-                                                                            let key = format!("{name}:{sym}", name = cfg.name, sym = sym);
+                                                                            let key = format!("{id}:{sym}", id = cfg.id, sym = sym);
                                                                             if let Some(tx) = channels.get(&key) {
-                                                                                if let Err(e) = tx.send(msg) {
+                                                                                if let Err(e) = tx.send(msg).await {
                                                                                     warn!(channel = %key, "failed to send depth update: {}", e);
                                                                                 }
                                                                             } else {
@@ -20,10 +20,10 @@ use tokio::{
 use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
 
 use super::ExchangeAdapter;
-use crate::{registry, ChannelRegistry, StreamSender, TaskSet};
+use crate::{instruments, registry, ChannelRegistry, StreamSender, StreamReceiver, TaskSet};
+use canonical::symbol::{ContractSpec, Instrument, VenueType};
 use futures::future::BoxFuture;
 use std::sync::Once;
-use tokio::sync::mpsc;
 use tracing::error;
 use std::collections::HashMap;
 
@@ -50,8 +50,13 @@ const SPOT_INFO_URL: &str = "https://api.coinex.com/v1/market/info";
 const PERP_INFO_URL: &str = "https://api.coinex.com/perpetual/v1/market/list";
 
 pub async fn fetch_symbols() -> Result<Vec<String>> {
+    Ok(instruments::symbols(&fetch_instruments().await?))
+}
+
+/// Retrieve metadata for all CoinEx spot markets and perpetual contracts.
+pub async fn fetch_instruments() -> Result<Vec<Instrument>> {
     let client = Client::new();
-    let mut symbols: Vec<String> = Vec::new();
+    let mut symbols: Vec<Instrument> = Vec::new();
 
     let mut page = 1;
     let limit = 100;
@@ -64,7 +69,17 @@ pub async fn fetch_symbols() -> Result<Vec<String>> {
             .and_then(|v| v.as_object())
             .ok_or_else(|| anyhow!("missing data object"))?;
         let count = obj.len();
-        symbols.extend(obj.keys().cloned());
+        symbols.extend(obj.iter().map(|(name, item)| {
+            let spec = ContractSpec {
+                venue: VenueType::Spot,
+                base: instruments::text(item, "trading_name"),
+                quote: instruments::text(item, "pricing_name"),
+                lot_step: instruments::decimals(item, "trading_decimal"),
+                price_step: instruments::decimals(item, "pricing_decimal"),
+                ..Default::default()
+            };
+            instruments::instrument("coinex", name, spec)
+        }));
         if count < limit {
             break;
         }
@@ -83,7 +98,24 @@ pub async fn fetch_symbols() -> Result<Vec<String>> {
         let count = arr.len();
         for item in arr {
             if let Some(sym) = item.get("name").and_then(|v| v.as_str()) {
-                symbols.push(sym.to_string());
+                let base = instruments::text(item, "stock");
+                // Market type 2 is an inverse contract settled in the stock.
+                let settle = if item.get("type").and_then(|v| v.as_i64()) == Some(2) {
+                    base.clone()
+                } else {
+                    String::new()
+                };
+                let spec = ContractSpec {
+                    venue: VenueType::Futures,
+                    base,
+                    quote: instruments::text(item, "money"),
+                    settle,
+                    lot_step: instruments::decimals(item, "amount_prec"),
+                    price_step: instruments::positive(item, "tick_size"),
+                    contract_size: instruments::positive(item, "multiplier"),
+                    expiry: None,
+                };
+                symbols.push(instruments::instrument("coinex", sym, spec));
             }
         }
         if count < limit {
@@ -92,8 +124,6 @@ pub async fn fetch_symbols() -> Result<Vec<String>> {
         page += 1;
     }
 
-    symbols.sort();
-    symbols.dedup();
     Ok(symbols)
 }
 
@@ -124,21 +154,20 @@ pub fn register() {
                           task_set: TaskSet,
                           channels: ChannelRegistry,
                           _tls_config: Arc<rustls::ClientConfig>|
-                          -> BoxFuture<
-                        'static,
-                        Result<Vec<mpsc::Receiver<core::events::StreamMessage<'static>>>>,
-                    > {
+                          -> BoxFuture<'static, Result<Vec<StreamReceiver>>> {
                         let cfg = cfg_ref;
                         let initial_symbols = exchange_cfg.symbols.clone();
                         Box::pin(async move {
-                            let mut symbols = initial_symbols;
-                            if symbols.is_empty() {
-                                symbols = fetch_symbols().await?;
-                            }
+                            let symbols = instruments::resolve_symbols(
+                                cfg.id,
+                                initial_symbols,
+                                fetch_instruments,
+                            )
+                            .await?;
 
                             let mut receivers = Vec::new();
                             for symbol in &symbols {
-                                let key = format!("{name}:{symbol}", name = cfg.name, symbol = symbol);
+                                let key = format!("{id}:{symbol}", id = cfg.id, symbol = symbol);
                                 let (_, rx) = channels.get_or_create(&key);
                                 if let Some(rx) = rx {
                                     receivers.push(rx);
@@ -235,7 +264,7 @@ impl ExchangeAdapter for CoinexAdapter {
             let handle = tokio::spawn(async move {
                 let mut senders: HashMap<String, StreamSender> = HashMap::new();
                 for s in &symbols {
-                    let key = format!("{name}:{sym}", name = cfg.name, sym = s);
+                    let key = format!("{id}:{sym}", id = cfg.id, sym = s);
                     if let Some(tx) = channels.get(&key) {
                         senders.insert(s.clone(), tx);
                     }
@@ -321,7 +350,7 @@ impl ExchangeAdapter for CoinexAdapter {
                                                                                 }),
                                                                             };
                                                                             if let Some(tx) = senders.get(&symbol) {
-                                                                                let _ = tx.send(event);
+                                                                                let _ = tx.send(event).await;
                                                                             }
                                                                         }
                                                                     }
@@ -341,7 +370,7 @@ impl ExchangeAdapter for CoinexAdapter {
                                                                                 }),
                                                                             };
                                                                             if let Some(tx) = senders.get(&symbol) {
-                                                                                let _ = tx.send(event);
+                                                                                let _ = tx.send(event).await;
                                                                             }
                                                                         }
                                                                     }
@@ -6,13 +6,14 @@ use reqwest::Client;
 use serde_json::{json, Value};
 use std::borrow::Cow;
 use std::sync::{Arc, Once};
-use tokio::sync::{mpsc, Mutex};
+use tokio::sync::Mutex;
 use tokio::time::{interval, sleep, Duration};
 use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
 use tracing::{error, info};
 
 use super::ExchangeAdapter;
-use crate::{registry, ChannelRegistry, TaskSet};
+use crate::{instruments, registry, ChannelRegistry, StreamReceiver, TaskSet};
+use canonical::symbol::{ContractSpec, Instrument, VenueType};
 use core::events::{
     Event, GateioDepth, GateioKline, GateioStreamMessage, GateioTrade, StreamMessage,
 };
@@ -45,7 +46,12 @@ pub const GATEIO_EXCHANGES: &[GateioConfig] = &[
 ///
 /// The caller must supply a reusable [`reqwest::Client`] instance for efficiency.
 pub async fn fetch_symbols(client: &Client, cfg: &GateioConfig) -> Result<Vec<String>> {
-    let mut result: Vec<String> = Vec::new();
+    Ok(instruments::symbols(&fetch_instruments(client, cfg).await?))
+}
+
+/// Retrieve metadata for all Gate.io trading instruments of `cfg`'s market type.
+pub async fn fetch_instruments(client: &Client, cfg: &GateioConfig) -> Result<Vec<Instrument>> {
+    let mut result: Vec<Instrument> = Vec::new();
     let limit = 100u32;
 
     if cfg.info_url.contains("{settle}") {
@@ -78,7 +84,14 @@ pub async fn fetch_symbols(client: &Client, cfg: &GateioConfig) -> Result<Vec<St
                             .unwrap_or(false);
                     if trading {
                         if let Some(name) = item.get("name").and_then(|v| v.as_str()) {
-                            result.push(name.to_string());
+                            let spec = ContractSpec {
+                                settle: settle.to_uppercase(),
+                                lot_step: instruments::positive(item, "order_size_min"),
+                                price_step: instruments::positive(item, "order_price_round"),
+                                contract_size: instruments::positive(item, "quanto_multiplier"),
+                                ..instruments::pair_spec(VenueType::Futures, name, '_')
+                            };
+                            result.push(instruments::instrument("gateio", name, spec));
                         }
                     }
                 }
@@ -111,7 +124,15 @@ pub async fn fetch_symbols(client: &Client, cfg: &GateioConfig) -> Result<Vec<St
                     .unwrap_or(false);
                 if tradable {
                     if let Some(id) = item.get("id").and_then(|v| v.as_str()) {
-                        result.push(id.to_string());
+                        let spec = ContractSpec {
+                            venue: VenueType::Spot,
+                            base: instruments::text(item, "base"),
+                            quote: instruments::text(item, "quote"),
+                            lot_step: instruments::decimals(item, "amount_precision"),
+                            price_step: instruments::decimals(item, "precision"),
+                            ..Default::default()
+                        };
+                        result.push(instruments::instrument("gateio", id, spec));
                     }
                 }
             }
@@ -122,8 +143,6 @@ pub async fn fetch_symbols(client: &Client, cfg: &GateioConfig) -> Result<Vec<St
         }
     }
 
-    result.sort();
-    result.dedup();
     Ok(result)
 }
 
@@ -142,21 +161,26 @@ pub fn register() {
                           task_set: TaskSet,
                           channels: ChannelRegistry,
                           _tls_config: Arc<rustls::ClientConfig>|
-                          -> BoxFuture<
-                        'static,
-                        Result<Vec<mpsc::Receiver<core::events::StreamMessage<'static>>>>,
-                    > {
+                          -> BoxFuture<'static, Result<Vec<StreamReceiver>>> {
                         let cfg = cfg_ref;
                         let initial_symbols = exchange_cfg.symbols.clone();
                         Box::pin(async move {
-                            let mut symbols = initial_symbols;
-                            if symbols.is_empty() {
-                                symbols = fetch_symbols(&client, cfg).await?;
-                            }
+                            let symbols = instruments::resolve_symbols(
+                                cfg.id,
+                                initial_symbols,
+                                {
+                                    let client = client.clone();
+                                    move || {
+                                        let client = client.clone();
+                                        async move { fetch_instruments(&client, cfg).await }
+                                    }
+                                },
+                            )
+                            .await?;
 
                             let mut receivers = Vec::new();
                             for symbol in &symbols {
-                                let key = format!("{name}:{symbol}", name = cfg.name, symbol = symbol);
+                                let key = format!("{id}:{symbol}", id = cfg.id, symbol = symbol);
                                 let (_, rx) = channels.get_or_create(&key);
                                 if let Some(rx) = rx {
                                     receivers.push(rx);
@@ -427,9 +451,9 @@ impl ExchangeAdapter for GateioAdapter {
                                             Event::Kline(ev) => &ev.symbol,
                                             _ => unreachable!(),
                                         };
-                                        let key = format!("{name}:{symbol}", name = self.cfg.name, symbol = symbol_key);
+                                        let key = format!("{id}:{symbol}", id = self.cfg.id, symbol = symbol_key);
                                         let (tx, _) = self.channels.get_or_create(&key);
-                                        if tx.send(event).is_err() {
+                                        if tx.send(event).await.is_err() {
                                             break;
                                         }
                                     }
@@ -1,5 +1,5 @@
 use super::ExchangeAdapter;
-use crate::{registry, ChannelRegistry, TaskSet};
+use crate::{registry, ChannelRegistry, StreamReceiver, TaskSet};
 use anyhow::Result;
 use arb_core as core;
 use async_trait::async_trait;
@@ -7,7 +7,6 @@ use futures::future::BoxFuture;
 use reqwest::Client;
 use rustls::ClientConfig;
 use std::sync::{Arc, Once};
-use tokio::sync::mpsc;
 use tracing::error;
 
 /// Basic configuration for a Kraken exchange endpoint.
@@ -72,10 +71,7 @@ pub fn register() {
                           task_set: TaskSet,
                           _channels: ChannelRegistry,
                           _tls_config: Arc<ClientConfig>|
-                          -> BoxFuture<
-                        'static,
-                        Result<Vec<mpsc::Receiver<core::events::StreamMessage<'static>>>>,
-                    > {
+                          -> BoxFuture<'static, Result<Vec<StreamReceiver>>> {
                         Box::pin(async move {
                             let adapter = KrakenAdapter::new(cfg_ref);
                             {
@@ -8,15 +8,13 @@ use std::{
     borrow::Cow,
     sync::{Arc, Once},
 };
-use tokio::{
-    sync::mpsc,
-    time::{interval, sleep, Duration},
-};
+use tokio::time::{interval, sleep, Duration};
 use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
 use uuid::Uuid;
 
 use super::ExchangeAdapter;
-use crate::{registry, ChannelRegistry, TaskSet};
+use crate::{instruments, registry, ChannelRegistry, StreamReceiver, TaskSet};
+use canonical::symbol::{ContractSpec, Instrument, VenueType};
 use core::events::{KucoinKline, KucoinLevel2, KucoinStreamMessage, KucoinTrade};
 use rustls::ClientConfig;
 
@@ -49,8 +47,13 @@ pub const KUCOIN_EXCHANGES: &[KucoinConfig] = &[
 
 /// Retrieve all trading symbols for KuCoin across spot and futures markets.
 pub async fn fetch_symbols() -> Result<Vec<String>> {
+    Ok(instruments::symbols(&fetch_instruments().await?))
+}
+
+/// Retrieve metadata for all KuCoin spot and futures instruments.
+pub async fn fetch_instruments() -> Result<Vec<Instrument>> {
     let client = Client::new();
-    let mut symbols: Vec<String> = Vec::new();
+    let mut symbols: Vec<Instrument> = Vec::new();
 
     let spot: Value = client
         .get("https://api.kucoin.com/api/v2/symbols")
@@ -70,7 +73,15 @@ pub async fn fetch_symbols() -> Result<Vec<String>> {
             .unwrap_or(false);
         if active {
             if let Some(sym) = item.get("symbol").and_then(|v| v.as_str()) {
-                symbols.push(sym.to_string());
+                let spec = ContractSpec {
+                    venue: VenueType::Spot,
+                    base: instruments::text(item, "baseCurrency"),
+                    quote: instruments::text(item, "quoteCurrency"),
+                    lot_step: instruments::positive(item, "baseIncrement"),
+                    price_step: instruments::positive(item, "priceIncrement"),
+                    ..Default::default()
+                };
+                symbols.push(instruments::instrument("kucoin", sym, spec));
             }
         }
     }
@@ -94,13 +105,22 @@ pub async fn fetch_symbols() -> Result<Vec<String>> {
             .unwrap_or(false);
         if active {
             if let Some(sym) = item.get("symbol").and_then(|v| v.as_str()) {
-                symbols.push(sym.to_string());
+                let spec = ContractSpec {
+                    venue: VenueType::Futures,
+                    base: instruments::text(item, "baseCurrency"),
+                    quote: instruments::text(item, "quoteCurrency"),
+                    settle: instruments::text(item, "settleCurrency"),
+                    lot_step: instruments::positive(item, "lotSize"),
+                    price_step: instruments::positive(item, "tickSize"),
+                    contract_size: instruments::positive(item, "multiplier"),
+                    // Perpetuals report a null `expireDate`.
+                    expiry: item.get("expireDate").and_then(|v| v.as_u64()),
+                };
+                symbols.push(instruments::instrument("kucoin", sym, spec));
             }
         }
     }
 
-    symbols.sort();
-    symbols.dedup();
     Ok(symbols)
 }
 
@@ -228,9 +248,9 @@ impl KucoinAdapter {
                                         _ => "",
                                     };
                                     if !symbol_key.is_empty() {
-                                        let key = format!("{name}:{symbol}", name = self.cfg.name, symbol = symbol_key);
+                                        let key = format!("{id}:{symbol}", id = self.cfg.id, symbol = symbol_key);
                                         let (tx, _) = self.channels.get_or_create(&key);
-                                        if tx.send(event).is_err() {
+                                        if tx.send(event).await.is_err() {
                                             break;
                                         }
                                     }
@@ -397,21 +417,20 @@ pub fn register() {
                           task_set: TaskSet,
                           channels: ChannelRegistry,
                           _tls_config: Arc<ClientConfig>|
-                          -> BoxFuture<
-                        'static,
-                        Result<Vec<mpsc::Receiver<core::events::StreamMessage<'static>>>>,
-                    > {
+                          -> BoxFuture<'static, Result<Vec<StreamReceiver>>> {
                         let cfg = cfg_ref;
                         let initial_symbols = exchange_cfg.symbols.clone();
                         Box::pin(async move {
-                            let mut symbols = initial_symbols;
-                            if symbols.is_empty() {
-                                symbols = fetch_symbols().await?;
-                            }
+                            let symbols = instruments::resolve_symbols(
+                                cfg.id,
+                                initial_symbols,
+                                fetch_instruments,
+                            )
+                            .await?;
 
                             let mut receivers = Vec::new();
                             for symbol in &symbols {
-                                let key = format!("{name}:{symbol}", name = cfg.name, symbol = symbol);
+                                let key = format!("{id}:{symbol}", id = cfg.id, symbol = symbol);
                                 let (_, rx) = channels.get_or_create(&key);
                                 if let Some(rx) = rx {
                                     receivers.push(rx);
@@ -1,5 +1,6 @@
 use super::ExchangeAdapter;
-use crate::{registry, ChannelRegistry, TaskSet};
+use crate::{frame::Frame, instruments, registry, ChannelRegistry, StreamReceiver, TaskSet};
+use canonical::symbol::{Instrument, VenueType};
 use anyhow::Result;
 use arb_core as core;
 use async_trait::async_trait;
@@ -7,24 +8,18 @@ use core::rate_limit::TokenBucket;
 use core::{chunk_streams_with_config, stream_config_for_exchange};
 use futures::{future::BoxFuture, SinkExt, StreamExt};
 use reqwest::Client;
+use serde::de::IgnoredAny;
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use std::borrow::Cow;
 use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
 use std::sync::Once;
-use tokio::sync::mpsc;
 use tokio::task::JoinHandle;
 use tokio::time::{interval, sleep, Duration, Instant, MissedTickBehavior};
 use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
 use tracing::{error, info};
-use core::events::{
-    self,
-    LatokenDepthEvent,
-    LatokenKlineEvent,
-    LatokenStreamMessage,
-    LatokenTradeEvent,
-    StreamMessage as CoreStreamMessage,
-};
+use core::events::{self, StreamMessage as CoreStreamMessage};
 
 /// Configuration for a single LATOKEN exchange endpoint.
 pub struct LatokenConfig {
@@ -44,20 +39,25 @@ pub const LATOKEN_EXCHANGES: &[LatokenConfig] = &[LatokenConfig {
 
 /// Retrieve all trading symbols for LATOKEN using its ticker endpoint.
 pub async fn fetch_symbols(client: &Client, info_url: &str) -> Result<Vec<String>> {
+    Ok(instruments::symbols(
+        &fetch_instruments(client, info_url).await?,
+    ))
+}
+
+/// Retrieve instruments for LATOKEN from its ticker endpoint, which only
+/// reports `BASE/QUOTE` pairs.
+pub async fn fetch_instruments(client: &Client, info_url: &str) -> Result<Vec<Instrument>> {
     let resp = client.get(info_url).send().await?.error_for_status()?;
     let data: Value = resp.json().await?;
     let arr = data.as_array().unwrap_or(&Vec::new()).clone();
-    let mut result: Vec<String> = arr
+    Ok(arr
         .iter()
         .filter_map(|s| {
-            s.get("symbol")
-                .and_then(|v| v.as_str())
-                .map(|v| v.to_string())
+            let symbol = s.get("symbol").and_then(|v| v.as_str())?;
+            let spec = instruments::pair_spec(VenueType::Spot, symbol, '/');
+            Some(instruments::instrument("latoken", symbol, spec))
         })
-        .collect();
-    result.sort();
-    result.dedup();
-    Ok(result)
+        .collect())
 }
 
 static REGISTER: Once = Once::new();
@@ -75,21 +75,26 @@ pub fn register() {
                           task_set: TaskSet,
                           channels: ChannelRegistry,
                           _tls_config: Arc<rustls::ClientConfig>|
-                          -> BoxFuture<
-                        'static,
-                        Result<Vec<mpsc::Receiver<core::events::StreamMessage<'static>>>>,
-                    > {
+                          -> BoxFuture<'static, Result<Vec<StreamReceiver>>> {
                         let cfg = cfg_ref;
                         let initial_symbols = exchange_cfg.symbols.clone();
                         Box::pin(async move {
-                            let mut symbols = initial_symbols;
-                            if symbols.is_empty() {
-                                symbols = fetch_symbols(&client, cfg.info_url).await?;
-                            }
+                            let symbols = instruments::resolve_symbols(
+                                cfg.id,
+                                initial_symbols,
+                                {
+                                    let client = client.clone();
+                                    move || {
+                                        let client = client.clone();
+                                        async move { fetch_instruments(&client, cfg.info_url).await }
+                                    }
+                                },
+                            )
+                            .await?;
 
                             let mut receivers = Vec::new();
                             for symbol in &symbols {
-                                let key = format!("{name}:{symbol}", name = cfg.name, symbol = symbol);
+                                let key = format!("{id}:{symbol}", id = cfg.id, symbol = symbol);
                                 let (_, rx) = channels.get_or_create(&key);
                                 if let Some(rx) = rx {
                                     receivers.push(rx);
@@ -178,9 +183,9 @@ impl ExchangeAdapter for LatokenAdapter {
             let url = self.cfg.ws_base.to_string();
             let shutdown = self.shutdown.clone();
             let channels = self.channels.clone();
-            let name = self.cfg.name;
+            let id = self.cfg.id;
             let handle = tokio::spawn(async move {
-                if let Err(e) = LatokenAdapter::run_symbol(url, symbol, name, channels, shutdown).await {
+                if let Err(e) = LatokenAdapter::run_symbol(url, symbol, id, channels, shutdown).await {
                     error!("latoken stream error: {}", e);
                 }
                 Ok(())
@@ -209,82 +214,148 @@ impl ExchangeAdapter for LatokenAdapter {
     }
 }
 
-fn parse_message(text: &str) -> Option<CoreStreamMessage<'static>> {
-    let msg: LatokenStreamMessage<'_> = serde_json::from_str(text).ok()?;
-    let symbol = msg.symbol.clone();
-    match msg.topic.as_ref() {
-        "trade" => {
-            let t: LatokenTradeEvent<'_> = serde_json::from_value(msg.data).ok()?;
-            let event = events::TradeEvent {
-                event_time: t.timestamp,
-                symbol: symbol.clone(),
-                trade_id: t.id.unwrap_or(0),
-                price: Cow::Owned(t.price.into_owned()),
-                quantity: Cow::Owned(t.quantity.into_owned()),
-                buyer_order_id: 0,
-                seller_order_id: 0,
-                trade_time: t.timestamp,
-                buyer_is_maker: t.maker.unwrap_or(false),
-                best_match: true,
-            };
-            Some(CoreStreamMessage {
-                stream: format!("{symbol}@trade"),
-                data: events::Event::Trade(event),
-            })
-        }
-        "depth" | "orderbook" => {
-            let d: LatokenDepthEvent<'_> = serde_json::from_value(msg.data).ok()?;
-            let bids = d
-                .bids
-                .into_iter()
-                .map(|[p, q]| [Cow::Owned(p.into_owned()), Cow::Owned(q.into_owned())])
-                .collect();
-            let asks = d
-                .asks
-                .into_iter()
-                .map(|[p, q]| [Cow::Owned(p.into_owned()), Cow::Owned(q.into_owned())])
-                .collect();
-            let event = events::DepthUpdateEvent {
-                event_time: d.timestamp,
-                symbol: symbol.clone(),
-                first_update_id: d.first_update_id.unwrap_or(0),
-                final_update_id: d.final_update_id.unwrap_or(0),
-                previous_final_update_id: 0,
-                bids,
-                asks,
-            };
-            Some(CoreStreamMessage {
-                stream: format!("{symbol}@depth"),
-                data: events::Event::DepthUpdate(event),
-            })
-        }
-        "kline" => {
-            let k: LatokenKlineEvent<'_> = serde_json::from_value(msg.data).ok()?;
-            let event = events::KlineEvent {
-                event_time: k.timestamp,
-                symbol: symbol.clone(),
-                kline: events::Kline {
-                    start_time: k.timestamp,
-                    close_time: k.timestamp,
-                    interval: "1m".to_string(),
-                    open: Cow::Owned(k.open.into_owned()),
-                    close: Cow::Owned(k.close.into_owned()),
-                    high: Cow::Owned(k.high.into_owned()),
-                    low: Cow::Owned(k.low.into_owned()),
-                    volume: Cow::Owned(k.volume.into_owned()),
-                    trades: 0,
-                    is_closed: true,
-                    quote_volume: Cow::Owned("0".to_string()),
-                    taker_buy_base_volume: Cow::Owned("0".to_string()),
-                    taker_buy_quote_volume: Cow::Owned("0".to_string()),
-                },
-            };
-            Some(CoreStreamMessage {
-                stream: format!("{symbol}@kline"),
-                data: events::Event::Kline(event),
-            })
+/// Stream or control frame, with the strings borrowed from the receive
+/// buffer.
+#[derive(Deserialize)]
+struct LatokenFrame<'a> {
+    topic: Option<&'a str>,
+    symbol: Option<&'a str>,
+    #[serde(borrow)]
+    data: Option<LatokenData<'a>>,
+    #[serde(borrow)]
+    ping: Option<LatokenPing<'a>>,
+    pong: Option<IgnoredAny>,
+}
+
+/// Payload fields of every topic, since `data` may precede `topic`. The
+/// names match [`events::LatokenTradeEvent`], [`events::LatokenDepthEvent`]
+/// and [`events::LatokenKlineEvent`], except that `volume` is kept apart because trades
+/// and klines both accept it.
+#[derive(Deserialize)]
+struct LatokenData<'a> {
+    #[serde(rename = "t", alias = "timestamp")]
+    timestamp: Option<u64>,
+    #[serde(rename = "p", alias = "price")]
+    price: Option<&'a str>,
+    #[serde(rename = "q", alias = "quantity")]
+    quantity: Option<&'a str>,
+    v: Option<&'a str>,
+    volume: Option<&'a str>,
+    #[serde(rename = "m", alias = "maker")]
+    maker: Option<bool>,
+    #[serde(rename = "id", alias = "tradeId", alias = "trade_id")]
+    id: Option<u64>,
+    #[serde(rename = "b", alias = "bids", default)]
+    bids: Vec<[&'a str; 2]>,
+    #[serde(rename = "a", alias = "asks", default)]
+    asks: Vec<[&'a str; 2]>,
+    #[serde(rename = "U", alias = "firstUpdateId")]
+    first_update_id: Option<u64>,
+    #[serde(rename = "u", alias = "finalUpdateId")]
+    final_update_id: Option<u64>,
+    #[serde(rename = "o", alias = "open")]
+    open: Option<&'a str>,
+    #[serde(rename = "c", alias = "close")]
+    close: Option<&'a str>,
+    #[serde(rename = "h", alias = "high")]
+    high: Option<&'a str>,
+    #[serde(rename = "l", alias = "low")]
+    low: Option<&'a str>,
+}
+
+/// Heartbeat id, echoed back in the pong.
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum LatokenPing<'a> {
+    Number(u64),
+    Text(&'a str),
+}
+
+fn owned(s: &str) -> Cow<'static, str> {
+    Cow::Owned(s.to_string())
+}
+
+/// Parse a text frame through `frame` into a trade, depth or kline message.
+pub fn parse_message(frame: &mut Frame, text: &str) -> Option<CoreStreamMessage<'static>> {
+    frame
+        .parse::<LatokenFrame<'_>>(text.as_bytes())
+        .ok()?
+        .into_message()
+}
+
+impl LatokenFrame<'_> {
+    fn into_message(self) -> Option<CoreStreamMessage<'static>> {
+        let symbol = self.symbol?;
+        let d = self.data?;
+        match self.topic? {
+            "trade" => {
+                let timestamp = d.timestamp?;
+                let event = events::TradeEvent {
+                    event_time: timestamp,
+                    symbol: symbol.to_string(),
+                    trade_id: d.id.unwrap_or(0),
+                    price: owned(d.price?),
+                    quantity: owned(d.quantity.or(d.volume)?),
+                    buyer_order_id: 0,
+                    seller_order_id: 0,
+                    trade_time: timestamp,
+                    buyer_is_maker: d.maker.unwrap_or(false),
+                    best_match: true,
+                };
+                Some(CoreStreamMessage {
+                    stream: format!("{symbol}@trade"),
+                    data: events::Event::Trade(event),
+                })
+            }
+            "depth" | "orderbook" => {
+                let levels = |levels: Vec<[&str; 2]>| {
+                    levels
+                        .into_iter()
+                        .map(|[p, q]| [owned(p), owned(q)])
+                        .collect()
+                };
+                let event = events::DepthUpdateEvent {
+                    event_time: d.timestamp?,
+                    symbol: symbol.to_string(),
+                    first_update_id: d.first_update_id.unwrap_or(0),
+                    final_update_id: d.final_update_id.unwrap_or(0),
+                    previous_final_update_id: 0,
+                    bids: levels(d.bids),
+                    asks: levels(d.asks),
+                };
+                Some(CoreStreamMessage {
+                    stream: format!("{symbol}@depth"),
+                    data: events::Event::DepthUpdate(event),
+                })
+            }
+            "kline" => {
+                let timestamp = d.timestamp?;
+                let event = events::KlineEvent {
+                    event_time: timestamp,
+                    symbol: symbol.to_string(),
+                    kline: events::Kline {
+                        start_time: timestamp,
+                        close_time: timestamp,
+                        interval: "1m".to_string(),
+                        open: owned(d.open?),
+                        close: owned(d.close?),
+                        high: owned(d.high?),
+                        low: owned(d.low?),
+                        volume: owned(d.v.or(d.volume)?),
+                        trades: 0,
+                        is_closed: true,
+                        quote_volume: Cow::Owned("0".to_string()),
+                        taker_buy_base_volume: Cow::Owned("0".to_string()),
+                        taker_buy_quote_volume: Cow::Owned("0".to_string()),
+                    },
+                };
+                Some(CoreStreamMessage {
+                    stream: format!("{symbol}@kline"),
+                    data: events::Event::Kline(event),
+                })
+            }
+            _ => None,
         }
-        _ => None,
     }
 }
 
@@ -292,11 +363,11 @@ impl LatokenAdapter {
     async fn run_symbol(
         url: String,
         symbol: String,
-        name: &'static str,
+        id: &'static str,
         channels: ChannelRegistry,
         shutdown: Arc<AtomicBool>,
     ) -> Result<()> {
-        let key = format!("{name}:{symbol}");
+        let key = format!("{id}:{symbol}");
         loop {
             let (ws_stream, _) = connect_async(&url).await?;
             info!("latoken connected: {}", symbol);
@@ -317,6 +388,7 @@ impl LatokenAdapter {
             let mut ping_interval = interval(Duration::from_secs(30));
             ping_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
             let mut last_pong = Instant::now();
+            let mut frame = Frame::new();
 
             loop {
                 tokio::select! {
@@ -332,13 +404,15 @@ impl LatokenAdapter {
                     msg = read.next() => {
                         match msg {
                             Some(Ok(Message::Text(text))) => {
-                                if let Some(event) = parse_message(&text) {
-                                    let _ = tx.send(event);
-                                } else if let Ok(v) = serde_json::from_str::<Value>(&text) {
-                                    if let Some(ping) = v.get("ping").cloned() {
+                                if let Ok(mut msg) = frame.parse::<LatokenFrame<'_>>(text.as_bytes()) {
+                                    let ping = msg.ping.take();
+                                    let pong = msg.pong.is_some();
+                                    if let Some(event) = msg.into_message() {
+                                        let _ = tx.send(event).await;
+                                    } else if let Some(ping) = ping {
                                         let pong = json!({"pong": ping});
                                         let _ = write.send(Message::Text(pong.to_string())).await;
-                                    } else if v.get("pong").is_some() {
+                                    } else if pong {
                                         last_pong = Instant::now();
                                     }
                                 }
@@ -3,17 +3,21 @@ use arb_core as core;
 use async_trait::async_trait;
 use futures::{future::BoxFuture, SinkExt, StreamExt};
 use reqwest::Client;
+use serde::Deserialize;
 use serde_json::{json, Value};
 use std::borrow::Cow;
 use std::sync::{Arc, Once};
-use tokio::sync::{mpsc, Mutex};
+use tokio::sync::Mutex;
 use tokio::time::{interval, Duration, Instant};
 use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
 use tracing::{error, info};
 use uuid::Uuid;
 
 use super::ExchangeAdapter;
-use crate::{registry, ChannelRegistry, StreamSender, TaskSet};
+use crate::{
+    frame::Frame, instruments, registry, ChannelRegistry, StreamReceiver, StreamSender, TaskSet,
+};
+use canonical::symbol::{Instrument, VenueType};
 
 /// Configuration for a single LBank exchange endpoint.
 pub struct LbankConfig {
@@ -34,9 +38,14 @@ const CONTRACT_URL: &str = "https://api.lbkex.com/v2/contract/pairs.do";
 
 /// Retrieve all trading symbols for LBank across spot and contract markets.
 pub async fn fetch_symbols() -> Result<Vec<String>> {
+    Ok(instruments::symbols(&fetch_instruments().await?))
+}
+
+/// Retrieve instruments for LBank across spot and contract markets.
+pub async fn fetch_instruments() -> Result<Vec<Instrument>> {
     let client = Client::new();
 
-    async fn fetch_from(client: &Client, url: &str) -> Result<Vec<String>> {
+    async fn fetch_from(client: &Client, url: &str, venue: VenueType) -> Result<Vec<Instrument>> {
         let resp = client.get(url).send().await?.error_for_status()?;
         let data: Value = resp.json().await?;
         let arr = data
@@ -45,24 +54,29 @@ pub async fn fetch_symbols() -> Result<Vec<String>> {
             .ok_or_else(|| anyhow!("missing data array"))?;
         let mut res = Vec::new();
         for item in arr {
-            if let Some(s) = item.as_str() {
-                res.push(s.to_string());
-            } else if let Some(s) = item.get("symbol").and_then(|v| v.as_str()) {
-                res.push(s.to_string());
-            } else if let Some(s) = item.get("pair").and_then(|v| v.as_str()) {
-                res.push(s.to_string());
+            let symbol = item
+                .as_str()
+                .or_else(|| item.get("symbol").and_then(|v| v.as_str()))
+                .or_else(|| item.get("pair").and_then(|v| v.as_str()));
+            if let Some(s) = symbol {
+                let mut spec = instruments::pair_spec(venue, s, '_');
+                if spec.base.is_empty() {
+                    spec.base = instruments::text(item, "baseCurrency");
+                    spec.quote = instruments::text(item, "clearCurrency");
+                }
+                spec.lot_step = instruments::positive(item, "volumeTick");
+                spec.price_step = instruments::positive(item, "priceTick");
+                res.push(instruments::instrument("lbank", s, spec));
             }
         }
         Ok(res)
     }
 
-    let mut symbols = fetch_from(&client, SPOT_URL).await?;
-    if let Ok(mut contracts) = fetch_from(&client, CONTRACT_URL).await {
+    let mut symbols = fetch_from(&client, SPOT_URL, VenueType::Spot).await?;
+    if let Ok(mut contracts) = fetch_from(&client, CONTRACT_URL, VenueType::Futures).await {
         symbols.append(&mut contracts);
     }
 
-    symbols.sort();
-    symbols.dedup();
     Ok(symbols)
 }
 
@@ -81,21 +95,20 @@ pub fn register() {
                           task_set: TaskSet,
                           channels: ChannelRegistry,
                           _tls_config: Arc<rustls::ClientConfig>|
-                          -> BoxFuture<
-                        'static,
-                        Result<Vec<mpsc::Receiver<core::events::StreamMessage<'static>>>>,
-                    > {
+                          -> BoxFuture<'static, Result<Vec<StreamReceiver>>> {
                         let cfg = cfg_ref;
                         let initial_symbols = exchange_cfg.symbols.clone();
                         Box::pin(async move {
-                            let mut symbols = initial_symbols;
-                            if symbols.is_empty() {
-                                symbols = fetch_symbols().await?;
-                            }
+                            let symbols = instruments::resolve_symbols(
+                                cfg.id,
+                                initial_symbols,
+                                fetch_instruments,
+                            )
+                            .await?;
 
                             let mut receivers = Vec::new();
                             for symbol in &symbols {
-                                let key = format!("{name}:{symbol}", name = cfg.name, symbol = symbol);
+                                let key = format!("{id}:{symbol}", id = cfg.id, symbol = symbol);
                                 let (_, rx) = channels.get_or_create(&key);
                                 if let Some(rx) = rx {
                                     receivers.push(rx);
@@ -210,16 +223,16 @@ impl LbankAdapter {
             });
 
             let pong_updater = last_pong.clone();
+            let mut frame = Frame::new();
             while let Some(msg) = read.next().await {
                 match msg {
                     Ok(Message::Text(text)) => {
-                        if let Some(event) = parse_message(&text) {
-                            if tx.send(event).is_err() {
-                                return Ok(());
-                            }
-                        } else if let Ok(v) = serde_json::from_str::<Value>(&text) {
-                            if v.get("action").and_then(|a| a.as_str()) == Some("ping") {
-                                if let Some(id) = v.get("ping").and_then(|p| p.as_str()) {
+                        let Ok(msg) = frame.parse::<LbankFrame<'_>>(text.as_bytes()) else {
+                            continue;
+                        };
+                        match msg.action {
+                            Some("ping") => {
+                                if let Some(id) = msg.ping {
                                     let pong = json!({"action":"pong","pong":id});
                                     let _ = write
                                         .lock()
@@ -227,8 +240,14 @@ impl LbankAdapter {
                                         .send(Message::Text(pong.to_string()))
                                         .await;
                                 }
-                            } else if v.get("action").and_then(|a| a.as_str()) == Some("pong") {
-                                *pong_updater.lock().await = Instant::now();
+                            }
+                            Some("pong") => *pong_updater.lock().await = Instant::now(),
+                            _ => {
+                                if let Some(event) = msg.into_message() {
+                                    if tx.send(event).await.is_err() {
+                                        return Ok(());
+                                    }
+                                }
                             }
                         }
                     }
@@ -243,155 +262,150 @@ impl LbankAdapter {
     }
 }
 
-/// Parse a raw JSON text into a canonical stream message if possible.
-fn parse_message(text: &str) -> Option<core::events::StreamMessage<'static>> {
-    if let Ok(val) = serde_json::from_str::<Value>(text) {
-        if let Some(t) = val.get("type").and_then(|v| v.as_str()) {
-            match t {
-                "trade" => return parse_trade_frame(&val).ok(),
-                "depth" => return parse_depth_frame(&val).ok(),
-                "kbar" => return parse_kbar_frame(&val).ok(),
-                _ => {}
-            }
+/// Push or control frame, with the strings borrowed from the receive buffer.
+#[derive(Deserialize)]
+struct LbankFrame<'a> {
+    #[serde(rename = "type")]
+    kind: Option<&'a str>,
+    pair: Option<&'a str>,
+    trade: Option<LbankTrade<'a>>,
+    depth: Option<LbankDepth>,
+    kbar: Option<LbankKbar>,
+    action: Option<&'a str>,
+    ping: Option<&'a str>,
+}
+
+#[derive(Deserialize)]
+struct LbankTrade<'a> {
+    #[serde(default)]
+    price: f64,
+    #[serde(default)]
+    volume: f64,
+    direction: Option<&'a str>,
+}
+
+#[derive(Deserialize)]
+struct LbankDepth {
+    #[serde(default)]
+    bids: Vec<[f64; 2]>,
+    #[serde(default)]
+    asks: Vec<[f64; 2]>,
+}
+
+#[derive(Deserialize)]
+struct LbankKbar {
+    #[serde(default)]
+    o: f64,
+    #[serde(default)]
+    h: f64,
+    #[serde(default)]
+    l: f64,
+    #[serde(default)]
+    c: f64,
+    #[serde(default)]
+    v: f64,
+    #[serde(default)]
+    n: u64,
+}
+
+/// Parse a raw JSON text through `frame` into a canonical stream message if
+/// possible.
+pub fn parse_message(
+    frame: &mut Frame,
+    text: &str,
+) -> Option<core::events::StreamMessage<'static>> {
+    frame
+        .parse::<LbankFrame<'_>>(text.as_bytes())
+        .ok()?
+        .into_message()
+}
+
+impl LbankFrame<'_> {
+    fn into_message(self) -> Option<core::events::StreamMessage<'static>> {
+        let pair = self.pair?;
+        match self.kind? {
+            "trade" => Some(trade_message(pair, self.trade?)),
+            "depth" => Some(depth_message(pair, self.depth?)),
+            "kbar" => Some(kbar_message(pair, self.kbar?)),
+            _ => None,
         }
     }
-    None
 }
 
-fn parse_kbar_frame(val: &Value) -> Result<core::events::StreamMessage<'static>> {
-    let pair = val
-        .get("pair")
-        .and_then(|v| v.as_str())
-        .ok_or_else(|| anyhow!("missing pair"))?
-        .to_string();
-    let kbar = val
-        .get("kbar")
-        .and_then(|v| v.as_object())
-        .ok_or_else(|| anyhow!("missing kbar"))?;
-    let open = kbar.get("o").and_then(|v| v.as_f64()).unwrap_or_default();
-    let high = kbar.get("h").and_then(|v| v.as_f64()).unwrap_or_default();
-    let low = kbar.get("l").and_then(|v| v.as_f64()).unwrap_or_default();
-    let close = kbar.get("c").and_then(|v| v.as_f64()).unwrap_or_default();
-    let volume = kbar.get("v").and_then(|v| v.as_f64()).unwrap_or_default();
+fn kbar_message(pair: &str, kbar: LbankKbar) -> core::events::StreamMessage<'static> {
     let event = core::events::KlineEvent {
         event_time: 0,
-        symbol: pair.clone(),
+        symbol: pair.to_string(),
         kline: core::events::Kline {
             start_time: 0,
             close_time: 0,
             interval: "1m".to_string(),
-            open: Cow::Owned(open.to_string()),
-            close: Cow::Owned(close.to_string()),
-            high: Cow::Owned(high.to_string()),
-            low: Cow::Owned(low.to_string()),
-            volume: Cow::Owned(volume.to_string()),
-            trades: kbar.get("n").and_then(|v| v.as_u64()).unwrap_or(0),
+            open: Cow::Owned(kbar.o.to_string()),
+            close: Cow::Owned(kbar.c.to_string()),
+            high: Cow::Owned(kbar.h.to_string()),
+            low: Cow::Owned(kbar.l.to_string()),
+            volume: Cow::Owned(kbar.v.to_string()),
+            trades: kbar.n,
             is_closed: true,
             quote_volume: Cow::Owned("0".to_string()),
             taker_buy_base_volume: Cow::Owned("0".to_string()),
             taker_buy_quote_volume: Cow::Owned("0".to_string()),
         },
     };
-    Ok(core::events::StreamMessage {
-        stream: Box::leak(format!("{pair}@kbar").into_boxed_str()).into(),
+    core::events::StreamMessage {
+        stream: format!("{pair}@kbar"),
         data: core::events::Event::Kline(event),
-    })
+    }
 }
 
-/// Parse trade frame into canonical event.
-pub fn parse_trade_frame(val: &Value) -> Result<core::events::StreamMessage<'static>> {
-    let pair = val
-        .get("pair")
-        .and_then(|v| v.as_str())
-        .ok_or_else(|| anyhow!("missing pair"))?
-        .to_string();
-    let trade = val
-        .get("trade")
-        .and_then(|v| v.as_object())
-        .ok_or_else(|| anyhow!("missing trade"))?;
-    let price = trade
-        .get("price")
-        .and_then(|v| v.as_f64())
-        .unwrap_or_default();
-    let volume = trade
-        .get("volume")
-        .and_then(|v| v.as_f64())
-        .unwrap_or_default();
-    let direction = trade
-        .get("direction")
-        .and_then(|v| v.as_str())
-        .unwrap_or("");
+fn trade_message(pair: &str, trade: LbankTrade<'_>) -> core::events::StreamMessage<'static> {
     let ev = core::events::TradeEvent {
         event_time: 0,
-        symbol: pair.clone(),
+        symbol: pair.to_string(),
         trade_id: 0,
-        price: Cow::Owned(price.to_string()),
-        quantity: Cow::Owned(volume.to_string()),
+        price: Cow::Owned(trade.price.to_string()),
+        quantity: Cow::Owned(trade.volume.to_string()),
         buyer_order_id: 0,
         seller_order_id: 0,
         trade_time: 0,
-        buyer_is_maker: direction.eq_ignore_ascii_case("sell"),
+        buyer_is_maker: trade.direction.unwrap_or("").eq_ignore_ascii_case("sell"),
         best_match: true,
     };
-    Ok(core::events::StreamMessage {
-        stream: Box::leak(format!("{pair}@trade").into_boxed_str()).into(),
+    core::events::StreamMessage {
+        stream: format!("{pair}@trade"),
         data: core::events::Event::Trade(ev),
-    })
+    }
 }
 
-/// Parse depth frame into canonical event.
-pub fn parse_depth_frame(val: &Value) -> Result<core::events::StreamMessage<'static>> {
-    let pair = val
-        .get("pair")
-        .and_then(|v| v.as_str())
-        .ok_or_else(|| anyhow!("missing pair"))?
-        .to_string();
-    let depth = val
-        .get("depth")
-        .and_then(|v| v.as_object())
-        .ok_or_else(|| anyhow!("missing depth"))?;
-    let mut bids = Vec::new();
-    if let Some(arr) = depth.get("bids").and_then(|v| v.as_array()) {
-        for level in arr.iter().filter_map(|l| l.as_array()) {
-            if level.len() == 2 {
-                let price = level[0].as_f64().unwrap_or_default().to_string();
-                let qty = level[1].as_f64().unwrap_or_default().to_string();
-                bids.push([Cow::Owned(price), Cow::Owned(qty)]);
-            }
-        }
-    }
-    let mut asks = Vec::new();
-    if let Some(arr) = depth.get("asks").and_then(|v| v.as_array()) {
-        for level in arr.iter().filter_map(|l| l.as_array()) {
-            if level.len() == 2 {
-                let price = level[0].as_f64().unwrap_or_default().to_string();
-                let qty = level[1].as_f64().unwrap_or_default().to_string();
-                asks.push([Cow::Owned(price), Cow::Owned(qty)]);
-            }
-        }
-    }
+fn depth_message(pair: &str, depth: LbankDepth) -> core::events::StreamMessage<'static> {
+    let levels = |levels: Vec<[f64; 2]>| {
+        levels
+            .into_iter()
+            .map(|[price, qty]| [Cow::Owned(price.to_string()), Cow::Owned(qty.to_string())])
+            .collect()
+    };
 
     let ev = core::events::DepthUpdateEvent {
         event_time: 0,
-        symbol: pair.clone(),
+        symbol: pair.to_string(),
         first_update_id: 0,
         final_update_id: 0,
         previous_final_update_id: 0,
-        bids,
-        asks,
+        bids: levels(depth.bids),
+        asks: levels(depth.asks),
     };
 
-    Ok(core::events::StreamMessage {
-        stream: Box::leak(format!("{pair}@depth").into_boxed_str()).into(),
+    core::events::StreamMessage {
+        stream: format!("{pair}@depth"),
         data: core::events::Event::DepthUpdate(ev),
-    })
+    }
 }
 
 #[async_trait]
 impl ExchangeAdapter for LbankAdapter {
     async fn subscribe(&mut self) -> Result<()> {
         for symbol in &self._symbols {
-            if let Some(tx) = self.channels.get(&format!("{name}:{symbol}", name = self._cfg.name, symbol = symbol)) {
+            if let Some(tx) = self.channels.get(&format!("{id}:{symbol}", id = self._cfg.id, symbol = symbol)) {
                 let url = self._cfg.ws_base.to_string();
                 let sym = symbol.clone();
                 tokio::spawn(async move {
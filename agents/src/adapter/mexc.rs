@@ -18,10 +18,10 @@ use tokio::{
 use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
 
 use super::ExchangeAdapter;
-use crate::{registry, ChannelRegistry, TaskSet};
+use crate::{instruments, registry, ChannelRegistry, StreamReceiver, TaskSet};
+use canonical::symbol::{ContractSpec, Instrument, VenueType};
 use futures::future::BoxFuture;
 use std::sync::Once;
-use tokio::sync::mpsc;
 use tracing::{error, info, warn};
 
 /// Configuration for a single MEXC exchange endpoint.
@@ -42,6 +42,11 @@ pub const MEXC_EXCHANGES: &[MexcConfig] = &[MexcConfig {
 
 /// Retrieve all trading symbols for MEXC using its `exchangeInfo` endpoint.
 pub async fn fetch_symbols(info_url: &str) -> Result<Vec<String>> {
+    Ok(instruments::symbols(&fetch_instruments(info_url).await?))
+}
+
+/// Retrieve metadata for all MEXC trading instruments from `exchangeInfo`.
+pub async fn fetch_instruments(info_url: &str) -> Result<Vec<Instrument>> {
     let resp = Client::new()
         .get(info_url)
         .send()
@@ -53,7 +58,7 @@ pub async fn fetch_symbols(info_url: &str) -> Result<Vec<String>> {
         .and_then(|v| v.as_array())
         .ok_or_else(|| anyhow!("missing symbols array"))?;
 
-    let mut result: Vec<String> = symbols
+    Ok(symbols
         .iter()
         .filter_map(|s| {
             let status_ok = s
@@ -66,17 +71,21 @@ pub async fn fetch_symbols(info_url: &str) -> Result<Vec<String>> {
                 })
                 .unwrap_or(false);
             if status_ok {
-                s.get("symbol")
-                    .and_then(|v| v.as_str())
-                    .map(|v| v.to_string())
+                let symbol = s.get("symbol").and_then(|v| v.as_str())?;
+                let spec = ContractSpec {
+                    venue: VenueType::Spot,
+                    base: instruments::text(s, "baseAsset"),
+                    quote: instruments::text(s, "quoteAsset"),
+                    lot_step: instruments::positive(s, "baseSizePrecision"),
+                    price_step: instruments::decimals(s, "quotePrecision"),
+                    ..Default::default()
+                };
+                Some(instruments::instrument("mexc", symbol, spec))
             } else {
                 None
             }
         })
-        .collect();
-    result.sort();
-    result.dedup();
-    Ok(result)
+        .collect())
 }
 
 static REGISTER: Once = Once::new();
@@ -94,21 +103,20 @@ pub fn register() {
                           task_set: TaskSet,
                           channels: ChannelRegistry,
                           _tls_config: Arc<rustls::ClientConfig>|
-                          -> BoxFuture<
-                        'static,
-                        Result<Vec<mpsc::Receiver<core::events::StreamMessage<'static>>>>,
-                    > {
+                          -> BoxFuture<'static, Result<Vec<StreamReceiver>>> {
                         let cfg = cfg_ref;
                         let initial_symbols = exchange_cfg.symbols.clone();
                         Box::pin(async move {
-                            let mut symbols = initial_symbols;
-                            if symbols.is_empty() {
-                                symbols = fetch_symbols(cfg.info_url).await?;
-                            }
+                            let symbols = instruments::resolve_symbols(
+                                cfg.id,
+                                initial_symbols,
+                                move || fetch_instruments(cfg.info_url),
+                            )
+                            .await?;
 
                             let mut receivers = Vec::new();
                             for symbol in &symbols {
-                                let key = format!("{}:{}", cfg.name, symbol);
+                                let key = format!("{}:{}", cfg.id, symbol);
                                 let (_, rx) = channels.get_or_create(&key);
                                 if let Some(rx) = rx {
                                     receivers.push(rx);
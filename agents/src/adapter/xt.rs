@@ -6,6 +6,7 @@ use core::{chunk_streams_with_config, stream_config_for_exchange, OrderBook};
 use dashmap::DashMap;
 use futures::{SinkExt, StreamExt};
 use reqwest::Client;
+use serde::Deserialize;
 use serde_json::Value;
 use std::{
     borrow::Cow,
@@ -23,15 +24,16 @@ use tokio::{
 use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
 
 use super::ExchangeAdapter;
-use crate::{registry, ChannelRegistry, StreamSender, TaskSet};
+use crate::{
+    frame::{Frame, MapOrSeq}, instruments, registry, ChannelRegistry, StreamReceiver, StreamSender, TaskSet,
+};
+use canonical::symbol::{ContractSpec, Instrument, VenueType};
 use futures::future::BoxFuture;
 use std::sync::Once;
-use tokio::sync::mpsc;
 use tracing::error;
 
 use core::events::{
     BookTickerEvent, DepthUpdateEvent, Event, Kline, KlineEvent, StreamMessage, TradeEvent,
-    XtEvent, XtStreamMessage,
 };
 
 pub const SPOT_SYMBOL_URL: &str = "https://api.xt.com/data/api/v4/public/symbol";
@@ -58,43 +60,58 @@ pub const XT_EXCHANGES: &[XtConfig] = &[
     },
 ];
 
-/// Recursively extract symbol strings from arbitrary JSON structures.
-fn extract_symbols(val: &Value, out: &mut Vec<String>) {
+/// Recursively extract instrument entries from arbitrary JSON structures.
+fn extract_instruments(val: &Value, venue: VenueType, out: &mut Vec<Instrument>) {
     match val {
         Value::Array(arr) => {
             for v in arr {
-                extract_symbols(v, out);
+                extract_instruments(v, venue, out);
             }
         }
         Value::Object(map) => {
             for (k, v) in map {
                 if (k == "symbol" || k == "s" || k == "id" || k == "pair") && v.is_string() {
                     if let Some(s) = v.as_str() {
-                        out.push(s.to_string());
+                        out.push(instruments::instrument("xt", s, xt_spec(val, venue, s)));
                     }
                 }
-                extract_symbols(v, out);
+                extract_instruments(v, venue, out);
             }
         }
         _ => {}
     }
 }
 
+fn xt_spec(item: &Value, venue: VenueType, symbol: &str) -> ContractSpec {
+    ContractSpec {
+        lot_step: instruments::decimals(item, "quantityPrecision"),
+        price_step: instruments::decimals(item, "pricePrecision"),
+        contract_size: instruments::positive(item, "contractSize"),
+        ..instruments::pair_spec(venue, symbol, '_')
+    }
+}
+
 /// Retrieve all trading symbols from both the spot and futures REST endpoints.
 pub async fn fetch_symbols() -> Result<Vec<String>> {
+    Ok(instruments::symbols(&fetch_instruments().await?))
+}
+
+/// Retrieve instrument metadata from both the spot and futures REST endpoints.
+pub async fn fetch_instruments() -> Result<Vec<Instrument>> {
     let client = Client::new();
-    let mut symbols = Vec::new();
-    for url in [SPOT_SYMBOL_URL, FUTURES_SYMBOL_URL] {
+    let mut instruments = Vec::new();
+    for (url, venue) in [
+        (SPOT_SYMBOL_URL, VenueType::Spot),
+        (FUTURES_SYMBOL_URL, VenueType::Futures),
+    ] {
         if let Ok(resp) = client.get(url).send().await {
             if let Ok(resp) = resp.error_for_status() {
                 let data: Value = resp.json().await?;
-                extract_symbols(&data, &mut symbols);
+                extract_instruments(&data, venue, &mut instruments);
             }
         }
     }
-    symbols.sort();
-    symbols.dedup();
-    Ok(symbols)
+    Ok(instruments)
 }
 
 static REGISTER: Once = Once::new();
@@ -112,21 +129,20 @@ pub fn register() {
                           task_set: TaskSet,
                           channels: ChannelRegistry,
                           _tls_config: Arc<rustls::ClientConfig>|
-                          -> BoxFuture<
-                        'static,
-                        Result<Vec<mpsc::Receiver<core::events::StreamMessage<'static>>>>,
-                    > {
+                          -> BoxFuture<'static, Result<Vec<StreamReceiver>>> {
                         let cfg = cfg_ref;
                         let initial_symbols = exchange_cfg.symbols.clone();
                         Box::pin(async move {
-                            let mut symbols = initial_symbols;
-                            if symbols.is_empty() {
-                                symbols = fetch_symbols().await?;
-                            }
+                            let symbols = instruments::resolve_symbols(
+                                cfg.id,
+                                initial_symbols,
+                                fetch_instruments,
+                            )
+                            .await?;
 
                             let mut receivers = Vec::new();
                             for symbol in &symbols {
-                                let key = format!("{name}:{symbol}", name = cfg.name, symbol = symbol);
+                                let key = format!("{id}:{symbol}", id = cfg.id, symbol = symbol);
                                 let (_, rx) = channels.get_or_create(&key);
                                 if let Some(rx) = rx {
                                     receivers.push(rx);
@@ -221,7 +237,7 @@ impl ExchangeAdapter for XtAdapter {
             let ws_bucket = self.ws_bucket.clone();
             let shutdown = self.shutdown.clone();
             let channels = self.channels.clone();
-            let exchange = self.cfg.name.to_string();
+            let exchange = self.cfg.id.to_string();
 
             let handle = tokio::spawn(async move {
                 let topics = topics;
@@ -253,6 +269,7 @@ impl ExchangeAdapter for XtAdapter {
                             tracing::info!("subscribed {} topics to {}", topic_count, ws_url);
 
                             let mut ping_intv = tokio::time::interval(Duration::from_secs(30));
+                            let mut frame = Frame::new();
                             loop {
                                 tokio::select! {
                                     _ = ping_intv.tick() => {
@@ -270,11 +287,10 @@ impl ExchangeAdapter for XtAdapter {
                                                 })?;
                                             },
                                             Some(Ok(Message::Text(text))) => {
-                                                if let Some(events) = parse_message(&text) {
-                                                    for (sym, event) in events {
-                                                        if let Some(tx) = senders.get(&sym) {
-                                                            let _ = tx.send(event);
-                                                        }
+                                                for event in parse_message(&mut frame, &text).unwrap_or_default() {
+                                                    let tx = event.data.symbol().and_then(|sym| senders.get(sym));
+                                                    if let Some(tx) = tx {
+                                                        let _ = tx.send(event).await;
                                                     }
                                                 }
                                             }
@@ -336,109 +352,146 @@ impl ExchangeAdapter for XtAdapter {
     }
 }
 
-/// Parse raw XT websocket text into one or more canonical stream messages.
-fn parse_message(text: &str) -> Option<Vec<(String, StreamMessage<'static>)>> {
-    let msg: XtStreamMessage<'_> = serde_json::from_str(text).ok()?;
-    let topic = msg.topic.as_ref();
-    let (symbol, _channel) = topic.split_once('@')?;
-    let symbol_string = symbol.to_string();
-
-    match msg.data {
-        XtEvent::Trade(trades) => {
-            let mut out = Vec::new();
-            for t in trades {
-                let event = TradeEvent {
-                    event_time: t.trade_time,
-                    symbol: symbol_string.clone(),
-                    trade_id: t.i.unwrap_or(0),
-                    price: Cow::Owned(t.price.into_owned()),
-                    quantity: Cow::Owned(t.quantity.into_owned()),
-                    buyer_order_id: 0,
-                    seller_order_id: 0,
-                    trade_time: t.trade_time,
-                    buyer_is_maker: t.buyer_is_maker.unwrap_or(false),
-                    best_match: true,
-                };
-                out.push((
-                    symbol_string.clone(),
+/// Push frame, with the strings borrowed from the receive buffer.
+#[derive(Deserialize)]
+struct XtFrame<'a> {
+    topic: &'a str,
+    #[serde(borrow)]
+    data: XtData<'a>,
+}
+
+/// `data` of a frame: an object whose fields tell a kline, ticker or depth
+/// update apart, tried in that order as for
+/// [`XtEvent`](core::events::XtEvent), or an array of trades.
+type XtData<'a> = MapOrSeq<XtFields<'a>, Vec<XtTradeRow<'a>>>;
+
+#[derive(Deserialize)]
+struct XtTradeRow<'a> {
+    i: Option<u64>,
+    p: &'a str,
+    q: &'a str,
+    #[serde(rename = "T")]
+    trade_time: u64,
+    m: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct XtFields<'a> {
+    t: u64,
+    o: Option<&'a str>,
+    c: Option<&'a str>,
+    h: Option<&'a str>,
+    l: Option<&'a str>,
+    v: Option<&'a str>,
+    bp: Option<&'a str>,
+    bq: Option<&'a str>,
+    ap: Option<&'a str>,
+    aq: Option<&'a str>,
+    #[serde(default)]
+    b: Vec<[&'a str; 2]>,
+    #[serde(default)]
+    a: Vec<[&'a str; 2]>,
+}
+
+fn owned(s: &str) -> Cow<'static, str> {
+    Cow::Owned(s.to_string())
+}
+
+/// Parse raw XT websocket text through `frame` into one or more canonical
+/// stream messages.
+pub fn parse_message(frame: &mut Frame, text: &str) -> Option<Vec<StreamMessage<'static>>> {
+    let msg: XtFrame<'_> = frame.parse(text.as_bytes()).ok()?;
+    let (symbol, _channel) = msg.topic.split_once('@')?;
+
+    let fields = match msg.data {
+        MapOrSeq::Seq(trades) => {
+            let out = trades
+                .into_iter()
+                .map(|t| {
+                    let event = TradeEvent {
+                        event_time: t.trade_time,
+                        symbol: symbol.to_string(),
+                        trade_id: t.i.unwrap_or(0),
+                        price: owned(t.p),
+                        quantity: owned(t.q),
+                        buyer_order_id: 0,
+                        seller_order_id: 0,
+                        trade_time: t.trade_time,
+                        buyer_is_maker: t.m.unwrap_or(false),
+                        best_match: true,
+                    };
                     StreamMessage {
                         stream: format!("{symbol}@trade"),
                         data: Event::Trade(event),
-                    },
-                ));
-            }
-            Some(out)
+                    }
+                })
+                .collect();
+            return Some(out);
         }
-        XtEvent::Depth(d) => {
-            let update = DepthUpdateEvent {
-                event_time: d.timestamp,
-                symbol: symbol_string.clone(),
-                first_update_id: 0,
-                final_update_id: 0,
-                previous_final_update_id: 0,
-                bids: d
-                    .bids
-                    .into_iter()
-                    .map(|[p, q]| [Cow::Owned(p.into_owned()), Cow::Owned(q.into_owned())])
-                    .collect(),
-                asks: d
-                    .asks
-                    .into_iter()
-                    .map(|[p, q]| [Cow::Owned(p.into_owned()), Cow::Owned(q.into_owned())])
-                    .collect(),
-            };
-            Some(vec![(
-                symbol_string.clone(),
-                StreamMessage {
-                    stream: format!("{symbol}@depth"),
-                    data: Event::DepthUpdate(update),
-                },
-            )])
+        MapOrSeq::Map(fields) => fields,
+    };
+
+    let message = if let (Some(o), Some(c), Some(h), Some(l), Some(v)) =
+        (fields.o, fields.c, fields.h, fields.l, fields.v)
+    {
+        let ev = KlineEvent {
+            event_time: fields.t,
+            symbol: symbol.to_string(),
+            kline: Kline {
+                start_time: fields.t,
+                close_time: fields.t,
+                interval: "1m".to_string(),
+                open: owned(o),
+                close: owned(c),
+                high: owned(h),
+                low: owned(l),
+                volume: owned(v),
+                trades: 0,
+                is_closed: true,
+                quote_volume: Cow::Owned("0".to_string()),
+                taker_buy_base_volume: Cow::Owned("0".to_string()),
+                taker_buy_quote_volume: Cow::Owned("0".to_string()),
+            },
+        };
+        StreamMessage {
+            stream: format!("{symbol}@kline"),
+            data: Event::Kline(ev),
         }
-        XtEvent::Kline(k) => {
-            let ev = KlineEvent {
-                event_time: k.timestamp,
-                symbol: symbol_string.clone(),
-                kline: Kline {
-                    start_time: k.timestamp,
-                    close_time: k.timestamp,
-                    interval: "1m".to_string(),
-                    open: Cow::Owned(k.open.into_owned()),
-                    close: Cow::Owned(k.close.into_owned()),
-                    high: Cow::Owned(k.high.into_owned()),
-                    low: Cow::Owned(k.low.into_owned()),
-                    volume: Cow::Owned(k.volume.into_owned()),
-                    trades: 0,
-                    is_closed: true,
-                    quote_volume: Cow::Owned("0".to_string()),
-                    taker_buy_base_volume: Cow::Owned("0".to_string()),
-                    taker_buy_quote_volume: Cow::Owned("0".to_string()),
-                },
-            };
-            Some(vec![(
-                symbol_string.clone(),
-                StreamMessage {
-                    stream: format!("{symbol}@kline"),
-                    data: Event::Kline(ev),
-                },
-            )])
+    } else if let (Some(bp), Some(bq), Some(ap), Some(aq)) =
+        (fields.bp, fields.bq, fields.ap, fields.aq)
+    {
+        let ev = BookTickerEvent {
+            update_id: fields.t,
+            symbol: symbol.to_string(),
+            best_bid_price: owned(bp),
+            best_bid_qty: owned(bq),
+            best_ask_price: owned(ap),
+            best_ask_qty: owned(aq),
+        };
+        StreamMessage {
+            stream: format!("{symbol}@ticker"),
+            data: Event::BookTicker(ev),
         }
-        XtEvent::Ticker(t) => {
-            let ev = BookTickerEvent {
-                update_id: t.timestamp,
-                symbol: symbol_string.clone(),
-                best_bid_price: Cow::Owned(t.bid_price.into_owned()),
-                best_bid_qty: Cow::Owned(t.bid_qty.into_owned()),
-                best_ask_price: Cow::Owned(t.ask_price.into_owned()),
-                best_ask_qty: Cow::Owned(t.ask_qty.into_owned()),
-            };
-            Some(vec![(
-                symbol_string.clone(),
-                StreamMessage {
-                    stream: format!("{symbol}@ticker"),
-                    data: Event::BookTicker(ev),
-                },
-            )])
+    } else {
+        let levels = |levels: Vec<[&str; 2]>| {
+            levels
+                .into_iter()
+                .map(|[p, q]| [owned(p), owned(q)])
+                .collect()
+        };
+        let update = DepthUpdateEvent {
+            event_time: fields.t,
+            symbol: symbol.to_string(),
+            first_update_id: 0,
+            final_update_id: 0,
+            previous_final_update_id: 0,
+            bids: levels(fields.b),
+            asks: levels(fields.a),
+        };
+        StreamMessage {
+            stream: format!("{symbol}@depth"),
+            data: Event::DepthUpdate(update),
         }
-    }
+    };
+    Some(vec![message])
 }
@@ -0,0 +1,55 @@
+//! Queue keeping only the newest message per stream.
+//!
+//! Book tickers and tickers are snapshots, so a newer message makes any
+//! queued one for the same stream obsolete. Replacing it in place keeps the
+//! queue as short as the number of streams, however bursty the venue, and
+//! never loses the latest value of one stream to make room for another.
+//! Partition classes with the `conflate` drop policy queue their messages
+//! here.
+
+use arb_core as core;
+use core::events::{Channel, StreamMessage};
+use std::collections::VecDeque;
+
+#[derive(Default)]
+pub(crate) struct Latest {
+    /// Messages in arrival order of their stream's oldest pending update.
+    queue: VecDeque<(Option<Channel>, StreamMessage<'static>)>,
+}
+
+impl Latest {
+    /// Queue `msg`, replacing the pending message of the same channel and
+    /// stream. Returns whether one was replaced.
+    pub fn push(&mut self, channel: Option<Channel>, msg: StreamMessage<'static>) -> bool {
+        // A partition serves one symbol, so only a handful of streams are
+        // ever pending and a scan beats hashing the stream name.
+        let pending = self
+            .queue
+            .iter_mut()
+            .find(|(c, m)| *c == channel && m.stream == msg.stream);
+        match pending {
+            Some((_, pending)) => {
+                *pending = msg;
+                true
+            }
+            None => {
+                self.queue.push_back((channel, msg));
+                false
+            }
+        }
+    }
+
+    /// Take the message whose stream has waited longest.
+    pub fn pop(&mut self) -> Option<StreamMessage<'static>> {
+        self.queue.pop_front().map(|(_, msg)| msg)
+    }
+
+    /// Messages waiting to be received.
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.queue.clear();
+    }
+}
@@ -0,0 +1,147 @@
+//! Pooled receive buffers for parsing websocket frames.
+//!
+//! Connection tasks of the Binance, Bitget, LBank, LATOKEN and XT adapters
+//! check a [`Frame`] out of a process wide pool and parse every text frame
+//! through it with `simd_json`, straight into types that borrow their
+//! strings from the buffer: Binance into a borrowed
+//! [`StreamMessage`](arb_core::events::StreamMessage), the others into
+//! venue structs. Adapters normalize those into owned messages before
+//! parsing the next frame, which the borrow checker enforces since the
+//! buffer is reused. Dropping the frame hands its buffers back to the pool.
+
+use once_cell::sync::Lazy;
+use serde::de::value::{MapAccessDeserializer, SeqAccessDeserializer};
+use serde::de::{Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::Deserialize;
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::Mutex;
+
+/// Buffers kept around for future connections.
+const POOL_SIZE: usize = 64;
+/// Buffers grown beyond this by an unusually large frame are freed rather
+/// than pooled.
+const MAX_RETAINED: usize = 1 << 20;
+/// Initial capacity, enough for typical trade and depth frames.
+const INITIAL_CAPACITY: usize = 4096;
+
+static POOL: Lazy<Mutex<Vec<Buffers>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+struct Buffers {
+    bytes: Vec<u8>,
+    scratch: simd_json::Buffers,
+}
+
+impl Buffers {
+    fn new() -> Self {
+        Self {
+            bytes: Vec::with_capacity(INITIAL_CAPACITY),
+            scratch: simd_json::Buffers::new(INITIAL_CAPACITY),
+        }
+    }
+}
+
+/// Receive buffer checked out of the pool.
+pub struct Frame {
+    buffers: Option<Buffers>,
+}
+
+impl Frame {
+    /// Take a buffer from the pool, allocating one if it is empty.
+    pub fn new() -> Self {
+        let pooled = POOL.lock().ok().and_then(|mut pool| pool.pop());
+        Self {
+            buffers: Some(pooled.unwrap_or_else(Buffers::new)),
+        }
+    }
+
+    /// Copy `text` into the buffer and parse it. `T` may borrow from the
+    /// buffer until the next call.
+    pub fn parse<'a, T>(&'a mut self, text: &[u8]) -> simd_json::Result<T>
+    where
+        T: Deserialize<'a>,
+    {
+        let buffers = self.buffers.get_or_insert_with(Buffers::new);
+        buffers.bytes.clear();
+        buffers.bytes.extend_from_slice(text);
+        simd_json::serde::from_slice_with_buffers(&mut buffers.bytes, &mut buffers.scratch)
+    }
+
+    /// Like [`Frame::parse`], but take over `text`'s allocation as the
+    /// buffer instead of copying it.
+    pub fn parse_in_place<'a, T>(&'a mut self, text: String) -> simd_json::Result<T>
+    where
+        T: Deserialize<'a>,
+    {
+        let buffers = self.buffers.get_or_insert_with(Buffers::new);
+        buffers.bytes = text.into_bytes();
+        simd_json::serde::from_slice_with_buffers(&mut buffers.bytes, &mut buffers.scratch)
+    }
+
+    /// The last frame parsed. Parsing unescapes strings in place, so it may
+    /// differ from the text received.
+    pub fn text(&self) -> &[u8] {
+        self.buffers.as_ref().map_or(&[], |b| &b.bytes)
+    }
+}
+
+impl Default for Frame {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Frame {
+    fn drop(&mut self) {
+        let Some(buffers) = self.buffers.take() else {
+            return;
+        };
+        if buffers.bytes.capacity() > MAX_RETAINED {
+            return;
+        }
+        if let Ok(mut pool) = POOL.lock() {
+            if pool.len() < POOL_SIZE {
+                pool.push(buffers);
+            }
+        }
+    }
+}
+
+/// JSON value that is an object deserialized as `M` or an array deserialized
+/// as `S`. Unlike `#[serde(untagged)]` it does not buffer the value first.
+pub(crate) enum MapOrSeq<M, S> {
+    Map(M),
+    Seq(S),
+}
+
+impl<'de, M, S> Deserialize<'de> for MapOrSeq<M, S>
+where
+    M: Deserialize<'de>,
+    S: Deserialize<'de>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct MapOrSeqVisitor<M, S>(PhantomData<(M, S)>);
+
+        impl<'de, M, S> Visitor<'de> for MapOrSeqVisitor<M, S>
+        where
+            M: Deserialize<'de>,
+            S: Deserialize<'de>,
+        {
+            type Value = MapOrSeq<M, S>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("an object or an array")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+                M::deserialize(MapAccessDeserializer::new(map)).map(MapOrSeq::Map)
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+                S::deserialize(SeqAccessDeserializer::new(seq)).map(MapOrSeq::Seq)
+            }
+        }
+
+        deserializer.deserialize_any(MapOrSeqVisitor(PhantomData))
+    }
+}
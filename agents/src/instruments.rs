@@ -0,0 +1,133 @@
+//! Instrument metadata collected from exchange-info endpoints while adapters
+//! start up, used to build the canonical symbol table.
+
+use anyhow::Result;
+use canonical::symbol::{ContractSpec, Instrument, VenueType};
+use dashmap::DashMap;
+use futures::future::BoxFuture;
+use once_cell::sync::Lazy;
+use serde_json::Value;
+use std::future::Future;
+use std::sync::Arc;
+use tracing::warn;
+
+type Fetcher = Arc<dyn Fn() -> BoxFuture<'static, Result<Vec<Instrument>>> + Send + Sync>;
+
+static CATALOG: Lazy<DashMap<&'static str, Vec<Instrument>>> = Lazy::new(DashMap::new);
+static FETCHERS: Lazy<DashMap<&'static str, Fetcher>> = Lazy::new(DashMap::new);
+
+/// Store the instruments fetched by the adapter registered as `adapter_id`,
+/// replacing any earlier list.
+pub fn record(adapter_id: &'static str, instruments: Vec<Instrument>) {
+    CATALOG.insert(adapter_id, instruments);
+}
+
+/// All instruments recorded so far, across adapters.
+pub fn snapshot() -> Vec<Instrument> {
+    CATALOG
+        .iter()
+        .flat_map(|entry| entry.value().clone())
+        .collect()
+}
+
+/// Sorted, de-duplicated venue symbols of `instruments`.
+pub fn symbols(instruments: &[Instrument]) -> Vec<String> {
+    let mut result: Vec<String> = instruments.iter().map(|i| i.symbol.clone()).collect();
+    result.sort();
+    result.dedup();
+    result
+}
+
+/// Re-fetch metadata from every started adapter, keeping the previous list
+/// for adapters whose endpoint fails.
+pub async fn refresh() {
+    let fetchers: Vec<(&'static str, Fetcher)> = FETCHERS
+        .iter()
+        .map(|entry| (*entry.key(), entry.value().clone()))
+        .collect();
+    for (adapter_id, fetch) in fetchers {
+        match fetch().await {
+            Ok(instruments) => record(adapter_id, instruments),
+            Err(e) => {
+                warn!(exchange = adapter_id, error = %e, "failed to refresh instrument metadata")
+            }
+        }
+    }
+}
+
+/// Fetch and record metadata for `adapter_id`, returning the symbols to
+/// subscribe to. Explicitly configured symbols take precedence, and a failed
+/// fetch is only fatal when there are none. `fetch` is kept for [`refresh`].
+pub(crate) async fn resolve_symbols<F, Fut>(
+    adapter_id: &'static str,
+    configured: Vec<String>,
+    fetch: F,
+) -> Result<Vec<String>>
+where
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<Vec<Instrument>>> + Send + 'static,
+{
+    let fetch: Fetcher = Arc::new(move || Box::pin(fetch()));
+    FETCHERS.insert(adapter_id, fetch.clone());
+    match fetch().await {
+        Ok(instruments) => {
+            let symbols = if configured.is_empty() {
+                symbols(&instruments)
+            } else {
+                configured
+            };
+            record(adapter_id, instruments);
+            Ok(symbols)
+        }
+        Err(e) if !configured.is_empty() => {
+            warn!(exchange = adapter_id, error = %e, "failed to fetch instrument metadata");
+            Ok(configured)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+pub(crate) fn instrument(exchange: &str, symbol: &str, spec: ContractSpec) -> Instrument {
+    Instrument {
+        exchange: exchange.to_string(),
+        symbol: symbol.to_string(),
+        spec,
+    }
+}
+
+/// Spec with base and quote taken from a delimited pair such as `BTC_USDT`.
+pub(crate) fn pair_spec(venue: VenueType, symbol: &str, sep: char) -> ContractSpec {
+    let (base, quote) = symbol.split_once(sep).unwrap_or_default();
+    ContractSpec {
+        venue,
+        base: base.to_uppercase(),
+        quote: quote.to_uppercase(),
+        ..Default::default()
+    }
+}
+
+/// Read a numeric field that venues encode either as a number or a string.
+pub(crate) fn num(v: &Value, key: &str) -> Option<f64> {
+    match v.get(key)? {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.parse().ok(),
+        _ => None,
+    }
+}
+
+pub(crate) fn text(v: &Value, key: &str) -> String {
+    v.get(key)
+        .and_then(|v| v.as_str())
+        .map(|s| s.to_uppercase())
+        .unwrap_or_default()
+}
+
+/// Step size for a venue that reports a number of decimal places.
+pub(crate) fn decimals(v: &Value, key: &str) -> Option<f64> {
+    num(v, key).map(|p| 10f64.powi(-(p as i32)))
+}
+
+/// Positive numeric field, treating zero as unknown.
+pub(crate) fn positive(v: &Value, key: &str) -> Option<f64> {
+    num(v, key).filter(|n| *n > 0.0)
+}
@@ -1,8 +1,10 @@
 use anyhow::{anyhow, Result};
 use arb_core as core;
+use core::channel_policy::{ChannelPolicy, Transport};
 use dashmap::DashMap;
 use reqwest::Client;
 use rustls::ClientConfig;
+use std::collections::HashMap;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use tokio::{
@@ -12,7 +14,12 @@ use tokio::{
 use tracing::error;
 
 pub mod adapter;
+mod conflate;
+pub mod frame;
+pub mod instruments;
+mod partition;
 pub mod registry;
+mod ring;
 pub use adapter::binance::{
     fetch_symbols as fetch_binance_symbols, BinanceAdapter, BINANCE_EXCHANGES,
 };
@@ -31,60 +38,81 @@ pub use adapter::ExchangeAdapter;
 pub type TaskSet = Arc<Mutex<JoinSet<()>>>;
 
 /// Channel sender wrapping separate queues for different market data channels.
+///
+/// Messages go to the queue of their channel's class in the partition's
+/// [`ChannelPolicy`], which decides whether a full queue waits, drops or
+/// conflates. With the default policy, depth diffs, trades and everything
+/// else wait for room and are never dropped, while book tickers, tickers and
+/// derivative prices are conflated to the newest message per stream.
 #[derive(Clone)]
 pub struct StreamSender {
-    book: mpsc::Sender<core::events::StreamMessage<'static>>,
-    trade: mpsc::Sender<core::events::StreamMessage<'static>>,
-    ticker: mpsc::Sender<core::events::StreamMessage<'static>>,
+    inner: Sender,
+}
+
+#[derive(Clone)]
+enum Sender {
+    Queue(partition::PartitionSender),
+    Ring(ring::RingSender),
 }
 
 impl StreamSender {
-    /// Route messages to the appropriate channel queue. Messages are dropped
-    /// if the corresponding queue is full.
-    #[allow(clippy::result_large_err)]
-    pub fn send(
+    /// Route messages to the appropriate channel queue. Fails only once the
+    /// consumer is gone.
+    pub async fn send(
         &self,
         msg: core::events::StreamMessage<'static>,
-    ) -> Result<(), mpsc::error::TrySendError<core::events::StreamMessage<'static>>> {
-        use core::events::Event;
+    ) -> Result<(), mpsc::error::SendError<core::events::StreamMessage<'static>>> {
+        match &self.inner {
+            Sender::Queue(tx) => tx.send(msg).await,
+            Sender::Ring(tx) => tx.send(msg).await,
+        }
+    }
+}
 
-        let metrics_enabled = core::config::metrics_enabled();
-        let channel;
+/// Consumer end of a partition, receiving its messages in the policy's
+/// priority order.
+pub struct StreamReceiver {
+    inner: Receiver,
+}
 
-        let res = match msg.data {
-            Event::DepthUpdate(_) | Event::BookTicker(_) => {
-                channel = "book";
-                self.book.try_send(msg)
-            }
-            Event::Trade(_) | Event::AggTrade(_) => {
-                channel = "trade";
-                self.trade.try_send(msg)
-            }
-            Event::Ticker(_) | Event::MiniTicker(_) => {
-                channel = "ticker";
-                self.ticker.try_send(msg)
-            }
-            _ => {
-                channel = "trade";
-                self.trade.try_send(msg)
-            }
-        };
-
-        if metrics_enabled {
-            let depth = match channel {
-                "book" => self.book.max_capacity() - self.book.capacity(),
-                "trade" => self.trade.max_capacity() - self.trade.capacity(),
-                "ticker" => self.ticker.max_capacity() - self.ticker.capacity(),
-                _ => 0,
-            } as f64;
-            metrics::gauge!("adapter_queue_depth", "channel" => channel).set(depth);
-
-            if matches!(res, Err(mpsc::error::TrySendError::Full(_))) {
-                metrics::counter!("md_backpressure_drops_total", "channel" => channel).increment(1);
-            }
+enum Receiver {
+    Queue(partition::PartitionReceiver),
+    Ring(ring::RingReceiver),
+}
+
+impl StreamReceiver {
+    /// Wait for the next message. Returns `None` once every sender is gone
+    /// and the queues are empty.
+    pub async fn recv(&mut self) -> Option<core::events::StreamMessage<'static>> {
+        match &mut self.inner {
+            Receiver::Queue(rx) => rx.recv().await,
+            Receiver::Ring(rx) => rx.recv().await,
+        }
+    }
+
+    /// Wait for messages and move up to `limit` of them into `buf`. Returns
+    /// `0` once every sender is gone and the queues are empty.
+    pub async fn recv_many(
+        &mut self,
+        buf: &mut Vec<core::events::StreamMessage<'static>>,
+        limit: usize,
+    ) -> usize {
+        match &mut self.inner {
+            Receiver::Queue(rx) => rx.recv_many(buf, limit).await,
+            Receiver::Ring(rx) => rx.recv_many(buf, limit).await,
+        }
+    }
+
+    /// Messages waiting to be received.
+    pub fn len(&self) -> usize {
+        match &self.inner {
+            Receiver::Queue(rx) => rx.len(),
+            Receiver::Ring(rx) => rx.len(),
         }
+    }
 
-        res
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 }
 
@@ -93,63 +121,101 @@ impl StreamSender {
 pub struct ChannelRegistry {
     senders: Arc<DashMap<String, StreamSender>>,
     seq_counters: Arc<DashMap<String, AtomicU64>>,
-    book_buffer: usize,
-    trade_buffer: usize,
-    ticker_buffer: usize,
+    policy: Arc<ChannelPolicy>,
+    transport: Transport,
+    /// Overrides keyed by exchange id.
+    exchange_policies: Arc<HashMap<String, ChannelPolicy>>,
 }
 
 impl ChannelRegistry {
     /// Create a new registry using the provided channel buffer size.
     pub fn new(buffer: usize) -> Self {
-        let trade = std::cmp::max(1, buffer / 2);
-        let ticker = std::cmp::max(1, buffer / 4);
-        Self {
+        Self::with_policy(ChannelPolicy::new(buffer)).expect("default channel policy is valid")
+    }
+
+    /// Create a registry whose partitions use `policy`.
+    pub fn with_policy(policy: ChannelPolicy) -> Result<Self> {
+        policy.validate()?;
+        Ok(Self {
             senders: Arc::new(DashMap::new()),
             seq_counters: Arc::new(DashMap::new()),
-            book_buffer: buffer,
-            trade_buffer: trade,
-            ticker_buffer: ticker,
+            policy: Arc::new(policy),
+            transport: Transport::Queue,
+            exchange_policies: Arc::new(HashMap::new()),
+        })
+    }
+
+    /// Create a registry with the configured default and per-exchange
+    /// policies.
+    pub fn from_config(cfg: &core::config::Config) -> Result<Self> {
+        let mut registry =
+            Self::with_policy(cfg.channel_policy.clone())?.with_transport(cfg.event_transport)?;
+        for exch in &cfg.exchanges {
+            if let Some(policy) = &exch.channel_policy {
+                registry = registry.with_exchange_policy(&exch.id, policy.clone())?;
+            }
+        }
+        Ok(registry)
+    }
+
+    /// Use `policy` for the partitions of exchange `id`, e.g.
+    /// `binance_futures`. Must be called before the exchange's partitions are
+    /// created. Fails if no adapter has that id.
+    pub fn with_exchange_policy(mut self, id: &str, policy: ChannelPolicy) -> Result<Self> {
+        register_adapters();
+        if registry::get_adapter(id).is_none() {
+            return Err(anyhow!(
+                "channel policy for unknown exchange {id}; supported exchanges: {}",
+                registry::registered_ids().join(", ")
+            ));
+        }
+        policy.validate_for(self.transport)?;
+        Arc::make_mut(&mut self.exchange_policies).insert(id.to_string(), policy);
+        Ok(self)
+    }
+
+    /// Implement partitions created from now on with `transport`.
+    pub fn with_transport(mut self, transport: Transport) -> Result<Self> {
+        self.policy.validate_for(transport)?;
+        for policy in self.exchange_policies.values() {
+            policy.validate_for(transport)?;
         }
+        self.transport = transport;
+        Ok(self)
     }
 
-    /// Get an existing channel sender or create a new channel pair.
+    /// Policy for partition `key`, looked up by its exchange id.
+    fn policy_for(&self, key: &str) -> &ChannelPolicy {
+        let exchange = key.split_once(':').map_or(key, |(exchange, _)| exchange);
+        self.exchange_policies.get(exchange).unwrap_or(&self.policy)
+    }
+
+    /// Get an existing channel sender or create a new channel pair. Adapters
+    /// key their partitions `<exchange id>:<symbol>`, e.g.
+    /// `lbank_spot:btc_usdt`.
     ///
     /// Returns the sender and `Some(receiver)` if a new channel was created.
-    pub fn get_or_create(
-        &self,
-        key: &str,
-    ) -> (
-        StreamSender,
-        Option<mpsc::Receiver<core::events::StreamMessage<'static>>>,
-    ) {
+    pub fn get_or_create(&self, key: &str) -> (StreamSender, Option<StreamReceiver>) {
         use dashmap::mapref::entry::Entry;
 
         match self.senders.entry(key.to_string()) {
             Entry::Occupied(entry) => (entry.get().clone(), None),
             Entry::Vacant(entry) => {
-                let (book_tx, book_rx) = mpsc::channel(self.book_buffer);
-                let (trade_tx, trade_rx) = mpsc::channel(self.trade_buffer);
-                let (ticker_tx, ticker_rx) = mpsc::channel(self.ticker_buffer);
-                let (out_tx, out_rx) =
-                    mpsc::channel(self.book_buffer + self.trade_buffer + self.ticker_buffer);
-
-                dispatcher(
-                    book_rx,
-                    trade_rx,
-                    ticker_rx,
-                    out_tx.clone(),
-                    self.book_buffer,
-                    self.trade_buffer,
-                    self.ticker_buffer,
-                );
-
-                let tx = StreamSender {
-                    book: book_tx,
-                    trade: trade_tx,
-                    ticker: ticker_tx,
+                let policy = self.policy_for(key);
+                let (tx, rx) = match self.transport {
+                    Transport::Queue => {
+                        let (tx, rx) = partition::channel(policy);
+                        (Sender::Queue(tx), Receiver::Queue(rx))
+                    }
+                    Transport::Ring => {
+                        let (tx, rx) = ring::channel(policy);
+                        (Sender::Ring(tx), Receiver::Ring(rx))
+                    }
                 };
+
+                let tx = StreamSender { inner: tx };
                 entry.insert(tx.clone());
-                (tx, Some(out_rx))
+                (tx, Some(StreamReceiver { inner: rx }))
             }
         }
     }
@@ -182,66 +248,6 @@ impl ChannelRegistry {
     }
 }
 
-fn dispatcher(
-    mut book_rx: mpsc::Receiver<core::events::StreamMessage<'static>>,
-    mut trade_rx: mpsc::Receiver<core::events::StreamMessage<'static>>,
-    mut ticker_rx: mpsc::Receiver<core::events::StreamMessage<'static>>,
-    out_tx: mpsc::Sender<core::events::StreamMessage<'static>>,
-    book_cap: usize,
-    trade_cap: usize,
-    ticker_cap: usize,
-) {
-    tokio::spawn(async move {
-        loop {
-            tokio::select! {
-                biased;
-                Some(msg) = book_rx.recv() => {
-                    if out_tx.send(msg).await.is_err() { break; }
-                }
-                Some(msg) = trade_rx.recv() => {
-                    if out_tx.send(msg).await.is_err() { break; }
-                }
-                Some(msg) = ticker_rx.recv() => {
-                    if out_tx.send(msg).await.is_err() { break; }
-                }
-                else => break,
-            }
-
-            if core::config::metrics_enabled() {
-                metrics::gauge!("md_queue_depth", "channel" => "book").set(book_rx.len() as f64);
-                metrics::gauge!("md_queue_depth", "channel" => "trade").set(trade_rx.len() as f64);
-                metrics::gauge!("md_queue_depth", "channel" => "ticker")
-                    .set(ticker_rx.len() as f64);
-            }
-
-            if ticker_rx.len() >= ticker_cap {
-                let _ = ticker_rx.try_recv();
-                if core::config::metrics_enabled() {
-                    metrics::counter!("md_backpressure_drops_total", "channel" => "ticker")
-                        .increment(1);
-                }
-            }
-            if trade_rx.len() >= trade_cap && ticker_rx.try_recv().is_err() {
-                let _ = trade_rx.try_recv();
-                if core::config::metrics_enabled() {
-                    metrics::counter!("md_backpressure_drops_total", "channel" => "trade")
-                        .increment(1);
-                }
-            }
-            if book_rx.len() >= book_cap
-                && ticker_rx.try_recv().is_err()
-                && trade_rx.try_recv().is_err()
-            {
-                let _ = book_rx.try_recv();
-                if core::config::metrics_enabled() {
-                    metrics::counter!("md_backpressure_drops_total", "channel" => "book")
-                        .increment(1);
-                }
-            }
-        }
-    });
-}
-
 /// Run a collection of exchange adapters to completion.
 ///
 /// Each adapter is spawned on the Tokio runtime and awaited. Errors from
@@ -277,21 +283,8 @@ pub async fn spawn_adapters(
     task_set: TaskSet,
     channels: ChannelRegistry,
     tls_config: Arc<ClientConfig>,
-) -> Result<Vec<mpsc::Receiver<core::events::StreamMessage<'static>>>> {
-    adapter::binance::register();
-    adapter::gateio::register();
-    adapter::mexc::register();
-    adapter::bingx::register();
-    adapter::kucoin::register();
-    adapter::xt::register();
-    adapter::bitmart::register();
-    adapter::coinex::register();
-    adapter::latoken::register();
-    adapter::lbank::register();
-    adapter::bitget::register();
-    adapter::okx::register();
-    adapter::coinbase::register();
-    adapter::kraken::register();
+) -> Result<Vec<StreamReceiver>> {
+    register_adapters();
 
     let mut receivers = Vec::new();
 
@@ -324,3 +317,21 @@ pub async fn spawn_adapters(
 
     Ok(receivers)
 }
+
+/// Register the factory of every adapter. Safe to call more than once.
+fn register_adapters() {
+    adapter::binance::register();
+    adapter::gateio::register();
+    adapter::mexc::register();
+    adapter::bingx::register();
+    adapter::kucoin::register();
+    adapter::xt::register();
+    adapter::bitmart::register();
+    adapter::coinex::register();
+    adapter::latoken::register();
+    adapter::lbank::register();
+    adapter::bitget::register();
+    adapter::okx::register();
+    adapter::coinbase::register();
+    adapter::kraken::register();
+}
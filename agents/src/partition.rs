@@ -0,0 +1,335 @@
+//! Class queues of one `<exchange>:<symbol>` partition.
+//!
+//! Messages are routed to the classes of a [`ChannelPolicy`] by channel.
+//! Each class queue applies its own [`DropPolicy`] once full, and the
+//! receiver takes from the classes in priority order. Conflating classes
+//! queue into a [`Latest`] instead and are never full.
+
+use crate::conflate::Latest;
+use arb_core as core;
+use core::channel_policy::{ChannelPolicy, DropPolicy};
+use core::events::{Channel, StreamMessage};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{mpsc::error::SendError, Notify};
+
+enum Queue {
+    Fifo(VecDeque<StreamMessage<'static>>),
+    Latest(Latest),
+}
+
+impl Queue {
+    fn len(&self) -> usize {
+        match self {
+            Queue::Fifo(queue) => queue.len(),
+            Queue::Latest(latest) => latest.len(),
+        }
+    }
+
+    fn pop(&mut self) -> Option<StreamMessage<'static>> {
+        match self {
+            Queue::Fifo(queue) => queue.pop_front(),
+            Queue::Latest(latest) => latest.pop(),
+        }
+    }
+
+    fn clear(&mut self) {
+        match self {
+            Queue::Fifo(queue) => queue.clear(),
+            Queue::Latest(latest) => latest.clear(),
+        }
+    }
+}
+
+struct Class {
+    name: String,
+    policy: DropPolicy,
+    capacity: usize,
+    queue: Mutex<Queue>,
+    /// Signalled when a message is taken from the queue or the receiver
+    /// is dropped.
+    space: Notify,
+}
+
+struct Shared {
+    classes: Vec<Class>,
+    /// Class of each channel, by `Channel as usize`, and of events without
+    /// one.
+    routes: Vec<usize>,
+    fallback: usize,
+    order: Vec<usize>,
+    /// Signalled when a message is queued or the partition closes.
+    data: Notify,
+    senders: AtomicUsize,
+    closed: AtomicBool,
+}
+
+impl Shared {
+    fn class_of(&self, channel: Option<Channel>) -> &Class {
+        let index = channel.map_or(self.fallback, |c| self.routes[c as usize]);
+        &self.classes[index]
+    }
+}
+
+/// Every [`Channel`], to resolve routes up front.
+pub(crate) const CHANNELS: [Channel; 13] = [
+    Channel::Trade,
+    Channel::Book,
+    Channel::Ticker,
+    Channel::MiniTicker,
+    Channel::Kline,
+    Channel::Depth,
+    Channel::AvgPrice,
+    Channel::MarkPrice,
+    Channel::IndexPrice,
+    Channel::FundingRate,
+    Channel::OpenInterest,
+    Channel::Liquidation,
+    Channel::Instrument,
+];
+
+/// Create the queues of a partition, closed once every sender is dropped.
+pub(crate) fn channel(policy: &ChannelPolicy) -> (PartitionSender, PartitionReceiver) {
+    let mut routes = vec![0; CHANNELS.len()];
+    for channel in CHANNELS {
+        routes[channel as usize] = policy.class_of(Some(channel));
+    }
+    let shared = Arc::new(Shared {
+        classes: policy
+            .classes
+            .iter()
+            .map(|class| Class {
+                name: class.name.clone(),
+                policy: class.policy,
+                capacity: class.buffer.max(1),
+                queue: Mutex::new(match class.policy {
+                    DropPolicy::Conflate => Queue::Latest(Latest::default()),
+                    _ => Queue::Fifo(VecDeque::new()),
+                }),
+                space: Notify::new(),
+            })
+            .collect(),
+        routes,
+        fallback: policy.class_of(None),
+        order: policy.drain_order(),
+        data: Notify::new(),
+        senders: AtomicUsize::new(1),
+        closed: AtomicBool::new(false),
+    });
+    (
+        PartitionSender {
+            shared: shared.clone(),
+        },
+        PartitionReceiver { shared },
+    )
+}
+
+pub(crate) struct PartitionSender {
+    shared: Arc<Shared>,
+}
+
+impl PartitionSender {
+    /// Queue `msg` in its class, waiting for room if the class blocks.
+    /// Fails only once the receiver is gone.
+    pub async fn send(
+        &self,
+        msg: StreamMessage<'static>,
+    ) -> Result<(), SendError<StreamMessage<'static>>> {
+        let metrics_enabled = core::config::metrics_enabled();
+        let channel = msg.data.channel();
+        let class = self.shared.class_of(channel);
+        let mut waited = false;
+        loop {
+            let space = class.space.notified();
+            tokio::pin!(space);
+            space.as_mut().enable();
+            // The guard is scoped so it is never held across the await.
+            {
+                let mut guard = class.queue.lock().expect("partition mutex poisoned");
+                if self.shared.closed.load(Ordering::Acquire) {
+                    return Err(SendError(msg));
+                }
+                let queue = match &mut *guard {
+                    Queue::Latest(latest) => {
+                        let replaced = latest.push(channel, msg);
+                        if metrics_enabled && replaced {
+                            let channel = channel.map_or("other", |c| c.as_str());
+                            metrics::counter!("md_conflated_total", "channel" => channel)
+                                .increment(1);
+                        } else if metrics_enabled {
+                            metrics::gauge!("adapter_queue_depth", "channel" => class.name.clone())
+                                .set(latest.len() as f64);
+                        }
+                        drop(guard);
+                        if !replaced {
+                            self.shared.data.notify_one();
+                        }
+                        return Ok(());
+                    }
+                    Queue::Fifo(queue) => queue,
+                };
+                let full = queue.len() >= class.capacity;
+                match class.policy {
+                    _ if !full => {}
+                    DropPolicy::Block => {
+                        if !waited && metrics_enabled {
+                            metrics::counter!(
+                                "md_backpressure_waits_total",
+                                "channel" => class.name.clone()
+                            )
+                            .increment(1);
+                        }
+                        waited = true;
+                    }
+                    DropPolicy::DropOldest => {
+                        queue.pop_front();
+                        count_drop(class, metrics_enabled);
+                    }
+                    DropPolicy::DropNewest => {
+                        count_drop(class, metrics_enabled);
+                        return Ok(());
+                    }
+                    DropPolicy::Conflate => unreachable!("conflating classes use a Latest queue"),
+                }
+                if !(full && class.policy == DropPolicy::Block) {
+                    queue.push_back(msg);
+                    if metrics_enabled {
+                        metrics::gauge!("adapter_queue_depth", "channel" => class.name.clone())
+                            .set(queue.len() as f64);
+                    }
+                    drop(guard);
+                    self.shared.data.notify_one();
+                    return Ok(());
+                }
+            }
+            space.await;
+        }
+    }
+}
+
+fn count_drop(class: &Class, metrics_enabled: bool) {
+    if metrics_enabled {
+        metrics::counter!("md_backpressure_drops_total", "channel" => class.name.clone())
+            .increment(1);
+    }
+}
+
+impl Clone for PartitionSender {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl Drop for PartitionSender {
+    fn drop(&mut self) {
+        if self.shared.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.shared.closed.store(true, Ordering::Release);
+            self.shared.data.notify_one();
+        }
+    }
+}
+
+pub(crate) struct PartitionReceiver {
+    shared: Arc<Shared>,
+}
+
+impl PartitionReceiver {
+    /// Wait for the next message of the highest-priority class that has
+    /// one. Returns `None` once every sender is gone and the queues are
+    /// empty.
+    pub async fn recv(&mut self) -> Option<StreamMessage<'static>> {
+        let mut buf = Vec::with_capacity(1);
+        self.recv_many(&mut buf, 1).await;
+        buf.pop()
+    }
+
+    /// Wait for messages and move up to `limit` of them into `buf`, highest
+    /// priority class first. Messages stay in their class queue until taken,
+    /// so conflation and priority apply to everything not yet received.
+    /// Returns `0` once every sender is gone and the queues are empty.
+    pub async fn recv_many(
+        &mut self,
+        buf: &mut Vec<StreamMessage<'static>>,
+        limit: usize,
+    ) -> usize {
+        if limit == 0 {
+            return 0;
+        }
+        loop {
+            let data = self.shared.data.notified();
+            tokio::pin!(data);
+            data.as_mut().enable();
+            // Read before draining, so messages queued by the last sender
+            // are still received.
+            let closed = self.shared.closed.load(Ordering::Acquire);
+            let taken = self.drain(buf, limit);
+            if taken > 0 || closed {
+                return taken;
+            }
+            data.await;
+        }
+    }
+
+    fn drain(&self, buf: &mut Vec<StreamMessage<'static>>, limit: usize) -> usize {
+        let mut taken = 0;
+        for &index in &self.shared.order {
+            let class = &self.shared.classes[index];
+            let before = taken;
+            {
+                let mut queue = class.queue.lock().expect("partition mutex poisoned");
+                while taken < limit {
+                    let Some(msg) = queue.pop() else {
+                        break;
+                    };
+                    buf.push(msg);
+                    taken += 1;
+                }
+            }
+            if taken > before {
+                class.space.notify_waiters();
+            }
+            if taken == limit {
+                break;
+            }
+        }
+        if taken > 0 && core::config::metrics_enabled() {
+            self.record_depths();
+        }
+        taken
+    }
+
+    /// Messages waiting in the queues.
+    pub fn len(&self) -> usize {
+        self.shared
+            .classes
+            .iter()
+            .map(|c| c.queue.lock().expect("partition mutex poisoned").len())
+            .sum()
+    }
+
+    /// Report the length of each class queue as `md_queue_depth`.
+    pub fn record_depths(&self) {
+        for class in &self.shared.classes {
+            let len = class.queue.lock().expect("partition mutex poisoned").len();
+            metrics::gauge!("md_queue_depth", "channel" => class.name.clone()).set(len as f64);
+        }
+    }
+}
+
+impl Drop for PartitionReceiver {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Release);
+        for class in &self.shared.classes {
+            class
+                .queue
+                .lock()
+                .expect("partition mutex poisoned")
+                .clear();
+            class.space.notify_waiters();
+        }
+    }
+}
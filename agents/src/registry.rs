@@ -6,9 +6,8 @@ use dashmap::DashMap;
 use futures::future::BoxFuture;
 use reqwest::Client;
 use rustls::ClientConfig;
-use tokio::sync::mpsc;
 
-use crate::{ChannelRegistry, TaskSet};
+use crate::{ChannelRegistry, StreamReceiver, TaskSet};
 
 pub type AdapterFactory = Arc<
     dyn Fn(
@@ -18,8 +17,7 @@ pub type AdapterFactory = Arc<
             TaskSet,
             ChannelRegistry,
             Arc<ClientConfig>,
-        )
-            -> BoxFuture<'static, Result<Vec<mpsc::Receiver<core::events::StreamMessage<'static>>>>>
+        ) -> BoxFuture<'static, Result<Vec<StreamReceiver>>>
         + Send
         + Sync,
 >;
@@ -0,0 +1,593 @@
+//! Lock-free class queues of one `<exchange>:<symbol>` partition.
+//!
+//! The alternative to [`crate::partition`] selected with
+//! [`Transport::Ring`](core::channel_policy::Transport::Ring). Every class
+//! gets a pre-allocated single-producer, single-consumer ring buffer, which
+//! the consumer drains in priority order and in batches, as it does the
+//! mutex-guarded queues. Neither side takes a lock: the only shared writes
+//! are the ring indices, and the other side is only woken when it is parked.
+//!
+//! Conflating classes hold one slot per stream. The producer swaps a new
+//! message into its stream's slot and only queues the slot's index if the
+//! slot was empty, so a newer message replaces a pending one in place.
+//! [`DropPolicy::DropOldest`] would need the producer to pop, so it is not
+//! supported.
+//!
+//! Senders of one partition take turns through an atomic flag. A partition
+//! is fed by a single connection task, so the flag is uncontended; a second
+//! sender waits to be notified when the first one is done.
+
+use arb_core as core;
+use core::channel_policy::{ChannelPolicy, DropPolicy};
+use core::events::{Channel, StreamMessage};
+use crossbeam_utils::CachePadded;
+use std::cell::UnsafeCell;
+use std::collections::VecDeque;
+use std::mem::MaybeUninit;
+use std::ptr;
+use std::sync::atomic::{fence, AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::{mpsc::error::SendError, Notify};
+
+use crate::partition::CHANNELS;
+
+/// Messages [`RingReceiver::recv`] takes from the rings at once.
+const BATCH: usize = 64;
+
+/// Bounded single-producer, single-consumer queue.
+struct Ring<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    mask: usize,
+    /// Next position to read, written by the consumer only.
+    head: CachePadded<AtomicUsize>,
+    /// Next position to write, written by the producer only.
+    tail: CachePadded<AtomicUsize>,
+}
+
+// Slots are only accessed by the single producer before publishing them
+// through `tail` and by the single consumer after reading `tail`.
+unsafe impl<T: Send> Send for Ring<T> {}
+unsafe impl<T: Send> Sync for Ring<T> {}
+
+impl<T> Ring<T> {
+    /// A ring of at least `capacity` slots, rounded up to a power of two.
+    fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1).next_power_of_two();
+        Self {
+            slots: (0..capacity)
+                .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+                .collect(),
+            mask: capacity - 1,
+            head: CachePadded::new(AtomicUsize::new(0)),
+            tail: CachePadded::new(AtomicUsize::new(0)),
+        }
+    }
+
+    fn len(&self) -> usize {
+        // Loading `head` first keeps the difference from going negative.
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Acquire);
+        tail.wrapping_sub(head).min(self.slots.len())
+    }
+
+    /// Append `value`, handing it back if the ring is full.
+    ///
+    /// # Safety
+    ///
+    /// Only one thread may push at a time.
+    unsafe fn push(&self, value: T) -> Result<(), T> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        if tail.wrapping_sub(self.head.load(Ordering::Acquire)) == self.slots.len() {
+            return Err(value);
+        }
+        (*self.slots[tail & self.mask].get()).write(value);
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    /// Take the oldest value.
+    ///
+    /// # Safety
+    ///
+    /// Only one thread may pop at a time.
+    unsafe fn pop(&self) -> Option<T> {
+        let head = self.head.load(Ordering::Relaxed);
+        if head == self.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        let value = (*self.slots[head & self.mask].get()).assume_init_read();
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+        Some(value)
+    }
+}
+
+impl<T> Drop for Ring<T> {
+    fn drop(&mut self) {
+        // SAFETY: `&mut self` excludes every other reader.
+        while unsafe { self.pop() }.is_some() {}
+    }
+}
+
+/// Newest message per stream of a conflating class.
+struct Latest {
+    /// Pending message of each stream, null once taken.
+    slots: Box<[AtomicPtr<StreamMessage<'static>>]>,
+    /// Indices of the slots holding a message, in the order they filled.
+    /// A slot is only queued when it goes from empty to full, so the ring
+    /// never holds more indices than there are slots.
+    pending: Ring<usize>,
+}
+
+impl Latest {
+    fn new(streams: usize) -> Self {
+        let streams = streams.max(1);
+        Self {
+            slots: (0..streams)
+                .map(|_| AtomicPtr::new(ptr::null_mut()))
+                .collect(),
+            pending: Ring::new(streams),
+        }
+    }
+
+    /// Store `msg` as the pending message of slot `index`. Returns true if
+    /// it replaced one.
+    ///
+    /// # Safety
+    ///
+    /// Only one thread may put at a time.
+    unsafe fn put(&self, index: usize, msg: StreamMessage<'static>) -> bool {
+        let new = Box::into_raw(Box::new(msg));
+        let old = self.slots[index].swap(new, Ordering::AcqRel);
+        if old.is_null() {
+            // Cannot fail: `index` is not queued while its slot is empty.
+            let _ = self.pending.push(index);
+            false
+        } else {
+            drop(Box::from_raw(old));
+            true
+        }
+    }
+
+    /// # Safety
+    ///
+    /// Only one thread may take at a time.
+    unsafe fn take(&self) -> Option<StreamMessage<'static>> {
+        let index = self.pending.pop()?;
+        let msg = self.slots[index].swap(ptr::null_mut(), Ordering::AcqRel);
+        // Only the consumer empties slots, and only after taking their index.
+        debug_assert!(!msg.is_null());
+        Some(*Box::from_raw(msg))
+    }
+}
+
+impl Drop for Latest {
+    fn drop(&mut self) {
+        for slot in self.slots.iter_mut() {
+            let msg = *slot.get_mut();
+            if !msg.is_null() {
+                // SAFETY: non-null slots own a message from `Box::into_raw`.
+                drop(unsafe { Box::from_raw(msg) });
+            }
+        }
+    }
+}
+
+enum Queue {
+    Ring(Ring<StreamMessage<'static>>),
+    Latest(Latest),
+}
+
+struct Class {
+    name: String,
+    policy: DropPolicy,
+    queue: Queue,
+}
+
+impl Class {
+    fn len(&self) -> usize {
+        match &self.queue {
+            Queue::Ring(ring) => ring.len(),
+            Queue::Latest(latest) => latest.pending.len(),
+        }
+    }
+}
+
+/// State only the sender holding [`Shared::producing`] may touch.
+struct Producer {
+    /// Streams of each conflating class, by slot.
+    streams: Vec<Vec<(Option<Channel>, String)>>,
+}
+
+struct Shared {
+    classes: Vec<Class>,
+    /// Class of each channel, by `Channel as usize`.
+    routes: Vec<usize>,
+    fallback: usize,
+    order: Vec<usize>,
+    producing: AtomicBool,
+    /// Signalled when `producing` is released.
+    turn: Notify,
+    producer: UnsafeCell<Producer>,
+    /// Set by the receiver before it waits for `data`.
+    consumer_parked: AtomicBool,
+    data: Notify,
+    /// Set by a sender before it waits for `space`.
+    producer_parked: AtomicBool,
+    space: Notify,
+    senders: AtomicUsize,
+    closed: AtomicBool,
+}
+
+// `producer` is only accessed while holding `producing`.
+unsafe impl Sync for Shared {}
+
+impl Shared {
+    fn class_index(&self, channel: Option<Channel>) -> usize {
+        channel.map_or(self.fallback, |c| self.routes[c as usize])
+    }
+
+    /// Wait for the turn to produce.
+    async fn produce(&self) -> Producing<'_> {
+        let take = || {
+            self.producing
+                .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+        };
+        loop {
+            if take() {
+                return Producing(self);
+            }
+            // Register before retrying, so a release in between is not
+            // missed.
+            let turn = self.turn.notified();
+            tokio::pin!(turn);
+            turn.as_mut().enable();
+            if take() {
+                return Producing(self);
+            }
+            turn.await;
+        }
+    }
+
+    /// Wake the receiver if it is parked. Pairs with the fence in
+    /// [`RingReceiver::recv_many`], so either the receiver sees the new
+    /// message or this sees it parked.
+    fn wake_consumer(&self) {
+        fence(Ordering::SeqCst);
+        if self.consumer_parked.load(Ordering::Relaxed)
+            && self.consumer_parked.swap(false, Ordering::AcqRel)
+        {
+            self.data.notify_one();
+        }
+    }
+
+    fn wake_producer(&self) {
+        fence(Ordering::SeqCst);
+        if self.producer_parked.load(Ordering::Relaxed)
+            && self.producer_parked.swap(false, Ordering::AcqRel)
+        {
+            self.space.notify_one();
+        }
+    }
+}
+
+struct Producing<'a>(&'a Shared);
+
+impl Producing<'_> {
+    fn producer(&mut self) -> &mut Producer {
+        // SAFETY: holding the `producing` flag grants exclusive access.
+        unsafe { &mut *self.0.producer.get() }
+    }
+}
+
+impl Drop for Producing<'_> {
+    fn drop(&mut self) {
+        self.0.producing.store(false, Ordering::Release);
+        self.0.turn.notify_one();
+    }
+}
+
+/// Create the rings of a partition, closed once every sender is dropped.
+/// The policy must not use [`DropPolicy::DropOldest`].
+pub(crate) fn channel(policy: &ChannelPolicy) -> (RingSender, RingReceiver) {
+    let mut routes = vec![0; CHANNELS.len()];
+    for channel in CHANNELS {
+        routes[channel as usize] = policy.class_of(Some(channel));
+    }
+    let classes: Vec<_> = policy
+        .classes
+        .iter()
+        .map(|class| Class {
+            name: class.name.clone(),
+            policy: class.policy,
+            queue: match class.policy {
+                DropPolicy::Conflate => Queue::Latest(Latest::new(class.buffer)),
+                _ => Queue::Ring(Ring::new(class.buffer)),
+            },
+        })
+        .collect();
+    let shared = Arc::new(Shared {
+        producer: UnsafeCell::new(Producer {
+            streams: vec![Vec::new(); classes.len()],
+        }),
+        classes,
+        routes,
+        fallback: policy.class_of(None),
+        order: policy.drain_order(),
+        producing: AtomicBool::new(false),
+        turn: Notify::new(),
+        consumer_parked: AtomicBool::new(false),
+        data: Notify::new(),
+        producer_parked: AtomicBool::new(false),
+        space: Notify::new(),
+        senders: AtomicUsize::new(1),
+        closed: AtomicBool::new(false),
+    });
+    (
+        RingSender {
+            shared: shared.clone(),
+        },
+        RingReceiver {
+            shared,
+            batch: VecDeque::with_capacity(BATCH),
+        },
+    )
+}
+
+pub(crate) struct RingSender {
+    shared: Arc<Shared>,
+}
+
+impl RingSender {
+    /// Queue `msg` in its class, waiting for room if the class blocks.
+    /// Fails only once the receiver is gone.
+    pub async fn send(
+        &self,
+        msg: StreamMessage<'static>,
+    ) -> Result<(), SendError<StreamMessage<'static>>> {
+        let shared = &*self.shared;
+        let metrics_enabled = core::config::metrics_enabled();
+        let channel = msg.data.channel();
+        let index = shared.class_index(channel);
+        let class = &shared.classes[index];
+        let mut producing = shared.produce().await;
+        let mut msg = msg;
+        let mut waited = false;
+        let mut parked = false;
+        loop {
+            if shared.closed.load(Ordering::Acquire) {
+                return Err(SendError(msg));
+            }
+            let ring = match &class.queue {
+                Queue::Ring(ring) => ring,
+                Queue::Latest(latest) => {
+                    let streams = &mut producing.producer().streams[index];
+                    // A partition serves one symbol, so only a handful of
+                    // streams are ever seen and a scan beats hashing the
+                    // stream name.
+                    let slot = match streams
+                        .iter()
+                        .position(|(c, s)| *c == channel && *s == msg.stream)
+                    {
+                        Some(slot) => slot,
+                        None if streams.len() < latest.slots.len() => {
+                            streams.push((channel, msg.stream.clone()));
+                            streams.len() - 1
+                        }
+                        None => {
+                            count_drop(class, metrics_enabled);
+                            return Ok(());
+                        }
+                    };
+                    // SAFETY: `producing` makes this the only producer.
+                    if unsafe { latest.put(slot, msg) } {
+                        if metrics_enabled {
+                            let channel = channel.map_or("other", |c| c.as_str());
+                            metrics::counter!("md_conflated_total", "channel" => channel)
+                                .increment(1);
+                        }
+                    } else {
+                        shared.wake_consumer();
+                    }
+                    return Ok(());
+                }
+            };
+            // SAFETY: `producing` makes this the only producer.
+            match unsafe { ring.push(msg) } {
+                Ok(()) => {
+                    if metrics_enabled {
+                        metrics::gauge!("adapter_queue_depth", "channel" => class.name.clone())
+                            .set(ring.len() as f64);
+                    }
+                    shared.wake_consumer();
+                    return Ok(());
+                }
+                Err(back) if class.policy == DropPolicy::DropNewest => {
+                    drop(back);
+                    count_drop(class, metrics_enabled);
+                    return Ok(());
+                }
+                Err(back) => {
+                    msg = back;
+                    if !waited && metrics_enabled {
+                        metrics::counter!(
+                            "md_backpressure_waits_total",
+                            "channel" => class.name.clone()
+                        )
+                        .increment(1);
+                    }
+                    waited = true;
+                    if parked {
+                        shared.space.notified().await;
+                        parked = false;
+                    } else {
+                        // Park, then retry once before waiting, so room made
+                        // in between is not missed.
+                        shared.producer_parked.store(true, Ordering::SeqCst);
+                        fence(Ordering::SeqCst);
+                        parked = true;
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn count_drop(class: &Class, metrics_enabled: bool) {
+    if metrics_enabled {
+        metrics::counter!("md_backpressure_drops_total", "channel" => class.name.clone())
+            .increment(1);
+    }
+}
+
+impl Clone for RingSender {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl Drop for RingSender {
+    fn drop(&mut self) {
+        if self.shared.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.shared.closed.store(true, Ordering::Release);
+            self.shared.data.notify_one();
+        }
+    }
+}
+
+pub(crate) struct RingReceiver {
+    shared: Arc<Shared>,
+    /// Messages taken from the rings but not yet returned by `recv`.
+    batch: VecDeque<StreamMessage<'static>>,
+}
+
+impl RingReceiver {
+    /// Wait for the next message, taking a batch from the rings when the
+    /// previous one is used up. Returns `None` once every sender is gone and
+    /// the rings are empty.
+    pub async fn recv(&mut self) -> Option<StreamMessage<'static>> {
+        if self.batch.is_empty() {
+            let mut batch = std::mem::take(&mut self.batch);
+            self.recv_many(&mut batch, BATCH).await;
+            self.batch = batch;
+        }
+        self.batch.pop_front()
+    }
+
+    /// Wait for messages and move up to `limit` of them into `buf`, highest
+    /// priority class first. Returns `0` once every sender is gone and the
+    /// rings are empty.
+    pub async fn recv_many(
+        &mut self,
+        buf: &mut impl Extend<StreamMessage<'static>>,
+        limit: usize,
+    ) -> usize {
+        let mut taken = 0;
+        while taken < limit {
+            let Some(msg) = self.batch.pop_front() else {
+                break;
+            };
+            buf.extend(Some(msg));
+            taken += 1;
+        }
+        if taken > 0 || limit == 0 {
+            return taken;
+        }
+        let shared = &*self.shared;
+        loop {
+            // Read before draining, so messages queued by the last sender
+            // are still received.
+            let closed = shared.closed.load(Ordering::Acquire);
+            let taken = self.drain(buf, limit);
+            if taken > 0 || closed {
+                return taken;
+            }
+            // Park, then drain once more before waiting, so messages queued
+            // in between are not missed.
+            shared.consumer_parked.store(true, Ordering::SeqCst);
+            fence(Ordering::SeqCst);
+            let taken = self.drain(buf, limit);
+            if taken > 0 {
+                shared.consumer_parked.store(false, Ordering::Relaxed);
+                return taken;
+            }
+            if !shared.closed.load(Ordering::Acquire) {
+                shared.data.notified().await;
+            }
+        }
+    }
+
+    fn drain(&self, buf: &mut impl Extend<StreamMessage<'static>>, limit: usize) -> usize {
+        let shared = &*self.shared;
+        let mut taken = 0;
+        for &index in &shared.order {
+            let class = &shared.classes[index];
+            while taken < limit {
+                // SAFETY: `&mut self` on every caller makes this the only
+                // consumer.
+                let msg = match &class.queue {
+                    Queue::Ring(ring) => unsafe { ring.pop() },
+                    Queue::Latest(latest) => unsafe { latest.take() },
+                };
+                let Some(msg) = msg else {
+                    break;
+                };
+                buf.extend(Some(msg));
+                taken += 1;
+            }
+        }
+        if taken > 0 {
+            shared.wake_producer();
+            if core::config::metrics_enabled() {
+                self.record_depths();
+            }
+        }
+        taken
+    }
+
+    /// Messages waiting in the rings.
+    pub fn len(&self) -> usize {
+        self.batch.len() + self.shared.classes.iter().map(Class::len).sum::<usize>()
+    }
+
+    /// Report the length of each class ring as `md_queue_depth`.
+    pub fn record_depths(&self) {
+        for class in &self.shared.classes {
+            metrics::gauge!("md_queue_depth", "channel" => class.name.clone())
+                .set(class.len() as f64);
+        }
+    }
+}
+
+impl Drop for RingReceiver {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Release);
+        self.shared.space.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring_wraps_and_reports_full() {
+        let ring = Ring::new(3);
+        unsafe {
+            for round in 0..3 {
+                for i in 0..4 {
+                    ring.push(round * 4 + i).unwrap();
+                }
+                assert_eq!(ring.push(99), Err(99));
+                assert_eq!(ring.len(), 4);
+                for i in 0..4 {
+                    assert_eq!(ring.pop(), Some(round * 4 + i));
+                }
+                assert_eq!(ring.pop(), None);
+            }
+        }
+    }
+}
@@ -2,8 +2,11 @@ use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Duration;
 
-use agents::adapter::binance::{connect_via_socks5, fetch_symbols, process_text_message};
-use agents::ChannelRegistry;
+use agents::adapter::binance::{
+    connect_via_socks5, fetch_instruments, fetch_symbols, process_text_message,
+};
+use canonical::symbol::VenueType;
+use agents::{frame::Frame, ChannelRegistry};
 use arb_core as core;
 use arb_core::rate_limit::TokenBucket;
 use dashmap::DashMap;
@@ -69,6 +72,7 @@ async fn process_text_message_updates_book_and_metrics() {
 
     process_text_message(
         json.to_string(),
+        &mut Frame::new(),
         &books,
         &channels,
         &Client::new(),
@@ -119,6 +123,56 @@ async fn fetch_symbols_uses_provided_client() {
     mock.assert();
 }
 
+#[tokio::test]
+async fn fetch_instruments_reads_contract_metadata() {
+    let server = MockServer::start();
+
+    let mock = server.mock(|when, then| {
+        when.method(GET).path("/exchangeInfo");
+        then.status(200).json_body(json!({
+            "symbols": [
+                {
+                    "symbol": "BTCUSDT",
+                    "status": "TRADING",
+                    "baseAsset": "BTC",
+                    "quoteAsset": "USDT",
+                    "filters": [
+                        {"filterType": "PRICE_FILTER", "tickSize": "0.01"},
+                        {"filterType": "LOT_SIZE", "stepSize": "0.00001"}
+                    ]
+                },
+                {
+                    "symbol": "BTCUSD_250926",
+                    "contractStatus": "TRADING",
+                    "contractType": "CURRENT_QUARTER",
+                    "deliveryDate": 1758873600000u64,
+                    "contractSize": 100,
+                    "baseAsset": "BTC",
+                    "quoteAsset": "USD",
+                    "marginAsset": "BTC",
+                    "filters": []
+                }
+            ]
+        }));
+    });
+
+    let url = format!("{}/exchangeInfo", server.base_url());
+    let instruments = fetch_instruments(&Client::new(), &url).await.unwrap();
+    mock.assert();
+
+    assert_eq!(instruments.len(), 2);
+    let spot = &instruments[0];
+    assert_eq!(spot.spec.venue, VenueType::Spot);
+    assert_eq!(spot.spec.price_step, Some(0.01));
+    assert_eq!(spot.spec.lot_step, Some(0.00001));
+    assert_eq!(spot.id(), "BTC-USDT");
+
+    let future = &instruments[1];
+    assert_eq!(future.spec.venue, VenueType::Futures);
+    assert_eq!(future.spec.contract_size, Some(100.0));
+    assert_eq!(future.id(), "BTC-USD-20250926:BTC");
+}
+
 #[tokio::test]
 async fn fetch_symbols_reuses_client_connections() {
     use std::convert::Infallible;
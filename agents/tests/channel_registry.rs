@@ -1,4 +1,13 @@
-use agents::ChannelRegistry;
+use agents::{spawn_adapters, ChannelRegistry, TaskSet};
+use arb_core::channel_policy::{ChannelClass, ChannelPolicy, DropPolicy, Transport};
+use arb_core::events::{Channel, Event, StreamMessage};
+use reqwest::Client;
+use rustls::{ClientConfig, RootCertStore};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::task::JoinSet;
+use tokio::time::timeout;
 
 #[tokio::test]
 async fn channels_only_created_when_subscribed() {
@@ -18,3 +27,351 @@ async fn channels_only_created_when_subscribed() {
     assert!(rx2.is_none());
     assert_eq!(registry.len(), 1);
 }
+
+fn message(json: &str) -> StreamMessage<'static> {
+    let mut bytes = json.as_bytes().to_vec();
+    simd_json::serde::from_slice::<StreamMessage<'_>>(&mut bytes)
+        .unwrap()
+        .into_owned()
+}
+
+fn book_ticker(update_id: u64) -> StreamMessage<'static> {
+    let json = format!(
+        r#"{{"stream":"btcusdt@bookTicker","data":{{"e":"bookTicker","u":{update_id},"s":"BTCUSDT","b":"1","B":"1","a":"2","A":"1"}}}}"#
+    );
+    message(&json)
+}
+
+fn trade(id: u64) -> StreamMessage<'static> {
+    let json = format!(
+        r#"{{"stream":"btcusdt@trade","data":{{"e":"trade","E":1,"s":"BTCUSDT","t":{id},"p":"1","q":"1","b":1,"a":2,"T":1,"m":true,"M":true}}}}"#
+    );
+    message(&json)
+}
+
+fn depth_update(update_id: u64) -> StreamMessage<'static> {
+    let json = format!(
+        r#"{{"stream":"btcusdt@depth","data":{{"e":"depthUpdate","E":1,"s":"BTCUSDT","U":{update_id},"u":{update_id},"pu":0,"b":[["1.0","2.0"]],"a":[]}}}}"#
+    );
+    message(&json)
+}
+
+#[tokio::test]
+async fn ticker_behind_depth_backlog_is_conflated_and_delivered_first() {
+    let class = |name: &str, channels: &[Channel], priority, policy| ChannelClass {
+        name: name.into(),
+        channels: channels.to_vec(),
+        buffer: 64,
+        priority,
+        policy,
+    };
+    let policy = ChannelPolicy {
+        classes: vec![
+            class("ticker", &[Channel::Book], 0, DropPolicy::Conflate),
+            class("rest", &[], 1, DropPolicy::Block),
+        ],
+        fallback: "rest".into(),
+    };
+    let registry = ChannelRegistry::with_policy(policy).unwrap();
+    let (tx, rx) = registry.get_or_create("Test:BTCUSDT");
+    let mut rx = rx.unwrap();
+
+    for id in 0..10 {
+        tx.send(depth_update(id)).await.unwrap();
+    }
+    // Give anything forwarding in the background time to move the backlog.
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    tx.send(book_ticker(1)).await.unwrap();
+    tx.send(book_ticker(2)).await.unwrap();
+
+    match rx.recv().await.unwrap().data {
+        Event::BookTicker(ev) => assert_eq!(ev.update_id, 2),
+        other => panic!("unexpected event {other:?}"),
+    }
+    let mut rest = Vec::new();
+    assert_eq!(rx.recv_many(&mut rest, 64).await, 10);
+    assert!(rest.iter().all(|m| matches!(m.data, Event::DepthUpdate(_))));
+    assert!(rx.is_empty());
+}
+
+#[tokio::test]
+async fn conflates_book_tickers_per_stream() {
+    let registry = ChannelRegistry::new(1);
+    let (tx, rx) = registry.get_or_create("Test:BTCUSDT");
+    let mut rx = rx.unwrap();
+
+    tx.send(book_ticker(1)).await.unwrap();
+    tx.send(book_ticker(2)).await.unwrap();
+    tx.send(message(
+        r#"{"stream":"btcusdt@miniTicker","data":{"e":"24hrMiniTicker","E":1,"s":"BTCUSDT","c":"1","o":"1","h":"1","l":"1","v":"1","q":"1"}}"#,
+    ))
+    .await
+    .unwrap();
+    tx.send(book_ticker(3)).await.unwrap();
+
+    // The newest book ticker keeps its stream's place ahead of the ticker.
+    match rx.recv().await.unwrap().data {
+        Event::BookTicker(ev) => assert_eq!(ev.update_id, 3),
+        other => panic!("unexpected event {other:?}"),
+    }
+    assert!(matches!(rx.recv().await.unwrap().data, Event::MiniTicker(_)));
+    assert!(timeout(Duration::from_millis(20), rx.recv()).await.is_err());
+}
+
+#[tokio::test]
+async fn trades_wait_for_room_instead_of_dropping() {
+    let registry = ChannelRegistry::new(1);
+    let (tx, rx) = registry.get_or_create("Test:BTCUSDT");
+    let mut rx = rx.unwrap();
+
+    let sender = tokio::spawn(async move {
+        for id in 0..10 {
+            tx.send(trade(id)).await.unwrap();
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert!(!sender.is_finished());
+
+    for id in 0..10 {
+        match rx.recv().await.unwrap().data {
+            Event::Trade(ev) => assert_eq!(ev.trade_id, id),
+            other => panic!("unexpected event {other:?}"),
+        }
+    }
+    sender.await.unwrap();
+}
+
+#[tokio::test]
+async fn exchange_policy_sets_drop_policy_and_priority() {
+    let class = |name: &str, channels: &[Channel], priority, policy| ChannelClass {
+        name: name.into(),
+        channels: channels.to_vec(),
+        buffer: 2,
+        priority,
+        policy,
+    };
+    let policy = ChannelPolicy {
+        classes: vec![
+            class("trade", &[Channel::Trade], 1, DropPolicy::DropOldest),
+            class("ticker", &[Channel::Book], 0, DropPolicy::Conflate),
+            class("rest", &[], 2, DropPolicy::Block),
+        ],
+        fallback: "rest".into(),
+    };
+    let registry = ChannelRegistry::new(1)
+        .with_exchange_policy("binance_futures", policy)
+        .unwrap();
+    let (tx, rx) = registry.get_or_create("binance_futures:BTCUSDT");
+    let mut rx = rx.unwrap();
+
+    for id in 0..5 {
+        tx.send(trade(id)).await.unwrap();
+    }
+    tx.send(book_ticker(1)).await.unwrap();
+
+    // Book tickers drain first, and only the two newest trades are kept.
+    assert!(matches!(rx.recv().await.unwrap().data, Event::BookTicker(_)));
+    for id in 3..5 {
+        match rx.recv().await.unwrap().data {
+            Event::Trade(ev) => assert_eq!(ev.trade_id, id),
+            other => panic!("unexpected event {other:?}"),
+        }
+    }
+    assert!(timeout(Duration::from_millis(20), rx.recv()).await.is_err());
+
+    // Other exchanges keep the default policy, where trades block.
+    let (tx, _rx) = registry.get_or_create("gateio_spot:BTC_USDT");
+    tx.send(trade(0)).await.unwrap();
+    assert!(timeout(Duration::from_millis(20), async {
+        for id in 1..20 {
+            tx.send(trade(id)).await.unwrap();
+        }
+    })
+    .await
+    .is_err());
+}
+
+#[test]
+fn rejects_invalid_exchange_policy() {
+    let mut policy = ChannelPolicy::new(8);
+    policy.fallback = "missing".into();
+    assert!(ChannelRegistry::new(1)
+        .with_exchange_policy("binance_futures", policy)
+        .is_err());
+}
+
+#[test]
+fn rejects_policy_for_unknown_exchange() {
+    // Overrides are keyed by adapter id, not display name.
+    assert!(ChannelRegistry::new(1)
+        .with_exchange_policy("LBank", ChannelPolicy::new(8))
+        .is_err());
+    assert!(ChannelRegistry::new(1)
+        .with_exchange_policy("lbank_spot", ChannelPolicy::new(8))
+        .is_ok());
+}
+
+#[tokio::test]
+async fn exchange_policy_applies_when_name_differs_from_id() {
+    std::env::set_var("API_KEY", "k");
+    std::env::set_var("API_SECRET", "s");
+    std::env::set_var("EXCHANGES", "lbank");
+    std::env::set_var("LBANK_SPOT_SYMBOLS", "btc_usdt");
+    let cfg = arb_core::config::Config::from_env().unwrap();
+    let cfg: &'static arb_core::config::Config = Box::leak(Box::new(cfg));
+
+    let policy = ChannelPolicy {
+        classes: vec![ChannelClass {
+            name: "all".into(),
+            channels: Vec::new(),
+            buffer: 1,
+            priority: 0,
+            policy: DropPolicy::DropNewest,
+        }],
+        fallback: "all".into(),
+    };
+    let registry = ChannelRegistry::new(1)
+        .with_exchange_policy("lbank_spot", policy)
+        .unwrap();
+    let tls_config = Arc::new(
+        ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(RootCertStore::empty())
+            .with_no_client_auth(),
+    );
+    let task_set: TaskSet = Arc::new(Mutex::new(JoinSet::new()));
+    let receivers = spawn_adapters(cfg, Client::new(), task_set, registry.clone(), tls_config)
+        .await
+        .unwrap();
+    assert_eq!(receivers.len(), 1);
+
+    // The LBank adapter ("LBank") keys its partition by id, so the
+    // override drops instead of blocking.
+    assert!(registry.get("LBank:btc_usdt").is_none());
+    let tx = registry.get("lbank_spot:btc_usdt").unwrap();
+    timeout(Duration::from_millis(200), async {
+        for id in 0..20 {
+            tx.send(trade(id)).await.unwrap();
+        }
+    })
+    .await
+    .unwrap();
+}
+
+fn ring_registry(buffer: usize) -> ChannelRegistry {
+    ChannelRegistry::new(buffer)
+        .with_transport(Transport::Ring)
+        .unwrap()
+}
+
+#[tokio::test]
+async fn ring_transport_conflates_and_drains_by_priority() {
+    let registry = ring_registry(1);
+    let (tx, rx) = registry.get_or_create("Test:BTCUSDT");
+    let mut rx = rx.unwrap();
+
+    tx.send(book_ticker(1)).await.unwrap();
+    tx.send(trade(7)).await.unwrap();
+    tx.send(book_ticker(2)).await.unwrap();
+    assert_eq!(rx.len(), 2);
+
+    // Trades drain before book tickers, which keep only the newest.
+    let mut batch = Vec::new();
+    assert_eq!(rx.recv_many(&mut batch, 16).await, 2);
+    match &batch[0].data {
+        Event::Trade(ev) => assert_eq!(ev.trade_id, 7),
+        other => panic!("unexpected event {other:?}"),
+    }
+    match &batch[1].data {
+        Event::BookTicker(ev) => assert_eq!(ev.update_id, 2),
+        other => panic!("unexpected event {other:?}"),
+    }
+    assert!(timeout(Duration::from_millis(20), rx.recv()).await.is_err());
+
+    drop(tx);
+    drop(registry);
+    assert!(rx.recv().await.is_none());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn ring_transport_delivers_every_trade_in_order() {
+    let registry = ring_registry(8);
+    let (tx, rx) = registry.get_or_create("Test:BTCUSDT");
+    let mut rx = rx.unwrap();
+    drop(registry);
+
+    const COUNT: u64 = 20_000;
+    let sender = tokio::spawn(async move {
+        for id in 0..COUNT {
+            tx.send(trade(id)).await.unwrap();
+        }
+    });
+    let mut next = 0;
+    while let Some(msg) = rx.recv().await {
+        match msg.data {
+            Event::Trade(ev) => assert_eq!(ev.trade_id, next),
+            other => panic!("unexpected event {other:?}"),
+        }
+        next += 1;
+    }
+    assert_eq!(next, COUNT);
+    sender.await.unwrap();
+}
+
+#[tokio::test]
+async fn ring_transport_fails_sends_once_receiver_is_gone() {
+    let registry = ring_registry(1);
+    let (tx, rx) = registry.get_or_create("Test:BTCUSDT");
+    tx.send(trade(0)).await.unwrap();
+    let blocked = tokio::spawn({
+        let tx = tx.clone();
+        async move { tx.send(trade(1)).await }
+    });
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert!(!blocked.is_finished());
+
+    drop(rx);
+    assert!(blocked.await.unwrap().is_err());
+    assert!(tx.send(trade(2)).await.is_err());
+}
+
+#[tokio::test]
+async fn ring_transport_senders_wait_their_turn() {
+    let registry = ring_registry(1);
+    let (tx, rx) = registry.get_or_create("Test:BTCUSDT");
+    let mut rx = rx.unwrap();
+    tx.send(trade(0)).await.unwrap();
+    // Parks on the full ring while holding the turn.
+    let first = tokio::spawn({
+        let tx = tx.clone();
+        async move { tx.send(trade(1)).await }
+    });
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    // Waits for the first sender to finish.
+    let second = tokio::spawn({
+        let tx = tx.clone();
+        async move { tx.send(trade(2)).await }
+    });
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert!(!first.is_finished());
+    assert!(!second.is_finished());
+
+    let mut seen = Vec::new();
+    while seen.len() < 3 {
+        match rx.recv().await.unwrap().data {
+            Event::Trade(ev) => seen.push(ev.trade_id),
+            other => panic!("unexpected event {other:?}"),
+        }
+    }
+    assert_eq!(seen, [0, 1, 2]);
+    first.await.unwrap().unwrap();
+    second.await.unwrap().unwrap();
+}
+
+#[test]
+fn ring_transport_rejects_drop_oldest() {
+    let mut policy = ChannelPolicy::new(8);
+    policy.classes[1].policy = DropPolicy::DropOldest;
+    let registry = ChannelRegistry::new(8).with_transport(Transport::Ring).unwrap();
+    assert!(registry.with_exchange_policy("binance_futures", policy).is_err());
+}
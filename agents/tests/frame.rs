@@ -0,0 +1,138 @@
+use agents::adapter::{bitget, latoken, xt};
+use agents::frame::Frame;
+use arb_core::events::Event;
+
+#[test]
+fn bitget_frames_parse_through_one_buffer() {
+    let mut frame = Frame::new();
+
+    let trade = r#"{"action":"update","arg":{"instType":"SPOT","channel":"trade","instId":"BTCUSDT"},"data":[{"ts":"1695709835822","price":"26293.4","size":"0.0013","side":"sell","tradeId":"7"}]}"#;
+    let msg = bitget::parse_message(&mut frame, trade).unwrap();
+    assert_eq!(msg.stream, "BTCUSDT@trade");
+    match msg.data {
+        Event::Trade(ev) => {
+            assert_eq!(ev.trade_id, 7);
+            assert_eq!(ev.price, "26293.4");
+            assert_eq!(ev.quantity, "0.0013");
+            assert!(ev.buyer_is_maker);
+        }
+        other => panic!("unexpected event {other:?}"),
+    }
+
+    let depth = r#"{"arg":{"channel":"depth","instId":"BTCUSDT"},"data":[{"ts":"1","bids":[["1.5","2"]],"asks":[["1.6","3"],["1.7","4"]]}]}"#;
+    match bitget::parse_message(&mut frame, depth).unwrap().data {
+        Event::DepthUpdate(ev) => {
+            assert_eq!(ev.bids, vec![["1.5", "2"]]);
+            assert_eq!(ev.asks.len(), 2);
+        }
+        other => panic!("unexpected event {other:?}"),
+    }
+
+    let candle = r#"{"arg":{"channel":"candle1m","instId":"BTCUSDT"},"data":[["1695709800000","1","3","0.5","2","10","20","20"]]}"#;
+    let msg = bitget::parse_message(&mut frame, candle).unwrap();
+    assert_eq!(msg.stream, "BTCUSDT@candle1m");
+    match msg.data {
+        Event::Kline(ev) => {
+            assert_eq!(ev.kline.interval, "1m");
+            assert_eq!(ev.kline.high, "3");
+            assert_eq!(ev.kline.volume, "10");
+        }
+        other => panic!("unexpected event {other:?}"),
+    }
+
+    let ack = r#"{"event":"subscribe","arg":{"channel":"trade","instId":"BTCUSDT"}}"#;
+    assert!(bitget::parse_message(&mut frame, ack).is_none());
+}
+
+#[test]
+fn xt_data_shape_selects_event() {
+    let mut frame = Frame::new();
+
+    let trades = r#"{"topic":"btc_usdt@trade","data":[{"i":1,"p":"1","q":"2","T":5},{"i":2,"p":"1.1","q":"3","T":6,"m":true}]}"#;
+    let msgs = xt::parse_message(&mut frame, trades).unwrap();
+    assert_eq!(msgs.len(), 2);
+    match &msgs[1].data {
+        Event::Trade(ev) => {
+            assert_eq!(ev.symbol, "btc_usdt");
+            assert_eq!(ev.trade_id, 2);
+            assert!(ev.buyer_is_maker);
+        }
+        other => panic!("unexpected event {other:?}"),
+    }
+
+    let kline =
+        r#"{"topic":"btc_usdt@kline","data":{"t":1,"o":"1","c":"2","h":"3","l":"0.5","v":"9"}}"#;
+    assert!(matches!(
+        xt::parse_message(&mut frame, kline).unwrap()[0].data,
+        Event::Kline(_)
+    ));
+
+    let ticker =
+        r#"{"topic":"btc_usdt@ticker","data":{"t":1,"bp":"1","bq":"2","ap":"3","aq":"4"}}"#;
+    match &xt::parse_message(&mut frame, ticker).unwrap()[0].data {
+        Event::BookTicker(ev) => assert_eq!(ev.best_ask_price, "3"),
+        other => panic!("unexpected event {other:?}"),
+    }
+
+    let depth = r#"{"topic":"btc_usdt@depth","data":{"t":1,"b":[["1","2"]],"a":[]}}"#;
+    match &xt::parse_message(&mut frame, depth).unwrap()[0].data {
+        Event::DepthUpdate(ev) => assert_eq!(ev.bids.len(), 1),
+        other => panic!("unexpected event {other:?}"),
+    }
+}
+
+#[test]
+fn latoken_accepts_data_before_topic_and_unescapes_strings() {
+    let mut frame = Frame::new();
+
+    let trade = r#"{"data":{"timestamp":5,"price":"1.5","volume":"2","maker":true},"symbol":"BTC\/USDT","topic":"trade"}"#;
+    let msg = latoken::parse_message(&mut frame, trade).unwrap();
+    assert_eq!(msg.stream, "BTC/USDT@trade");
+    match msg.data {
+        Event::Trade(ev) => {
+            assert_eq!(ev.event_time, 5);
+            assert_eq!(ev.quantity, "2");
+            assert!(ev.buyer_is_maker);
+        }
+        other => panic!("unexpected event {other:?}"),
+    }
+
+    let kline = r#"{"topic":"kline","symbol":"BTC-USDT","data":{"t":1,"o":"1","c":"2","h":"3","l":"0.5","volume":"9"}}"#;
+    match latoken::parse_message(&mut frame, kline).unwrap().data {
+        Event::Kline(ev) => assert_eq!(ev.kline.volume, "9"),
+        other => panic!("unexpected event {other:?}"),
+    }
+
+    assert!(latoken::parse_message(&mut frame, r#"{"ping":123}"#).is_none());
+}
+
+#[test]
+fn binance_events_borrow_from_the_frame() {
+    use arb_core::events::StreamMessage;
+    use std::borrow::Cow;
+
+    let mut frame = Frame::new();
+    let depth = r#"{"stream":"btcusdt@depth","data":{"e":"depthUpdate","E":1,"s":"BTCUSDT","U":2,"u":3,"pu":1,"b":[["1.0","2.0"]],"a":[["2.0","3.0"]]}}"#;
+    let msg: StreamMessage<'_> = frame.parse_in_place(depth.to_string()).unwrap();
+    match &msg.data {
+        Event::DepthUpdate(ev) => {
+            assert!(matches!(ev.bids[0][0], Cow::Borrowed("1.0")));
+            assert!(matches!(ev.asks[0][1], Cow::Borrowed("3.0")));
+        }
+        other => panic!("unexpected event {other:?}"),
+    }
+    match msg.into_owned().data {
+        Event::DepthUpdate(ev) => assert_eq!(ev.bids, vec![["1.0", "2.0"]]),
+        other => panic!("unexpected event {other:?}"),
+    }
+
+    let trade = r#"{"stream":"btcusdt@trade","data":{"e":"trade","E":1,"s":"BTCUSDT","t":1,"p":"0.001","q":"100","b":1,"a":2,"T":1,"m":true,"M":true}}"#;
+    match frame
+        .parse_in_place::<StreamMessage<'_>>(trade.to_string())
+        .unwrap()
+        .data
+    {
+        Event::Trade(ev) => assert!(matches!(ev.price, Cow::Borrowed("0.001"))),
+        other => panic!("unexpected event {other:?}"),
+    }
+}
@@ -1,6 +1,6 @@
-use agents::adapter::lbank::{parse_depth_frame, parse_trade_frame};
+use agents::adapter::lbank::parse_message;
+use agents::frame::Frame;
 use arb_core::events::Event;
-use serde_json::Value;
 
 #[test]
 fn lbank_parse_trade_and_print() {
@@ -11,8 +11,7 @@ fn lbank_parse_trade_and_print() {
         "SERVER":"V2",
         "TS":"2019-06-28T19:55:49.466"
     }"#;
-    let v: Value = serde_json::from_str(raw).unwrap();
-    let msg = parse_trade_frame(&v).unwrap();
+    let msg = parse_message(&mut Frame::new(), raw).unwrap();
     if let Event::Trade(ev) = &msg.data {
         assert_eq!(ev.price, "12129");
         assert_eq!(ev.quantity, "6.3607");
@@ -41,8 +40,7 @@ fn lbank_parse_depth_and_print() {
         "SERVER":"V2",
         "TS":"2019-06-28T17:49:22.722"
     }"#;
-    let v: Value = serde_json::from_str(raw).unwrap();
-    let msg = parse_depth_frame(&v).unwrap();
+    let msg = parse_message(&mut Frame::new(), raw).unwrap();
     if let Event::DepthUpdate(ev) = &msg.data {
         assert_eq!(ev.bids.len(), 2);
         assert_eq!(ev.asks.len(), 2);
@@ -16,7 +16,7 @@ use arb_core as core;
 use async_trait::async_trait;
 use reqwest::Client;
 use rustls::{ClientConfig, RootCertStore};
-use tokio::{sync::{mpsc, Mutex}, task::JoinSet};
+use tokio::{sync::Mutex, task::JoinSet};
 use serial_test::serial;
 
 struct TestAdapter {
@@ -57,10 +57,10 @@ async fn spawn_adapters_ok() {
     // Register a dummy adapter that returns a single receiver.
     registry::register_adapter(
         "test",
-        Arc::new(|_, _, _, _, _, _| {
-            Box::pin(async {
-                let (_tx, rx) = mpsc::channel(1);
-                Ok(vec![rx])
+        Arc::new(|_, _, _, _, channels: ChannelRegistry, _| {
+            Box::pin(async move {
+                let (_tx, rx) = channels.get_or_create("Test:BTCUSDT");
+                Ok(rx.into_iter().collect())
             })
         }),
     );
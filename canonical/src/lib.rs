@@ -2,8 +2,11 @@ use rust_decimal::prelude::ToPrimitive;
 use serde::{Deserialize, Serialize};
 use std::convert::TryFrom;
 
+pub mod sbe;
 pub mod symbol;
-pub use symbol::{normalize_symbol, ContractSpec, SymbolId, VenueType};
+mod validation;
+pub use symbol::{normalize_symbol, ContractSpec, InstrumentId, SymbolId, VenueType};
+pub use validation::{ValidationError, ValidationRules};
 
 pub use arb_core::events;
 use arb_core::events::Channel;
@@ -23,13 +26,16 @@ use events::{
     OpenInterestEvent, TradeEvent, XtEvent,
 };
 
-pub const SCHEMA_VERSION: u32 = 1;
+pub const SCHEMA_VERSION: u32 = 2;
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct MdEvent {
     pub schema_version: u32,
     #[serde(flatten)]
     pub event: MdEventKind,
+    /// Set when the event failed validation but was passed through.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub validation: Option<ValidationError>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -46,6 +52,7 @@ pub enum MdEventKind {
     FundingRate(FundingRate),
     OpenInterest(OpenInterest),
     Liquidation(Liquidation),
+    InstrumentUpdate(InstrumentUpdate),
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -448,6 +455,35 @@ impl Default for Liquidation {
     }
 }
 
+/// What happened to an instrument between two symbol table versions.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum InstrumentChange {
+    Added,
+    Removed,
+    /// The spec changed, e.g. a new tick or lot size.
+    Updated,
+}
+
+/// Emitted when a symbol table reload adds, removes or changes a venue
+/// instrument.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct InstrumentUpdate {
+    pub schema_version: u32,
+    pub exchange: String,
+    /// Venue-native symbol.
+    pub symbol: String,
+    /// Canonical id the symbol maps to.
+    pub instrument: SymbolId,
+    pub change: InstrumentChange,
+    /// Spec after the change; the last known spec for removals.
+    pub spec: Option<ContractSpec>,
+    pub ts: u64,
+    pub ingest_ts_monotonic: u64,
+    pub ingest_ts_utc: u64,
+    pub seq_no: u64,
+}
+
 impl<'a> From<TradeEvent<'a>> for Trade {
     fn from(ev: TradeEvent<'a>) -> Self {
         let side = if ev.buyer_is_maker {
@@ -485,6 +521,7 @@ impl<'a> From<TradeEvent<'a>> for MdEvent {
         MdEvent {
             schema_version: SCHEMA_VERSION,
             event: MdEventKind::Trade(ev.into()),
+            validation: None,
         }
     }
 }
@@ -544,6 +581,7 @@ impl<'a> From<DepthUpdateEvent<'a>> for MdEvent {
         MdEvent {
             schema_version: SCHEMA_VERSION,
             event: MdEventKind::DepthL2Update(ev.into()),
+            validation: None,
         }
     }
 }
@@ -588,6 +626,7 @@ impl<'a> From<BookTickerEvent<'a>> for MdEvent {
         MdEvent {
             schema_version: SCHEMA_VERSION,
             event: MdEventKind::BookTicker(ev.into()),
+            validation: None,
         }
     }
 }
@@ -645,6 +684,7 @@ impl<'a> From<MiniTickerEvent<'a>> for MdEvent {
         MdEvent {
             schema_version: SCHEMA_VERSION,
             event: MdEventKind::MiniTicker(ev.into()),
+            validation: None,
         }
     }
 }
@@ -691,6 +731,7 @@ impl<'a> From<KlineEvent<'a>> for MdEvent {
         MdEvent {
             schema_version: SCHEMA_VERSION,
             event: MdEventKind::Kline(ev.into()),
+            validation: None,
         }
     }
 }
@@ -738,6 +779,7 @@ impl From<CoreDepthSnapshot> for MdEvent {
         MdEvent {
             schema_version: SCHEMA_VERSION,
             event: MdEventKind::DepthSnapshot(s.into()),
+            validation: None,
         }
     }
 }
@@ -765,6 +807,7 @@ impl<'a> From<TickerEvent<'a>> for MdEvent {
         MdEvent {
             schema_version: SCHEMA_VERSION,
             event: MdEventKind::AvgPrice(ev.into()),
+            validation: None,
         }
     }
 }
@@ -791,6 +834,7 @@ impl<'a> From<MarkPriceEvent<'a>> for MdEvent {
         MdEvent {
             schema_version: SCHEMA_VERSION,
             event: MdEventKind::MarkPrice(ev.into()),
+            validation: None,
         }
     }
 }
@@ -817,6 +861,7 @@ impl<'a> From<IndexPriceEvent<'a>> for MdEvent {
         MdEvent {
             schema_version: SCHEMA_VERSION,
             event: MdEventKind::IndexPrice(ev.into()),
+            validation: None,
         }
     }
 }
@@ -843,6 +888,7 @@ impl<'a> From<FundingRateEvent<'a>> for MdEvent {
         MdEvent {
             schema_version: SCHEMA_VERSION,
             event: MdEventKind::FundingRate(ev.into()),
+            validation: None,
         }
     }
 }
@@ -869,6 +915,7 @@ impl<'a> From<OpenInterestEvent<'a>> for MdEvent {
         MdEvent {
             schema_version: SCHEMA_VERSION,
             event: MdEventKind::OpenInterest(ev.into()),
+            validation: None,
         }
     }
 }
@@ -903,14 +950,16 @@ impl<'a> From<ForceOrderEvent<'a>> for MdEvent {
         MdEvent {
             schema_version: SCHEMA_VERSION,
             event: MdEventKind::Liquidation(ev.into()),
+            validation: None,
         }
     }
 }
 
-impl<'a> TryFrom<Event<'a>> for MdEvent {
-    type Error = ();
-    fn try_from(ev: Event<'a>) -> Result<Self, Self::Error> {
-        let md = match ev {
+impl MdEvent {
+    /// Convert a stream event without validating it, for callers that apply
+    /// their own validation policy. Returns `None` for unsupported events.
+    pub fn normalize(ev: Event<'_>) -> Option<Self> {
+        Some(match ev {
             Event::Trade(e) => MdEvent::from(e),
             Event::DepthUpdate(e) => MdEvent::from(e),
             Event::BookTicker(e) => MdEvent::from(e),
@@ -922,9 +971,15 @@ impl<'a> TryFrom<Event<'a>> for MdEvent {
             Event::FundingRate(e) => MdEvent::from(e),
             Event::OpenInterest(e) => MdEvent::from(e),
             Event::ForceOrder(e) => MdEvent::from(e),
-            _ => return Err(()),
-        };
+            _ => return None,
+        })
+    }
+}
 
+impl<'a> TryFrom<Event<'a>> for MdEvent {
+    type Error = ();
+    fn try_from(ev: Event<'a>) -> Result<Self, Self::Error> {
+        let md = MdEvent::normalize(ev).ok_or(())?;
         md.validate().map_err(|_| ())?;
         Ok(md)
     }
@@ -954,6 +1009,7 @@ impl<'a> TryFrom<BingxStreamMessage<'a>> for MdEvent {
                         side,
                         ..Default::default()
                     }),
+                    validation: None,
                 })
             }
             BingxStreamMessage::DepthUpdate(d) => {
@@ -994,6 +1050,7 @@ impl<'a> TryFrom<BingxStreamMessage<'a>> for MdEvent {
                         previous_final_update_id: None,
                         ..Default::default()
                     }),
+                    validation: None,
                 })
             }
             BingxStreamMessage::Unknown => Err(()),
@@ -1032,6 +1089,7 @@ impl<'a> TryFrom<BitgetStreamMessage<'a>> for MdEvent {
                     side,
                     ..Default::default()
                 }),
+                validation: None,
             })
         } else if channel == "depth" {
             let d: BitgetDepthEvent = serde_json::from_value(v).map_err(|_| ())?;
@@ -1072,6 +1130,7 @@ impl<'a> TryFrom<BitgetStreamMessage<'a>> for MdEvent {
                     previous_final_update_id: None,
                     ..Default::default()
                 }),
+                validation: None,
             })
         } else if channel == "ticker" {
             let t: BitgetTickerEvent = serde_json::from_value(v).map_err(|_| ())?;
@@ -1087,6 +1146,7 @@ impl<'a> TryFrom<BitgetStreamMessage<'a>> for MdEvent {
                     ask_quantity: t.ask_qty.parse().ok().ok_or(())?,
                     ..Default::default()
                 }),
+                validation: None,
             })
         } else if channel.starts_with("candle") {
             let arr = v.as_array().ok_or(())?;
@@ -1112,6 +1172,7 @@ impl<'a> TryFrom<BitgetStreamMessage<'a>> for MdEvent {
                     volume,
                     ..Default::default()
                 }),
+                validation: None,
             })
         } else {
             Err(())
@@ -1148,6 +1209,7 @@ impl<'a> TryFrom<BitmartStreamMessage<'a>> for MdEvent {
                     side,
                     ..Default::default()
                 }),
+                validation: None,
             })
         } else if table.contains("depth") {
             let d: BitmartDepthEvent = serde_json::from_value(v).map_err(|_| ())?;
@@ -1188,6 +1250,7 @@ impl<'a> TryFrom<BitmartStreamMessage<'a>> for MdEvent {
                     previous_final_update_id: d.prev_version,
                     ..Default::default()
                 }),
+                validation: None,
             })
         } else if table.contains("ticker") {
             let t: BitmartTickerEvent = serde_json::from_value(v).map_err(|_| ())?;
@@ -1203,6 +1266,7 @@ impl<'a> TryFrom<BitmartStreamMessage<'a>> for MdEvent {
                     ask_quantity: t.best_ask_size.parse().ok().ok_or(())?,
                     ..Default::default()
                 }),
+                validation: None,
             })
         } else if table.contains("kline") {
             let k: BitmartKlineEvent = serde_json::from_value(v).map_err(|_| ())?;
@@ -1219,6 +1283,7 @@ impl<'a> TryFrom<BitmartStreamMessage<'a>> for MdEvent {
                     volume: k.volume.parse().ok().ok_or(())?,
                     ..Default::default()
                 }),
+                validation: None,
             })
         } else if table.contains("fundingRate") {
             let f: BitmartFundingRateEvent = serde_json::from_value(v).map_err(|_| ())?;
@@ -1231,6 +1296,7 @@ impl<'a> TryFrom<BitmartStreamMessage<'a>> for MdEvent {
                     rate: f.funding_rate.parse().ok().ok_or(())?,
                     ..Default::default()
                 }),
+                validation: None,
             })
         } else {
             Err(())
@@ -1269,6 +1335,7 @@ impl<'a> TryFrom<CoinexStreamMessage<'a>> for MdEvent {
                         side,
                         ..Default::default()
                     }),
+                    validation: None,
                 })
             }
             "depth.update" => {
@@ -1313,6 +1380,7 @@ impl<'a> TryFrom<CoinexStreamMessage<'a>> for MdEvent {
                         previous_final_update_id: None,
                         ..Default::default()
                     }),
+                    validation: None,
                 })
             }
             "bbo.update" => {
@@ -1331,6 +1399,7 @@ impl<'a> TryFrom<CoinexStreamMessage<'a>> for MdEvent {
                         ask_quantity: bbo.ask_qty.parse().ok().ok_or(())?,
                         ..Default::default()
                     }),
+                    validation: None,
                 })
             }
             "kline.update" => {
@@ -1352,6 +1421,7 @@ impl<'a> TryFrom<CoinexStreamMessage<'a>> for MdEvent {
                         volume: k.volume.parse().ok().ok_or(())?,
                         ..Default::default()
                     }),
+                    validation: None,
                 })
             }
             "index.update" => {
@@ -1373,6 +1443,7 @@ impl<'a> TryFrom<CoinexStreamMessage<'a>> for MdEvent {
                         price,
                         ..Default::default()
                     }),
+                    validation: None,
                 })
             }
             _ => Err(()),
@@ -1407,6 +1478,7 @@ impl<'a> TryFrom<MexcStreamMessage<'a>> for MdEvent {
                         side,
                         ..Default::default()
                     }),
+                    validation: None,
                 })
             }
             MexcEvent::Depth { data } => {
@@ -1447,6 +1519,7 @@ impl<'a> TryFrom<MexcStreamMessage<'a>> for MdEvent {
                         previous_final_update_id: None,
                         ..Default::default()
                     }),
+                    validation: None,
                 })
             }
             MexcEvent::BookTicker { data } => {
@@ -1466,6 +1539,7 @@ impl<'a> TryFrom<MexcStreamMessage<'a>> for MdEvent {
                         ask_quantity,
                         ..Default::default()
                     }),
+                    validation: None,
                 })
             }
         }
@@ -1503,6 +1577,7 @@ impl<'a> TryFrom<GateioStreamMessage<'a>> for MdEvent {
                         side,
                         ..Default::default()
                     }),
+                    validation: None,
                 })
             }
             "depth.update" => {
@@ -1551,6 +1626,7 @@ impl<'a> TryFrom<GateioStreamMessage<'a>> for MdEvent {
                         previous_final_update_id: None,
                         ..Default::default()
                     }),
+                    validation: None,
                 })
             }
             "kline.update" => {
@@ -1572,6 +1648,7 @@ impl<'a> TryFrom<GateioStreamMessage<'a>> for MdEvent {
                         volume: k.volume.parse().ok().ok_or(())?,
                         ..Default::default()
                     }),
+                    validation: None,
                 })
             }
             _ => Err(()),
@@ -1605,6 +1682,7 @@ impl<'a> TryFrom<XtStreamMessage<'a>> for MdEvent {
                         side,
                         ..Default::default()
                     }),
+                    validation: None,
                 })
             }
             XtEvent::Depth(d) if channel == "depth" => {
@@ -1645,6 +1723,7 @@ impl<'a> TryFrom<XtStreamMessage<'a>> for MdEvent {
                         previous_final_update_id: None,
                         ..Default::default()
                     }),
+                    validation: None,
                 })
             }
             XtEvent::Kline(k) if channel == "kline" => Ok(MdEvent {
@@ -1660,6 +1739,7 @@ impl<'a> TryFrom<XtStreamMessage<'a>> for MdEvent {
                     volume: k.volume.parse().ok().ok_or(())?,
                     ..Default::default()
                 }),
+                validation: None,
             }),
             XtEvent::Ticker(t) if channel == "ticker" => Ok(MdEvent {
                 schema_version: SCHEMA_VERSION,
@@ -1673,6 +1753,7 @@ impl<'a> TryFrom<XtStreamMessage<'a>> for MdEvent {
                     ask_quantity: t.ask_qty.parse().ok().ok_or(())?,
                     ..Default::default()
                 }),
+                validation: None,
             }),
             _ => Err(()),
         }
@@ -1709,6 +1790,7 @@ impl<'a> TryFrom<LatokenStreamMessage<'a>> for MdEvent {
                         side,
                         ..Default::default()
                     }),
+                    validation: None,
                 })
             }
             "depth" | "orderbook" => {
@@ -1750,6 +1832,7 @@ impl<'a> TryFrom<LatokenStreamMessage<'a>> for MdEvent {
                         previous_final_update_id: None,
                         ..Default::default()
                     }),
+                    validation: None,
                 })
             }
             "kline" => {
@@ -1767,6 +1850,7 @@ impl<'a> TryFrom<LatokenStreamMessage<'a>> for MdEvent {
                         volume: k.volume.parse().ok().ok_or(())?,
                         ..Default::default()
                     }),
+                    validation: None,
                 })
             }
             "ticker" => {
@@ -1783,6 +1867,7 @@ impl<'a> TryFrom<LatokenStreamMessage<'a>> for MdEvent {
                         ask_quantity: t.ask_qty.parse().ok().unwrap_or_default(),
                         ..Default::default()
                     }),
+                    validation: None,
                 })
             }
             _ => Err(()),
@@ -1816,6 +1901,7 @@ impl<'a> TryFrom<LbankStreamMessage<'a>> for MdEvent {
                         side,
                         ..Default::default()
                     }),
+                    validation: None,
                 })
             }
             LbankStreamMessage::Depth { pair, depth } => {
@@ -1856,6 +1942,7 @@ impl<'a> TryFrom<LbankStreamMessage<'a>> for MdEvent {
                         previous_final_update_id: None,
                         ..Default::default()
                     }),
+                    validation: None,
                 })
             }
             LbankStreamMessage::Kbar { pair, kbar } => Ok(MdEvent {
@@ -1871,6 +1958,7 @@ impl<'a> TryFrom<LbankStreamMessage<'a>> for MdEvent {
                     volume: kbar.volume.parse().ok().ok_or(())?,
                     ..Default::default()
                 }),
+                validation: None,
             }),
             LbankStreamMessage::Unknown => Err(()),
         }
@@ -1904,6 +1992,7 @@ impl TryFrom<KucoinStreamMessage> for MdEvent {
                         side,
                         ..Default::default()
                     }),
+                    validation: None,
                 })
             }
             "trade.l2update" => {
@@ -1951,6 +2040,7 @@ impl TryFrom<KucoinStreamMessage> for MdEvent {
                         previous_final_update_id: None,
                         ..Default::default()
                     }),
+                    validation: None,
                 })
             }
             "trade.candles.update" => {
@@ -1973,6 +2063,7 @@ impl TryFrom<KucoinStreamMessage> for MdEvent {
                         volume,
                         ..Default::default()
                     }),
+                    validation: None,
                 })
             }
             _ => Err(()),
@@ -1980,9 +2071,6 @@ impl TryFrom<KucoinStreamMessage> for MdEvent {
     }
 }
 
-#[derive(Debug)]
-pub struct ValidationError;
-
 impl MdEvent {
     pub fn channel(&self) -> Channel {
         match &self.event {
@@ -1998,61 +2086,9 @@ impl MdEvent {
             MdEventKind::FundingRate(e) => e.channel(),
             MdEventKind::OpenInterest(e) => e.channel(),
             MdEventKind::Liquidation(e) => e.channel(),
+            MdEventKind::InstrumentUpdate(e) => e.channel(),
         }
     }
-
-    pub fn validate(&self) -> Result<(), ValidationError> {
-        match &self.event {
-            MdEventKind::Trade(t) => {
-                if t.quantity < 0.0 {
-                    return Err(ValidationError);
-                }
-            }
-            MdEventKind::BookTicker(b) => {
-                if b.bid_quantity < 0.0 || b.ask_quantity < 0.0 {
-                    return Err(ValidationError);
-                }
-            }
-            MdEventKind::MiniTicker(m) => {
-                if m.volume < 0.0 || m.quote_volume < 0.0 {
-                    return Err(ValidationError);
-                }
-            }
-            MdEventKind::Kline(k) => {
-                if k.volume < 0.0 {
-                    return Err(ValidationError);
-                }
-            }
-            MdEventKind::DepthL2Update(d) => {
-                if !is_sorted_desc(&d.bids) || !is_sorted_asc(&d.asks) {
-                    return Err(ValidationError);
-                }
-                if d.bids.iter().chain(d.asks.iter()).any(|l| l.quantity < 0.0) {
-                    return Err(ValidationError);
-                }
-            }
-            MdEventKind::DepthSnapshot(d) => {
-                if !is_sorted_desc(&d.bids) || !is_sorted_asc(&d.asks) {
-                    return Err(ValidationError);
-                }
-                if d.bids.iter().chain(d.asks.iter()).any(|l| l.quantity < 0.0) {
-                    return Err(ValidationError);
-                }
-            }
-            MdEventKind::OpenInterest(o) => {
-                if o.open_interest < 0.0 {
-                    return Err(ValidationError);
-                }
-            }
-            MdEventKind::Liquidation(l) => {
-                if l.quantity < 0.0 {
-                    return Err(ValidationError);
-                }
-            }
-            _ => {}
-        }
-        Ok(())
-    }
 }
 
 impl Trade {
@@ -2126,3 +2162,9 @@ impl Liquidation {
         Channel::Liquidation
     }
 }
+
+impl InstrumentUpdate {
+    pub fn channel(&self) -> Channel {
+        Channel::Instrument
+    }
+}
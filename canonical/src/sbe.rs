@@ -0,0 +1,751 @@
+//! Fixed-layout binary encoding for latency sensitive consumers.
+//!
+//! The layout follows Simple Binary Encoding conventions: every frame is a
+//! little-endian `u32` length prefix followed by an 8 byte message header
+//! (`block_length`, `template_id`, `schema_id`, `version`), a fixed-size
+//! block and optional repeating groups. Readers borrow the underlying buffer
+//! and decode fields on access, so consuming a frame never allocates.
+//!
+//! Exchange and symbol names are interned as numeric ids. The [`Encoder`]
+//! announces every new id once through a [`TemplateId::Dictionary`] frame,
+//! which consumers apply to a [`Dictionary`] before resolving ids. Event kinds
+//! without a dedicated template are carried as JSON inside a
+//! [`TemplateId::Json`] frame.
+
+use anyhow::{anyhow, bail, Result};
+use std::collections::HashMap;
+
+use crate::{BookKind, BookTicker, DepthL2Update, Level, MdEvent, MdEventKind, Side, Trade};
+
+/// Identifier of the message schema carried in every header.
+pub const SCHEMA_ID: u16 = 1;
+
+/// Size of the frame length prefix in bytes.
+pub const LENGTH_PREFIX_LEN: usize = 4;
+
+/// Size of the message header in bytes.
+pub const HEADER_LEN: usize = 8;
+
+const GROUP_HEADER_LEN: usize = 4;
+const LEVEL_LEN: usize = 16;
+const NULL_U64: u64 = u64::MAX;
+
+/// Templates understood by this schema.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
+pub enum TemplateId {
+    Dictionary = 1,
+    Trade = 2,
+    BookTicker = 3,
+    DepthL2Update = 4,
+    Json = 5,
+}
+
+/// Namespace of an interned dictionary entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum DictKind {
+    Exchange = 0,
+    Symbol = 1,
+}
+
+impl DictKind {
+    fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            0 => Some(DictKind::Exchange),
+            1 => Some(DictKind::Symbol),
+            _ => None,
+        }
+    }
+}
+
+macro_rules! fixed_fields {
+    ($($(#[$meta:meta])* $name:ident: $ty:ty = $offset:expr;)*) => {
+        $(
+            $(#[$meta])*
+            #[inline]
+            pub fn $name(&self) -> $ty {
+                const LEN: usize = std::mem::size_of::<$ty>();
+                let mut raw = [0u8; LEN];
+                raw.copy_from_slice(&self.block[$offset..$offset + LEN]);
+                <$ty>::from_le_bytes(raw)
+            }
+        )*
+    };
+}
+
+macro_rules! nullable_fields {
+    ($($(#[$meta:meta])* $name:ident = $offset:expr;)*) => {
+        $(
+            $(#[$meta])*
+            #[inline]
+            pub fn $name(&self) -> Option<u64> {
+                let mut raw = [0u8; 8];
+                raw.copy_from_slice(&self.block[$offset..$offset + 8]);
+                Some(u64::from_le_bytes(raw)).filter(|v| *v != NULL_U64)
+            }
+        )*
+    };
+}
+
+/// Message header preceding every block.
+#[derive(Debug, Clone, Copy)]
+pub struct Header<'a> {
+    block: &'a [u8],
+}
+
+impl<'a> Header<'a> {
+    fixed_fields! {
+        block_length: u16 = 0;
+        template_id: u16 = 2;
+        schema_id: u16 = 4;
+        version: u16 = 6;
+    }
+}
+
+/// Reader for a [`TemplateId::Dictionary`] frame.
+#[derive(Debug, Clone, Copy)]
+pub struct DictionaryReader<'a> {
+    block: &'a [u8],
+    value: &'a str,
+}
+
+impl<'a> DictionaryReader<'a> {
+    const BLOCK_LEN: usize = 5;
+
+    fn wrap(block: &'a [u8], var: &'a [u8]) -> Result<Self> {
+        let value = read_var_data_u16(var)?;
+        let value = std::str::from_utf8(value)?;
+        Ok(Self { block, value })
+    }
+
+    fixed_fields! {
+        id: u32 = 0;
+    }
+
+    pub fn kind(&self) -> Option<DictKind> {
+        DictKind::from_u8(self.block[4])
+    }
+
+    pub fn value(&self) -> &'a str {
+        self.value
+    }
+}
+
+/// Reader for a [`TemplateId::Trade`] frame.
+#[derive(Debug, Clone, Copy)]
+pub struct TradeReader<'a> {
+    block: &'a [u8],
+}
+
+impl<'a> TradeReader<'a> {
+    const BLOCK_LEN: usize = 81;
+
+    fixed_fields! {
+        exchange_id: u32 = 0;
+        symbol_id: u32 = 4;
+        price: f64 = 8;
+        quantity: f64 = 16;
+        timestamp: u64 = 48;
+        ingest_ts_monotonic: u64 = 56;
+        ingest_ts_utc: u64 = 64;
+        seq_no: u64 = 72;
+    }
+
+    nullable_fields! {
+        trade_id = 24;
+        buyer_order_id = 32;
+        seller_order_id = 40;
+    }
+
+    pub fn side(&self) -> Option<Side> {
+        side_from_u8(self.block[80])
+    }
+}
+
+/// Reader for a [`TemplateId::BookTicker`] frame.
+#[derive(Debug, Clone, Copy)]
+pub struct BookTickerReader<'a> {
+    block: &'a [u8],
+}
+
+impl<'a> BookTickerReader<'a> {
+    const BLOCK_LEN: usize = 72;
+
+    fixed_fields! {
+        exchange_id: u32 = 0;
+        symbol_id: u32 = 4;
+        ts: u64 = 8;
+        bid_price: f64 = 16;
+        bid_quantity: f64 = 24;
+        ask_price: f64 = 32;
+        ask_quantity: f64 = 40;
+        ingest_ts_monotonic: u64 = 48;
+        ingest_ts_utc: u64 = 56;
+        seq_no: u64 = 64;
+    }
+}
+
+/// Reader for a [`TemplateId::DepthL2Update`] frame.
+#[derive(Debug, Clone, Copy)]
+pub struct DepthL2UpdateReader<'a> {
+    block: &'a [u8],
+    bids: LevelGroup<'a>,
+    asks: LevelGroup<'a>,
+}
+
+impl<'a> DepthL2UpdateReader<'a> {
+    const BLOCK_LEN: usize = 64;
+
+    fn wrap(block: &'a [u8], groups: &'a [u8]) -> Result<Self> {
+        let (bids, rest) = LevelGroup::wrap(groups)?;
+        let (asks, _) = LevelGroup::wrap(rest)?;
+        Ok(Self { block, bids, asks })
+    }
+
+    fixed_fields! {
+        exchange_id: u32 = 0;
+        symbol_id: u32 = 4;
+        ts: u64 = 8;
+        ingest_ts_monotonic: u64 = 40;
+        ingest_ts_utc: u64 = 48;
+        seq_no: u64 = 56;
+    }
+
+    nullable_fields! {
+        first_update_id = 16;
+        final_update_id = 24;
+        previous_final_update_id = 32;
+    }
+
+    pub fn bids(&self) -> LevelGroup<'a> {
+        self.bids
+    }
+
+    pub fn asks(&self) -> LevelGroup<'a> {
+        self.asks
+    }
+}
+
+/// Repeating group of `(price, quantity)` pairs.
+#[derive(Debug, Clone, Copy)]
+pub struct LevelGroup<'a> {
+    entry_len: usize,
+    count: usize,
+    entries: &'a [u8],
+}
+
+impl<'a> LevelGroup<'a> {
+    fn wrap(buf: &'a [u8]) -> Result<(Self, &'a [u8])> {
+        if buf.len() < GROUP_HEADER_LEN {
+            bail!("truncated group header");
+        }
+        let entry_len = u16::from_le_bytes([buf[0], buf[1]]) as usize;
+        let count = u16::from_le_bytes([buf[2], buf[3]]) as usize;
+        if entry_len < LEVEL_LEN {
+            bail!("group entry length {entry_len} too small");
+        }
+        let end = GROUP_HEADER_LEN + entry_len * count;
+        if buf.len() < end {
+            bail!("truncated group");
+        }
+        let group = Self {
+            entry_len,
+            count,
+            entries: &buf[GROUP_HEADER_LEN..end],
+        };
+        Ok((group, &buf[end..]))
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Returns `(price, quantity)` for the entry at `idx`.
+    pub fn get(&self, idx: usize) -> Option<(f64, f64)> {
+        if idx >= self.count {
+            return None;
+        }
+        let entry = &self.entries[idx * self.entry_len..];
+        let mut price = [0u8; 8];
+        let mut qty = [0u8; 8];
+        price.copy_from_slice(&entry[0..8]);
+        qty.copy_from_slice(&entry[8..16]);
+        Some((f64::from_le_bytes(price), f64::from_le_bytes(qty)))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (f64, f64)> + 'a {
+        let group = *self;
+        (0..group.count).filter_map(move |i| group.get(i))
+    }
+}
+
+/// A decoded frame borrowing from the input buffer.
+#[derive(Debug, Clone, Copy)]
+pub enum Frame<'a> {
+    Dictionary(DictionaryReader<'a>),
+    Trade(TradeReader<'a>),
+    BookTicker(BookTickerReader<'a>),
+    DepthL2Update(DepthL2UpdateReader<'a>),
+    Json(&'a [u8]),
+    /// Frame with a template this reader does not know; safe to skip.
+    Unknown(u16),
+}
+
+/// Decoded frame together with its header.
+#[derive(Debug, Clone, Copy)]
+pub struct Message<'a> {
+    pub header: Header<'a>,
+    pub frame: Frame<'a>,
+}
+
+/// Iterate over consecutive length-prefixed frames in `buf`.
+pub fn frames(buf: &[u8]) -> Frames<'_> {
+    Frames { buf }
+}
+
+/// Iterator returned by [`frames`].
+pub struct Frames<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Iterator for Frames<'a> {
+    type Item = Result<Message<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buf.is_empty() {
+            return None;
+        }
+        match split_frame(self.buf) {
+            Ok((frame, rest)) => {
+                self.buf = rest;
+                Some(decode_frame(frame))
+            }
+            Err(e) => {
+                self.buf = &[];
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+fn split_frame(buf: &[u8]) -> Result<(&[u8], &[u8])> {
+    if buf.len() < LENGTH_PREFIX_LEN {
+        bail!("truncated length prefix");
+    }
+    let len = u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+    let end = LENGTH_PREFIX_LEN + len;
+    if buf.len() < end {
+        bail!("truncated frame: expected {len} bytes");
+    }
+    Ok((&buf[LENGTH_PREFIX_LEN..end], &buf[end..]))
+}
+
+/// Decode a single frame without its length prefix.
+pub fn decode_frame(buf: &[u8]) -> Result<Message<'_>> {
+    if buf.len() < HEADER_LEN {
+        bail!("truncated message header");
+    }
+    let header = Header {
+        block: &buf[..HEADER_LEN],
+    };
+    if header.schema_id() != SCHEMA_ID {
+        bail!("unexpected schema id {}", header.schema_id());
+    }
+    let block_len = header.block_length() as usize;
+    let body = &buf[HEADER_LEN..];
+    if body.len() < block_len {
+        bail!("truncated block");
+    }
+    let (block, var) = body.split_at(block_len);
+    let require = |min: usize| -> Result<()> {
+        if block_len < min {
+            return Err(anyhow!("block length {block_len} shorter than {min}"));
+        }
+        Ok(())
+    };
+    let frame = match header.template_id() {
+        t if t == TemplateId::Dictionary as u16 => {
+            require(DictionaryReader::BLOCK_LEN)?;
+            Frame::Dictionary(DictionaryReader::wrap(block, var)?)
+        }
+        t if t == TemplateId::Trade as u16 => {
+            require(TradeReader::BLOCK_LEN)?;
+            Frame::Trade(TradeReader { block })
+        }
+        t if t == TemplateId::BookTicker as u16 => {
+            require(BookTickerReader::BLOCK_LEN)?;
+            Frame::BookTicker(BookTickerReader { block })
+        }
+        t if t == TemplateId::DepthL2Update as u16 => {
+            require(DepthL2UpdateReader::BLOCK_LEN)?;
+            Frame::DepthL2Update(DepthL2UpdateReader::wrap(block, var)?)
+        }
+        t if t == TemplateId::Json as u16 => Frame::Json(read_var_data_u32(var)?),
+        other => Frame::Unknown(other),
+    };
+    Ok(Message { header, frame })
+}
+
+fn read_var_data_u16(buf: &[u8]) -> Result<&[u8]> {
+    if buf.len() < 2 {
+        bail!("truncated var data length");
+    }
+    let len = u16::from_le_bytes([buf[0], buf[1]]) as usize;
+    buf.get(2..2 + len)
+        .ok_or_else(|| anyhow!("truncated var data"))
+}
+
+fn read_var_data_u32(buf: &[u8]) -> Result<&[u8]> {
+    if buf.len() < 4 {
+        bail!("truncated var data length");
+    }
+    let len = u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+    buf.get(4..4 + len)
+        .ok_or_else(|| anyhow!("truncated var data"))
+}
+
+fn side_from_u8(v: u8) -> Option<Side> {
+    match v {
+        1 => Some(Side::Buy),
+        2 => Some(Side::Sell),
+        _ => None,
+    }
+}
+
+fn side_to_u8(side: Option<Side>) -> u8 {
+    match side {
+        None => 0,
+        Some(Side::Buy) => 1,
+        Some(Side::Sell) => 2,
+    }
+}
+
+// Encoding ------------------------------------------------------------------
+
+/// Interns exchange and symbol names and writes events as binary frames.
+#[derive(Debug, Default)]
+pub struct Encoder {
+    exchanges: HashMap<String, u32>,
+    symbols: HashMap<String, u32>,
+    pending: Vec<(DictKind, u32, String)>,
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Forget all interned names so they are announced again.
+    pub fn reset(&mut self) {
+        self.exchanges.clear();
+        self.symbols.clear();
+        self.pending.clear();
+    }
+
+    /// Append the event frame for `ev` to `out`.
+    ///
+    /// Newly interned names are queued and must be written with
+    /// [`Encoder::take_dictionary`] before consumers read the event.
+    pub fn encode(&mut self, ev: &MdEvent, out: &mut Vec<u8>) -> Result<()> {
+        let ids = self.intern_event(ev);
+        write_event(ev, ids, out)
+    }
+
+    /// Append dictionary frames for names interned since the last call.
+    ///
+    /// Returns `true` if any frames were written.
+    pub fn take_dictionary(&mut self, out: &mut Vec<u8>) -> bool {
+        if self.pending.is_empty() {
+            return false;
+        }
+        for (kind, id, value) in self.pending.drain(..) {
+            write_dictionary(kind, id, &value, out);
+        }
+        true
+    }
+
+    /// Append any pending dictionary frames followed by the event frame.
+    pub fn encode_with_dictionary(&mut self, ev: &MdEvent, out: &mut Vec<u8>) -> Result<()> {
+        let ids = self.intern_event(ev);
+        self.take_dictionary(out);
+        write_event(ev, ids, out)
+    }
+
+    fn intern_event(&mut self, ev: &MdEvent) -> (u32, u32) {
+        let (exchange, symbol) = match &ev.event {
+            MdEventKind::Trade(e) => (&e.exchange, &e.symbol),
+            MdEventKind::BookTicker(e) => (&e.exchange, &e.symbol),
+            MdEventKind::DepthL2Update(e) => (&e.exchange, &e.symbol),
+            _ => return (0, 0),
+        };
+        (
+            self.intern(DictKind::Exchange, exchange),
+            self.intern(DictKind::Symbol, symbol),
+        )
+    }
+
+    fn intern(&mut self, kind: DictKind, value: &str) -> u32 {
+        let map = match kind {
+            DictKind::Exchange => &mut self.exchanges,
+            DictKind::Symbol => &mut self.symbols,
+        };
+        if let Some(id) = map.get(value) {
+            return *id;
+        }
+        let id = map.len() as u32;
+        map.insert(value.to_string(), id);
+        self.pending.push((kind, id, value.to_string()));
+        id
+    }
+}
+
+fn begin_frame(out: &mut Vec<u8>, template: TemplateId, block_len: usize, version: u32) -> usize {
+    let start = out.len();
+    out.extend_from_slice(&0u32.to_le_bytes());
+    out.extend_from_slice(&(block_len as u16).to_le_bytes());
+    out.extend_from_slice(&(template as u16).to_le_bytes());
+    out.extend_from_slice(&SCHEMA_ID.to_le_bytes());
+    out.extend_from_slice(&(version as u16).to_le_bytes());
+    start
+}
+
+fn end_frame(out: &mut [u8], start: usize) {
+    let len = (out.len() - start - LENGTH_PREFIX_LEN) as u32;
+    out[start..start + LENGTH_PREFIX_LEN].copy_from_slice(&len.to_le_bytes());
+}
+
+fn put_u64_opt(out: &mut Vec<u8>, v: Option<u64>) {
+    out.extend_from_slice(&v.unwrap_or(NULL_U64).to_le_bytes());
+}
+
+fn write_dictionary(kind: DictKind, id: u32, value: &str, out: &mut Vec<u8>) {
+    let start = begin_frame(
+        out,
+        TemplateId::Dictionary,
+        DictionaryReader::BLOCK_LEN,
+        crate::SCHEMA_VERSION,
+    );
+    out.extend_from_slice(&id.to_le_bytes());
+    out.push(kind as u8);
+    // Cut over-long names on a character boundary so they stay valid UTF-8.
+    let bytes = &value.as_bytes()[..value.floor_char_boundary(u16::MAX as usize)];
+    out.extend_from_slice(&(bytes.len() as u16).to_le_bytes());
+    out.extend_from_slice(bytes);
+    end_frame(out, start);
+}
+
+fn write_event(ev: &MdEvent, (exchange, symbol): (u32, u32), out: &mut Vec<u8>) -> Result<()> {
+    let version = ev.schema_version;
+    // Fixed layouts have no room for a validation flag, so flagged events
+    // travel as JSON.
+    let kind = if ev.validation.is_some() {
+        None
+    } else {
+        Some(&ev.event)
+    };
+    match kind {
+        Some(MdEventKind::Trade(t)) => {
+            let start = begin_frame(out, TemplateId::Trade, TradeReader::BLOCK_LEN, version);
+            out.extend_from_slice(&exchange.to_le_bytes());
+            out.extend_from_slice(&symbol.to_le_bytes());
+            out.extend_from_slice(&t.price.to_le_bytes());
+            out.extend_from_slice(&t.quantity.to_le_bytes());
+            put_u64_opt(out, t.trade_id);
+            put_u64_opt(out, t.buyer_order_id);
+            put_u64_opt(out, t.seller_order_id);
+            out.extend_from_slice(&t.timestamp.to_le_bytes());
+            out.extend_from_slice(&t.ingest_ts_monotonic.to_le_bytes());
+            out.extend_from_slice(&t.ingest_ts_utc.to_le_bytes());
+            out.extend_from_slice(&t.seq_no.to_le_bytes());
+            out.push(side_to_u8(t.side));
+            end_frame(out, start);
+        }
+        Some(MdEventKind::BookTicker(b)) => {
+            let start = begin_frame(
+                out,
+                TemplateId::BookTicker,
+                BookTickerReader::BLOCK_LEN,
+                version,
+            );
+            out.extend_from_slice(&exchange.to_le_bytes());
+            out.extend_from_slice(&symbol.to_le_bytes());
+            out.extend_from_slice(&b.ts.to_le_bytes());
+            out.extend_from_slice(&b.bid_price.to_le_bytes());
+            out.extend_from_slice(&b.bid_quantity.to_le_bytes());
+            out.extend_from_slice(&b.ask_price.to_le_bytes());
+            out.extend_from_slice(&b.ask_quantity.to_le_bytes());
+            out.extend_from_slice(&b.ingest_ts_monotonic.to_le_bytes());
+            out.extend_from_slice(&b.ingest_ts_utc.to_le_bytes());
+            out.extend_from_slice(&b.seq_no.to_le_bytes());
+            end_frame(out, start);
+        }
+        Some(MdEventKind::DepthL2Update(d)) => {
+            if d.bids.len() > u16::MAX as usize || d.asks.len() > u16::MAX as usize {
+                bail!("depth update exceeds {} levels per side", u16::MAX);
+            }
+            let start = begin_frame(
+                out,
+                TemplateId::DepthL2Update,
+                DepthL2UpdateReader::BLOCK_LEN,
+                version,
+            );
+            out.extend_from_slice(&exchange.to_le_bytes());
+            out.extend_from_slice(&symbol.to_le_bytes());
+            out.extend_from_slice(&d.ts.to_le_bytes());
+            put_u64_opt(out, d.first_update_id);
+            put_u64_opt(out, d.final_update_id);
+            put_u64_opt(out, d.previous_final_update_id);
+            out.extend_from_slice(&d.ingest_ts_monotonic.to_le_bytes());
+            out.extend_from_slice(&d.ingest_ts_utc.to_le_bytes());
+            out.extend_from_slice(&d.seq_no.to_le_bytes());
+            for side in [&d.bids, &d.asks] {
+                out.extend_from_slice(&(LEVEL_LEN as u16).to_le_bytes());
+                out.extend_from_slice(&(side.len() as u16).to_le_bytes());
+                for lvl in side {
+                    out.extend_from_slice(&lvl.price.to_le_bytes());
+                    out.extend_from_slice(&lvl.quantity.to_le_bytes());
+                }
+            }
+            end_frame(out, start);
+        }
+        _ => {
+            let json = serde_json::to_vec(ev)?;
+            let start = begin_frame(out, TemplateId::Json, 0, version);
+            out.extend_from_slice(&(json.len() as u32).to_le_bytes());
+            out.extend_from_slice(&json);
+            end_frame(out, start);
+        }
+    }
+    Ok(())
+}
+
+// Decoding ------------------------------------------------------------------
+
+/// Mapping from interned ids back to exchange and symbol names.
+#[derive(Debug, Default, Clone)]
+pub struct Dictionary {
+    exchanges: HashMap<u32, String>,
+    symbols: HashMap<u32, String>,
+}
+
+impl Dictionary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the entry carried by a dictionary frame.
+    pub fn apply(&mut self, entry: &DictionaryReader<'_>) {
+        let map = match entry.kind() {
+            Some(DictKind::Exchange) => &mut self.exchanges,
+            Some(DictKind::Symbol) => &mut self.symbols,
+            None => return,
+        };
+        map.insert(entry.id(), entry.value().to_string());
+    }
+
+    pub fn exchange(&self, id: u32) -> Option<&str> {
+        self.exchanges.get(&id).map(String::as_str)
+    }
+
+    pub fn symbol(&self, id: u32) -> Option<&str> {
+        self.symbols.get(&id).map(String::as_str)
+    }
+
+    fn names(&self, exchange: u32, symbol: u32) -> Result<(String, String)> {
+        let exchange = self
+            .exchange(exchange)
+            .ok_or_else(|| anyhow!("unknown exchange id {exchange}"))?;
+        let symbol = self
+            .symbol(symbol)
+            .ok_or_else(|| anyhow!("unknown symbol id {symbol}"))?;
+        Ok((exchange.to_string(), symbol.to_string()))
+    }
+
+    /// Convert a frame into an owned [`MdEvent`].
+    ///
+    /// Dictionary frames update `self` and yield `None`, as do frames with an
+    /// unknown template.
+    pub fn decode(&mut self, msg: &Message<'_>) -> Result<Option<MdEvent>> {
+        let schema_version = msg.header.version() as u32;
+        let event = match msg.frame {
+            Frame::Dictionary(entry) => {
+                self.apply(&entry);
+                return Ok(None);
+            }
+            Frame::Unknown(_) => return Ok(None),
+            Frame::Json(json) => return Ok(Some(serde_json::from_slice(json)?)),
+            Frame::Trade(t) => {
+                let (exchange, symbol) = self.names(t.exchange_id(), t.symbol_id())?;
+                MdEventKind::Trade(Trade {
+                    schema_version,
+                    exchange,
+                    symbol,
+                    price: t.price(),
+                    quantity: t.quantity(),
+                    trade_id: t.trade_id(),
+                    buyer_order_id: t.buyer_order_id(),
+                    seller_order_id: t.seller_order_id(),
+                    timestamp: t.timestamp(),
+                    side: t.side(),
+                    ingest_ts_monotonic: t.ingest_ts_monotonic(),
+                    ingest_ts_utc: t.ingest_ts_utc(),
+                    seq_no: t.seq_no(),
+                })
+            }
+            Frame::BookTicker(b) => {
+                let (exchange, symbol) = self.names(b.exchange_id(), b.symbol_id())?;
+                MdEventKind::BookTicker(BookTicker {
+                    schema_version,
+                    exchange,
+                    symbol,
+                    ts: b.ts(),
+                    bid_price: b.bid_price(),
+                    bid_quantity: b.bid_quantity(),
+                    ask_price: b.ask_price(),
+                    ask_quantity: b.ask_quantity(),
+                    ingest_ts_monotonic: b.ingest_ts_monotonic(),
+                    ingest_ts_utc: b.ingest_ts_utc(),
+                    seq_no: b.seq_no(),
+                })
+            }
+            Frame::DepthL2Update(d) => {
+                let (exchange, symbol) = self.names(d.exchange_id(), d.symbol_id())?;
+                let levels = |group: LevelGroup<'_>, kind: BookKind| -> Vec<Level> {
+                    group
+                        .iter()
+                        .map(|(price, quantity)| Level {
+                            schema_version,
+                            price,
+                            quantity,
+                            kind,
+                        })
+                        .collect()
+                };
+                MdEventKind::DepthL2Update(DepthL2Update {
+                    schema_version,
+                    exchange,
+                    symbol,
+                    ts: d.ts(),
+                    bids: levels(d.bids(), BookKind::Bid),
+                    asks: levels(d.asks(), BookKind::Ask),
+                    first_update_id: d.first_update_id(),
+                    final_update_id: d.final_update_id(),
+                    previous_final_update_id: d.previous_final_update_id(),
+                    ingest_ts_monotonic: d.ingest_ts_monotonic(),
+                    ingest_ts_utc: d.ingest_ts_utc(),
+                    seq_no: d.seq_no(),
+                })
+            }
+        };
+        Ok(Some(MdEvent {
+            schema_version,
+            event,
+            validation: None,
+        }))
+    }
+}
@@ -1,13 +1,23 @@
-use anyhow::Result;
-use once_cell::sync::OnceCell;
-use serde::Deserialize;
+use anyhow::{bail, Context, Result};
+use arc_swap::{ArcSwap, Guard};
+use once_cell::sync::Lazy;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use std::collections::HashMap;
+use std::fmt;
 use std::fs::File;
 use std::io::Read;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-pub type SymbolId = String;
+use crate::{InstrumentChange, InstrumentUpdate, SCHEMA_VERSION};
 
-#[derive(Debug, Clone, Deserialize, Default)]
+/// Canonical instrument identifier, see [`InstrumentId`].
+pub type SymbolId = InstrumentId;
+
+#[derive(
+    Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq, Hash, PartialOrd, Ord,
+)]
 #[serde(rename_all = "lowercase")]
 pub enum VenueType {
     Spot,
@@ -17,15 +27,246 @@ pub enum VenueType {
     Unknown,
 }
 
-#[derive(Debug, Clone, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
 pub struct ContractSpec {
     pub venue: VenueType,
     pub base: String,
     pub quote: String,
+    /// Asset the contract settles and margins in; empty when it is the
+    /// quote asset.
+    #[serde(default)]
+    pub settle: String,
     #[serde(default)]
     pub lot_step: Option<f64>,
     #[serde(default)]
     pub price_step: Option<f64>,
+    /// Units of the base asset per contract, for derivatives.
+    #[serde(default)]
+    pub contract_size: Option<f64>,
+    /// Expiry in milliseconds since the Unix epoch; `None` for spot and
+    /// perpetual instruments.
+    #[serde(default)]
+    pub expiry: Option<u64>,
+}
+
+/// Structured, venue-independent instrument identifier.
+///
+/// Its canonical string form is `BASE-QUOTE` for spot, `BASE-QUOTE-PERP` for
+/// perpetuals and `BASE-QUOTE-YYYYMMDD` for dated futures, followed by
+/// `:SETTLE` when the contract settles in an asset other than the quote, e.g.
+/// `BTC-USD-PERP:BTC` for an inverse perpetual. A spot and a perpetual on the
+/// same pair share `base` and `quote` but never compare equal.
+///
+/// Symbols that cannot be resolved keep the venue spelling in `base` with an
+/// [`VenueType::Unknown`] venue. Ids with an unknown venue are written with a
+/// leading `~`, e.g. `~ETH-USDT` or `~ETHUSDT`, so they never read back as
+/// spot.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct InstrumentId {
+    pub base: String,
+    pub quote: String,
+    pub venue: VenueType,
+    /// Settlement asset, only set when it differs from `quote`.
+    pub settle: Option<String>,
+    /// Expiry date as `YYYYMMDD` (UTC) for dated futures.
+    pub expiry: Option<u32>,
+}
+
+impl InstrumentId {
+    /// Build the id described by `spec`, applying the asset alias table.
+    /// Returns `None` when the spec lacks base or quote assets or describes an
+    /// option, whose strike and type it does not carry.
+    pub fn from_spec(spec: &ContractSpec) -> Option<Self> {
+        Self::from_spec_in(&table(), spec)
+    }
+
+    fn from_spec_in(tbl: &SymbolTable, spec: &ContractSpec) -> Option<Self> {
+        if spec.base.is_empty() || spec.quote.is_empty() || spec.venue == VenueType::Options {
+            return None;
+        }
+        let quote = tbl.asset(&spec.quote);
+        let settle = Some(tbl.asset(&spec.settle)).filter(|s| !s.is_empty() && *s != quote);
+        let expiry = match spec.venue {
+            VenueType::Futures => spec.expiry.map(date_from_ms),
+            _ => None,
+        };
+        Some(Self {
+            base: tbl.asset(&spec.base),
+            quote,
+            venue: spec.venue,
+            settle,
+            expiry,
+        })
+    }
+
+    /// Best-effort id for a venue symbol missing from the table. Symbols
+    /// delimited by `_`, `-` or `/` are split into base and quote; anything
+    /// else is kept whole.
+    pub fn unresolved(raw: &str) -> Self {
+        Self::unresolved_in(&table(), raw)
+    }
+
+    fn unresolved_in(tbl: &SymbolTable, raw: &str) -> Self {
+        let raw = raw.to_uppercase().replace(['_', '/'], "-");
+        let (base, quote) = match raw.split_once('-') {
+            Some((base, quote)) => (tbl.asset(base), tbl.asset(quote)),
+            None => (raw, String::new()),
+        };
+        Self {
+            base,
+            quote,
+            ..Default::default()
+        }
+    }
+
+    /// Whether both ids trade the same asset pair, whatever the venue type.
+    pub fn same_pair(&self, other: &Self) -> bool {
+        self.base == other.base && self.quote == other.quote
+    }
+}
+
+impl fmt::Display for InstrumentId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.venue == VenueType::Unknown {
+            f.write_str("~")?;
+        }
+        f.write_str(&self.base)?;
+        if !self.quote.is_empty() {
+            write!(f, "-{}", self.quote)?;
+        }
+        match (self.venue, self.expiry) {
+            (VenueType::Futures, Some(date)) => write!(f, "-{date:08}")?,
+            (VenueType::Futures, None) => f.write_str("-PERP")?,
+            _ => {}
+        }
+        if let Some(settle) = &self.settle {
+            write!(f, ":{settle}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for InstrumentId {
+    type Err = anyhow::Error;
+
+    /// Parse the canonical string form. Plain `BASE-QUOTE` ids are read as
+    /// spot, `~`-prefixed ones and ids with more segments than the canonical
+    /// form as unresolved.
+    fn from_str(s: &str) -> Result<Self> {
+        let (body, settle) = match s.split_once(':') {
+            Some((body, settle)) => (body, Some(settle.to_uppercase())),
+            None => (s, None),
+        };
+        if body.is_empty() || body == "~" {
+            bail!("empty instrument id");
+        }
+        if let Some(raw) = body.strip_prefix('~') {
+            // Written by `Display`, so the assets are already canonical.
+            let raw = raw.to_uppercase();
+            let (base, quote) = match raw.split_once('-') {
+                Some((base, quote)) => (base.to_string(), quote.to_string()),
+                None => (raw, String::new()),
+            };
+            return Ok(Self {
+                settle: settle.filter(|s| *s != quote),
+                base,
+                quote,
+                ..Default::default()
+            });
+        }
+        let parts: Vec<String> = body.split('-').map(str::to_uppercase).collect();
+        let mut id = match parts.as_slice() {
+            [base, quote] => Self {
+                base: base.clone(),
+                quote: quote.clone(),
+                venue: VenueType::Spot,
+                ..Default::default()
+            },
+            [base, quote, kind] => {
+                let expiry = match kind.as_str() {
+                    "PERP" => None,
+                    date if date.len() == 8 => Some(
+                        date.parse()
+                            .with_context(|| format!("invalid expiry in instrument id {s}"))?,
+                    ),
+                    _ => return Ok(Self::unresolved(s)),
+                };
+                Self {
+                    base: base.clone(),
+                    quote: quote.clone(),
+                    venue: VenueType::Futures,
+                    expiry,
+                    ..Default::default()
+                }
+            }
+            _ => return Ok(Self::unresolved(s)),
+        };
+        id.settle = settle.filter(|s| *s != id.quote);
+        Ok(id)
+    }
+}
+
+impl PartialEq<str> for InstrumentId {
+    fn eq(&self, other: &str) -> bool {
+        self.to_string().as_str() == other
+    }
+}
+
+impl PartialEq<&str> for InstrumentId {
+    fn eq(&self, other: &&str) -> bool {
+        self == *other
+    }
+}
+
+impl Serialize for InstrumentId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for InstrumentId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+/// Instrument metadata as reported by a venue's exchange-info endpoint.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Instrument {
+    /// Exchange name as it appears on canonical events, e.g. `binance`.
+    pub exchange: String,
+    /// Venue-native symbol, e.g. `BTCUSDT` or `BTC_USDT`.
+    pub symbol: String,
+    pub spec: ContractSpec,
+}
+
+impl Instrument {
+    /// Canonical id derived from the spec, falling back to
+    /// [`InstrumentId::unresolved`] when the venue did not report base and
+    /// quote assets.
+    pub fn id(&self) -> SymbolId {
+        self.id_in(&table())
+    }
+
+    fn id_in(&self, tbl: &SymbolTable) -> SymbolId {
+        InstrumentId::from_spec_in(tbl, &self.spec)
+            .unwrap_or_else(|| InstrumentId::unresolved_in(tbl, &self.symbol))
+    }
+}
+
+/// Asset codes venues use for the same asset, mapped to the canonical code.
+/// Override files add to these.
+const ASSET_ALIASES: [(&str, &str); 4] = [
+    ("XBT", "BTC"),
+    ("XDG", "DOGE"),
+    ("BCHABC", "BCH"),
+    ("BCHSV", "BSV"),
+];
+
+/// Canonical code for the asset a venue calls `code`.
+pub fn canonical_asset(code: &str) -> String {
+    table().asset(code)
 }
 
 #[derive(Debug, Deserialize)]
@@ -37,56 +278,306 @@ pub struct SymbolConfig {
     pub aliases: HashMap<String, Vec<String>>, // exchange -> raw symbols
 }
 
-#[derive(Default)]
+/// What a venue symbol resolves to, shared by every lookup of it.
+#[derive(Debug, PartialEq)]
+pub struct Resolved {
+    pub id: SymbolId,
+    /// `id` in its canonical string form.
+    pub name: String,
+    pub spec: Option<Arc<ContractSpec>>,
+}
+
 struct SymbolTable {
+    /// Venue asset codes mapped to canonical ones, part of the table so a
+    /// reload replaces them together with the ids derived from them.
+    assets: HashMap<String, String>,
     aliases: HashMap<(String, String), SymbolId>,
-    specs: HashMap<SymbolId, ContractSpec>,
+    /// Spec of each venue symbol in `aliases`. Venues listing the same id
+    /// still differ in tick and lot size, so events are checked against
+    /// these rather than `specs`.
+    venue_specs: HashMap<(String, String), Arc<ContractSpec>>,
+    /// Spec per canonical id: the override's, otherwise the first venue's.
+    specs: HashMap<SymbolId, Arc<ContractSpec>>,
+    /// `aliases` by exchange, then upper-cased symbol, filled in by
+    /// [`SymbolTable::index`] so lookups need no owned key.
+    resolved: HashMap<String, HashMap<String, Arc<Resolved>>>,
+}
+
+impl Default for SymbolTable {
+    fn default() -> Self {
+        Self {
+            assets: ASSET_ALIASES
+                .into_iter()
+                .map(|(alias, asset)| (alias.to_string(), asset.to_string()))
+                .collect(),
+            aliases: HashMap::new(),
+            venue_specs: HashMap::new(),
+            specs: HashMap::new(),
+            resolved: HashMap::new(),
+        }
+    }
 }
 
-static TABLE: OnceCell<SymbolTable> = OnceCell::new();
+impl SymbolTable {
+    fn asset(&self, code: &str) -> String {
+        let code = code.to_uppercase();
+        self.assets.get(&code).cloned().unwrap_or(code)
+    }
 
-fn table() -> &'static SymbolTable {
-    TABLE.get_or_init(SymbolTable::default)
+    fn add_assets(&mut self, assets: HashMap<String, String>) {
+        for (alias, asset) in assets {
+            self.assets.insert(alias.to_uppercase(), asset.to_uppercase());
+        }
+    }
+
+    fn add_instrument(&mut self, inst: Instrument) {
+        let id = inst.id_in(self);
+        let key = alias_key(&inst.exchange, &inst.symbol);
+        let spec = Arc::new(inst.spec);
+        // Some venues reuse one raw symbol for spot and derivatives under the
+        // same exchange name; events cannot tell them apart, so spot wins.
+        let replace = match self.venue_specs.get(&key) {
+            None => true,
+            Some(existing) => spec.venue == VenueType::Spot && existing.venue != VenueType::Spot,
+        };
+        if replace {
+            self.aliases.insert(key.clone(), id.clone());
+            self.venue_specs.insert(key, spec.clone());
+        }
+        self.specs.entry(id).or_insert(spec);
+    }
+
+    /// Build `resolved` once every instrument and override is in.
+    fn index(mut self) -> Self {
+        for (key, id) in &self.aliases {
+            let resolved = Arc::new(Resolved {
+                id: id.clone(),
+                name: id.to_string(),
+                spec: self.venue_specs.get(key).cloned(),
+            });
+            let (exchange, symbol) = key;
+            self.resolved
+                .entry(exchange.clone())
+                .or_default()
+                .insert(symbol.clone(), resolved);
+        }
+        self
+    }
+
+    fn venue_spec(&self, key: &(String, String)) -> Option<ContractSpec> {
+        self.venue_specs.get(key).map(|s| ContractSpec::clone(s))
+    }
+
+    fn add_override(&mut self, entry: SymbolConfig) {
+        let id = entry.id;
+        let spec = Arc::new(entry.spec);
+        for (ex, raws) in entry.aliases {
+            for raw in raws {
+                let key = alias_key(&ex, &raw);
+                self.aliases.insert(key.clone(), id.clone());
+                self.venue_specs.insert(key, spec.clone());
+            }
+        }
+        self.specs.insert(id, spec);
+    }
 }
 
-pub fn load_from_path(path: &str) -> Result<()> {
+/// The active symbol table. Reloads build a new table and swap it in, so
+/// lookups never block and always see one consistent version.
+static TABLE: Lazy<ArcSwap<SymbolTable>> =
+    Lazy::new(|| ArcSwap::from_pointee(SymbolTable::default()));
+
+fn table() -> Guard<Arc<SymbolTable>> {
+    TABLE.load()
+}
+
+fn alias_key(exchange: &str, raw: &str) -> (String, String) {
+    (exchange.to_string(), raw.to_uppercase())
+}
+
+/// Contents of a symbol override file: either a bare list of symbol entries
+/// or a table with `assets` aliases and `symbols` entries.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Overrides {
+    Symbols(Vec<SymbolConfig>),
+    Full {
+        #[serde(default)]
+        assets: HashMap<String, String>,
+        #[serde(default)]
+        symbols: Vec<SymbolConfig>,
+    },
+}
+
+/// Read an override file, returning its asset aliases and symbol entries.
+fn read_overrides(path: &str) -> Result<(HashMap<String, String>, Vec<SymbolConfig>)> {
     let mut file = File::open(path)?;
     let mut buf = String::new();
     file.read_to_string(&mut buf)?;
     if buf.trim().is_empty() {
-        return Ok(());
+        return Ok(Default::default());
     }
 
     // Try JSON first, then TOML
-    let entries: Vec<SymbolConfig> = if let Ok(v) = serde_json::from_str(&buf) {
+    let overrides = if let Ok(v) = serde_json::from_str(&buf) {
         v
     } else {
         toml::from_str(&buf)?
     };
+    Ok(match overrides {
+        Overrides::Symbols(symbols) => (HashMap::new(), symbols),
+        Overrides::Full { assets, symbols } => (assets, symbols),
+    })
+}
 
+fn build(instruments: impl IntoIterator<Item = Instrument>, overrides: Option<&str>) -> Result<SymbolTable> {
+    let (assets, entries) = match overrides {
+        Some(path) => read_overrides(path)?,
+        None => Default::default(),
+    };
+    // Asset aliases must be known before instrument ids are derived.
     let mut tbl = SymbolTable::default();
+    tbl.add_assets(assets);
+    for inst in instruments {
+        tbl.add_instrument(inst);
+    }
     for entry in entries {
-        let id = entry.id.clone();
-        tbl.specs.insert(id.clone(), entry.spec);
-        for (ex, raws) in entry.aliases {
-            for raw in raws {
-                tbl.aliases.insert((ex.clone(), raw), id.clone());
+        tbl.add_override(entry);
+    }
+    Ok(tbl.index())
+}
+
+/// Build the symbol table from venue metadata, then apply the entries in
+/// `overrides` (a `symbols.json` style file) on top of it.
+pub fn init(
+    instruments: impl IntoIterator<Item = Instrument>,
+    overrides: Option<&str>,
+) -> Result<()> {
+    TABLE.store(Arc::new(build(instruments, overrides)?));
+    Ok(())
+}
+
+/// Rebuild the table like [`init`] and swap it in, returning an update for
+/// every venue symbol that was added, removed or changed spec. The current
+/// table stays in place if the overrides cannot be read.
+pub fn reload(
+    instruments: impl IntoIterator<Item = Instrument>,
+    overrides: Option<&str>,
+) -> Result<Vec<InstrumentUpdate>> {
+    let new = Arc::new(build(instruments, overrides)?);
+    let old = TABLE.swap(new.clone());
+    Ok(diff(&old, &new))
+}
+
+pub fn load_from_path(path: &str) -> Result<()> {
+    let (assets, entries) = read_overrides(path)?;
+    if assets.is_empty() && entries.is_empty() {
+        return Ok(());
+    }
+    let mut tbl = SymbolTable::default();
+    tbl.add_assets(assets);
+    for entry in entries {
+        tbl.add_override(entry);
+    }
+    TABLE.store(Arc::new(tbl.index()));
+    Ok(())
+}
+
+/// Per venue symbol changes between two table versions.
+fn diff(old: &SymbolTable, new: &SymbolTable) -> Vec<InstrumentUpdate> {
+    let ts = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    let update = |(exchange, symbol): &(String, String), id: &SymbolId, change, spec| {
+        InstrumentUpdate {
+            schema_version: SCHEMA_VERSION,
+            exchange: exchange.clone(),
+            symbol: symbol.clone(),
+            instrument: id.clone(),
+            change,
+            spec,
+            ts,
+            ingest_ts_monotonic: 0,
+            ingest_ts_utc: 0,
+            seq_no: 0,
+        }
+    };
+
+    let mut updates = Vec::new();
+    for (key, id) in &new.aliases {
+        let spec = new.venue_spec(key);
+        match old.aliases.get(key) {
+            None => updates.push(update(key, id, InstrumentChange::Added, spec)),
+            Some(old_id) if old_id != id || old.venue_spec(key) != spec => {
+                updates.push(update(key, id, InstrumentChange::Updated, spec))
             }
+            Some(_) => {}
         }
     }
-    TABLE.set(tbl).ok();
-    Ok(())
+    for (key, id) in &old.aliases {
+        if !new.aliases.contains_key(key) {
+            let spec = old.venue_spec(key);
+            updates.push(update(key, id, InstrumentChange::Removed, spec));
+        }
+    }
+    updates.sort_by(|a, b| (&a.exchange, &a.symbol).cmp(&(&b.exchange, &b.symbol)));
+    updates
 }
 
-pub fn normalize_symbol(exchange: &str, raw: &str) -> SymbolId {
+/// Table entry of a venue symbol, looked up without allocating when the
+/// symbol is already upper case.
+pub fn resolve(exchange: &str, raw: &str) -> Option<Arc<Resolved>> {
     let tbl = table();
-    if let Some(id) = tbl.aliases.get(&(exchange.to_string(), raw.to_string())) {
-        return id.clone();
+    let symbols = tbl.resolved.get(exchange)?;
+    if raw.chars().any(char::is_lowercase) {
+        symbols.get(&raw.to_uppercase()).cloned()
+    } else {
+        symbols.get(raw).cloned()
+    }
+}
+
+pub fn normalize_symbol(exchange: &str, raw: &str) -> SymbolId {
+    match resolve(exchange, raw) {
+        Some(resolved) => resolved.id.clone(),
+        // fallback: standard formatting
+        None => InstrumentId::unresolved(raw),
     }
-    // fallback: standard formatting
-    raw.to_uppercase().replace('_', "-")
 }
 
-pub fn get_spec(id: &str) -> Option<ContractSpec> {
+/// Spec of a canonical id. Venues listing the id may differ in tick and lot
+/// size; [`resolve`] returns a venue symbol's own spec.
+pub fn get_spec(id: &SymbolId) -> Option<Arc<ContractSpec>> {
     table().specs.get(id).cloned()
 }
+
+/// Every venue symbol in the table with its canonical id and spec, ordered by
+/// exchange and symbol. Symbols are upper-cased as stored in the table.
+pub fn instruments() -> Vec<(String, String, SymbolId, Option<ContractSpec>)> {
+    let tbl = table();
+    let mut out: Vec<_> = tbl
+        .aliases
+        .iter()
+        .map(|(key, id)| {
+            let (exchange, symbol) = key;
+            (exchange.clone(), symbol.clone(), id.clone(), tbl.venue_spec(key))
+        })
+        .collect();
+    out.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+    out
+}
+
+/// Convert a millisecond Unix timestamp to a `YYYYMMDD` date (UTC).
+pub fn date_from_ms(ms: u64) -> u32 {
+    // Civil-from-days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = (ms / 86_400_000) as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year * 10_000 + month * 100 + day) as u32
+}
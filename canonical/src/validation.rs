@@ -0,0 +1,490 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+
+use crate::symbol::resolve;
+use crate::{is_sorted_asc, is_sorted_desc, Level, MdEvent, MdEventKind};
+
+/// Reason an event was rejected by [`MdEvent::validate`] or
+/// [`MdEvent::validate_with`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum ValidationError {
+    /// A price or quantity is NaN or infinite.
+    NonFinite { field: String },
+    /// A quantity, volume or open interest is below zero.
+    Negative { field: String, value: f64 },
+    /// Book levels are not ordered best first.
+    Unsorted { field: String },
+    /// The best bid is above the best ask.
+    Crossed { bid: f64, ask: f64 },
+    /// The exchange timestamp is ahead of the ingest clock by more than the
+    /// allowed skew.
+    FutureTimestamp { field: String, ts: u64 },
+    /// The exchange timestamp is older than the allowed age.
+    StaleTimestamp { field: String, ts: u64 },
+    /// The exchange timestamp is zero on a venue expected to supply one.
+    MissingTimestamp { field: String },
+    /// A price is not a multiple of the instrument's tick size.
+    OffTick {
+        field: String,
+        value: f64,
+        step: f64,
+    },
+    /// A quantity is not a multiple of the instrument's lot size.
+    OffLot {
+        field: String,
+        value: f64,
+        step: f64,
+    },
+}
+
+impl ValidationError {
+    /// Short, stable label suitable for metrics.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            ValidationError::NonFinite { .. } => "non_finite",
+            ValidationError::Negative { .. } => "negative",
+            ValidationError::Unsorted { .. } => "unsorted",
+            ValidationError::Crossed { .. } => "crossed",
+            ValidationError::FutureTimestamp { .. } => "future_timestamp",
+            ValidationError::StaleTimestamp { .. } => "stale_timestamp",
+            ValidationError::MissingTimestamp { .. } => "missing_timestamp",
+            ValidationError::OffTick { .. } => "off_tick",
+            ValidationError::OffLot { .. } => "off_lot",
+        }
+    }
+
+    /// Name of the offending field.
+    pub fn field(&self) -> &str {
+        match self {
+            ValidationError::NonFinite { field }
+            | ValidationError::Negative { field, .. }
+            | ValidationError::Unsorted { field }
+            | ValidationError::FutureTimestamp { field, .. }
+            | ValidationError::StaleTimestamp { field, .. }
+            | ValidationError::MissingTimestamp { field }
+            | ValidationError::OffTick { field, .. }
+            | ValidationError::OffLot { field, .. } => field,
+            ValidationError::Crossed { .. } => "bid_price",
+        }
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::NonFinite { field } => write!(f, "{field} is not finite"),
+            ValidationError::Negative { field, value } => {
+                write!(f, "{field} is negative ({value})")
+            }
+            ValidationError::Unsorted { field } => write!(f, "{field} are not sorted"),
+            ValidationError::Crossed { bid, ask } => {
+                write!(f, "book is crossed (bid {bid} > ask {ask})")
+            }
+            ValidationError::FutureTimestamp { field, ts } => {
+                write!(f, "{field} {ts} is in the future")
+            }
+            ValidationError::StaleTimestamp { field, ts } => {
+                write!(f, "{field} {ts} is too old")
+            }
+            ValidationError::MissingTimestamp { field } => write!(f, "{field} is missing"),
+            ValidationError::OffTick { field, value, step } => {
+                write!(f, "{field} {value} is not a multiple of tick {step}")
+            }
+            ValidationError::OffLot { field, value, step } => {
+                write!(f, "{field} {value} is not a multiple of lot {step}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// Checks applied by [`MdEvent::validate_with`] on top of the intrinsic
+/// checks performed by [`MdEvent::validate`].
+#[derive(Debug, Clone)]
+pub struct ValidationRules {
+    /// Maximum amount an exchange timestamp may lead the ingest clock.
+    pub max_future_skew: Option<Duration>,
+    /// Maximum amount an exchange timestamp may trail the ingest clock.
+    pub max_age: Option<Duration>,
+    /// Check prices and quantities against the tick and lot sizes of the
+    /// instrument's [`ContractSpec`](crate::ContractSpec), when one is known.
+    pub check_steps: bool,
+    /// Events allowed to carry no exchange timestamp, as `exchange` or
+    /// `exchange:Kind` entries. Any other event with a zero timestamp is
+    /// rejected.
+    pub missing_ts_allowed: Vec<String>,
+}
+
+impl Default for ValidationRules {
+    fn default() -> Self {
+        Self {
+            max_future_skew: Some(Duration::from_secs(5)),
+            max_age: Some(Duration::from_secs(24 * 60 * 60)),
+            check_steps: true,
+            // Feeds whose messages have no event time.
+            missing_ts_allowed: [
+                "binance:BookTicker",
+                "binance:DepthSnapshot",
+                "coinex:BookTicker",
+                "coinex:IndexPrice",
+                "lbank",
+            ]
+            .map(String::from)
+            .to_vec(),
+        }
+    }
+}
+
+impl ValidationRules {
+    fn allows_missing_ts(&self, exchange: &str, kind: &str) -> bool {
+        self.missing_ts_allowed
+            .iter()
+            .any(|entry| match entry.split_once(':') {
+                Some((venue, k)) => venue == exchange && k == kind,
+                None => entry == exchange,
+            })
+    }
+}
+
+impl MdEvent {
+    /// Exchange the event originated from.
+    pub fn exchange(&self) -> &str {
+        match &self.event {
+            MdEventKind::Trade(e) => &e.exchange,
+            MdEventKind::DepthL2Update(e) => &e.exchange,
+            MdEventKind::BookTicker(e) => &e.exchange,
+            MdEventKind::MiniTicker(e) => &e.exchange,
+            MdEventKind::Kline(e) => &e.exchange,
+            MdEventKind::DepthSnapshot(e) => &e.exchange,
+            MdEventKind::AvgPrice(e) => &e.exchange,
+            MdEventKind::MarkPrice(e) => &e.exchange,
+            MdEventKind::IndexPrice(e) => &e.exchange,
+            MdEventKind::FundingRate(e) => &e.exchange,
+            MdEventKind::OpenInterest(e) => &e.exchange,
+            MdEventKind::Liquidation(e) => &e.exchange,
+            MdEventKind::InstrumentUpdate(e) => &e.exchange,
+        }
+    }
+
+    /// Exchange-native symbol of the event.
+    pub fn symbol(&self) -> &str {
+        match &self.event {
+            MdEventKind::Trade(e) => &e.symbol,
+            MdEventKind::DepthL2Update(e) => &e.symbol,
+            MdEventKind::BookTicker(e) => &e.symbol,
+            MdEventKind::MiniTicker(e) => &e.symbol,
+            MdEventKind::Kline(e) => &e.symbol,
+            MdEventKind::DepthSnapshot(e) => &e.symbol,
+            MdEventKind::AvgPrice(e) => &e.symbol,
+            MdEventKind::MarkPrice(e) => &e.symbol,
+            MdEventKind::IndexPrice(e) => &e.symbol,
+            MdEventKind::FundingRate(e) => &e.symbol,
+            MdEventKind::OpenInterest(e) => &e.symbol,
+            MdEventKind::Liquidation(e) => &e.symbol,
+            MdEventKind::InstrumentUpdate(e) => &e.symbol,
+        }
+    }
+
+    /// Name of the event variant, as it appears in serialized events.
+    pub fn kind(&self) -> &'static str {
+        match &self.event {
+            MdEventKind::Trade(_) => "Trade",
+            MdEventKind::DepthL2Update(_) => "DepthL2Update",
+            MdEventKind::BookTicker(_) => "BookTicker",
+            MdEventKind::MiniTicker(_) => "MiniTicker",
+            MdEventKind::Kline(_) => "Kline",
+            MdEventKind::DepthSnapshot(_) => "DepthSnapshot",
+            MdEventKind::AvgPrice(_) => "AvgPrice",
+            MdEventKind::MarkPrice(_) => "MarkPrice",
+            MdEventKind::IndexPrice(_) => "IndexPrice",
+            MdEventKind::FundingRate(_) => "FundingRate",
+            MdEventKind::OpenInterest(_) => "OpenInterest",
+            MdEventKind::Liquidation(_) => "Liquidation",
+            MdEventKind::InstrumentUpdate(_) => "InstrumentUpdate",
+        }
+    }
+
+    /// Monotonic ingest time in nanoseconds since the process started.
+    pub fn ingest_ts_monotonic(&self) -> u64 {
+        match &self.event {
+            MdEventKind::Trade(e) => e.ingest_ts_monotonic,
+            MdEventKind::DepthL2Update(e) => e.ingest_ts_monotonic,
+            MdEventKind::BookTicker(e) => e.ingest_ts_monotonic,
+            MdEventKind::MiniTicker(e) => e.ingest_ts_monotonic,
+            MdEventKind::Kline(e) => e.ingest_ts_monotonic,
+            MdEventKind::DepthSnapshot(e) => e.ingest_ts_monotonic,
+            MdEventKind::AvgPrice(e) => e.ingest_ts_monotonic,
+            MdEventKind::MarkPrice(e) => e.ingest_ts_monotonic,
+            MdEventKind::IndexPrice(e) => e.ingest_ts_monotonic,
+            MdEventKind::FundingRate(e) => e.ingest_ts_monotonic,
+            MdEventKind::OpenInterest(e) => e.ingest_ts_monotonic,
+            MdEventKind::Liquidation(e) => e.ingest_ts_monotonic,
+            MdEventKind::InstrumentUpdate(e) => e.ingest_ts_monotonic,
+        }
+    }
+
+    /// Wall-clock time the event was ingested, in nanoseconds since the Unix
+    /// epoch.
+    pub fn ingest_ts_utc(&self) -> u64 {
+        match &self.event {
+            MdEventKind::Trade(e) => e.ingest_ts_utc,
+            MdEventKind::DepthL2Update(e) => e.ingest_ts_utc,
+            MdEventKind::BookTicker(e) => e.ingest_ts_utc,
+            MdEventKind::MiniTicker(e) => e.ingest_ts_utc,
+            MdEventKind::Kline(e) => e.ingest_ts_utc,
+            MdEventKind::DepthSnapshot(e) => e.ingest_ts_utc,
+            MdEventKind::AvgPrice(e) => e.ingest_ts_utc,
+            MdEventKind::MarkPrice(e) => e.ingest_ts_utc,
+            MdEventKind::IndexPrice(e) => e.ingest_ts_utc,
+            MdEventKind::FundingRate(e) => e.ingest_ts_utc,
+            MdEventKind::OpenInterest(e) => e.ingest_ts_utc,
+            MdEventKind::Liquidation(e) => e.ingest_ts_utc,
+            MdEventKind::InstrumentUpdate(e) => e.ingest_ts_utc,
+        }
+    }
+
+    /// Exchange timestamp of the event and the name of the field holding it.
+    fn exchange_ts(&self) -> (&'static str, u64) {
+        match &self.event {
+            MdEventKind::Trade(e) => ("timestamp", e.timestamp),
+            MdEventKind::DepthL2Update(e) => ("ts", e.ts),
+            MdEventKind::BookTicker(e) => ("ts", e.ts),
+            MdEventKind::MiniTicker(e) => ("ts", e.ts),
+            MdEventKind::Kline(e) => ("ts", e.ts),
+            MdEventKind::DepthSnapshot(e) => ("ts", e.ts),
+            MdEventKind::AvgPrice(e) => ("ts", e.ts),
+            MdEventKind::MarkPrice(e) => ("ts", e.ts),
+            MdEventKind::IndexPrice(e) => ("ts", e.ts),
+            MdEventKind::FundingRate(e) => ("ts", e.ts),
+            MdEventKind::OpenInterest(e) => ("ts", e.ts),
+            MdEventKind::Liquidation(e) => ("ts", e.ts),
+            MdEventKind::InstrumentUpdate(e) => ("ts", e.ts),
+        }
+    }
+
+    /// Check invariants that hold for every event regardless of
+    /// configuration: finite values, non-negative sizes, sorted and
+    /// uncrossed books.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        match &self.event {
+            MdEventKind::Trade(t) => {
+                finite("price", t.price)?;
+                non_negative("quantity", t.quantity)?;
+            }
+            MdEventKind::BookTicker(b) => {
+                finite("bid_price", b.bid_price)?;
+                finite("ask_price", b.ask_price)?;
+                non_negative("bid_quantity", b.bid_quantity)?;
+                non_negative("ask_quantity", b.ask_quantity)?;
+                uncrossed(b.bid_price, b.ask_price)?;
+            }
+            MdEventKind::MiniTicker(m) => {
+                finite("open", m.open)?;
+                finite("high", m.high)?;
+                finite("low", m.low)?;
+                finite("close", m.close)?;
+                non_negative("volume", m.volume)?;
+                non_negative("quote_volume", m.quote_volume)?;
+            }
+            MdEventKind::Kline(k) => {
+                finite("open", k.open)?;
+                finite("close", k.close)?;
+                finite("high", k.high)?;
+                finite("low", k.low)?;
+                non_negative("volume", k.volume)?;
+            }
+            MdEventKind::DepthL2Update(d) => {
+                book(&d.bids, &d.asks)?;
+            }
+            MdEventKind::DepthSnapshot(d) => {
+                book(&d.bids, &d.asks)?;
+                if let (Some(bid), Some(ask)) = (d.bids.first(), d.asks.first()) {
+                    uncrossed(bid.price, ask.price)?;
+                }
+            }
+            MdEventKind::AvgPrice(p) => finite("price", p.price)?,
+            MdEventKind::MarkPrice(p) => finite("price", p.price)?,
+            MdEventKind::IndexPrice(p) => finite("price", p.price)?,
+            MdEventKind::FundingRate(f) => finite("rate", f.rate)?,
+            MdEventKind::OpenInterest(o) => non_negative("open_interest", o.open_interest)?,
+            MdEventKind::Liquidation(l) => {
+                finite("price", l.price)?;
+                non_negative("quantity", l.quantity)?;
+            }
+            MdEventKind::InstrumentUpdate(_) => {}
+        }
+        Ok(())
+    }
+
+    /// Run [`validate`](Self::validate) followed by the configurable checks
+    /// in `rules`. `now_ns` is the ingest wall clock in nanoseconds since the
+    /// Unix epoch.
+    pub fn validate_with(
+        &self,
+        rules: &ValidationRules,
+        now_ns: u64,
+    ) -> Result<(), ValidationError> {
+        self.validate()?;
+
+        // Zero means the venue did not supply a timestamp.
+        let (field, ts) = self.exchange_ts();
+        if ts == 0 {
+            if !rules.allows_missing_ts(self.exchange(), self.kind()) {
+                return Err(ValidationError::MissingTimestamp {
+                    field: field.into(),
+                });
+            }
+        } else {
+            let ts_ns = to_nanos(ts);
+            if let Some(skew) = rules.max_future_skew {
+                if ts_ns > now_ns.saturating_add(skew.as_nanos() as u64) {
+                    return Err(ValidationError::FutureTimestamp {
+                        field: field.into(),
+                        ts,
+                    });
+                }
+            }
+            if let Some(age) = rules.max_age {
+                if ts_ns < now_ns.saturating_sub(age.as_nanos() as u64) {
+                    return Err(ValidationError::StaleTimestamp {
+                        field: field.into(),
+                        ts,
+                    });
+                }
+            }
+        }
+
+        if rules.check_steps {
+            let resolved = resolve(self.exchange(), self.symbol());
+            if let Some(spec) = resolved.as_ref().and_then(|r| r.spec.as_ref()) {
+                let tick = spec.price_step.filter(|s| *s > 0.0);
+                let lot = spec.lot_step.filter(|s| *s > 0.0);
+                self.check_steps(tick, lot)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn check_steps(&self, tick: Option<f64>, lot: Option<f64>) -> Result<(), ValidationError> {
+        let price = |field: &str, value: f64| match tick {
+            Some(step) if !on_step(value, step) => Err(ValidationError::OffTick {
+                field: field.into(),
+                value,
+                step,
+            }),
+            _ => Ok(()),
+        };
+        let quantity = |field: &str, value: f64| match lot {
+            Some(step) if !on_step(value, step) => Err(ValidationError::OffLot {
+                field: field.into(),
+                value,
+                step,
+            }),
+            _ => Ok(()),
+        };
+        let levels = |field: &str, levels: &[Level]| {
+            levels.iter().try_for_each(|l| {
+                price(&format!("{field}.price"), l.price)?;
+                quantity(&format!("{field}.quantity"), l.quantity)
+            })
+        };
+
+        match &self.event {
+            MdEventKind::Trade(t) => {
+                price("price", t.price)?;
+                quantity("quantity", t.quantity)?;
+            }
+            MdEventKind::BookTicker(b) => {
+                price("bid_price", b.bid_price)?;
+                price("ask_price", b.ask_price)?;
+                quantity("bid_quantity", b.bid_quantity)?;
+                quantity("ask_quantity", b.ask_quantity)?;
+            }
+            MdEventKind::DepthL2Update(d) => {
+                levels("bids", &d.bids)?;
+                levels("asks", &d.asks)?;
+            }
+            MdEventKind::DepthSnapshot(d) => {
+                levels("bids", &d.bids)?;
+                levels("asks", &d.asks)?;
+            }
+            MdEventKind::Liquidation(l) => {
+                price("price", l.price)?;
+                quantity("quantity", l.quantity)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+fn finite(field: &str, value: f64) -> Result<(), ValidationError> {
+    if value.is_finite() {
+        Ok(())
+    } else {
+        Err(ValidationError::NonFinite {
+            field: field.into(),
+        })
+    }
+}
+
+fn non_negative(field: &str, value: f64) -> Result<(), ValidationError> {
+    finite(field, value)?;
+    if value < 0.0 {
+        return Err(ValidationError::Negative {
+            field: field.into(),
+            value,
+        });
+    }
+    Ok(())
+}
+
+/// Zero prices mean an empty side and never count as crossed.
+fn uncrossed(bid: f64, ask: f64) -> Result<(), ValidationError> {
+    if bid > 0.0 && ask > 0.0 && bid > ask {
+        return Err(ValidationError::Crossed { bid, ask });
+    }
+    Ok(())
+}
+
+fn book(bids: &[Level], asks: &[Level]) -> Result<(), ValidationError> {
+    for (field, levels) in [("bids", bids), ("asks", asks)] {
+        for l in levels {
+            finite(&format!("{field}.price"), l.price)?;
+            non_negative(&format!("{field}.quantity"), l.quantity)?;
+        }
+    }
+    if !is_sorted_desc(bids) {
+        return Err(ValidationError::Unsorted {
+            field: "bids".into(),
+        });
+    }
+    if !is_sorted_asc(asks) {
+        return Err(ValidationError::Unsorted {
+            field: "asks".into(),
+        });
+    }
+    Ok(())
+}
+
+/// Venues report timestamps in seconds, milliseconds, microseconds or
+/// nanoseconds; infer the unit from the magnitude.
+fn to_nanos(ts: u64) -> u64 {
+    if ts < 100_000_000_000 {
+        ts.saturating_mul(1_000_000_000)
+    } else if ts < 100_000_000_000_000 {
+        ts.saturating_mul(1_000_000)
+    } else if ts < 100_000_000_000_000_000 {
+        ts.saturating_mul(1_000)
+    } else {
+        ts
+    }
+}
+
+fn on_step(value: f64, step: f64) -> bool {
+    let ratio = value / step;
+    (ratio - ratio.round()).abs() <= 1e-6
+}
@@ -0,0 +1,133 @@
+use canonical::symbol::{
+    get_spec, init, instruments, normalize_symbol, resolve, ContractSpec, Instrument, InstrumentId,
+    VenueType,
+};
+use std::sync::Arc;
+
+fn instrument(exchange: &str, symbol: &str, spec: ContractSpec) -> Instrument {
+    Instrument {
+        exchange: exchange.to_string(),
+        symbol: symbol.to_string(),
+        spec,
+    }
+}
+
+fn spec(venue: VenueType, base: &str, quote: &str) -> ContractSpec {
+    ContractSpec {
+        venue,
+        base: base.into(),
+        quote: quote.into(),
+        lot_step: Some(0.001),
+        price_step: Some(0.1),
+        ..Default::default()
+    }
+}
+
+fn id(s: &str) -> InstrumentId {
+    s.parse().unwrap()
+}
+
+#[test]
+fn builds_table_from_instruments_with_overrides() {
+    let mut path = std::env::temp_dir();
+    path.push(format!(
+        "symbol_overrides_{}.json",
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos()
+    ));
+    let data = r#"
+        {
+            "assets": { "BTCB": "BTC" },
+            "symbols": [
+                {
+                    "id": "BTC-USDT",
+                    "spec": { "venue": "spot", "base": "BTC", "quote": "USDT", "price_step": 0.01 },
+                    "aliases": { "latoken": ["BTC/USDT"] }
+                }
+            ]
+        }
+    "#;
+    std::fs::write(&path, data).unwrap();
+
+    let inverse = ContractSpec {
+        settle: "BTC".into(),
+        ..spec(VenueType::Futures, "BTC", "USD")
+    };
+    let dated = ContractSpec {
+        expiry: Some(1_758_873_600_000),
+        ..spec(VenueType::Futures, "XBT", "USDT")
+    };
+    init(
+        vec![
+            instrument("binance", "BTCUSDT", spec(VenueType::Spot, "BTC", "USDT")),
+            instrument("binance", "BTCUSD_PERP", inverse),
+            instrument("gateio", "BTC_USDT", spec(VenueType::Spot, "BTC", "USDT")),
+            instrument(
+                "gateio",
+                "BTC_USDT",
+                spec(VenueType::Futures, "BTC", "USDT"),
+            ),
+            instrument(
+                "kucoin",
+                "XBTUSDTM",
+                spec(VenueType::Futures, "XBT", "USDT"),
+            ),
+            instrument("kucoin", "XBTMU25", dated),
+            instrument("pancake", "BTCBUSDT", spec(VenueType::Spot, "BTCB", "USDT")),
+        ],
+        Some(path.to_str().unwrap()),
+    )
+    .unwrap();
+
+    assert_eq!(normalize_symbol("binance", "btcusdt"), "BTC-USDT");
+    assert_eq!(
+        normalize_symbol("binance", "BTCUSD_PERP"),
+        "BTC-USD-PERP:BTC"
+    );
+    // Spot wins when a venue reuses a raw symbol across markets.
+    assert_eq!(normalize_symbol("gateio", "BTC_USDT"), "BTC-USDT");
+    assert_eq!(normalize_symbol("kucoin", "XBTUSDTM"), "BTC-USDT-PERP");
+    assert_eq!(normalize_symbol("kucoin", "XBTMU25"), "BTC-USDT-20250926");
+    assert_eq!(normalize_symbol("pancake", "BTCBUSDT"), "BTC-USDT");
+    assert_eq!(normalize_symbol("latoken", "BTC/USDT"), "BTC-USDT");
+    assert_eq!(normalize_symbol("binance", "XBT_USDT"), "~BTC-USDT");
+    assert_eq!(normalize_symbol("binance", "ETHUSDT"), "~ETHUSDT");
+
+    // Lookups of a venue symbol share one entry, carrying that venue's spec.
+    let upper = resolve("binance", "BTCUSDT").unwrap();
+    assert!(Arc::ptr_eq(&upper, &resolve("binance", "btcusdt").unwrap()));
+    assert_eq!(upper.name, "BTC-USDT");
+    assert_eq!(upper.spec.as_ref().unwrap().price_step, Some(0.1));
+    let gateio = resolve("gateio", "BTC_USDT").unwrap();
+    assert_eq!(gateio.id, upper.id);
+    assert_eq!(gateio.spec.as_ref().unwrap().venue, VenueType::Spot);
+    let latoken = resolve("latoken", "BTC/USDT").unwrap();
+    assert_eq!(latoken.spec.as_ref().unwrap().price_step, Some(0.01));
+    assert!(resolve("binance", "ETHUSDT").is_none());
+
+    let spot = normalize_symbol("binance", "BTCUSDT");
+    let perp = normalize_symbol("kucoin", "XBTUSDTM");
+    assert!(spot.same_pair(&perp));
+    assert_ne!(spot, perp);
+
+    // The override replaces the fetched spec.
+    let spec = get_spec(&id("BTC-USDT")).unwrap();
+    assert_eq!(spec.price_step, Some(0.01));
+    assert_eq!(spec.lot_step, None);
+    assert_eq!(
+        get_spec(&id("BTC-USDT-PERP")).unwrap().lot_step,
+        Some(0.001)
+    );
+
+    let listed = instruments();
+    let kucoin: Vec<_> = listed.iter().filter(|i| i.0 == "kucoin").collect();
+    assert_eq!(kucoin.len(), 2);
+    assert_eq!(kucoin[0].1, "XBTMU25");
+    assert_eq!(kucoin[0].2, "BTC-USDT-20250926");
+    assert_eq!(kucoin[1].3.as_ref().unwrap().venue, VenueType::Futures);
+    assert!(listed.iter().any(|i| i.0 == "latoken" && i.1 == "BTC/USDT"));
+
+    let _ = std::fs::remove_file(path);
+}
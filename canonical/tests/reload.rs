@@ -0,0 +1,89 @@
+use canonical::symbol::{
+    canonical_asset, get_spec, init, normalize_symbol, reload, ContractSpec, Instrument, VenueType,
+};
+use canonical::InstrumentChange;
+
+fn instrument(symbol: &str, base: &str, price_step: f64) -> Instrument {
+    Instrument {
+        exchange: "binance".into(),
+        symbol: symbol.into(),
+        spec: ContractSpec {
+            venue: VenueType::Spot,
+            base: base.into(),
+            quote: "USDT".into(),
+            price_step: Some(price_step),
+            ..Default::default()
+        },
+    }
+}
+
+#[test]
+fn reload_swaps_table_and_reports_changes() {
+    init(
+        vec![
+            instrument("BTCUSDT", "BTC", 0.01),
+            instrument("ETHUSDT", "ETH", 0.01),
+        ],
+        None,
+    )
+    .unwrap();
+    assert_eq!(normalize_symbol("binance", "ETHUSDT"), "ETH-USDT");
+
+    let updates = reload(
+        vec![
+            instrument("BTCUSDT", "BTC", 0.1),
+            instrument("SOLUSDT", "SOL", 0.01),
+        ],
+        None,
+    )
+    .unwrap();
+
+    let changes: Vec<_> = updates
+        .iter()
+        .map(|u| (u.symbol.as_str(), u.change))
+        .collect();
+    assert_eq!(
+        changes,
+        vec![
+            ("BTCUSDT", InstrumentChange::Updated),
+            ("ETHUSDT", InstrumentChange::Removed),
+            ("SOLUSDT", InstrumentChange::Added),
+        ]
+    );
+    assert_eq!(updates[0].instrument, "BTC-USDT");
+    assert_eq!(updates[0].spec.as_ref().unwrap().price_step, Some(0.1));
+    assert_eq!(updates[1].spec.as_ref().unwrap().price_step, Some(0.01));
+
+    assert_eq!(normalize_symbol("binance", "SOLUSDT"), "SOL-USDT");
+    assert_eq!(normalize_symbol("binance", "ETHUSDT"), "~ETHUSDT");
+    let id = "BTC-USDT".parse().unwrap();
+    assert_eq!(get_spec(&id).unwrap().price_step, Some(0.1));
+
+    // Reloading the same instruments changes nothing.
+    let updates = reload(
+        vec![
+            instrument("BTCUSDT", "BTC", 0.1),
+            instrument("SOLUSDT", "SOL", 0.01),
+        ],
+        None,
+    )
+    .unwrap();
+    assert!(updates.is_empty());
+
+    // Asset aliases come and go with the override file that declares them,
+    // and a reload that fails leaves them alone.
+    let path = std::env::temp_dir().join(format!("reload_assets_{}.json", std::process::id()));
+    std::fs::write(&path, r#"{ "assets": { "WBTC": "BTC" } }"#).unwrap();
+    let overrides = path.to_str().unwrap();
+    reload(vec![instrument("WBTCUSDT", "WBTC", 0.01)], Some(overrides)).unwrap();
+    assert_eq!(normalize_symbol("binance", "WBTCUSDT"), "BTC-USDT");
+
+    std::fs::write(&path, "{ not json").unwrap();
+    assert!(reload(vec![instrument("WBTCUSDT", "WBTC", 0.01)], Some(overrides)).is_err());
+    assert_eq!(canonical_asset("WBTC"), "BTC");
+
+    let _ = std::fs::remove_file(&path);
+    reload(vec![instrument("WBTCUSDT", "WBTC", 0.01)], None).unwrap();
+    assert_eq!(normalize_symbol("binance", "WBTCUSDT"), "WBTC-USDT");
+    assert_eq!(canonical_asset("WBTC"), "WBTC");
+}
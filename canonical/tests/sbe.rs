@@ -0,0 +1,203 @@
+use canonical::sbe::{frames, Dictionary, Encoder, Frame};
+use canonical::{
+    BookKind, BookTicker, DepthL2Update, FundingRate, Level, MdEvent, MdEventKind, Side, Trade,
+    SCHEMA_VERSION,
+};
+
+fn md(event: MdEventKind) -> MdEvent {
+    MdEvent {
+        schema_version: SCHEMA_VERSION,
+        event,
+        validation: None,
+    }
+}
+
+fn trade() -> MdEvent {
+    md(MdEventKind::Trade(Trade {
+        exchange: "binance".into(),
+        symbol: "BTCUSDT".into(),
+        price: 100.5,
+        quantity: 2.0,
+        trade_id: Some(7),
+        buyer_order_id: None,
+        seller_order_id: Some(9),
+        timestamp: 1_000,
+        side: Some(Side::Sell),
+        ingest_ts_monotonic: 11,
+        ingest_ts_utc: 12,
+        seq_no: 3,
+        ..Default::default()
+    }))
+}
+
+fn book_ticker() -> MdEvent {
+    md(MdEventKind::BookTicker(BookTicker {
+        exchange: "binance".into(),
+        symbol: "ETHUSDT".into(),
+        ts: 5,
+        bid_price: 1.0,
+        bid_quantity: 2.0,
+        ask_price: 1.5,
+        ask_quantity: 3.0,
+        seq_no: 4,
+        ..Default::default()
+    }))
+}
+
+fn depth() -> MdEvent {
+    let level = |price, quantity, kind| Level {
+        schema_version: SCHEMA_VERSION,
+        price,
+        quantity,
+        kind,
+    };
+    md(MdEventKind::DepthL2Update(DepthL2Update {
+        exchange: "kucoin".into(),
+        symbol: "BTC-USDT".into(),
+        ts: 9,
+        bids: vec![
+            level(10.0, 1.0, BookKind::Bid),
+            level(9.5, 2.0, BookKind::Bid),
+        ],
+        asks: vec![level(10.5, 0.5, BookKind::Ask)],
+        first_update_id: Some(1),
+        final_update_id: Some(2),
+        previous_final_update_id: None,
+        ..Default::default()
+    }))
+}
+
+fn decode_all(buf: &[u8]) -> Vec<MdEvent> {
+    let mut dict = Dictionary::new();
+    frames(buf)
+        .filter_map(|msg| dict.decode(&msg.unwrap()).unwrap())
+        .collect()
+}
+
+#[test]
+fn hot_types_roundtrip() {
+    let events = vec![trade(), book_ticker(), depth()];
+    let mut enc = Encoder::new();
+    let mut buf = Vec::new();
+    for ev in &events {
+        enc.encode_with_dictionary(ev, &mut buf).unwrap();
+    }
+    assert_eq!(decode_all(&buf), events);
+}
+
+#[test]
+fn other_kinds_fall_back_to_json() {
+    let ev = md(MdEventKind::FundingRate(FundingRate {
+        exchange: "binance".into(),
+        symbol: "BTCUSDT".into(),
+        rate: 0.0001,
+        ..Default::default()
+    }));
+    let mut enc = Encoder::new();
+    let mut buf = Vec::new();
+    enc.encode_with_dictionary(&ev, &mut buf).unwrap();
+    let msg = frames(&buf).next().unwrap().unwrap();
+    assert!(matches!(msg.frame, Frame::Json(_)));
+    assert_eq!(decode_all(&buf), vec![ev]);
+}
+
+#[test]
+fn dictionary_is_announced_once() {
+    let mut enc = Encoder::new();
+    let mut first = Vec::new();
+    let mut second = Vec::new();
+    enc.encode_with_dictionary(&trade(), &mut first).unwrap();
+    enc.encode_with_dictionary(&trade(), &mut second).unwrap();
+
+    let dict_frames = |buf: &[u8]| {
+        frames(buf)
+            .filter(|m| matches!(m.as_ref().unwrap().frame, Frame::Dictionary(_)))
+            .count()
+    };
+    assert_eq!(dict_frames(&first), 2);
+    assert_eq!(dict_frames(&second), 0);
+
+    enc.reset();
+    let mut third = Vec::new();
+    enc.encode_with_dictionary(&trade(), &mut third).unwrap();
+    assert_eq!(dict_frames(&third), 2);
+}
+
+#[test]
+fn side_channel_dictionary() {
+    let mut enc = Encoder::new();
+    let mut event = Vec::new();
+    let mut dict = Vec::new();
+    enc.encode(&book_ticker(), &mut event).unwrap();
+    assert!(enc.take_dictionary(&mut dict));
+    assert!(!enc.take_dictionary(&mut dict));
+
+    let mut names = Dictionary::new();
+    for msg in frames(&dict) {
+        assert!(names.decode(&msg.unwrap()).unwrap().is_none());
+    }
+    let msg = frames(&event).next().unwrap().unwrap();
+    match msg.frame {
+        Frame::BookTicker(r) => {
+            assert_eq!(names.exchange(r.exchange_id()), Some("binance"));
+            assert_eq!(names.symbol(r.symbol_id()), Some("ETHUSDT"));
+            assert_eq!(r.ask_price(), 1.5);
+            assert_eq!(r.seq_no(), 4);
+        }
+        other => panic!("unexpected frame {other:?}"),
+    }
+}
+
+#[test]
+fn readers_borrow_levels() {
+    let mut enc = Encoder::new();
+    let mut buf = Vec::new();
+    enc.encode(&depth(), &mut buf).unwrap();
+    let msg = frames(&buf).next().unwrap().unwrap();
+    match msg.frame {
+        Frame::DepthL2Update(r) => {
+            assert_eq!(r.bids().len(), 2);
+            assert_eq!(r.bids().get(1), Some((9.5, 2.0)));
+            assert_eq!(r.asks().iter().collect::<Vec<_>>(), vec![(10.5, 0.5)]);
+            assert_eq!(r.previous_final_update_id(), None);
+            assert_eq!(r.final_update_id(), Some(2));
+        }
+        other => panic!("unexpected frame {other:?}"),
+    }
+}
+
+#[test]
+fn missing_dictionary_entry_is_an_error() {
+    let mut enc = Encoder::new();
+    let mut buf = Vec::new();
+    enc.encode(&trade(), &mut buf).unwrap();
+    let msg = frames(&buf).next().unwrap().unwrap();
+    assert!(Dictionary::new().decode(&msg).is_err());
+}
+
+#[test]
+fn truncated_frame_is_rejected() {
+    let mut enc = Encoder::new();
+    let mut buf = Vec::new();
+    enc.encode_with_dictionary(&trade(), &mut buf).unwrap();
+    buf.truncate(buf.len() - 1);
+    assert!(frames(&buf).any(|m| m.is_err()));
+}
+
+#[test]
+fn long_names_are_cut_on_a_char_boundary() {
+    // The two byte `é` straddles the u16 length limit.
+    let symbol = format!("{}é", "x".repeat(u16::MAX as usize - 1));
+    let mut ev = trade();
+    if let MdEventKind::Trade(t) = &mut ev.event {
+        t.symbol = symbol.clone();
+    }
+    let mut enc = Encoder::new();
+    let mut buf = Vec::new();
+    enc.encode_with_dictionary(&ev, &mut buf).unwrap();
+    let decoded = decode_all(&buf);
+    match &decoded[0].event {
+        MdEventKind::Trade(t) => assert_eq!(t.symbol, symbol[..symbol.len() - 2]),
+        other => panic!("unexpected event {other:?}"),
+    }
+}
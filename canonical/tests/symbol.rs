@@ -1,4 +1,4 @@
-use canonical::symbol::{get_spec, load_from_path, normalize_symbol};
+use canonical::symbol::{get_spec, load_from_path, normalize_symbol, InstrumentId, VenueType};
 
 #[test]
 fn normalize_and_get_spec() {
@@ -34,10 +34,47 @@ fn normalize_and_get_spec() {
     assert_eq!(normalize_symbol("binance", "btcusdt"), "BTC-USD");
     assert_eq!(normalize_symbol("binance", "BTCUSDT"), "BTC-USD");
 
-    let spec = get_spec("BTC-USD").unwrap();
+    let spec = get_spec(&"BTC-USD".parse().unwrap()).unwrap();
     assert_eq!(spec.lot_step, Some(0.01));
     assert_eq!(spec.price_step, Some(0.1));
 
     // Clean up temporary file
     let _ = std::fs::remove_file(path);
 }
+
+#[test]
+fn instrument_id_round_trips_canonical_form() {
+    for raw in [
+        "BTC-USDT",
+        "BTC-USDT-PERP",
+        "BTC-USD-PERP:BTC",
+        "ETH-USD-20250926:ETH",
+    ] {
+        let id: InstrumentId = raw.parse().unwrap();
+        assert_eq!(id.to_string(), raw);
+    }
+
+    let spot: InstrumentId = "BTC-USDT".parse().unwrap();
+    let perp: InstrumentId = "BTC-USDT-PERP".parse().unwrap();
+    assert_eq!(spot.venue, VenueType::Spot);
+    assert_eq!(perp.venue, VenueType::Futures);
+    assert!(spot.same_pair(&perp));
+    assert_ne!(spot, perp);
+
+    // Settlement in the quote asset is implied.
+    let linear: InstrumentId = "BTC-USDT-PERP:USDT".parse().unwrap();
+    assert_eq!(linear, perp);
+
+    let dated: InstrumentId = "ETH-USD-20250926".parse().unwrap();
+    assert_eq!(dated.expiry, Some(20250926));
+
+    // Unresolved ids keep their unknown venue through a round trip.
+    for raw in ["ETH_USDT", "ETHUSDT"] {
+        let id = InstrumentId::unresolved(raw);
+        let back: InstrumentId = id.to_string().parse().unwrap();
+        assert_eq!(back, id);
+        assert_eq!(back.venue, VenueType::Unknown);
+    }
+    assert_eq!(InstrumentId::unresolved("ETH_USDT"), "~ETH-USDT");
+    assert!("~".parse::<InstrumentId>().is_err());
+}
@@ -0,0 +1,207 @@
+use canonical::symbol::{init, ContractSpec, Instrument, VenueType};
+use canonical::{
+    BookTicker, MdEvent, MdEventKind, Trade, ValidationError, ValidationRules, SCHEMA_VERSION,
+};
+use std::time::Duration;
+
+const NOW_NS: u64 = 1_700_000_000_000_000_000;
+
+fn md(event: MdEventKind) -> MdEvent {
+    MdEvent {
+        schema_version: SCHEMA_VERSION,
+        event,
+        validation: None,
+    }
+}
+
+fn trade(price: f64, quantity: f64, timestamp: u64) -> MdEvent {
+    md(MdEventKind::Trade(Trade {
+        exchange: "test".into(),
+        symbol: "BTCUSDT".into(),
+        price,
+        quantity,
+        timestamp,
+        ..Default::default()
+    }))
+}
+
+#[test]
+fn crossed_book_ticker_rejected() {
+    let ev = md(MdEventKind::BookTicker(BookTicker {
+        bid_price: 101.0,
+        ask_price: 100.0,
+        ..Default::default()
+    }));
+    assert_eq!(
+        ev.validate(),
+        Err(ValidationError::Crossed {
+            bid: 101.0,
+            ask: 100.0
+        })
+    );
+
+    // An empty side is not a crossed book.
+    let ev = md(MdEventKind::BookTicker(BookTicker {
+        bid_price: 101.0,
+        ask_price: 0.0,
+        ..Default::default()
+    }));
+    assert!(ev.validate().is_ok());
+}
+
+#[test]
+fn non_finite_and_negative_values_rejected() {
+    let err = trade(f64::NAN, 1.0, 0).validate().unwrap_err();
+    assert_eq!(err.reason(), "non_finite");
+    assert_eq!(err.field(), "price");
+
+    let err = trade(1.0, -1.0, 0).validate().unwrap_err();
+    assert_eq!(err.reason(), "negative");
+    assert_eq!(err.field(), "quantity");
+}
+
+#[test]
+fn timestamp_window_infers_units() {
+    let rules = ValidationRules {
+        max_future_skew: Some(Duration::from_secs(5)),
+        max_age: Some(Duration::from_secs(60)),
+        check_steps: false,
+        missing_ts_allowed: Vec::new(),
+    };
+    let now_ms = NOW_NS / 1_000_000;
+
+    assert!(trade(1.0, 1.0, now_ms)
+        .validate_with(&rules, NOW_NS)
+        .is_ok());
+    assert!(trade(1.0, 1.0, NOW_NS)
+        .validate_with(&rules, NOW_NS)
+        .is_ok());
+    let err = trade(1.0, 1.0, 0)
+        .validate_with(&rules, NOW_NS)
+        .unwrap_err();
+    assert_eq!(
+        err,
+        ValidationError::MissingTimestamp {
+            field: "timestamp".into()
+        }
+    );
+
+    let err = trade(1.0, 1.0, now_ms + 10_000)
+        .validate_with(&rules, NOW_NS)
+        .unwrap_err();
+    assert_eq!(err.reason(), "future_timestamp");
+
+    let err = trade(1.0, 1.0, now_ms / 1_000 - 120)
+        .validate_with(&rules, NOW_NS)
+        .unwrap_err();
+    assert_eq!(err.reason(), "stale_timestamp");
+
+    let relaxed = ValidationRules {
+        max_age: None,
+        ..rules.clone()
+    };
+    assert!(trade(1.0, 1.0, now_ms / 1_000 - 120)
+        .validate_with(&relaxed, NOW_NS)
+        .is_ok());
+
+    // Opting out by venue or by venue and kind.
+    for entry in ["test", "test:Trade"] {
+        let rules = ValidationRules {
+            missing_ts_allowed: vec![entry.into()],
+            ..rules.clone()
+        };
+        assert!(trade(1.0, 1.0, 0).validate_with(&rules, NOW_NS).is_ok());
+    }
+    let rules = ValidationRules {
+        missing_ts_allowed: vec!["test:BookTicker".into()],
+        ..rules
+    };
+    assert!(trade(1.0, 1.0, 0).validate_with(&rules, NOW_NS).is_err());
+}
+
+#[test]
+fn steps_checked_against_contract_spec() {
+    let path = std::env::temp_dir().join(format!("validation_symbols_{}.json", std::process::id()));
+    let data = r#"
+        [
+            {
+                "id": "BTC-USDT",
+                "spec": { "venue": "spot", "base": "BTC", "quote": "USDT", "lot_step": 0.001, "price_step": 0.01 },
+                "aliases": { "test": ["BTCUSDT"] }
+            }
+        ]
+    "#;
+    std::fs::write(&path, data).unwrap();
+    // Another venue lists the same id with a coarser tick.
+    let other = Instrument {
+        exchange: "other".into(),
+        symbol: "BTC_USDT".into(),
+        spec: ContractSpec {
+            venue: VenueType::Spot,
+            base: "BTC".into(),
+            quote: "USDT".into(),
+            price_step: Some(0.5),
+            ..Default::default()
+        },
+    };
+    init(vec![other], Some(path.to_str().unwrap())).unwrap();
+    let _ = std::fs::remove_file(&path);
+
+    let rules = ValidationRules {
+        missing_ts_allowed: vec!["test".into()],
+        ..Default::default()
+    };
+    assert!(trade(30000.01, 0.123, 0)
+        .validate_with(&rules, NOW_NS)
+        .is_ok());
+
+    let err = trade(30000.015, 0.123, 0)
+        .validate_with(&rules, NOW_NS)
+        .unwrap_err();
+    assert_eq!(err.reason(), "off_tick");
+
+    let err = trade(30000.01, 0.1234, 0)
+        .validate_with(&rules, NOW_NS)
+        .unwrap_err();
+    assert_eq!(err.reason(), "off_lot");
+
+    // Each venue's events are checked against its own step.
+    let on_other = |price: f64| {
+        let mut ev = trade(price, 0.1234, 0);
+        if let MdEventKind::Trade(t) = &mut ev.event {
+            t.exchange = "other".into();
+            t.symbol = "BTC_USDT".into();
+        }
+        ev
+    };
+    let rules = ValidationRules {
+        missing_ts_allowed: vec!["test".into(), "other".into()],
+        ..rules
+    };
+    assert!(on_other(30000.5).validate_with(&rules, NOW_NS).is_ok());
+    let err = on_other(30000.01)
+        .validate_with(&rules, NOW_NS)
+        .unwrap_err();
+    assert_eq!(err.reason(), "off_tick");
+
+    let rules = ValidationRules {
+        check_steps: false,
+        ..rules
+    };
+    assert!(trade(30000.015, 0.1234, 0)
+        .validate_with(&rules, NOW_NS)
+        .is_ok());
+}
+
+#[test]
+fn flag_round_trips_through_json() {
+    let mut ev = trade(1.0, -1.0, 0);
+    ev.validation = ev.validate().err();
+    let json = serde_json::to_string(&ev).unwrap();
+    let back: MdEvent = serde_json::from_str(&json).unwrap();
+    assert_eq!(back, ev);
+
+    // Unflagged events omit the field entirely.
+    let json = serde_json::to_value(trade(1.0, 1.0, 0)).unwrap();
+    assert!(json.get("validation").is_none());
+}
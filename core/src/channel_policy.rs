@@ -0,0 +1,364 @@
+//! Queues of an event partition and how they are drained.
+//!
+//! Each `<exchange>:<symbol>` partition splits its events into classes by
+//! [`Channel`]. A class has its own queue with a buffer size and a
+//! [`DropPolicy`] for when it is full, and classes are drained in priority
+//! order, so a burst of one kind of event cannot delay the others.
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use simd_json::serde::from_slice;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::str::FromStr;
+
+use crate::events::Channel;
+
+/// What a full class queue does with a new message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DropPolicy {
+    /// Wait for room, pushing back on the adapter.
+    Block,
+    /// Drop the oldest queued message.
+    DropOldest,
+    /// Drop the new message.
+    DropNewest,
+    /// Keep only the newest message per stream, replacing the queued one in
+    /// place. The queue grows with the number of streams instead of being
+    /// bounded by the buffer, so it is only allowed for snapshot channels.
+    Conflate,
+}
+
+/// How partition queues are implemented.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Transport {
+    /// Mutex-guarded class queues, drained by the consumer in batches.
+    #[default]
+    Queue,
+    /// Pre-allocated lock-free ring buffers per class, drained by the
+    /// consumer in batches. Does not support [`DropPolicy::DropOldest`].
+    Ring,
+}
+
+impl FromStr for Transport {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "queue" => Ok(Transport::Queue),
+            "ring" => Ok(Transport::Ring),
+            other => bail!("unknown event transport {other}"),
+        }
+    }
+}
+
+/// A queue of a partition and the channels routed to it.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChannelClass {
+    /// Name used in metrics, e.g. `md_queue_depth{channel="book"}`.
+    pub name: String,
+    pub channels: Vec<Channel>,
+    /// Messages buffered; `0` takes `event_buffer_size`.
+    #[serde(default)]
+    pub buffer: usize,
+    /// Classes with a lower value are drained first; ties go to the class
+    /// listed first.
+    #[serde(default)]
+    pub priority: u8,
+    #[serde(default = "default_drop_policy")]
+    pub policy: DropPolicy,
+}
+
+fn default_drop_policy() -> DropPolicy {
+    DropPolicy::Block
+}
+
+/// Classes of a partition.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChannelPolicy {
+    pub classes: Vec<ChannelClass>,
+    /// Class for events whose channel no class lists, such as option greeks.
+    pub fallback: String,
+}
+
+/// Channels where a newer message makes queued ones for the same stream
+/// obsolete.
+pub fn is_conflatable(channel: Channel) -> bool {
+    matches!(
+        channel,
+        Channel::Book
+            | Channel::Ticker
+            | Channel::MiniTicker
+            | Channel::AvgPrice
+            | Channel::MarkPrice
+            | Channel::IndexPrice
+            | Channel::FundingRate
+            | Channel::OpenInterest
+    )
+}
+
+impl ChannelPolicy {
+    /// Default classes for a partition buffer of `buffer` messages: depth
+    /// diffs, then trades and liquidations, then conflated tickers and
+    /// derivative prices, then klines and everything else. Nothing but the
+    /// conflated snapshots is ever dropped.
+    pub fn new(buffer: usize) -> Self {
+        let class =
+            |name: &str, channels: &[Channel], buffer: usize, priority, policy| ChannelClass {
+                name: name.into(),
+                channels: channels.to_vec(),
+                buffer: buffer.max(1),
+                priority,
+                policy,
+            };
+        Self {
+            classes: vec![
+                class("book", &[Channel::Depth], buffer, 0, DropPolicy::Block),
+                class("trade", &[Channel::Trade], buffer / 2, 1, DropPolicy::Block),
+                class(
+                    "liquidation",
+                    &[Channel::Liquidation],
+                    buffer / 4,
+                    1,
+                    DropPolicy::Block,
+                ),
+                class(
+                    "ticker",
+                    &[Channel::Book, Channel::Ticker, Channel::MiniTicker],
+                    buffer / 4,
+                    2,
+                    DropPolicy::Conflate,
+                ),
+                class(
+                    "funding",
+                    &[
+                        Channel::AvgPrice,
+                        Channel::MarkPrice,
+                        Channel::IndexPrice,
+                        Channel::FundingRate,
+                        Channel::OpenInterest,
+                    ],
+                    buffer / 4,
+                    2,
+                    DropPolicy::Conflate,
+                ),
+                class("kline", &[Channel::Kline], buffer / 4, 3, DropPolicy::Block),
+                class(
+                    "other",
+                    &[Channel::Instrument],
+                    buffer / 4,
+                    3,
+                    DropPolicy::Block,
+                ),
+            ],
+            fallback: "other".into(),
+        }
+    }
+
+    /// Index of the class `channel` is routed to. `None` stands for events
+    /// without a channel, which go to the fallback class.
+    pub fn class_of(&self, channel: Option<Channel>) -> usize {
+        channel
+            .and_then(|channel| {
+                self.classes
+                    .iter()
+                    .position(|c| c.channels.contains(&channel))
+            })
+            .or_else(|| self.classes.iter().position(|c| c.name == self.fallback))
+            .unwrap_or(self.classes.len().saturating_sub(1))
+    }
+
+    /// Class indices in the order they are drained.
+    pub fn drain_order(&self) -> Vec<usize> {
+        let mut order: Vec<_> = (0..self.classes.len()).collect();
+        order.sort_by_key(|&i| self.classes[i].priority);
+        order
+    }
+
+    /// Check that `transport` supports every class's drop policy.
+    pub fn validate_for(&self, transport: Transport) -> Result<()> {
+        self.validate()?;
+        if transport == Transport::Ring {
+            if let Some(class) = self
+                .classes
+                .iter()
+                .find(|c| c.policy == DropPolicy::DropOldest)
+            {
+                bail!(
+                    "channel class {} cannot drop_oldest with the ring transport",
+                    class.name
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Give classes without a buffer size `buffer`.
+    fn with_default_buffer(mut self, buffer: usize) -> Self {
+        for class in &mut self.classes {
+            if class.buffer == 0 {
+                class.buffer = buffer;
+            }
+        }
+        self
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.classes.is_empty() {
+            bail!("channel policy defines no classes");
+        }
+        let mut names = HashSet::new();
+        let mut routed = HashSet::new();
+        for class in &self.classes {
+            if !names.insert(class.name.as_str()) {
+                bail!("channel class {} is defined twice", class.name);
+            }
+            if class.buffer == 0 {
+                bail!("channel class {} needs a buffer", class.name);
+            }
+            for &channel in &class.channels {
+                if !routed.insert(channel) {
+                    bail!(
+                        "channel {} is routed to more than one class",
+                        channel.as_str()
+                    );
+                }
+                if class.policy == DropPolicy::Conflate && !is_conflatable(channel) {
+                    bail!(
+                        "channel class {} cannot conflate {} events",
+                        class.name,
+                        channel.as_str()
+                    );
+                }
+            }
+        }
+        let fallback = self
+            .classes
+            .iter()
+            .find(|c| c.name == self.fallback)
+            .ok_or_else(|| anyhow!("fallback channel class {} is not defined", self.fallback))?;
+        if fallback.policy == DropPolicy::Conflate {
+            bail!("fallback channel class {} cannot conflate", fallback.name);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct PolicyFile {
+    #[serde(default)]
+    default: Option<ChannelPolicy>,
+    #[serde(default)]
+    exchanges: HashMap<String, ChannelPolicy>,
+}
+
+/// Policies read from a `CHANNEL_POLICY_FILE`: the default, if the file sets
+/// one, and overrides keyed by exchange id. Classes without a buffer size
+/// get `buffer`.
+pub fn load(
+    path: &str,
+    buffer: usize,
+) -> Result<(Option<ChannelPolicy>, HashMap<String, ChannelPolicy>)> {
+    let mut content = fs::read(path).with_context(|| format!("reading {path}"))?;
+    let file: PolicyFile = from_slice(&mut content).with_context(|| format!("parsing {path}"))?;
+    let default = file.default.map(|p| p.with_default_buffer(buffer));
+    let exchanges = file
+        .exchanges
+        .into_iter()
+        .map(|(id, p)| (id, p.with_default_buffer(buffer)))
+        .collect();
+    Ok((default, exchanges))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn default_policy_routes_every_channel() {
+        let policy = ChannelPolicy::new(1024);
+        policy.validate().unwrap();
+        let name = |channel| policy.classes[policy.class_of(channel)].name.as_str();
+        assert_eq!(name(Some(Channel::Depth)), "book");
+        assert_eq!(name(Some(Channel::Book)), "ticker");
+        assert_eq!(name(Some(Channel::FundingRate)), "funding");
+        assert_eq!(name(Some(Channel::Liquidation)), "liquidation");
+        assert_eq!(name(Some(Channel::Kline)), "kline");
+        assert_eq!(name(None), "other");
+        assert_eq!(policy.classes[1].buffer, 512);
+        let order: Vec<_> = policy
+            .drain_order()
+            .into_iter()
+            .map(|i| policy.classes[i].name.as_str())
+            .collect();
+        assert_eq!(
+            order,
+            [
+                "book",
+                "trade",
+                "liquidation",
+                "ticker",
+                "funding",
+                "kline",
+                "other"
+            ]
+        );
+    }
+
+    #[test]
+    fn loads_and_validates_policy_file() {
+        let path = env::temp_dir().join(format!("channel_policy_{}.json", std::process::id()));
+        fs::write(
+            &path,
+            r#"{
+                "default": {
+                    "fallback": "rest",
+                    "classes": [
+                        {"name": "trade", "channels": ["trade"], "buffer": 64, "policy": "drop_oldest"},
+                        {"name": "rest", "channels": ["depth", "kline"], "priority": 1}
+                    ]
+                },
+                "exchanges": {
+                    "binance_futures": {
+                        "fallback": "all",
+                        "classes": [{"name": "all", "channels": []}]
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+        let (default, exchanges) = load(path.to_str().unwrap(), 256).unwrap();
+        fs::remove_file(&path).unwrap();
+        let default = default.unwrap();
+        default.validate().unwrap();
+        assert_eq!(default.classes[0].policy, DropPolicy::DropOldest);
+        assert_eq!(default.classes[1].buffer, 256);
+        assert_eq!(default.classes[1].policy, DropPolicy::Block);
+        assert_eq!(default.class_of(Some(Channel::Book)), 1);
+        exchanges["binance_futures"].validate().unwrap();
+    }
+
+    #[test]
+    fn rejects_invalid_policies() {
+        let mut policy = ChannelPolicy::new(16);
+        policy.classes[1].policy = DropPolicy::Conflate;
+        assert!(policy.validate().is_err());
+
+        let mut policy = ChannelPolicy::new(16);
+        policy.classes[1].channels.push(Channel::Depth);
+        assert!(policy.validate().is_err());
+
+        let mut policy = ChannelPolicy::new(16);
+        policy.fallback = "missing".into();
+        assert!(policy.validate().is_err());
+
+        let mut policy = ChannelPolicy::new(16);
+        policy.classes[1].policy = DropPolicy::DropOldest;
+        policy.validate_for(Transport::Queue).unwrap();
+        assert!(policy.validate_for(Transport::Ring).is_err());
+        assert_eq!("RING".parse::<Transport>().unwrap(), Transport::Ring);
+    }
+}
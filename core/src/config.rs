@@ -2,7 +2,9 @@ use anyhow::{anyhow, Context, Result};
 use once_cell::sync::OnceCell;
 use serde::Deserialize;
 use simd_json::serde::from_slice;
-use std::{env, fs};
+use std::{collections::HashMap, env, fs};
+
+use crate::channel_policy::{self, ChannelPolicy, Transport};
 
 #[derive(Clone, Deserialize)]
 pub struct Credentials {
@@ -24,6 +26,9 @@ pub struct ExchangeConfig {
     pub id: String,
     #[serde(default)]
     pub symbols: Vec<String>,
+    /// Overrides [`Config::channel_policy`] for this exchange's partitions.
+    #[serde(default)]
+    pub channel_policy: Option<ChannelPolicy>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -40,6 +45,12 @@ pub struct Config {
     pub mexc_symbols: Symbols,
     pub chunk_size: usize,
     pub event_buffer_size: usize,
+    /// Channel classes of each partition, derived from `event_buffer_size`
+    /// unless `CHANNEL_POLICY_FILE` sets them.
+    pub channel_policy: ChannelPolicy,
+    /// Implementation of the partition queues, from `EVENT_TRANSPORT`.
+    #[serde(default)]
+    pub event_transport: Transport,
     pub http_timeout_secs: u64,
     pub book_refresh_secs: u64,
     pub http_burst: u32,
@@ -201,6 +212,21 @@ impl Config {
         let enable_mexc = parse_bool_env("ENABLE_MEXC", false);
         let enable_metrics = parse_bool_env("ENABLE_METRICS", true);
 
+        let event_transport = match env::var("EVENT_TRANSPORT") {
+            Ok(v) => v.parse()?,
+            Err(_) => Transport::default(),
+        };
+        let (channel_policy, exchange_policies) = match env::var("CHANNEL_POLICY_FILE") {
+            Ok(path) => channel_policy::load(&path, event_buffer_size)?,
+            Err(_) => (None, HashMap::new()),
+        };
+        let channel_policy =
+            channel_policy.unwrap_or_else(|| ChannelPolicy::new(event_buffer_size));
+        let mut exchange_policies: HashMap<_, _> = exchange_policies
+            .into_iter()
+            .map(|(id, policy)| (resolve_exchange_id(&id), policy))
+            .collect();
+
         let exchange_ids = parse_list_env("EXCHANGES");
         let exchanges = exchange_ids
             .into_iter()
@@ -211,9 +237,22 @@ impl Config {
                     Symbols::All => Vec::new(),
                     Symbols::List(list) => list,
                 };
-                ExchangeConfig { id, symbols }
+                let channel_policy = exchange_policies.remove(&id);
+                ExchangeConfig {
+                    id,
+                    symbols,
+                    channel_policy,
+                }
             })
-            .collect();
+            .collect::<Vec<_>>();
+        if !exchange_policies.is_empty() {
+            let mut ids: Vec<_> = exchange_policies.into_keys().collect();
+            ids.sort();
+            return Err(anyhow!(
+                "CHANNEL_POLICY_FILE overrides exchanges not in EXCHANGES: {}",
+                ids.join(", ")
+            ));
+        }
 
         let credentials = load_credentials()?;
         let ca_bundle = env::var("CA_BUNDLE").ok();
@@ -226,6 +265,8 @@ impl Config {
             mexc_symbols,
             chunk_size,
             event_buffer_size,
+            channel_policy,
+            event_transport,
             http_timeout_secs,
             book_refresh_secs,
             http_burst,
@@ -307,11 +348,24 @@ impl Config {
         Ok(())
     }
 
+    fn validate_channel_policies(&self) -> Result<()> {
+        self.channel_policy.validate_for(self.event_transport)?;
+        for exch in &self.exchanges {
+            if let Some(policy) = &exch.channel_policy {
+                policy
+                    .validate_for(self.event_transport)
+                    .with_context(|| format!("channel policy of {}", exch.id))?;
+            }
+        }
+        Ok(())
+    }
+
     pub fn validate(&self) -> Result<()> {
         self.validate_api_credentials()?;
         self.validate_symbols()?;
         self.validate_sizes()?;
         self.validate_rate_limits()?;
+        self.validate_channel_policies()?;
         Ok(())
     }
 }
@@ -4,7 +4,7 @@ use serde_json::Value;
 use std::borrow::Cow;
 use std::str::FromStr;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Channel {
     Trade,
     Book,
@@ -18,50 +18,107 @@ pub enum Channel {
     FundingRate,
     OpenInterest,
     Liquidation,
+    /// Instrument listings, delistings and spec changes.
+    Instrument,
 }
 
+impl Channel {
+    /// Snake_case name, the inverse of [`Channel::from_str`].
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Channel::Trade => "trade",
+            Channel::Book => "book",
+            Channel::Ticker => "ticker",
+            Channel::MiniTicker => "mini_ticker",
+            Channel::Kline => "kline",
+            Channel::Depth => "depth",
+            Channel::AvgPrice => "avg_price",
+            Channel::MarkPrice => "mark_price",
+            Channel::IndexPrice => "index_price",
+            Channel::FundingRate => "funding_rate",
+            Channel::OpenInterest => "open_interest",
+            Channel::Liquidation => "liquidation",
+            Channel::Instrument => "instrument",
+        }
+    }
+}
+
+impl FromStr for Channel {
+    type Err = String;
+
+    /// Parse a snake_case channel name such as `trade` or `mark_price`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "trade" => Channel::Trade,
+            "book" => Channel::Book,
+            "ticker" => Channel::Ticker,
+            "mini_ticker" => Channel::MiniTicker,
+            "kline" => Channel::Kline,
+            "depth" => Channel::Depth,
+            "avg_price" => Channel::AvgPrice,
+            "mark_price" => Channel::MarkPrice,
+            "index_price" => Channel::IndexPrice,
+            "funding_rate" => Channel::FundingRate,
+            "open_interest" => Channel::OpenInterest,
+            "liquidation" => Channel::Liquidation,
+            "instrument" => Channel::Instrument,
+            other => return Err(format!("unknown channel {other}")),
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for Channel {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        name.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Combined stream message. Prices and quantities borrow from the input when
+/// it is deserialized from a buffer, see [`StreamMessage::into_owned`].
 #[derive(Debug, Deserialize)]
 pub struct StreamMessage<'a> {
     pub stream: String,
+    #[serde(borrow)]
     pub data: Event<'a>,
 }
 
 #[derive(Debug, Deserialize)]
 #[serde(tag = "e")]
 pub enum Event<'a> {
-    #[serde(rename = "trade")]
+    #[serde(rename = "trade", borrow)]
     Trade(TradeEvent<'a>),
-    #[serde(rename = "aggTrade")]
+    #[serde(rename = "aggTrade", borrow)]
     AggTrade(AggTradeEvent<'a>),
-    #[serde(rename = "depthUpdate")]
+    #[serde(rename = "depthUpdate", borrow)]
     DepthUpdate(DepthUpdateEvent<'a>),
-    #[serde(rename = "kline")]
+    #[serde(rename = "kline", borrow)]
     Kline(KlineEvent<'a>),
-    #[serde(rename = "24hrMiniTicker")]
+    #[serde(rename = "24hrMiniTicker", borrow)]
     MiniTicker(MiniTickerEvent<'a>),
-    #[serde(rename = "24hrTicker")]
+    #[serde(rename = "24hrTicker", borrow)]
     Ticker(TickerEvent<'a>),
-    #[serde(rename = "bookTicker")]
+    #[serde(rename = "bookTicker", borrow)]
     BookTicker(BookTickerEvent<'a>),
-    #[serde(rename = "indexPriceUpdate")]
+    #[serde(rename = "indexPriceUpdate", borrow)]
     IndexPrice(IndexPriceEvent<'a>),
-    #[serde(rename = "markPriceUpdate")]
+    #[serde(rename = "markPriceUpdate", borrow)]
     MarkPrice(MarkPriceEvent<'a>),
-    #[serde(rename = "fundingRate")]
+    #[serde(rename = "fundingRate", borrow)]
     FundingRate(FundingRateEvent<'a>),
-    #[serde(rename = "markPriceKline")]
+    #[serde(rename = "markPriceKline", borrow)]
     MarkPriceKline(MarkPriceKlineEvent<'a>),
-    #[serde(rename = "indexPriceKline")]
+    #[serde(rename = "indexPriceKline", borrow)]
     IndexPriceKline(IndexPriceKlineEvent<'a>),
-    #[serde(rename = "continuous_kline")]
+    #[serde(rename = "continuous_kline", borrow)]
     ContinuousKline(ContinuousKlineEvent<'a>),
-    #[serde(rename = "forceOrder")]
+    #[serde(rename = "forceOrder", borrow)]
     ForceOrder(ForceOrderEvent<'a>),
-    #[serde(rename = "greeks")]
+    #[serde(rename = "greeks", borrow)]
     Greeks(GreeksEvent<'a>),
-    #[serde(rename = "openInterest")]
+    #[serde(rename = "openInterest", borrow)]
     OpenInterest(OpenInterestEvent<'a>),
-    #[serde(rename = "impliedVolatility")]
+    #[serde(rename = "impliedVolatility", borrow)]
     ImpliedVolatility(ImpliedVolatilityEvent<'a>),
     #[serde(other)]
     Unknown,
@@ -124,9 +181,9 @@ pub struct TradeEvent<'a> {
     pub symbol: String,
     #[serde(rename = "t")]
     pub trade_id: u64,
-    #[serde(rename = "p")]
+    #[serde(rename = "p", borrow)]
     pub price: Cow<'a, str>,
-    #[serde(rename = "q")]
+    #[serde(rename = "q", borrow)]
     pub quantity: Cow<'a, str>,
     #[serde(rename = "b")]
     pub buyer_order_id: u64,
@@ -148,9 +205,9 @@ pub struct AggTradeEvent<'a> {
     pub symbol: String,
     #[serde(rename = "a")]
     pub agg_trade_id: u64,
-    #[serde(rename = "p")]
+    #[serde(rename = "p", borrow)]
     pub price: Cow<'a, str>,
-    #[serde(rename = "q")]
+    #[serde(rename = "q", borrow)]
     pub quantity: Cow<'a, str>,
     #[serde(rename = "f")]
     pub first_trade_id: u64,
@@ -176,9 +233,9 @@ pub struct DepthUpdateEvent<'a> {
     pub final_update_id: u64,
     #[serde(rename = "pu")]
     pub previous_final_update_id: u64,
-    #[serde(rename = "b")]
+    #[serde(rename = "b", borrow, deserialize_with = "borrow_levels")]
     pub bids: Vec<[Cow<'a, str>; 2]>,
-    #[serde(rename = "a")]
+    #[serde(rename = "a", borrow, deserialize_with = "borrow_levels")]
     pub asks: Vec<[Cow<'a, str>; 2]>,
 }
 
@@ -188,7 +245,7 @@ pub struct KlineEvent<'a> {
     pub event_time: u64,
     #[serde(rename = "s")]
     pub symbol: String,
-    #[serde(rename = "k")]
+    #[serde(rename = "k", borrow)]
     pub kline: Kline<'a>,
 }
 
@@ -200,25 +257,25 @@ pub struct Kline<'a> {
     pub close_time: u64,
     #[serde(rename = "i")]
     pub interval: String,
-    #[serde(rename = "o")]
+    #[serde(rename = "o", borrow)]
     pub open: Cow<'a, str>,
-    #[serde(rename = "c")]
+    #[serde(rename = "c", borrow)]
     pub close: Cow<'a, str>,
-    #[serde(rename = "h")]
+    #[serde(rename = "h", borrow)]
     pub high: Cow<'a, str>,
-    #[serde(rename = "l")]
+    #[serde(rename = "l", borrow)]
     pub low: Cow<'a, str>,
-    #[serde(rename = "v")]
+    #[serde(rename = "v", borrow)]
     pub volume: Cow<'a, str>,
     #[serde(rename = "n")]
     pub trades: u64,
     #[serde(rename = "x")]
     pub is_closed: bool,
-    #[serde(rename = "q")]
+    #[serde(rename = "q", borrow)]
     pub quote_volume: Cow<'a, str>,
-    #[serde(rename = "V")]
+    #[serde(rename = "V", borrow)]
     pub taker_buy_base_volume: Cow<'a, str>,
-    #[serde(rename = "Q")]
+    #[serde(rename = "Q", borrow)]
     pub taker_buy_quote_volume: Cow<'a, str>,
 }
 
@@ -228,17 +285,17 @@ pub struct MiniTickerEvent<'a> {
     pub event_time: u64,
     #[serde(rename = "s")]
     pub symbol: String,
-    #[serde(rename = "c")]
+    #[serde(rename = "c", borrow)]
     pub close_price: Cow<'a, str>,
-    #[serde(rename = "o")]
+    #[serde(rename = "o", borrow)]
     pub open_price: Cow<'a, str>,
-    #[serde(rename = "h")]
+    #[serde(rename = "h", borrow)]
     pub high_price: Cow<'a, str>,
-    #[serde(rename = "l")]
+    #[serde(rename = "l", borrow)]
     pub low_price: Cow<'a, str>,
-    #[serde(rename = "v")]
+    #[serde(rename = "v", borrow)]
     pub volume: Cow<'a, str>,
-    #[serde(rename = "q")]
+    #[serde(rename = "q", borrow)]
     pub quote_volume: Cow<'a, str>,
 }
 
@@ -248,35 +305,35 @@ pub struct TickerEvent<'a> {
     pub event_time: u64,
     #[serde(rename = "s")]
     pub symbol: String,
-    #[serde(rename = "p")]
+    #[serde(rename = "p", borrow)]
     pub price_change: Cow<'a, str>,
-    #[serde(rename = "P")]
+    #[serde(rename = "P", borrow)]
     pub price_change_percent: Cow<'a, str>,
-    #[serde(rename = "w")]
+    #[serde(rename = "w", borrow)]
     pub weighted_avg_price: Cow<'a, str>,
-    #[serde(rename = "x")]
+    #[serde(rename = "x", borrow)]
     pub prev_close_price: Cow<'a, str>,
-    #[serde(rename = "c")]
+    #[serde(rename = "c", borrow)]
     pub last_price: Cow<'a, str>,
-    #[serde(rename = "Q")]
+    #[serde(rename = "Q", borrow)]
     pub last_qty: Cow<'a, str>,
-    #[serde(rename = "b")]
+    #[serde(rename = "b", borrow)]
     pub best_bid_price: Cow<'a, str>,
-    #[serde(rename = "B")]
+    #[serde(rename = "B", borrow)]
     pub best_bid_qty: Cow<'a, str>,
-    #[serde(rename = "a")]
+    #[serde(rename = "a", borrow)]
     pub best_ask_price: Cow<'a, str>,
-    #[serde(rename = "A")]
+    #[serde(rename = "A", borrow)]
     pub best_ask_qty: Cow<'a, str>,
-    #[serde(rename = "o")]
+    #[serde(rename = "o", borrow)]
     pub open_price: Cow<'a, str>,
-    #[serde(rename = "h")]
+    #[serde(rename = "h", borrow)]
     pub high_price: Cow<'a, str>,
-    #[serde(rename = "l")]
+    #[serde(rename = "l", borrow)]
     pub low_price: Cow<'a, str>,
-    #[serde(rename = "v")]
+    #[serde(rename = "v", borrow)]
     pub volume: Cow<'a, str>,
-    #[serde(rename = "q")]
+    #[serde(rename = "q", borrow)]
     pub quote_volume: Cow<'a, str>,
     #[serde(rename = "O")]
     pub open_time: u64,
@@ -296,13 +353,13 @@ pub struct BookTickerEvent<'a> {
     pub update_id: u64,
     #[serde(rename = "s")]
     pub symbol: String,
-    #[serde(rename = "b")]
+    #[serde(rename = "b", borrow)]
     pub best_bid_price: Cow<'a, str>,
-    #[serde(rename = "B")]
+    #[serde(rename = "B", borrow)]
     pub best_bid_qty: Cow<'a, str>,
-    #[serde(rename = "a")]
+    #[serde(rename = "a", borrow)]
     pub best_ask_price: Cow<'a, str>,
-    #[serde(rename = "A")]
+    #[serde(rename = "A", borrow)]
     pub best_ask_qty: Cow<'a, str>,
 }
 
@@ -322,11 +379,11 @@ pub struct MarkPriceEvent<'a> {
     pub event_time: u64,
     #[serde(rename = "s")]
     pub symbol: String,
-    #[serde(rename = "p")]
+    #[serde(rename = "p", borrow)]
     pub mark_price: Cow<'a, str>,
-    #[serde(rename = "i")]
+    #[serde(rename = "i", borrow)]
     pub index_price: Cow<'a, str>,
-    #[serde(rename = "r")]
+    #[serde(rename = "r", borrow)]
     pub funding_rate: Cow<'a, str>,
     #[serde(rename = "T")]
     pub next_funding_time: u64,
@@ -340,7 +397,7 @@ pub struct FundingRateEvent<'a> {
     pub event_time: u64,
     #[serde(rename = "s")]
     pub symbol: String,
-    #[serde(rename = "r")]
+    #[serde(rename = "r", borrow)]
     pub funding_rate: Cow<'a, str>,
     #[serde(rename = "T")]
     pub funding_time: u64,
@@ -352,7 +409,7 @@ pub struct MarkPriceKlineEvent<'a> {
     pub event_time: u64,
     #[serde(rename = "s")]
     pub symbol: String,
-    #[serde(rename = "k")]
+    #[serde(rename = "k", borrow)]
     pub kline: Kline<'a>,
 }
 
@@ -362,7 +419,7 @@ pub struct IndexPriceKlineEvent<'a> {
     pub event_time: u64,
     #[serde(rename = "s")]
     pub symbol: String,
-    #[serde(rename = "k")]
+    #[serde(rename = "k", borrow)]
     pub kline: Kline<'a>,
 }
 
@@ -374,7 +431,7 @@ pub struct ContinuousKlineEvent<'a> {
     pub pair: String,
     #[serde(rename = "ct")]
     pub contract_type: String,
-    #[serde(rename = "k")]
+    #[serde(rename = "k", borrow)]
     pub kline: Kline<'a>,
 }
 
@@ -382,7 +439,7 @@ pub struct ContinuousKlineEvent<'a> {
 pub struct ForceOrderEvent<'a> {
     #[serde(rename = "E")]
     pub event_time: u64,
-    #[serde(rename = "o")]
+    #[serde(rename = "o", borrow)]
     pub order: ForceOrder<'a>,
 }
 
@@ -396,27 +453,27 @@ pub struct ForceOrder<'a> {
     pub order_type: String,
     #[serde(rename = "f")]
     pub time_in_force: String,
-    #[serde(rename = "q")]
+    #[serde(rename = "q", borrow)]
     pub original_quantity: Cow<'a, str>,
-    #[serde(rename = "p")]
+    #[serde(rename = "p", borrow)]
     pub price: Cow<'a, str>,
-    #[serde(rename = "ap")]
+    #[serde(rename = "ap", borrow)]
     pub average_price: Cow<'a, str>,
     #[serde(rename = "X")]
     pub status: String,
-    #[serde(rename = "l")]
+    #[serde(rename = "l", borrow)]
     pub last_filled_quantity: Cow<'a, str>,
-    #[serde(rename = "z")]
+    #[serde(rename = "z", borrow)]
     pub filled_accumulated_quantity: Cow<'a, str>,
     #[serde(rename = "T")]
     pub trade_time: u64,
-    #[serde(rename = "L")]
+    #[serde(rename = "L", borrow)]
     pub last_filled_price: Cow<'a, str>,
     #[serde(rename = "t")]
     pub trade_id: u64,
-    #[serde(rename = "b")]
+    #[serde(rename = "b", borrow)]
     pub bids_notional: Cow<'a, str>,
-    #[serde(rename = "a")]
+    #[serde(rename = "a", borrow)]
     pub ask_notional: Cow<'a, str>,
     #[serde(rename = "m")]
     pub is_maker: bool,
@@ -430,13 +487,13 @@ pub struct GreeksEvent<'a> {
     pub event_time: u64,
     #[serde(rename = "s")]
     pub symbol: String,
-    #[serde(rename = "d")]
+    #[serde(rename = "d", borrow)]
     pub delta: Cow<'a, str>,
-    #[serde(rename = "g")]
+    #[serde(rename = "g", borrow)]
     pub gamma: Cow<'a, str>,
-    #[serde(rename = "v")]
+    #[serde(rename = "v", borrow)]
     pub vega: Cow<'a, str>,
-    #[serde(rename = "t")]
+    #[serde(rename = "t", borrow)]
     pub theta: Cow<'a, str>,
     #[serde(rename = "r", default)]
     pub rho: Option<Cow<'a, str>>,
@@ -448,7 +505,7 @@ pub struct OpenInterestEvent<'a> {
     pub event_time: u64,
     #[serde(rename = "s")]
     pub symbol: String,
-    #[serde(rename = "o")]
+    #[serde(rename = "o", borrow)]
     pub open_interest: Cow<'a, str>,
 }
 
@@ -458,7 +515,7 @@ pub struct ImpliedVolatilityEvent<'a> {
     pub event_time: u64,
     #[serde(rename = "s")]
     pub symbol: String,
-    #[serde(rename = "v")]
+    #[serde(rename = "v", borrow)]
     pub implied_volatility: Cow<'a, str>,
 }
 
@@ -1183,6 +1240,185 @@ impl<'a> Event<'a> {
     }
 }
 
+impl StreamMessage<'_> {
+    /// Copy every string still borrowed from the input, so the message can
+    /// outlive the buffer it was parsed from.
+    pub fn into_owned(self) -> StreamMessage<'static> {
+        StreamMessage {
+            stream: self.stream,
+            data: self.data.into_owned(),
+        }
+    }
+}
+
+impl Event<'_> {
+    /// See [`StreamMessage::into_owned`].
+    pub fn into_owned(self) -> Event<'static> {
+        match self {
+            Event::Trade(e) => Event::Trade(e.into_static()),
+            Event::AggTrade(e) => Event::AggTrade(e.into_static()),
+            Event::DepthUpdate(e) => Event::DepthUpdate(e.into_static()),
+            Event::Kline(e) => Event::Kline(e.into_static()),
+            Event::MiniTicker(e) => Event::MiniTicker(e.into_static()),
+            Event::Ticker(e) => Event::Ticker(e.into_static()),
+            Event::BookTicker(e) => Event::BookTicker(e.into_static()),
+            Event::IndexPrice(e) => Event::IndexPrice(e.into_static()),
+            Event::MarkPrice(e) => Event::MarkPrice(e.into_static()),
+            Event::FundingRate(e) => Event::FundingRate(e.into_static()),
+            Event::MarkPriceKline(e) => Event::MarkPriceKline(e.into_static()),
+            Event::IndexPriceKline(e) => Event::IndexPriceKline(e.into_static()),
+            Event::ContinuousKline(e) => Event::ContinuousKline(e.into_static()),
+            Event::ForceOrder(e) => Event::ForceOrder(e.into_static()),
+            Event::Greeks(e) => Event::Greeks(e.into_static()),
+            Event::OpenInterest(e) => Event::OpenInterest(e.into_static()),
+            Event::ImpliedVolatility(e) => Event::ImpliedVolatility(e.into_static()),
+            Event::Unknown => Event::Unknown,
+        }
+    }
+}
+
+/// Detach a value from the input it borrows from.
+trait IntoStatic {
+    type Static;
+    fn into_static(self) -> Self::Static;
+}
+
+macro_rules! into_static_identity {
+    ($($ty:ty),*) => {
+        $(impl IntoStatic for $ty {
+            type Static = $ty;
+            fn into_static(self) -> $ty {
+                self
+            }
+        })*
+    };
+}
+
+into_static_identity!(u64, bool, String);
+
+impl IntoStatic for Cow<'_, str> {
+    type Static = Cow<'static, str>;
+    fn into_static(self) -> Cow<'static, str> {
+        Cow::Owned(self.into_owned())
+    }
+}
+
+impl<T: IntoStatic> IntoStatic for Option<T> {
+    type Static = Option<T::Static>;
+    fn into_static(self) -> Self::Static {
+        self.map(T::into_static)
+    }
+}
+
+impl<T: IntoStatic> IntoStatic for Vec<[T; 2]> {
+    type Static = Vec<[T::Static; 2]>;
+    fn into_static(self) -> Self::Static {
+        self.into_iter()
+            .map(|[a, b]| [a.into_static(), b.into_static()])
+            .collect()
+    }
+}
+
+macro_rules! into_static_events {
+    ($($ty:ident { $($field:ident),* $(,)? })*) => {
+        $(impl IntoStatic for $ty<'_> {
+            type Static = $ty<'static>;
+            fn into_static(self) -> $ty<'static> {
+                $ty { $($field: self.$field.into_static()),* }
+            }
+        })*
+    };
+}
+
+into_static_events! {
+    TradeEvent {
+        event_time, symbol, trade_id, price, quantity, buyer_order_id, seller_order_id, trade_time,
+        buyer_is_maker, best_match,
+    }
+    AggTradeEvent {
+        event_time, symbol, agg_trade_id, price, quantity, first_trade_id, last_trade_id,
+        trade_time, buyer_is_maker, best_match,
+    }
+    DepthUpdateEvent {
+        event_time, symbol, first_update_id, final_update_id, previous_final_update_id, bids, asks,
+    }
+    KlineEvent { event_time, symbol, kline }
+    Kline {
+        start_time, close_time, interval, open, close, high, low, volume, trades, is_closed,
+        quote_volume, taker_buy_base_volume, taker_buy_quote_volume,
+    }
+    MiniTickerEvent {
+        event_time, symbol, close_price, open_price, high_price, low_price, volume, quote_volume,
+    }
+    TickerEvent {
+        event_time, symbol, price_change, price_change_percent, weighted_avg_price,
+        prev_close_price, last_price, last_qty, best_bid_price, best_bid_qty, best_ask_price,
+        best_ask_qty, open_price, high_price, low_price, volume, quote_volume, open_time,
+        close_time, first_trade_id, last_trade_id, count,
+    }
+    BookTickerEvent {
+        update_id, symbol, best_bid_price, best_bid_qty, best_ask_price, best_ask_qty,
+    }
+    IndexPriceEvent { event_time, symbol, index_price }
+    MarkPriceEvent {
+        event_time, symbol, mark_price, index_price, funding_rate, next_funding_time,
+        estimated_settle_price,
+    }
+    FundingRateEvent { event_time, symbol, funding_rate, funding_time }
+    MarkPriceKlineEvent { event_time, symbol, kline }
+    IndexPriceKlineEvent { event_time, symbol, kline }
+    ContinuousKlineEvent { event_time, pair, contract_type, kline }
+    ForceOrderEvent { event_time, order }
+    ForceOrder {
+        symbol, side, order_type, time_in_force, original_quantity, price, average_price, status,
+        last_filled_quantity, filled_accumulated_quantity, trade_time, last_filled_price, trade_id,
+        bids_notional, ask_notional, is_maker, reduce_only,
+    }
+    GreeksEvent { event_time, symbol, delta, gamma, vega, theta, rho }
+    OpenInterestEvent { event_time, symbol, open_interest }
+    ImpliedVolatilityEvent { event_time, symbol, implied_volatility }
+}
+
+/// Price levels as `[price, quantity]` pairs. Serde only borrows a `Cow`
+/// that is a field of its own, so nested ones are visited by hand.
+fn borrow_levels<'de: 'a, 'a, D>(deserializer: D) -> Result<Vec<[Cow<'a, str>; 2]>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    struct Level<'a>(Cow<'a, str>);
+
+    impl<'de: 'a, 'a> Deserialize<'de> for Level<'a> {
+        fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            struct Visitor;
+
+            impl<'de> serde::de::Visitor<'de> for Visitor {
+                type Value = Cow<'de, str>;
+
+                fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                    f.write_str("a string")
+                }
+
+                fn visit_borrowed_str<E>(self, v: &'de str) -> Result<Self::Value, E> {
+                    Ok(Cow::Borrowed(v))
+                }
+
+                fn visit_str<E>(self, v: &str) -> Result<Self::Value, E> {
+                    Ok(Cow::Owned(v.to_string()))
+                }
+
+                fn visit_string<E>(self, v: String) -> Result<Self::Value, E> {
+                    Ok(Cow::Owned(v))
+                }
+            }
+
+            deserializer.deserialize_str(Visitor).map(Level)
+        }
+    }
+
+    let levels = Vec::<[Level<'a>; 2]>::deserialize(deserializer)?;
+    Ok(levels.into_iter().map(|[price, qty]| [price.0, qty.0]).collect())
+}
+
 fn parse_decimal(s: &str) -> Result<Decimal, rust_decimal::Error> {
     Decimal::from_str(s)
 }
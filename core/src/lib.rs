@@ -1,3 +1,4 @@
+pub mod channel_policy;
 pub mod config;
 pub mod events;
 pub mod metrics;
@@ -11,3 +11,19 @@ fn default_buffer_size_is_exposed() {
         toml::from_str(include_str!("../../config/default.toml")).expect("valid config");
     assert_eq!(cfg.event_buffer_size, 1024);
 }
+
+#[test]
+fn channel_names_parse() {
+    use arb_core::events::Channel;
+    assert_eq!("trade".parse::<Channel>(), Ok(Channel::Trade));
+    assert_eq!("Mark_Price".parse::<Channel>(), Ok(Channel::MarkPrice));
+    assert!("trades".parse::<Channel>().is_err());
+}
+
+#[test]
+fn channel_names_round_trip() {
+    use arb_core::events::Channel;
+    for channel in [Channel::Trade, Channel::MiniTicker, Channel::Instrument] {
+        assert_eq!(channel.as_str().parse::<Channel>(), Ok(channel));
+    }
+}
@@ -1,4 +1,5 @@
 use arb_core as core;
+use core::channel_policy::{ChannelPolicy, Transport};
 use core::config::{Config, Credentials, Symbols};
 
 #[test]
@@ -10,6 +11,8 @@ fn empty_spot_symbols_fails() {
         mexc_symbols: Symbols::List(vec![]),
         chunk_size: 1,
         event_buffer_size: 1,
+        channel_policy: ChannelPolicy::new(1),
+        event_transport: Transport::Queue,
         http_timeout_secs: 30,
         book_refresh_secs: 60,
         http_burst: 1,
@@ -40,6 +43,8 @@ fn empty_futures_symbols_fails() {
         mexc_symbols: Symbols::List(vec![]),
         chunk_size: 1,
         event_buffer_size: 1,
+        channel_policy: ChannelPolicy::new(1),
+        event_transport: Transport::Queue,
         http_timeout_secs: 30,
         book_refresh_secs: 60,
         http_burst: 1,
@@ -70,6 +75,8 @@ fn invalid_chunk_size_fails() {
         mexc_symbols: Symbols::List(vec![]),
         chunk_size: 0,
         event_buffer_size: 1,
+        channel_policy: ChannelPolicy::new(1),
+        event_transport: Transport::Queue,
         http_timeout_secs: 30,
         book_refresh_secs: 60,
         http_burst: 1,
@@ -100,6 +107,8 @@ fn invalid_event_buffer_size_fails() {
         mexc_symbols: Symbols::List(vec![]),
         chunk_size: 10,
         event_buffer_size: 0,
+        channel_policy: ChannelPolicy::new(1),
+        event_transport: Transport::Queue,
         http_timeout_secs: 30,
         book_refresh_secs: 60,
         http_burst: 1,
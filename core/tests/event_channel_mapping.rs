@@ -147,6 +147,6 @@ fn channel_enum_matches_events() {
     };
     assert_eq!(liq.channel(), Channel::Liquidation);
 
-    let md: MdEvent = MdEvent { schema_version: SCHEMA_VERSION, event: MdEventKind::Trade(trade) };
+    let md: MdEvent = MdEvent { schema_version: SCHEMA_VERSION, event: MdEventKind::Trade(trade), validation: None };
     assert_eq!(md.channel(), Channel::Trade);
 }
@@ -6,10 +6,21 @@ use futures::executor::block_on;
 use hdrhistogram::Histogram;
 use simd_json::serde::from_slice as simd_from_slice;
 use std::alloc::{GlobalAlloc, Layout, System};
+use sink::{Encoding, FileSink, FileSinkOptions, MetricNames, Pipeline, PipelineOptions, Sink};
 use std::cell::RefCell;
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::time::Instant;
 
+// The ingestor is a binary, so the sink modules are built into the bench
+// directly. The file sink depends on the archiver.
+#[allow(dead_code, unused_imports)]
+#[path = "../src/archive/mod.rs"]
+mod archive;
+#[allow(dead_code, unused_imports)]
+#[path = "../src/sink/mod.rs"]
+mod sink;
+
 struct CountingAlloc;
 
 static ALLOCS: AtomicUsize = AtomicUsize::new(0);
@@ -35,6 +46,8 @@ impl NoopSink {
 
 const RAW: &str = r#"{"stream":"btcusdt@trade","data":{"e":"trade","E":123,"s":"BTCUSDT","t":1,"p":"0.001","q":"100","b":1,"a":2,"T":123,"m":true,"M":true}}"#;
 const BATCH_SIZE: usize = 1024;
+/// Events a consumer hands to the sink at once, as in `spawn_consumers`.
+const CONSUMER_BATCH: usize = 64;
 
 fn bench_ingestor(c: &mut Criterion) {
     let batch: Vec<Vec<u8>> = (0..BATCH_SIZE).map(|_| RAW.as_bytes().to_vec()).collect();
@@ -64,7 +77,58 @@ fn bench_ingestor(c: &mut Criterion) {
             }
         });
     });
+
+    let events: Vec<MdEvent> = batch
+        .iter()
+        .map(|raw| {
+            let mut bytes = raw.clone();
+            let msg: StreamMessage<'_> = simd_from_slice(&mut bytes).unwrap();
+            MdEvent::try_from(msg.data).unwrap()
+        })
+        .collect();
+    // Consumers publish into the queue of a pipeline draining into a file
+    // sink, as `run` sets it up. Each iteration waits for the file writes.
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let dir = std::env::temp_dir().join(format!("md_bench_{}", std::process::id()));
+    let pipeline = rt.block_on(async {
+        let options = FileSinkOptions {
+            max_bytes: Some(64 << 20),
+            retention_bytes: Some(256 << 20),
+            ..Default::default()
+        };
+        let path = dir.join("events.jsonl");
+        let file = FileSink::new(path.to_string_lossy(), Encoding::Json, options)
+            .await
+            .unwrap();
+        let names = MetricNames {
+            prefix: "md_bench",
+            label: "sink",
+        };
+        Pipeline::new("bench", Arc::new(file), PipelineOptions::default(), names, false)
+    });
+    group.bench_function("pipeline_publish_per_event", |b| {
+        b.iter(|| {
+            rt.block_on(async {
+                for ev in &events {
+                    pipeline.publish(black_box(ev)).await.unwrap();
+                }
+                pipeline.flush().await.unwrap();
+            })
+        });
+    });
+    group.bench_function("pipeline_publish_batch", |b| {
+        b.iter(|| {
+            rt.block_on(async {
+                for chunk in events.chunks(CONSUMER_BATCH) {
+                    pipeline.publish_batch(black_box(chunk)).await.unwrap();
+                }
+                pipeline.flush().await.unwrap();
+            })
+        });
+    });
     group.finish();
+    drop(pipeline);
+    let _ = std::fs::remove_dir_all(&dir);
 
     let p99 = latency.borrow().value_at_percentile(99.0);
     let alloc_p99 = allocs.borrow().value_at_percentile(99.0);
@@ -0,0 +1,846 @@
+//! Upload of closed output files to S3-compatible object storage.
+//!
+//! Files are uploaded one at a time as multipart uploads to
+//! `<prefix><name>`. The store verifies the signed SHA-256 of every part,
+//! each part's returned ETag is checked against its MD5 and the completed
+//! object's ETag against the MD5 of those, and the SHA-256 of the whole file
+//! is stored as `x-amz-meta-sha256`.
+//!
+//! The upload in progress is recorded in `<state_dir>/upload.json`. After a
+//! restart it continues with the parts the store does not hold yet, or starts
+//! over when the file changed. Finished uploads are appended to
+//! `<state_dir>/manifest.jsonl` and never repeated.
+
+use anyhow::{bail, Context, Result};
+use md5::{Digest as _, Md5};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::env;
+use std::io::{self, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex as StdMutex, Weak};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::{mpsc, Mutex};
+use tracing::{info, warn};
+
+mod s3;
+
+pub use s3::{S3Client, S3Options};
+
+/// S3 allows at most this many parts per upload.
+const MAX_PARTS: u64 = 10_000;
+
+/// Object naming, part size and bookkeeping of an [`Archiver`].
+#[derive(Debug, Clone)]
+pub struct ArchiveOptions {
+    /// Prepended to each file's name to form its key.
+    pub prefix: String,
+    /// Bytes per part. S3 requires at least 5 MiB for all but the last.
+    pub part_size: u64,
+    /// Directory of the manifest and the state of the upload in progress.
+    pub state_dir: PathBuf,
+    /// Remove local files once they are uploaded.
+    pub delete_uploaded: bool,
+    /// Wait before retrying a file whose upload failed.
+    pub retry_delay: Duration,
+}
+
+impl Default for ArchiveOptions {
+    fn default() -> Self {
+        Self {
+            prefix: String::new(),
+            part_size: 8 << 20,
+            state_dir: PathBuf::from("md.archive"),
+            delete_uploaded: false,
+            retry_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl ArchiveOptions {
+    /// Read `MD_ARCHIVE_S3_PREFIX`, `MD_ARCHIVE_PART_SIZE`,
+    /// `MD_ARCHIVE_STATE_DIR`, `MD_ARCHIVE_DELETE_UPLOADED` and
+    /// `MD_ARCHIVE_RETRY_SECS`, falling back to the defaults.
+    pub fn from_env() -> Result<Self> {
+        let mut opts = Self::default();
+        let var = |name: &str| env::var(name).ok().map(|v| v.trim().to_string());
+        if let Some(v) = var("MD_ARCHIVE_S3_PREFIX") {
+            opts.prefix = v;
+        }
+        if let Some(v) = var("MD_ARCHIVE_PART_SIZE") {
+            opts.part_size = v.parse().context("MD_ARCHIVE_PART_SIZE")?;
+            if opts.part_size == 0 {
+                bail!("MD_ARCHIVE_PART_SIZE must be positive");
+            }
+        }
+        if let Some(v) = var("MD_ARCHIVE_STATE_DIR").filter(|v| !v.is_empty()) {
+            opts.state_dir = PathBuf::from(v);
+        }
+        if let Some(v) = var("MD_ARCHIVE_DELETE_UPLOADED") {
+            opts.delete_uploaded = matches!(v.to_ascii_lowercase().as_str(), "1" | "true" | "yes");
+        }
+        if let Some(v) = var("MD_ARCHIVE_RETRY_SECS") {
+            opts.retry_delay = Duration::from_secs(v.parse().context("MD_ARCHIVE_RETRY_SECS")?);
+        }
+        Ok(opts)
+    }
+}
+
+/// Archiver configured by `MD_ARCHIVE_S3_BUCKET` and the other
+/// `MD_ARCHIVE_*` variables, or `None` when no bucket is set.
+pub async fn from_env(metrics_enabled: bool) -> Result<Option<Arc<Archiver>>> {
+    let Some(bucket) = env::var("MD_ARCHIVE_S3_BUCKET")
+        .ok()
+        .filter(|b| !b.trim().is_empty())
+    else {
+        return Ok(None);
+    };
+    let client = S3Client::new(S3Options::from_env(&bucket)?)?;
+    let archiver = Archiver::new(client, ArchiveOptions::from_env()?, metrics_enabled).await?;
+    info!(bucket = %bucket.trim(), "archiving closed output files to s3");
+    Ok(Some(archiver))
+}
+
+/// Manifest entry of an uploaded file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Uploaded {
+    pub path: PathBuf,
+    pub bucket: String,
+    pub key: String,
+    pub size: u64,
+    /// Hex SHA-256 of the file.
+    pub sha256: String,
+    /// ETag of the object, the MD5 of its parts' MD5s followed by the part
+    /// count.
+    pub etag: String,
+    pub parts: u32,
+    pub uploaded_ms: u64,
+}
+
+/// Upload in progress, as recorded in `upload.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Pending {
+    path: PathBuf,
+    key: String,
+    upload_id: String,
+    size: u64,
+    modified_ms: u64,
+    sha256: String,
+    part_size: u64,
+}
+
+impl Pending {
+    fn parts(&self) -> u32 {
+        self.size.div_ceil(self.part_size).max(1) as u32
+    }
+
+    /// Offset and length of part `number`.
+    fn range(&self, number: u32) -> (u64, u64) {
+        let start = u64::from(number - 1) * self.part_size;
+        (start, self.part_size.min(self.size - start))
+    }
+}
+
+/// Uploads files queued with [`submit`](Self::submit) in the background,
+/// retrying failed ones every `retry_delay`.
+pub struct Archiver {
+    client: S3Client,
+    options: ArchiveOptions,
+    metrics_enabled: bool,
+    /// Held for the duration of an upload, so they run one at a time.
+    uploading: Mutex<()>,
+    /// Paths in the manifest.
+    uploaded: StdMutex<HashSet<PathBuf>>,
+    queue: mpsc::UnboundedSender<(PathBuf, String)>,
+}
+
+impl Archiver {
+    /// Load the manifest from `options.state_dir` and start the upload task,
+    /// which first finishes an upload interrupted by a restart.
+    pub async fn new(
+        client: S3Client,
+        options: ArchiveOptions,
+        metrics_enabled: bool,
+    ) -> Result<Arc<Self>> {
+        tokio::fs::create_dir_all(&options.state_dir)
+            .await
+            .with_context(|| format!("creating {}", options.state_dir.display()))?;
+        let uploaded = read_manifest(&options.state_dir.join("manifest.jsonl"))
+            .await?
+            .into_iter()
+            .map(|u| u.path)
+            .collect();
+        let (queue, rx) = mpsc::unbounded_channel();
+        let retry_delay = options.retry_delay;
+        let archiver = Arc::new(Self {
+            client,
+            options,
+            metrics_enabled,
+            uploading: Mutex::new(()),
+            uploaded: StdMutex::new(uploaded),
+            queue,
+        });
+        tokio::spawn(run(Arc::downgrade(&archiver), rx, retry_delay));
+        Ok(archiver)
+    }
+
+    /// Queue `path` for upload as `<prefix><name>`.
+    pub fn submit(&self, path: PathBuf, name: String) {
+        // The task only stops once the archiver is dropped.
+        let _ = self.queue.send((path, name));
+    }
+
+    /// Upload `path` as `<prefix><name>` now, after finishing any interrupted
+    /// upload. Returns `false` when it was uploaded before or no longer
+    /// exists.
+    pub async fn archive(&self, path: &Path, name: &str) -> Result<bool> {
+        let _uploading = self.uploading.lock().await;
+        self.resume_locked().await?;
+        if self.is_archived(path) {
+            return Ok(false);
+        }
+        let key = format!("{}{name}", self.options.prefix);
+        let Some(pending) = self.start(path, key).await? else {
+            warn!(path = %path.display(), "file to archive no longer exists");
+            return Ok(false);
+        };
+        self.finish(pending, BTreeMap::new()).await?;
+        Ok(true)
+    }
+
+    /// Finish the upload recorded in the state directory, if any.
+    pub async fn resume(&self) -> Result<()> {
+        let _uploading = self.uploading.lock().await;
+        self.resume_locked().await
+    }
+
+    /// Whether `path` is recorded in the manifest, so its local copy may be
+    /// removed.
+    pub fn is_archived(&self, path: &Path) -> bool {
+        self.uploaded
+            .lock()
+            .expect("archive manifest mutex poisoned")
+            .contains(path)
+    }
+
+    fn state_path(&self) -> PathBuf {
+        self.options.state_dir.join("upload.json")
+    }
+
+    async fn resume_locked(&self) -> Result<()> {
+        let state = self.state_path();
+        let pending: Pending = match tokio::fs::read(&state).await {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .with_context(|| format!("reading {}", state.display()))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e).with_context(|| format!("reading {}", state.display())),
+        };
+        let unchanged = file_id(&pending.path)
+            .await?
+            .is_some_and(|id| id == (pending.size, pending.modified_ms));
+        let parts = match unchanged {
+            true => {
+                self.client
+                    .list_parts(&pending.key, &pending.upload_id)
+                    .await?
+            }
+            false => None,
+        };
+        let Some(parts) = parts else {
+            // The file changed or the store dropped the upload: start over.
+            if unchanged {
+                warn!(key = %pending.key, "interrupted upload no longer exists, restarting it");
+            } else {
+                warn!(path = %pending.path.display(), "file changed during its upload, restarting it");
+                if let Err(e) = self
+                    .client
+                    .abort_multipart_upload(&pending.key, &pending.upload_id)
+                    .await
+                {
+                    warn!(error = %e, key = %pending.key, "failed to abort upload");
+                }
+            }
+            tokio::fs::remove_file(&state).await?;
+            if let Some(pending) = self.start(&pending.path, pending.key).await? {
+                self.finish(pending, BTreeMap::new()).await?;
+            }
+            return Ok(());
+        };
+
+        // Keep the parts that still match the file.
+        let mut file = tokio::fs::File::open(&pending.path).await?;
+        let mut done = BTreeMap::new();
+        for part in parts {
+            if part.number == 0 || part.number > pending.parts() {
+                continue;
+            }
+            let (offset, len) = pending.range(part.number);
+            if part.size != len {
+                continue;
+            }
+            let md5 = hex::encode(Md5::digest(read_at(&mut file, offset, len).await?));
+            if part.etag == md5 {
+                done.insert(part.number, md5);
+            }
+        }
+        info!(key = %pending.key, parts = done.len(), of = pending.parts(), "resuming upload");
+        self.finish(pending, done).await
+    }
+
+    /// Hash `path` and create its upload, recording it as in progress.
+    async fn start(&self, path: &Path, key: String) -> Result<Option<Pending>> {
+        let Some((size, modified_ms)) = file_id(path).await? else {
+            return Ok(None);
+        };
+        let sha256 = sha256_file(path).await?;
+        let upload_id = self
+            .client
+            .create_multipart_upload(&key, &[("sha256", &sha256)])
+            .await?;
+        let pending = Pending {
+            path: path.to_path_buf(),
+            key,
+            upload_id,
+            size,
+            modified_ms,
+            sha256,
+            // Grow parts for files that would otherwise need too many.
+            part_size: self.options.part_size.max(size.div_ceil(MAX_PARTS)),
+        };
+        write_atomic(&self.state_path(), &serde_json::to_vec(&pending)?).await?;
+        Ok(Some(pending))
+    }
+
+    /// Upload the parts missing from `done`, complete the upload and record
+    /// it in the manifest.
+    async fn finish(&self, pending: Pending, mut done: BTreeMap<u32, String>) -> Result<()> {
+        let mut file = tokio::fs::File::open(&pending.path)
+            .await
+            .with_context(|| format!("opening {}", pending.path.display()))?;
+        for number in 1..=pending.parts() {
+            if done.contains_key(&number) {
+                continue;
+            }
+            let (offset, len) = pending.range(number);
+            let body = read_at(&mut file, offset, len).await?;
+            let md5 = hex::encode(Md5::digest(&body));
+            let etag = self
+                .client
+                .upload_part(&pending.key, &pending.upload_id, number, body)
+                .await?;
+            if etag != md5 {
+                bail!(
+                    "part {number} of {} stored with ETag {etag}, expected {md5}",
+                    pending.key
+                );
+            }
+            done.insert(number, md5);
+        }
+
+        let parts: Vec<_> = done.into_iter().collect();
+        let mut md5s = Md5::new();
+        for (_, md5) in &parts {
+            md5s.update(hex::decode(md5)?);
+        }
+        let expected = format!("{}-{}", hex::encode(md5s.finalize()), parts.len());
+        let etag = self
+            .client
+            .complete_multipart_upload(&pending.key, &pending.upload_id, &parts)
+            .await?;
+        if etag != expected {
+            bail!(
+                "{} stored with ETag {etag}, expected {expected}",
+                pending.key
+            );
+        }
+
+        let entry = Uploaded {
+            path: pending.path.clone(),
+            bucket: self.client.bucket().to_string(),
+            key: pending.key,
+            size: pending.size,
+            sha256: pending.sha256,
+            etag,
+            parts: parts.len() as u32,
+            uploaded_ms: now_ms(),
+        };
+        append_manifest(&self.options.state_dir.join("manifest.jsonl"), &entry).await?;
+        tokio::fs::remove_file(self.state_path()).await?;
+        self.uploaded
+            .lock()
+            .expect("archive manifest mutex poisoned")
+            .insert(entry.path.clone());
+        info!(path = %entry.path.display(), key = %entry.key, bytes = entry.size, "archived file");
+        if self.metrics_enabled {
+            metrics::counter!("md_archive_files_total").increment(1);
+            metrics::counter!("md_archive_bytes_total").increment(entry.size);
+        }
+        if self.options.delete_uploaded {
+            if let Err(e) = tokio::fs::remove_file(&entry.path).await {
+                warn!(error = %e, path = %entry.path.display(), "failed to remove archived file");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Upload queued files until the archiver is dropped.
+async fn run(
+    archiver: Weak<Archiver>,
+    mut rx: mpsc::UnboundedReceiver<(PathBuf, String)>,
+    retry_delay: Duration,
+) {
+    if let Some(archiver) = archiver.upgrade() {
+        if let Err(e) = archiver.resume().await {
+            warn!(error = %e, "failed to resume interrupted upload");
+        }
+    }
+    let mut queue = VecDeque::new();
+    loop {
+        if queue.is_empty() {
+            match rx.recv().await {
+                Some(job) => queue.push_back(job),
+                None => return,
+            }
+        }
+        while let Ok(job) = rx.try_recv() {
+            queue.push_back(job);
+        }
+        let Some(archiver) = archiver.upgrade() else {
+            return;
+        };
+        let (path, name) = queue.pop_front().expect("queue is not empty");
+        if let Err(e) = archiver.archive(&path, &name).await {
+            warn!(error = %e, path = %path.display(), "failed to archive file, will retry");
+            if archiver.metrics_enabled {
+                metrics::counter!("md_archive_failures_total").increment(1);
+            }
+            queue.push_front((path, name));
+            drop(archiver);
+            tokio::time::sleep(retry_delay).await;
+        }
+    }
+}
+
+/// Size and modification time of `path`, or `None` when it does not exist.
+async fn file_id(path: &Path) -> Result<Option<(u64, u64)>> {
+    let meta = match tokio::fs::metadata(path).await {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("reading {}", path.display())),
+    };
+    let modified = meta
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    Ok(Some((meta.len(), modified.as_millis() as u64)))
+}
+
+async fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .with_context(|| format!("opening {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 1 << 20];
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(hex::encode(hasher.finalize()));
+        }
+        hasher.update(&buf[..n]);
+    }
+}
+
+async fn read_at(file: &mut tokio::fs::File, offset: u64, len: u64) -> Result<Vec<u8>> {
+    let mut buf = vec![0; len as usize];
+    file.seek(SeekFrom::Start(offset)).await?;
+    file.read_exact(&mut buf).await?;
+    Ok(buf)
+}
+
+/// Entries of the manifest at `path`, empty when it does not exist yet.
+pub async fn read_manifest(path: &Path) -> Result<Vec<Uploaded>> {
+    let text = match tokio::fs::read_to_string(path).await {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("reading {}", path.display())),
+    };
+    text.lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| serde_json::from_str(l).with_context(|| format!("parsing {}", path.display())))
+        .collect()
+}
+
+async fn append_manifest(path: &Path, entry: &Uploaded) -> Result<()> {
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+        .with_context(|| format!("opening {}", path.display()))?;
+    file.write_all(&line).await?;
+    file.sync_all().await?;
+    Ok(())
+}
+
+async fn write_atomic(path: &Path, bytes: &[u8]) -> Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let mut file = tokio::fs::File::create(&tmp).await?;
+    file.write_all(bytes).await?;
+    file.sync_all().await?;
+    tokio::fs::rename(&tmp, path).await?;
+    Ok(())
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Bytes;
+    use axum::extract::{Path as UrlPath, Query, State};
+    use axum::http::{HeaderMap, Method, StatusCode};
+    use axum::response::{IntoResponse, Response};
+    use axum::routing::any;
+    use std::collections::HashMap;
+    use std::sync::Mutex as StdMutex;
+
+    struct Upload {
+        key: String,
+        sha256: String,
+        parts: BTreeMap<u32, Vec<u8>>,
+    }
+
+    #[derive(Default)]
+    struct Store {
+        uploads: HashMap<String, Upload>,
+        /// Content and metadata SHA-256 of each object.
+        objects: HashMap<String, (Vec<u8>, String)>,
+        created: usize,
+        /// Parts stored, by number.
+        stored: BTreeMap<u32, usize>,
+        failing_part: Option<u32>,
+    }
+
+    type Shared = Arc<StdMutex<Store>>;
+
+    fn error(status: StatusCode, code: &str) -> Response {
+        let body = format!("<Error><Code>{code}</Code><Message>{code}</Message></Error>");
+        (status, body).into_response()
+    }
+
+    /// Path-style S3 stand-in implementing multipart uploads. It checks the
+    /// payload hash of every request the way S3 does.
+    async fn s3(
+        State(store): State<Shared>,
+        method: Method,
+        UrlPath((_bucket, key)): UrlPath<(String, String)>,
+        Query(query): Query<HashMap<String, String>>,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> Response {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default()
+        };
+        if !header("authorization").starts_with("AWS4-HMAC-SHA256 Credential=test/") {
+            return error(StatusCode::FORBIDDEN, "AccessDenied");
+        }
+        if header("x-amz-content-sha256") != hex::encode(Sha256::digest(&body)) {
+            return error(StatusCode::BAD_REQUEST, "XAmzContentSHA256Mismatch");
+        }
+        let mut store = store.lock().unwrap();
+        let upload_id = query.get("uploadId").cloned().unwrap_or_default();
+        if method == Method::POST && query.contains_key("uploads") {
+            store.created += 1;
+            let id = format!("upload-{}", store.created);
+            let sha256 = header("x-amz-meta-sha256").to_string();
+            let upload = Upload {
+                key,
+                sha256,
+                parts: BTreeMap::new(),
+            };
+            store.uploads.insert(id.clone(), upload);
+            return format!("<InitiateMultipartUploadResult><UploadId>{id}</UploadId></InitiateMultipartUploadResult>")
+                .into_response();
+        }
+        if !store.uploads.contains_key(&upload_id) {
+            return error(StatusCode::NOT_FOUND, "NoSuchUpload");
+        }
+        match method {
+            Method::PUT => {
+                let number: u32 = query["partNumber"].parse().unwrap();
+                if store.failing_part == Some(number) {
+                    return error(StatusCode::INTERNAL_SERVER_ERROR, "InternalError");
+                }
+                *store.stored.entry(number).or_default() += 1;
+                store
+                    .uploads
+                    .get_mut(&upload_id)
+                    .unwrap()
+                    .parts
+                    .insert(number, body.to_vec());
+                let etag = format!("\"{}\"", hex::encode(Md5::digest(&body)));
+                ([("etag", etag)], "").into_response()
+            }
+            Method::GET => {
+                let mut xml = "<ListPartsResult><IsTruncated>false</IsTruncated>".to_string();
+                for (number, part) in &store.uploads[&upload_id].parts {
+                    let md5 = hex::encode(Md5::digest(part));
+                    xml.push_str(&format!(
+                        "<Part><PartNumber>{number}</PartNumber><ETag>&quot;{md5}&quot;</ETag><Size>{}</Size></Part>",
+                        part.len()
+                    ));
+                }
+                xml.push_str("</ListPartsResult>");
+                xml.into_response()
+            }
+            Method::POST => {
+                let Upload { key, sha256, parts } = store.uploads.remove(&upload_id).unwrap();
+                let body = String::from_utf8(body.to_vec()).unwrap();
+                let mut content = Vec::new();
+                let mut md5s = Md5::new();
+                for part in body.split("<PartNumber>").skip(1) {
+                    let (number, etag) = part.split_once("</PartNumber>").unwrap();
+                    let data = &parts[&number.parse::<u32>().unwrap()];
+                    let md5 = Md5::digest(data);
+                    assert!(etag.starts_with(&format!("<ETag>\"{}\"", hex::encode(md5))));
+                    md5s.update(md5);
+                    content.extend_from_slice(data);
+                }
+                let etag = format!("{}-{}", hex::encode(md5s.finalize()), parts.len());
+                store.objects.insert(key, (content, sha256));
+                format!("<CompleteMultipartUploadResult><ETag>&quot;{etag}&quot;</ETag></CompleteMultipartUploadResult>")
+                    .into_response()
+            }
+            Method::DELETE => {
+                store.uploads.remove(&upload_id);
+                StatusCode::NO_CONTENT.into_response()
+            }
+            _ => error(StatusCode::METHOD_NOT_ALLOWED, "MethodNotAllowed"),
+        }
+    }
+
+    /// Client of a stand-in serving `store`.
+    fn mock_s3(store: &Shared) -> S3Client {
+        let app = axum::Router::new()
+            .route("/:bucket/*key", any(s3))
+            .with_state(store.clone());
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+        S3Client::new(S3Options {
+            endpoint,
+            bucket: "archive".into(),
+            region: "us-east-1".into(),
+            access_key: "test".into(),
+            secret_key: "secret".into(),
+            session_token: None,
+            retries: 0,
+            backoff: Duration::from_millis(1),
+        })
+        .unwrap()
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("{name}_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn options(dir: &Path) -> ArchiveOptions {
+        ArchiveOptions {
+            prefix: "md/".into(),
+            part_size: 4096,
+            state_dir: dir.join("state"),
+            retry_delay: Duration::from_millis(10),
+            ..Default::default()
+        }
+    }
+
+    fn content(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 31 % 251) as u8).collect()
+    }
+
+    #[tokio::test]
+    async fn uploads_in_parts_and_records_the_manifest() {
+        let dir = temp_dir("archive_upload");
+        let path = dir.join("out.jsonl.1717000000000");
+        std::fs::write(&path, content(10_000)).unwrap();
+        let store = Shared::default();
+        let archiver = Archiver::new(mock_s3(&store), options(&dir), false)
+            .await
+            .unwrap();
+        assert!(archiver
+            .archive(&path, "out.jsonl.1717000000000")
+            .await
+            .unwrap());
+        assert!(!archiver
+            .archive(&path, "out.jsonl.1717000000000")
+            .await
+            .unwrap());
+
+        let sha256 = hex::encode(Sha256::digest(content(10_000)));
+        {
+            let store = store.lock().unwrap();
+            assert_eq!(store.stored, BTreeMap::from([(1, 1), (2, 1), (3, 1)]));
+            let (object, meta) = &store.objects["md/out.jsonl.1717000000000"];
+            assert_eq!((object, meta), (&content(10_000), &sha256));
+        }
+        let manifest = read_manifest(&dir.join("state/manifest.jsonl"))
+            .await
+            .unwrap();
+        assert_eq!(manifest.len(), 1);
+        let entry = &manifest[0];
+        assert_eq!(
+            (entry.key.as_str(), entry.size, entry.parts),
+            ("md/out.jsonl.1717000000000", 10_000, 3)
+        );
+        assert_eq!(entry.sha256, sha256);
+        assert!(entry.etag.ends_with("-3"));
+        assert!(!dir.join("state/upload.json").exists());
+
+        // Queued files go through the background task; the manifest survives
+        // a restart.
+        let archiver = Archiver::new(mock_s3(&store), options(&dir), false)
+            .await
+            .unwrap();
+        assert!(!archiver
+            .archive(&path, "out.jsonl.1717000000000")
+            .await
+            .unwrap());
+        let second = dir.join("out.jsonl.1717000000001");
+        std::fs::write(&second, b"last").unwrap();
+        archiver.submit(second.clone(), "out.jsonl.1717000000001".into());
+        for _ in 0..500 {
+            if archiver.is_archived(&second) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let manifest = read_manifest(&dir.join("state/manifest.jsonl"))
+            .await
+            .unwrap();
+        assert_eq!(manifest.len(), 2);
+        assert_eq!((manifest[1].size, manifest[1].parts), (4, 1));
+    }
+
+    #[tokio::test]
+    async fn resumes_an_interrupted_upload_after_restart() {
+        let dir = temp_dir("archive_resume");
+        let path = dir.join("out.jsonl.1717000000000");
+        std::fs::write(&path, content(10_000)).unwrap();
+        let store = Shared::default();
+        store.lock().unwrap().failing_part = Some(2);
+        let archiver = Archiver::new(mock_s3(&store), options(&dir), false)
+            .await
+            .unwrap();
+        assert!(archiver
+            .archive(&path, "out.jsonl.1717000000000")
+            .await
+            .is_err());
+        assert!(dir.join("state/upload.json").exists());
+        // Stop the first archiver as if its process exited.
+        let stopped = Arc::downgrade(&archiver);
+        drop(archiver);
+        while stopped.strong_count() > 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        // The new process talks to the same store, which has part 1.
+        store.lock().unwrap().failing_part = None;
+        let archiver = Archiver::new(mock_s3(&store), options(&dir), false)
+            .await
+            .unwrap();
+        archiver.resume().await.unwrap();
+        {
+            let store = store.lock().unwrap();
+            assert_eq!(store.created, 1);
+            assert_eq!(store.stored, BTreeMap::from([(1, 1), (2, 1), (3, 1)]));
+            assert_eq!(
+                store.objects["md/out.jsonl.1717000000000"].0,
+                content(10_000)
+            );
+        }
+        assert!(!dir.join("state/upload.json").exists());
+
+        // A file that changed since its upload started is uploaded again
+        // from scratch.
+        let path = dir.join("out.jsonl.1717000000001");
+        std::fs::write(&path, content(5_000)).unwrap();
+        store.lock().unwrap().failing_part = Some(2);
+        assert!(archiver
+            .archive(&path, "out.jsonl.1717000000001")
+            .await
+            .is_err());
+        std::fs::write(&path, content(6_000)).unwrap();
+        store.lock().unwrap().failing_part = None;
+        archiver.resume().await.unwrap();
+        let store = store.lock().unwrap();
+        assert_eq!(store.created, 3);
+        assert!(store.uploads.is_empty(), "the stale upload is aborted");
+        assert_eq!(
+            store.objects["md/out.jsonl.1717000000001"].0,
+            content(6_000)
+        );
+    }
+
+    /// Upload to the store at `MD_TEST_S3_ENDPOINT`, e.g. a MinIO started
+    /// with `minio server`, into the existing `MD_TEST_S3_BUCKET` (default
+    /// `md-archive`) with `MD_TEST_S3_ACCESS_KEY` and `MD_TEST_S3_SECRET_KEY`
+    /// (default `minioadmin`).
+    #[tokio::test]
+    async fn uploads_to_a_live_store() {
+        let Ok(endpoint) = env::var("MD_TEST_S3_ENDPOINT") else {
+            eprintln!("MD_TEST_S3_ENDPOINT not set, skipping");
+            return;
+        };
+        let var = |name, default: &str| env::var(name).unwrap_or_else(|_| default.into());
+        let client = S3Client::new(S3Options {
+            endpoint,
+            bucket: var("MD_TEST_S3_BUCKET", "md-archive"),
+            region: "us-east-1".into(),
+            access_key: var("MD_TEST_S3_ACCESS_KEY", "minioadmin"),
+            secret_key: var("MD_TEST_S3_SECRET_KEY", "minioadmin"),
+            session_token: None,
+            retries: 1,
+            backoff: Duration::from_millis(100),
+        })
+        .unwrap();
+        let dir = temp_dir("archive_live");
+        let path = dir.join("out.jsonl.1717000000000");
+        // S3 requires 5 MiB for every part but the last.
+        std::fs::write(&path, content((5 << 20) + 1000)).unwrap();
+        let options = ArchiveOptions {
+            prefix: format!("test/{}/", now_ms()),
+            part_size: 5 << 20,
+            ..options(&dir)
+        };
+        let archiver = Archiver::new(client, options, false).await.unwrap();
+        assert!(archiver
+            .archive(&path, "out.jsonl.1717000000000")
+            .await
+            .unwrap());
+        let manifest = read_manifest(&dir.join("state/manifest.jsonl"))
+            .await
+            .unwrap();
+        assert_eq!(manifest[0].parts, 2);
+    }
+}
@@ -0,0 +1,790 @@
+//! gRPC market-data service, enabled with the `grpc` feature.
+//!
+//! Serves `proto/md.proto` on `MD_GRPC_ADDR`. Subscriptions and book
+//! snapshots are backed by the same [`StreamHub`] as the WebSocket stream, so
+//! both see the live pipeline and the books it maintains.
+
+use anyhow::{Context, Result};
+use arb_core::events::Channel;
+use canonical::{ContractSpec, MdEventKind, ValidationError, VenueType};
+use futures::Stream;
+use std::env;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use tonic::{Request, Response, Status};
+use tracing::{error, info};
+
+use crate::ops::stream::StreamHub;
+use crate::sink::RouteFilter;
+
+pub mod pb {
+    tonic::include_proto!("md.v1");
+}
+
+use pb::market_data_server::{MarketData, MarketDataServer};
+
+/// Start the service when `MD_GRPC_ADDR` is set, e.g. `0.0.0.0:50051`.
+///
+/// Reuses `hub` when the WebSocket stream is enabled and creates one
+/// otherwise; the returned hub must be fed by a
+/// [`StreamingSink`](crate::ops::stream::StreamingSink).
+pub fn serve_from_env(
+    hub: Option<Arc<StreamHub>>,
+    metrics_enabled: bool,
+) -> Result<Option<Arc<StreamHub>>> {
+    let Ok(addr) = env::var("MD_GRPC_ADDR") else {
+        return Ok(hub);
+    };
+    let addr: SocketAddr = addr.trim().parse().context("MD_GRPC_ADDR")?;
+    let hub = match hub {
+        Some(hub) => hub,
+        None => StreamHub::with_env_queue(metrics_enabled)?,
+    };
+    let service = MarketDataService { hub: hub.clone() };
+    tokio::spawn(async move {
+        info!(%addr, "serving gRPC market data");
+        if let Err(e) = tonic::transport::Server::builder()
+            .add_service(MarketDataServer::new(service))
+            .serve(addr)
+            .await
+        {
+            error!("gRPC server error: {e}");
+        }
+    });
+    Ok(Some(hub))
+}
+
+pub struct MarketDataService {
+    hub: Arc<StreamHub>,
+}
+
+type EventStream = Pin<Box<dyn Stream<Item = Result<pb::MdEvent, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl MarketData for MarketDataService {
+    type SubscribeStream = EventStream;
+
+    async fn subscribe(
+        &self,
+        request: Request<pb::SubscribeRequest>,
+    ) -> Result<Response<Self::SubscribeStream>, Status> {
+        let pb::SubscribeRequest {
+            exchanges,
+            symbols,
+            channels,
+            kinds,
+        } = request.into_inner();
+        let channels = channels
+            .iter()
+            .map(|c| c.parse::<Channel>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(Status::invalid_argument)?;
+        let sub = self.hub.connect();
+        sub.subscribe(Some(RouteFilter {
+            exchanges,
+            symbols,
+            channels,
+            kinds,
+        }));
+        let stream = futures::stream::unfold(Some(sub), |sub| async move {
+            let mut sub = sub?;
+            match sub.recv().await {
+                Some(ev) => Some((Ok(pb::MdEvent::from(ev.as_ref())), Some(sub))),
+                None if sub.is_slow() => {
+                    Some((Err(Status::resource_exhausted("slow consumer")), None))
+                }
+                None => None,
+            }
+        });
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    async fn get_book_snapshot(
+        &self,
+        request: Request<pb::BookSnapshotRequest>,
+    ) -> Result<Response<pb::BookSnapshot>, Status> {
+        let pb::BookSnapshotRequest { exchange, symbol } = request.into_inner();
+        let (depth, ticker) = self.hub.book(&exchange, &symbol);
+        let ticker = ticker.and_then(|ev| match &ev.event {
+            MdEventKind::BookTicker(t) => Some(pb::BookTicker::from(t)),
+            _ => None,
+        });
+        if depth.is_none() && ticker.is_none() {
+            return Err(Status::not_found(format!(
+                "no book for {exchange} {symbol}"
+            )));
+        }
+        Ok(Response::new(pb::BookSnapshot {
+            depth: depth.as_ref().map(pb::DepthSnapshot::from),
+            ticker,
+        }))
+    }
+
+    async fn list_instruments(
+        &self,
+        request: Request<pb::ListInstrumentsRequest>,
+    ) -> Result<Response<pb::ListInstrumentsResponse>, Status> {
+        let pb::ListInstrumentsRequest { exchange } = request.into_inner();
+        let instruments = canonical::symbol::instruments()
+            .into_iter()
+            .filter(|(ex, ..)| exchange.is_empty() || ex.eq_ignore_ascii_case(&exchange))
+            .map(|(exchange, symbol, id, spec)| pb::Instrument {
+                exchange,
+                symbol,
+                instrument: id.to_string(),
+                spec: spec.as_ref().map(pb::ContractSpec::from),
+            })
+            .collect();
+        Ok(Response::new(pb::ListInstrumentsResponse { instruments }))
+    }
+}
+
+// Conversions destructure without `..`, so a field added to a canonical
+// struct fails to compile here until it is added to proto/md.proto.
+
+impl From<&canonical::MdEvent> for pb::MdEvent {
+    fn from(ev: &canonical::MdEvent) -> Self {
+        use pb::md_event::Event;
+        let canonical::MdEvent {
+            schema_version,
+            event,
+            validation,
+        } = ev;
+        let event = match event {
+            MdEventKind::Trade(e) => Event::Trade(e.into()),
+            MdEventKind::DepthL2Update(e) => Event::DepthL2Update(e.into()),
+            MdEventKind::BookTicker(e) => Event::BookTicker(e.into()),
+            MdEventKind::MiniTicker(e) => Event::MiniTicker(e.into()),
+            MdEventKind::Kline(e) => Event::Kline(e.into()),
+            MdEventKind::DepthSnapshot(e) => Event::DepthSnapshot(e.into()),
+            MdEventKind::AvgPrice(e) => Event::AvgPrice(e.into()),
+            MdEventKind::MarkPrice(e) => Event::MarkPrice(e.into()),
+            MdEventKind::IndexPrice(e) => Event::IndexPrice(e.into()),
+            MdEventKind::FundingRate(e) => Event::FundingRate(e.into()),
+            MdEventKind::OpenInterest(e) => Event::OpenInterest(e.into()),
+            MdEventKind::Liquidation(e) => Event::Liquidation(e.into()),
+            MdEventKind::InstrumentUpdate(e) => Event::InstrumentUpdate(e.into()),
+        };
+        Self {
+            schema_version: *schema_version,
+            event: Some(event),
+            validation: validation.as_ref().map(pb::ValidationFailure::from),
+        }
+    }
+}
+
+impl From<&ValidationError> for pb::ValidationFailure {
+    fn from(e: &ValidationError) -> Self {
+        Self {
+            reason: e.reason().to_string(),
+            field: e.field().to_string(),
+            message: e.to_string(),
+        }
+    }
+}
+
+impl From<&canonical::Level> for pb::Level {
+    fn from(l: &canonical::Level) -> Self {
+        let canonical::Level {
+            schema_version,
+            price,
+            quantity,
+            kind,
+        } = l;
+        let kind = match kind {
+            canonical::BookKind::Bid => pb::BookKind::Bid,
+            canonical::BookKind::Ask => pb::BookKind::Ask,
+        };
+        Self {
+            schema_version: *schema_version,
+            price: *price,
+            quantity: *quantity,
+            kind: kind.into(),
+        }
+    }
+}
+
+fn levels(levels: &[canonical::Level]) -> Vec<pb::Level> {
+    levels.iter().map(pb::Level::from).collect()
+}
+
+impl From<&canonical::Trade> for pb::Trade {
+    fn from(e: &canonical::Trade) -> Self {
+        let canonical::Trade {
+            schema_version,
+            exchange,
+            symbol,
+            price,
+            quantity,
+            trade_id,
+            buyer_order_id,
+            seller_order_id,
+            timestamp,
+            side,
+            ingest_ts_monotonic,
+            ingest_ts_utc,
+            seq_no,
+        } = e;
+        let side = match side {
+            Some(canonical::Side::Buy) => pb::Side::Buy,
+            Some(canonical::Side::Sell) => pb::Side::Sell,
+            None => pb::Side::Unspecified,
+        };
+        Self {
+            schema_version: *schema_version,
+            exchange: exchange.clone(),
+            symbol: symbol.clone(),
+            price: *price,
+            quantity: *quantity,
+            trade_id: *trade_id,
+            buyer_order_id: *buyer_order_id,
+            seller_order_id: *seller_order_id,
+            timestamp: *timestamp,
+            side: side.into(),
+            ingest_ts_monotonic: *ingest_ts_monotonic,
+            ingest_ts_utc: *ingest_ts_utc,
+            seq_no: *seq_no,
+        }
+    }
+}
+
+impl From<&canonical::DepthL2Update> for pb::DepthL2Update {
+    fn from(e: &canonical::DepthL2Update) -> Self {
+        let canonical::DepthL2Update {
+            schema_version,
+            exchange,
+            symbol,
+            ts,
+            bids,
+            asks,
+            first_update_id,
+            final_update_id,
+            previous_final_update_id,
+            ingest_ts_monotonic,
+            ingest_ts_utc,
+            seq_no,
+        } = e;
+        Self {
+            schema_version: *schema_version,
+            exchange: exchange.clone(),
+            symbol: symbol.clone(),
+            ts: *ts,
+            bids: levels(bids),
+            asks: levels(asks),
+            first_update_id: *first_update_id,
+            final_update_id: *final_update_id,
+            previous_final_update_id: *previous_final_update_id,
+            ingest_ts_monotonic: *ingest_ts_monotonic,
+            ingest_ts_utc: *ingest_ts_utc,
+            seq_no: *seq_no,
+        }
+    }
+}
+
+impl From<&canonical::BookTicker> for pb::BookTicker {
+    fn from(e: &canonical::BookTicker) -> Self {
+        let canonical::BookTicker {
+            schema_version,
+            exchange,
+            symbol,
+            ts,
+            bid_price,
+            bid_quantity,
+            ask_price,
+            ask_quantity,
+            ingest_ts_monotonic,
+            ingest_ts_utc,
+            seq_no,
+        } = e;
+        Self {
+            schema_version: *schema_version,
+            exchange: exchange.clone(),
+            symbol: symbol.clone(),
+            ts: *ts,
+            bid_price: *bid_price,
+            bid_quantity: *bid_quantity,
+            ask_price: *ask_price,
+            ask_quantity: *ask_quantity,
+            ingest_ts_monotonic: *ingest_ts_monotonic,
+            ingest_ts_utc: *ingest_ts_utc,
+            seq_no: *seq_no,
+        }
+    }
+}
+
+impl From<&canonical::MiniTicker> for pb::MiniTicker {
+    fn from(e: &canonical::MiniTicker) -> Self {
+        let canonical::MiniTicker {
+            schema_version,
+            exchange,
+            symbol,
+            ts,
+            open,
+            high,
+            low,
+            close,
+            volume,
+            quote_volume,
+            ingest_ts_monotonic,
+            ingest_ts_utc,
+            seq_no,
+        } = e;
+        Self {
+            schema_version: *schema_version,
+            exchange: exchange.clone(),
+            symbol: symbol.clone(),
+            ts: *ts,
+            open: *open,
+            high: *high,
+            low: *low,
+            close: *close,
+            volume: *volume,
+            quote_volume: *quote_volume,
+            ingest_ts_monotonic: *ingest_ts_monotonic,
+            ingest_ts_utc: *ingest_ts_utc,
+            seq_no: *seq_no,
+        }
+    }
+}
+
+impl From<&canonical::Kline> for pb::Kline {
+    fn from(e: &canonical::Kline) -> Self {
+        let canonical::Kline {
+            schema_version,
+            exchange,
+            symbol,
+            ts,
+            open,
+            close,
+            high,
+            low,
+            volume,
+            ingest_ts_monotonic,
+            ingest_ts_utc,
+            seq_no,
+        } = e;
+        Self {
+            schema_version: *schema_version,
+            exchange: exchange.clone(),
+            symbol: symbol.clone(),
+            ts: *ts,
+            open: *open,
+            close: *close,
+            high: *high,
+            low: *low,
+            volume: *volume,
+            ingest_ts_monotonic: *ingest_ts_monotonic,
+            ingest_ts_utc: *ingest_ts_utc,
+            seq_no: *seq_no,
+        }
+    }
+}
+
+impl From<&canonical::DepthSnapshot> for pb::DepthSnapshot {
+    fn from(e: &canonical::DepthSnapshot) -> Self {
+        let canonical::DepthSnapshot {
+            schema_version,
+            exchange,
+            symbol,
+            ts,
+            last_update_id,
+            bids,
+            asks,
+            ingest_ts_monotonic,
+            ingest_ts_utc,
+            seq_no,
+        } = e;
+        Self {
+            schema_version: *schema_version,
+            exchange: exchange.clone(),
+            symbol: symbol.clone(),
+            ts: *ts,
+            last_update_id: *last_update_id,
+            bids: levels(bids),
+            asks: levels(asks),
+            ingest_ts_monotonic: *ingest_ts_monotonic,
+            ingest_ts_utc: *ingest_ts_utc,
+            seq_no: *seq_no,
+        }
+    }
+}
+
+/// Conversion for the events carrying one price, which differ only in the
+/// name of their value field.
+macro_rules! single_value {
+    ($($ty:ident { $field:ident }),* $(,)?) => {$(
+        impl From<&canonical::$ty> for pb::$ty {
+            fn from(e: &canonical::$ty) -> Self {
+                let canonical::$ty {
+                    schema_version,
+                    exchange,
+                    symbol,
+                    ts,
+                    $field,
+                    ingest_ts_monotonic,
+                    ingest_ts_utc,
+                    seq_no,
+                } = e;
+                Self {
+                    schema_version: *schema_version,
+                    exchange: exchange.clone(),
+                    symbol: symbol.clone(),
+                    ts: *ts,
+                    $field: *$field,
+                    ingest_ts_monotonic: *ingest_ts_monotonic,
+                    ingest_ts_utc: *ingest_ts_utc,
+                    seq_no: *seq_no,
+                }
+            }
+        }
+    )*};
+}
+
+single_value!(
+    AvgPrice { price },
+    MarkPrice { price },
+    IndexPrice { price },
+    FundingRate { rate },
+    OpenInterest { open_interest },
+);
+
+impl From<&canonical::Liquidation> for pb::Liquidation {
+    fn from(e: &canonical::Liquidation) -> Self {
+        let canonical::Liquidation {
+            schema_version,
+            exchange,
+            symbol,
+            ts,
+            price,
+            quantity,
+            ingest_ts_monotonic,
+            ingest_ts_utc,
+            seq_no,
+        } = e;
+        Self {
+            schema_version: *schema_version,
+            exchange: exchange.clone(),
+            symbol: symbol.clone(),
+            ts: *ts,
+            price: *price,
+            quantity: *quantity,
+            ingest_ts_monotonic: *ingest_ts_monotonic,
+            ingest_ts_utc: *ingest_ts_utc,
+            seq_no: *seq_no,
+        }
+    }
+}
+
+impl From<&ContractSpec> for pb::ContractSpec {
+    fn from(s: &ContractSpec) -> Self {
+        let ContractSpec {
+            venue,
+            base,
+            quote,
+            settle,
+            lot_step,
+            price_step,
+            contract_size,
+            expiry,
+        } = s;
+        let venue = match venue {
+            VenueType::Spot => pb::VenueType::Spot,
+            VenueType::Futures => pb::VenueType::Futures,
+            VenueType::Options => pb::VenueType::Options,
+            VenueType::Unknown => pb::VenueType::Unknown,
+        };
+        Self {
+            venue: venue.into(),
+            base: base.clone(),
+            quote: quote.clone(),
+            settle: settle.clone(),
+            lot_step: *lot_step,
+            price_step: *price_step,
+            contract_size: *contract_size,
+            expiry: *expiry,
+        }
+    }
+}
+
+impl From<&canonical::InstrumentUpdate> for pb::InstrumentUpdate {
+    fn from(e: &canonical::InstrumentUpdate) -> Self {
+        let canonical::InstrumentUpdate {
+            schema_version,
+            exchange,
+            symbol,
+            instrument,
+            change,
+            spec,
+            ts,
+            ingest_ts_monotonic,
+            ingest_ts_utc,
+            seq_no,
+        } = e;
+        let change = match change {
+            canonical::InstrumentChange::Added => pb::InstrumentChange::Added,
+            canonical::InstrumentChange::Removed => pb::InstrumentChange::Removed,
+            canonical::InstrumentChange::Updated => pb::InstrumentChange::Updated,
+        };
+        Self {
+            schema_version: *schema_version,
+            exchange: exchange.clone(),
+            symbol: symbol.clone(),
+            instrument: instrument.to_string(),
+            change: change.into(),
+            spec: spec.as_ref().map(pb::ContractSpec::from),
+            ts: *ts,
+            ingest_ts_monotonic: *ingest_ts_monotonic,
+            ingest_ts_utc: *ingest_ts_utc,
+            seq_no: *seq_no,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::pb::market_data_client::MarketDataClient;
+    use super::pb::md_event::Event;
+    use super::*;
+    use crate::sink::testing::event;
+    use canonical::{BookKind, BookTicker, DepthSnapshot, Level, Trade, SCHEMA_VERSION};
+    use futures::StreamExt;
+    use std::collections::{HashMap, HashSet};
+    use tonic::transport::server::TcpIncoming;
+    use tonic::Code;
+
+    fn level(price: f64, kind: BookKind) -> Level {
+        Level {
+            schema_version: SCHEMA_VERSION,
+            price,
+            quantity: 1.0,
+            kind,
+        }
+    }
+
+    /// Field names of every message in `proto/md.proto`, including those
+    /// inside a `oneof`.
+    fn proto_fields() -> HashMap<String, HashSet<String>> {
+        let mut messages: HashMap<String, HashSet<String>> = HashMap::new();
+        let mut current = None;
+        let mut depth = 0;
+        for line in include_str!("../proto/md.proto").lines() {
+            let line = line.trim();
+            if line.starts_with("//") {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix("message ") {
+                current = Some(name.trim_end_matches('{').trim().to_string());
+            } else if let (Some(message), Some((decl, _))) = (&current, line.split_once('=')) {
+                if let Some(field) = decl.split_whitespace().last() {
+                    messages
+                        .entry(message.clone())
+                        .or_default()
+                        .insert(field.into());
+                }
+            }
+            depth += line.matches('{').count();
+            depth -= line.matches('}').count();
+            if depth == 0 {
+                current = None;
+            }
+        }
+        messages
+    }
+
+    fn snake_case(name: &str) -> String {
+        let mut out = String::new();
+        for (i, c) in name.chars().enumerate() {
+            if c.is_ascii_uppercase() && i > 0 {
+                out.push('_');
+            }
+            out.push(c.to_ascii_lowercase());
+        }
+        out
+    }
+
+    async fn serve(hub: Arc<StreamHub>) -> MarketDataClient<tonic::transport::Channel> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let incoming = TcpIncoming::from_listener(listener, true, None).unwrap();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(MarketDataServer::new(MarketDataService { hub }))
+                .serve_with_incoming(incoming),
+        );
+        MarketDataClient::connect(format!("http://{addr}"))
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn streams_books_then_live_events() {
+        let hub = Arc::new(StreamHub::new(64, false));
+        hub.broadcast(&event(MdEventKind::DepthSnapshot(DepthSnapshot {
+            exchange: "binance".into(),
+            symbol: "BTCUSDT".into(),
+            last_update_id: 10,
+            bids: vec![level(100.0, BookKind::Bid)],
+            asks: vec![level(101.0, BookKind::Ask)],
+            ..Default::default()
+        })));
+        hub.broadcast(&event(MdEventKind::BookTicker(BookTicker {
+            exchange: "binance".into(),
+            symbol: "BTCUSDT".into(),
+            bid_price: 100.0,
+            ask_price: 101.0,
+            ..Default::default()
+        })));
+        let mut client = serve(hub.clone()).await;
+
+        let book = client
+            .get_book_snapshot(pb::BookSnapshotRequest {
+                exchange: "binance".into(),
+                symbol: "btcusdt".into(),
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        let depth = book.depth.unwrap();
+        assert_eq!(depth.last_update_id, 10);
+        assert_eq!(depth.asks[0].kind(), pb::BookKind::Ask);
+        assert_eq!(book.ticker.unwrap().ask_price, 101.0);
+        let missing = client
+            .get_book_snapshot(pb::BookSnapshotRequest {
+                exchange: "okx".into(),
+                symbol: "BTC-USDT".into(),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(missing.code(), Code::NotFound);
+
+        let bogus = client
+            .subscribe(pb::SubscribeRequest {
+                channels: vec!["bogus".into()],
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(bogus.code(), Code::InvalidArgument);
+
+        let mut stream = client
+            .subscribe(pb::SubscribeRequest {
+                exchanges: vec!["binance".into()],
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        let mut snapshots = Vec::new();
+        for _ in 0..2 {
+            let ev = stream.next().await.unwrap().unwrap();
+            assert_eq!(ev.schema_version, SCHEMA_VERSION);
+            snapshots.push(ev.event.unwrap());
+        }
+        assert!(snapshots
+            .iter()
+            .any(|e| matches!(e, Event::DepthSnapshot(_))));
+        assert!(snapshots.iter().any(|e| matches!(e, Event::BookTicker(_))));
+
+        hub.broadcast(&event(MdEventKind::Trade(Trade {
+            exchange: "okx".into(),
+            symbol: "BTC-USDT".into(),
+            ..Default::default()
+        })));
+        hub.broadcast(&event(MdEventKind::Trade(Trade {
+            exchange: "binance".into(),
+            symbol: "BTCUSDT".into(),
+            trade_id: Some(7),
+            side: Some(canonical::Side::Sell),
+            ..Default::default()
+        })));
+        let Some(Event::Trade(trade)) = stream.next().await.unwrap().unwrap().event else {
+            panic!("expected a trade");
+        };
+        assert_eq!(trade.exchange, "binance");
+        assert_eq!(trade.trade_id, Some(7));
+        assert_eq!(trade.side(), pb::Side::Sell);
+
+        let all = client
+            .list_instruments(pb::ListInstrumentsRequest::default())
+            .await
+            .unwrap()
+            .into_inner();
+        let none = client
+            .list_instruments(pb::ListInstrumentsRequest {
+                exchange: "no-such-venue".into(),
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(
+            all.instruments.len(),
+            canonical::symbol::instruments().len()
+        );
+        assert!(none.instruments.is_empty());
+    }
+
+    #[test]
+    fn every_canonical_field_has_a_proto_counterpart() {
+        let proto = proto_fields();
+        let missing = |message: &str, value: serde_json::Value| -> Vec<String> {
+            let fields = &proto[message];
+            let serde_json::Value::Object(object) = value else {
+                panic!("{message} does not serialize to an object");
+            };
+            object
+                .keys()
+                .filter(|key| !fields.contains(*key))
+                .map(|key| format!("{message}.{key}"))
+                .collect()
+        };
+        let instrument_update = canonical::InstrumentUpdate {
+            schema_version: SCHEMA_VERSION,
+            exchange: "binance".into(),
+            symbol: "BTCUSDT".into(),
+            instrument: canonical::symbol::InstrumentId::unresolved("BTCUSDT"),
+            change: canonical::InstrumentChange::Added,
+            spec: Some(ContractSpec::default()),
+            ts: 0,
+            ingest_ts_monotonic: 0,
+            ingest_ts_utc: 0,
+            seq_no: 0,
+        };
+        let kinds = [
+            MdEventKind::Trade(Trade::default()),
+            MdEventKind::DepthL2Update(Default::default()),
+            MdEventKind::BookTicker(Default::default()),
+            MdEventKind::MiniTicker(Default::default()),
+            MdEventKind::Kline(Default::default()),
+            MdEventKind::DepthSnapshot(Default::default()),
+            MdEventKind::AvgPrice(Default::default()),
+            MdEventKind::MarkPrice(Default::default()),
+            MdEventKind::IndexPrice(Default::default()),
+            MdEventKind::FundingRate(Default::default()),
+            MdEventKind::OpenInterest(Default::default()),
+            MdEventKind::Liquidation(Default::default()),
+            MdEventKind::InstrumentUpdate(instrument_update),
+        ];
+
+        let mut unmapped = Vec::new();
+        for kind in kinds {
+            let mut ev = serde_json::to_value(event(kind)).unwrap();
+            let object = ev.as_object_mut().unwrap();
+            object.remove("schema_version");
+            let (variant, payload) = object.iter().next().unwrap();
+            if !proto["MdEvent"].contains(&snake_case(variant)) {
+                unmapped.push(format!("MdEventKind::{variant}"));
+            }
+            unmapped.extend(missing(variant, payload.clone()));
+        }
+        unmapped.extend(missing(
+            "Level",
+            serde_json::to_value(level(1.0, BookKind::Bid)).unwrap(),
+        ));
+        unmapped.extend(missing(
+            "ContractSpec",
+            serde_json::to_value(ContractSpec::default()).unwrap(),
+        ));
+        assert!(unmapped.is_empty(), "missing from md.proto: {unmapped:?}");
+    }
+}
@@ -2,29 +2,38 @@ use anyhow::{Context, Result};
 use lru::LruCache;
 use once_cell::sync::Lazy;
 use reqwest::{Client, Proxy};
-use std::future::Future;
 use std::time::{Instant, SystemTime, UNIX_EPOCH};
 use std::{env, num::NonZeroUsize, sync::Arc};
-use tokio::time::{sleep, Duration};
-use tokio::{
-    sync::{mpsc, Mutex},
-    task::JoinSet,
-};
+use tokio::time::Duration;
+use tokio::{sync::Mutex, task::JoinSet};
 use tokio_rustls::rustls::ClientConfig;
 use tracing::{debug, error};
 use tracing_subscriber::EnvFilter;
 
-use agents::ChannelRegistry;
+use agents::{ChannelRegistry, StreamReceiver};
 use agents::{spawn_adapters, TaskSet};
 use arb_core as core;
 use canonical::{MdEvent, MdEventKind};
 use core::config;
 use core::events::StreamMessage;
 use core::tls;
-use sink::{FileSink, KafkaSink, Sink, Wal};
+use sink::{
+    ClickHouseOptions, ClickHouseSink, DeadLetterQueue, Encoding, FileSink, FileSinkOptions,
+    KafkaOptions, KafkaSink, MetricNames, MulticastOptions, MulticastSink, NatsOptions, NatsSink,
+    ParquetOptions, ParquetSink, Pipeline, PipelineOptions, PostgresOptions, PostgresSink,
+    QuestDbOptions, QuestDbSink, RedisOptions, RedisStreamSink, RouterSink, Sink, Wal,
+    WalOptions,
+};
+use validation::Validator;
 
+mod archive;
+mod dead_letters;
+#[cfg(feature = "grpc")]
+mod grpc;
 mod ops;
 mod sink;
+mod symbols;
+mod validation;
 
 fn init_tracing() {
     tracing_subscriber::fmt()
@@ -73,17 +82,17 @@ fn dedupe_key(ev: &MdEvent) -> Option<DedupeKey> {
     }
 }
 
-async fn process_stream_event<F, Fut>(
+/// Normalize, dedupe, stamp and validate `msg`, returning the event to
+/// forward if any.
+async fn prepare_event(
     msg: StreamMessage<'static>,
     metrics_enabled: bool,
-    channels: ChannelRegistry,
-    mut forward_fn: F,
-) where
-    F: FnMut(&MdEvent) -> Fut,
-    Fut: Future<Output = Result<()>>,
-{
-    match MdEvent::try_from(msg.data) {
-        Ok(mut ev) => {
+    channels: &ChannelRegistry,
+    validator: &Validator,
+) -> Option<MdEvent> {
+    // Validation runs as a separate stage below so its policy applies.
+    match MdEvent::normalize(msg.data) {
+        Some(mut ev) => {
             if let Some(key) = dedupe_key(&ev) {
                 let mut cache = DEDUPE_CACHE.lock().await;
                 if cache.put(key, ()).is_some() {
@@ -92,7 +101,7 @@ async fn process_stream_event<F, Fut>(
                     }
                     #[cfg(feature = "debug-logs")]
                     debug!(stream = %msg.stream, "duplicate event dropped");
-                    return;
+                    return None;
                 }
             }
 
@@ -102,10 +111,6 @@ async fn process_stream_event<F, Fut>(
             #[cfg(feature = "debug-logs")]
             debug!(?ev, stream = %msg.stream, "normalized event");
 
-            let mut attempts = 0;
-            let max_retries = 3;
-            let mut delay = Duration::from_millis(100);
-            let max_delay = Duration::from_secs(1);
             let monotonic = START.elapsed().as_nanos() as u64;
             let utc = SystemTime::now()
                 .duration_since(UNIX_EPOCH)
@@ -173,72 +178,191 @@ async fn process_stream_event<F, Fut>(
                     e.ingest_ts_utc = utc;
                     e.seq_no = seq_no;
                 }
-            }
-
-            loop {
-                let start = Instant::now();
-                let res = forward_fn(&ev).await;
-                if metrics_enabled {
-                    metrics::histogram!("sink_latency_us")
-                        .record(start.elapsed().as_micros() as f64);
-                }
-                match res {
-                    Ok(()) => break,
-                    Err(e) if attempts < max_retries => {
-                        attempts += 1;
-                        error!(
-                            error = %e,
-                            attempt = attempts,
-                            "failed to forward event, retrying",
-                        );
-                        sleep(delay).await;
-                        delay = std::cmp::min(delay.saturating_mul(2), max_delay);
-                    }
-                    Err(e) => {
-                        error!(error = %e, "failed to forward event, giving up");
-                        break;
-                    }
+                MdEventKind::InstrumentUpdate(e) => {
+                    e.ingest_ts_monotonic = monotonic;
+                    e.ingest_ts_utc = utc;
+                    e.seq_no = seq_no;
                 }
             }
+
+            validator.check(&mut ev, utc, metrics_enabled).then_some(ev)
         }
-        Err(e) => {
-            error!(error = %format!("{:?}", e), stream = %msg.stream, "failed to normalize event");
+        None => {
+            error!(stream = %msg.stream, "failed to normalize event");
+            None
         }
     }
 }
 
+/// Hand a consumer's batch to the sink. Queueing, retries and the overflow
+/// policy belong to the sink's pipeline, so a rejection, e.g. by a full
+/// `drop_newest` queue, is final.
+async fn forward(sink: &dyn Sink, events: &[MdEvent], metrics_enabled: bool) {
+    if events.is_empty() {
+        return;
+    }
+    let start = Instant::now();
+    let res = sink.publish_batch(events).await;
+    if metrics_enabled {
+        metrics::histogram!("sink_latency_us").record(start.elapsed().as_micros() as f64);
+    }
+    if let Err(e) = res {
+        error!(error = %e, events = events.len(), "failed to forward events");
+    }
+}
+
+/// Messages a consumer takes from its partition at once.
+const CONSUMER_BATCH: usize = 64;
+
 async fn spawn_consumers(
-    receivers: Vec<mpsc::Receiver<StreamMessage<'static>>>,
+    receivers: Vec<StreamReceiver>,
     join_set: TaskSet,
     metrics_enabled: bool,
     channels: ChannelRegistry,
     sink: Arc<dyn Sink>,
+    validator: Arc<Validator>,
 ) {
     for mut event_rx in receivers {
         let set = join_set.clone();
         let channels = channels.clone();
         let sink = sink.clone();
+        let validator = validator.clone();
         let mut set = set.lock().await;
         set.spawn(async move {
-            while let Some(msg) = event_rx.recv().await {
+            let mut batch = Vec::with_capacity(CONSUMER_BATCH);
+            let mut events = Vec::with_capacity(CONSUMER_BATCH);
+            while event_rx.recv_many(&mut batch, CONSUMER_BATCH).await > 0 {
                 if metrics_enabled {
                     metrics::gauge!("consumer_queue_depth").set(event_rx.len() as f64);
                 }
-                let sink_cl = sink.clone();
-                process_stream_event(msg, metrics_enabled, channels.clone(), move |ev| {
-                    let sink_cl = sink_cl.clone();
-                    let ev = ev.clone();
-                    async move { sink_cl.publish(&ev).await }
-                })
-                .await;
+                for msg in batch.drain(..) {
+                    if let Some(ev) =
+                        prepare_event(msg, metrics_enabled, &channels, &validator).await
+                    {
+                        events.push(ev);
+                    }
+                }
+                forward(sink.as_ref(), &events, metrics_enabled).await;
+                events.clear();
             }
             if let Err(e) = sink.flush().await {
                 error!(error = %e, "failed to flush sink");
             }
+            validator.flush().await;
         });
     }
 }
 
+/// Sink selected by the `MD_SINK_*` variables. With `wal`, Kafka, NATS,
+/// ClickHouse, QuestDB and PostgreSQL output is written through the WAL and the WAL's dead letter queue is returned as well.
+/// With `queue`, output goes through a [`Pipeline`], placed between the WAL
+/// and the sink so events are durable before they are queued. Routes queue
+/// for their sinks themselves.
+async fn build_sink(
+    wal: bool,
+    queue: Option<PipelineOptions>,
+) -> Result<(Arc<dyn Sink>, Option<Arc<DeadLetterQueue>>)> {
+    if let Ok(routes) = env::var("MD_SINK_ROUTES") {
+        let router = RouterSink::from_file(routes, wal, core::config::metrics_enabled()).await?;
+        return Ok((Arc::new(router), None));
+    }
+    let brokers = env::var("MD_SINK_KAFKA_BROKERS").unwrap_or_default();
+    let nats_url = env::var("MD_SINK_NATS_URL").unwrap_or_default();
+    if !brokers.is_empty() {
+        let kafka = KafkaSink::new(
+            &brokers,
+            Encoding::from_env("MD_SINK_KAFKA_ENCODING")?,
+            KafkaOptions::from_env()?,
+        )?;
+        return with_wal(kafka, wal, queue).await;
+    }
+    if !nats_url.is_empty() {
+        let nats = NatsSink::new(
+            &nats_url,
+            Encoding::from_env("MD_SINK_NATS_ENCODING")?,
+            NatsOptions::from_env()?,
+        )
+        .await?;
+        return with_wal(nats, wal, queue).await;
+    }
+    if let Ok(url) = env::var("MD_SINK_REDIS_URL") {
+        let sink = RedisStreamSink::new(&url, RedisOptions::from_env()?).await?;
+        return Ok((queued(sink, queue), None));
+    }
+    if let Ok(addr) = env::var("MD_SINK_MULTICAST_ADDR") {
+        let group = addr.trim().parse().context("MD_SINK_MULTICAST_ADDR")?;
+        let metrics_enabled = core::config::metrics_enabled();
+        let sink = MulticastSink::new(group, MulticastOptions::from_env()?, metrics_enabled).await?;
+        return Ok((queued(sink, queue), None));
+    }
+    if let Ok(url) = env::var("MD_SINK_CLICKHOUSE_URL") {
+        let clickhouse = ClickHouseSink::new(&url, ClickHouseOptions::from_env()?)?;
+        return with_wal(clickhouse, wal, queue).await;
+    }
+    if let Ok(addr) = env::var("MD_SINK_QUESTDB_ADDR") {
+        let questdb = QuestDbSink::new(&addr, QuestDbOptions::from_env()?).await?;
+        return with_wal(questdb, wal, queue).await;
+    }
+    if let Ok(url) = env::var("MD_SINK_POSTGRES_URL") {
+        let postgres = PostgresSink::new(&url, PostgresOptions::from_env()?).await?;
+        return with_wal(postgres, wal, queue).await;
+    }
+    if let Ok(dir) = env::var("MD_SINK_PARQUET_DIR") {
+        let sink = ParquetSink::new(dir, ParquetOptions::from_env()?)?;
+        return Ok((queued(sink, queue), None));
+    }
+    // Default to a local JSON Lines file when MD_SINK_FILE is not set.
+    let sink_path = env::var("MD_SINK_FILE").unwrap_or_else(|_| "output.jsonl".into());
+    let encoding = Encoding::from_env("MD_SINK_FILE_ENCODING")?;
+    let mut sink = FileSink::new(sink_path, encoding, FileSinkOptions::from_env()?).await?;
+    if let Some(archiver) = archive::from_env(core::config::metrics_enabled()).await? {
+        sink = sink.with_archiver(archiver)?;
+    }
+    Ok((queued(sink, queue), None))
+}
+
+/// Put `sink` behind the WAL when `wal` is set, queueing between the two.
+async fn with_wal<T: Sink + 'static>(
+    sink: T,
+    wal: bool,
+    queue: Option<PipelineOptions>,
+) -> Result<(Arc<dyn Sink>, Option<Arc<DeadLetterQueue>>)> {
+    if !wal {
+        return Ok((queued(sink, queue), None));
+    }
+    match queue {
+        Some(options) => open_wal(pipeline(Arc::new(sink), options)).await,
+        None => open_wal(sink).await,
+    }
+}
+
+async fn open_wal<T: Sink + 'static>(
+    sink: T,
+) -> Result<(Arc<dyn Sink>, Option<Arc<DeadLetterQueue>>)> {
+    let wal_path = env::var("MD_SINK_WAL_FILE").unwrap_or_else(|_| "md.wal".into());
+    let wal_encoding = Encoding::from_env("MD_SINK_WAL_ENCODING")?;
+    let wal = Wal::new(wal_path, sink, wal_encoding, WalOptions::from_env()?).await?;
+    let dead_letters = wal.dead_letters();
+    Ok((Arc::new(wal), Some(dead_letters)))
+}
+
+/// `sink` behind a pipeline when `queue` is set.
+fn queued<T: Sink + 'static>(sink: T, queue: Option<PipelineOptions>) -> Arc<dyn Sink> {
+    match queue {
+        Some(options) => Arc::new(pipeline(Arc::new(sink), options)),
+        None => Arc::new(sink),
+    }
+}
+
+fn pipeline(sink: Arc<dyn Sink>, options: PipelineOptions) -> Pipeline {
+    let names = MetricNames {
+        prefix: "md_sink",
+        label: "sink",
+    };
+    let metrics_enabled = core::config::metrics_enabled();
+    Pipeline::new("output", sink, options, names, metrics_enabled)
+}
+
 pub async fn run() -> Result<()> {
     init_tracing();
 
@@ -249,30 +373,24 @@ pub async fn run() -> Result<()> {
     let client = build_client(cfg, tls_config.clone())?;
 
     let metrics_enabled = core::config::metrics_enabled();
-    ops::serve_all(metrics_enabled)?;
-
-    let sink: Arc<dyn Sink> = if let Ok(brokers) = env::var("MD_SINK_KAFKA_BROKERS") {
-        if brokers.is_empty() {
-            // Default to a local JSON Lines file when MD_SINK_FILE is not set.
-            let sink_path = env::var("MD_SINK_FILE").unwrap_or_else(|_| "output.jsonl".into());
-            Arc::new(FileSink::new(sink_path).await?)
-        } else {
-            let wal_path = env::var("MD_SINK_WAL_FILE").unwrap_or_else(|_| "md.wal".into());
-            let kafka = KafkaSink::new(&brokers, "md_events")?;
-            Arc::new(Wal::new(wal_path, kafka).await?)
-        }
-    } else {
-        // Default to a local JSON Lines file when MD_SINK_FILE is not set.
-        let sink_path = env::var("MD_SINK_FILE").unwrap_or_else(|_| "output.jsonl".into());
-        Arc::new(FileSink::new(sink_path).await?)
+    let stream_hub = ops::stream::StreamHub::from_env(metrics_enabled)?;
+    ops::serve_all(metrics_enabled, stream_hub.clone())?;
+    #[cfg(feature = "grpc")]
+    let stream_hub = grpc::serve_from_env(stream_hub, metrics_enabled)?;
+    let stream_hub = ops::top_of_book::publish_from_env(stream_hub, metrics_enabled)?;
+
+    let (sink, dead_letters) = build_sink(true, Some(PipelineOptions::from_env()?)).await?;
+    let sink: Arc<dyn Sink> = match stream_hub {
+        Some(hub) => Arc::new(ops::stream::StreamingSink::new(sink, hub)),
+        None => sink,
     };
+    let validator = Arc::new(Validator::from_env(dead_letters).await?);
 
     let join_set: TaskSet = Arc::new(Mutex::new(JoinSet::new()));
     // Install signal-based shutdown handling before starting intake tasks.
-    ops::shutdown::install(join_set.clone());
+    ops::shutdown::install(join_set.clone(), sink.clone());
 
-    let event_buffer_size = cfg.event_buffer_size;
-    let channels = agents::ChannelRegistry::new(event_buffer_size);
+    let channels = agents::ChannelRegistry::from_config(cfg)?;
 
     // Create and spawn exchange adapters, collecting receivers for each partition.
     let receivers = spawn_adapters(
@@ -284,6 +402,11 @@ pub async fn run() -> Result<()> {
     )
     .await?;
 
+    // Adapters have fetched their instrument metadata by now; build the
+    // symbol table before any event is normalized.
+    symbols::load()?;
+    symbols::spawn_reloader(sink.clone(), metrics_enabled);
+
     // Spawn a consumer task per partition to normalize events.
     spawn_consumers(
         receivers,
@@ -291,6 +414,7 @@ pub async fn run() -> Result<()> {
         metrics_enabled,
         channels.clone(),
         sink.clone(),
+        validator,
     )
     .await;
 
@@ -316,157 +440,84 @@ pub async fn run() -> Result<()> {
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    run().await
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("dlq") => dead_letters::main(&args[1..]).await,
+        _ => run().await,
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use anyhow::anyhow;
-    use std::sync::atomic::{AtomicUsize, Ordering};
-    use std::sync::{Arc, Mutex};
-    use std::time::{Duration, Instant};
-    use tokio::time as ttime;
+    use async_trait::async_trait;
+    use std::sync::Mutex;
+    use validation::ValidationAction;
 
     fn sample_msg() -> StreamMessage<'static> {
         let json = r#"{"stream":"btcusdt@bookTicker","data":{"e":"bookTicker","u":1,"s":"BTCUSDT","b":"0.1","B":"2","a":"0.2","A":"3"}}"#;
         serde_json::from_str(json).unwrap()
     }
 
-    #[tokio::test]
-    async fn backoff_doubles_delay() {
-        let msg = sample_msg();
-        let channels = ChannelRegistry::new(1);
-        let times = Arc::new(Mutex::new(Vec::new()));
-        let times_clone = times.clone();
-
-        let forward = move |_ev: &MdEvent| {
-            let times_clone = times_clone.clone();
-            async move {
-                times_clone.lock().unwrap().push(Instant::now());
-                Err(anyhow!("fail"))
-            }
-        };
+    fn crossed_msg() -> StreamMessage<'static> {
+        let json = r#"{"stream":"btcusdt@bookTicker","data":{"e":"bookTicker","u":2,"s":"BTCUSDT","b":"0.3","B":"2","a":"0.2","A":"3"}}"#;
+        serde_json::from_str(json).unwrap()
+    }
 
-        tokio::spawn(process_stream_event(msg, false, channels, forward))
-            .await
-            .unwrap();
+    fn validator() -> Arc<Validator> {
+        Arc::new(Validator::new(
+            ValidationAction::Drop,
+            canonical::ValidationRules::default(),
+        ))
+    }
 
-        let times = times.lock().unwrap();
-        assert_eq!(times.len(), 4);
-        let deltas: Vec<_> = times.windows(2).map(|w| w[1] - w[0]).collect();
-        assert!(deltas[0] >= Duration::from_millis(100) && deltas[0] < Duration::from_millis(150));
-        assert!(deltas[1] >= Duration::from_millis(200) && deltas[1] < Duration::from_millis(300));
-        assert!(deltas[2] >= Duration::from_millis(400) && deltas[2] < Duration::from_millis(500));
+    /// Records the batches it is given and fails them all.
+    #[derive(Default)]
+    struct Failing {
+        batches: Mutex<Vec<usize>>,
     }
 
-    #[tokio::test]
-    async fn stops_after_max_retries() {
-        let msg = sample_msg();
-        let channels = ChannelRegistry::new(1);
-        let attempts = Arc::new(AtomicUsize::new(0));
-        let attempts_clone = attempts.clone();
-
-        let forward = move |_ev: &MdEvent| {
-            let attempts_clone = attempts_clone.clone();
-            async move {
-                attempts_clone.fetch_add(1, Ordering::SeqCst);
-                Err(anyhow!("fail"))
-            }
-        };
+    #[async_trait]
+    impl Sink for Failing {
+        async fn publish(&self, event: &MdEvent) -> Result<()> {
+            self.publish_batch(std::slice::from_ref(event)).await
+        }
 
-        tokio::spawn(process_stream_event(msg, false, channels, forward))
-            .await
-            .unwrap();
+        async fn publish_batch(&self, events: &[MdEvent]) -> Result<()> {
+            self.batches.lock().unwrap().push(events.len());
+            Err(anyhow!("queue is full"))
+        }
 
-        assert_eq!(attempts.load(Ordering::SeqCst), 4);
+        async fn flush(&self) -> Result<()> {
+            Ok(())
+        }
     }
 
     #[tokio::test(start_paused = true)]
-    async fn backoff_increments_and_stops_after_max_attempts() {
-        let msg = sample_msg();
+    async fn rejected_batch_is_not_retried() {
         let channels = ChannelRegistry::new(1);
-        let attempts = Arc::new(AtomicUsize::new(0));
-        let times: Arc<Mutex<Vec<ttime::Instant>>> = Arc::new(Mutex::new(Vec::new()));
-        let attempts_clone = attempts.clone();
-        let times_clone = times.clone();
-
-        let fail_times = 5;
-        let forward = move |_ev: &MdEvent| {
-            let attempts_clone = attempts_clone.clone();
-            let times_clone = times_clone.clone();
-            async move {
-                times_clone.lock().unwrap().push(ttime::Instant::now());
-                let attempt = attempts_clone.fetch_add(1, Ordering::SeqCst);
-                if attempt < fail_times {
-                    Err(anyhow!("fail"))
-                } else {
-                    Ok(())
-                }
-            }
-        };
-
-        let task = tokio::spawn(process_stream_event(msg, false, channels, forward));
-
-        tokio::task::yield_now().await;
-        assert_eq!(attempts.load(Ordering::SeqCst), 1);
-
-        ttime::advance(Duration::from_millis(99)).await;
-        tokio::task::yield_now().await;
-        assert_eq!(attempts.load(Ordering::SeqCst), 1);
-        ttime::advance(Duration::from_millis(1)).await;
-        tokio::task::yield_now().await;
-        assert_eq!(attempts.load(Ordering::SeqCst), 2);
-
-        ttime::advance(Duration::from_millis(199)).await;
-        tokio::task::yield_now().await;
-        assert_eq!(attempts.load(Ordering::SeqCst), 2);
-        ttime::advance(Duration::from_millis(1)).await;
-        tokio::task::yield_now().await;
-        assert_eq!(attempts.load(Ordering::SeqCst), 3);
-
-        ttime::advance(Duration::from_millis(399)).await;
-        tokio::task::yield_now().await;
-        assert_eq!(attempts.load(Ordering::SeqCst), 3);
-        ttime::advance(Duration::from_millis(1)).await;
-        tokio::task::yield_now().await;
-        assert_eq!(attempts.load(Ordering::SeqCst), 4);
-
-        ttime::advance(Duration::from_secs(1)).await;
-        tokio::task::yield_now().await;
-        assert_eq!(attempts.load(Ordering::SeqCst), 4);
-
-        task.await.unwrap();
-
-        let times = times.lock().unwrap();
-        assert_eq!(times.len(), 4);
-        let deltas: Vec<_> = times.windows(2).map(|w| w[1] - w[0]).collect();
-        assert_eq!(
-            deltas,
-            vec![
-                Duration::from_millis(100),
-                Duration::from_millis(200),
-                Duration::from_millis(400)
-            ]
-        );
+        let mut events = Vec::new();
+        for msg in [sample_msg(), crossed_msg()] {
+            // Only the crossed ticker is invalid and dropped.
+            events.extend(prepare_event(msg, false, &channels, &validator()).await);
+        }
+        let sink = Failing::default();
+        let start = tokio::time::Instant::now();
+        forward(&sink, &events, false).await;
+        assert_eq!(*sink.batches.lock().unwrap(), [1]);
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        forward(&sink, &[], false).await;
+        assert_eq!(sink.batches.lock().unwrap().len(), 1);
     }
 
     #[tokio::test]
     async fn stamps_ingest_fields_and_seq() {
-        let msg = sample_msg();
         let channels = ChannelRegistry::new(1);
-        let first = Arc::new(Mutex::new(None));
-        let first_cl = first.clone();
-        let forward = move |ev: &MdEvent| {
-            let first_cl = first_cl.clone();
-            let ev_clone = ev.clone();
-            async move {
-                *first_cl.lock().unwrap() = Some(ev_clone);
-                Ok(())
-            }
-        };
-        process_stream_event(msg, false, channels.clone(), forward).await;
-        let ev1 = first.lock().unwrap().clone().unwrap();
+        let ev1 = prepare_event(sample_msg(), false, &channels, &validator())
+            .await
+            .unwrap();
         match ev1.event {
             MdEventKind::BookTicker(bt) => {
                 assert!(bt.ingest_ts_monotonic > 0);
@@ -476,19 +527,9 @@ mod tests {
             _ => panic!("expected book ticker"),
         }
 
-        let second = Arc::new(Mutex::new(None));
-        let second_cl = second.clone();
-        let forward2 = move |ev: &MdEvent| {
-            let second_cl = second_cl.clone();
-            let ev_clone = ev.clone();
-            async move {
-                *second_cl.lock().unwrap() = Some(ev_clone);
-                Ok(())
-            }
-        };
-        let msg2 = sample_msg();
-        process_stream_event(msg2, false, channels.clone(), forward2).await;
-        let ev2 = second.lock().unwrap().clone().unwrap();
+        let ev2 = prepare_event(sample_msg(), false, &channels, &validator())
+            .await
+            .unwrap();
         match ev2.event {
             MdEventKind::BookTicker(bt) => {
                 assert_eq!(bt.seq_no, 1);
@@ -496,4 +537,45 @@ mod tests {
             _ => panic!("expected book ticker"),
         }
     }
+
+    #[tokio::test]
+    async fn invalid_event_is_dropped() {
+        let ev = prepare_event(crossed_msg(), false, &ChannelRegistry::new(1), &validator()).await;
+        assert!(ev.is_none());
+    }
+
+    #[tokio::test]
+    async fn invalid_event_is_flagged() {
+        let validator = Validator::new(
+            ValidationAction::Flag,
+            canonical::ValidationRules::default(),
+        );
+        let ev = prepare_event(crossed_msg(), false, &ChannelRegistry::new(1), &validator)
+            .await
+            .expect("flagged event is forwarded");
+        let err = ev.validation.as_ref().expect("flagged");
+        assert_eq!(err.reason(), "crossed");
+    }
+
+    #[tokio::test]
+    async fn invalid_event_is_quarantined() {
+        let path = std::env::temp_dir().join(format!("quarantine_{}.dlq", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let dlq = Arc::new(sink::DeadLetterQueue::open(&path).await.unwrap());
+        let validator = Validator::new(
+            ValidationAction::Quarantine(dlq),
+            canonical::ValidationRules::default(),
+        );
+        let ev = prepare_event(crossed_msg(), false, &ChannelRegistry::new(1), &validator).await;
+        assert!(ev.is_none());
+        validator.flush().await;
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let letter: sink::DeadLetter = serde_json::from_str(contents.trim()).unwrap();
+        assert_eq!(letter.reason, "validation:crossed");
+        assert!(letter.ts > 0);
+        let ev = letter.event.expect("quarantined event");
+        assert_eq!(ev.validation.map(|e| e.reason()), Some("crossed"));
+        std::fs::remove_file(&path).unwrap();
+    }
 }
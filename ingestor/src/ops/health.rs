@@ -1,4 +1,9 @@
-use axum::{extract::State, http::StatusCode, routing::get, Router};
+use axum::{
+    extract::{ConnectInfo, State},
+    http::{header::AUTHORIZATION, HeaderMap, StatusCode},
+    routing::{get, post},
+    Router,
+};
 use once_cell::sync::Lazy;
 use std::{
     env,
@@ -10,6 +15,8 @@ use std::{
 };
 use tracing::error;
 
+use super::stream::StreamHub;
+
 static READY: Lazy<Arc<AtomicBool>> = Lazy::new(|| Arc::new(AtomicBool::new(true)));
 
 pub fn set_ready(ready: bool) {
@@ -24,23 +31,90 @@ async fn readyz(State(state): State<Arc<AtomicBool>>) -> StatusCode {
     }
 }
 
-pub fn serve() {
+/// Whether a request may use the admin endpoints: with `token` set it must
+/// carry `Authorization: Bearer <token>`, otherwise it must come from
+/// loopback.
+fn admin_allowed(peer: SocketAddr, headers: &HeaderMap, token: Option<&str>) -> bool {
+    let Some(token) = token else {
+        return peer.ip().is_loopback();
+    };
+    let given = headers
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    // Compare every byte so the time taken does not reveal the prefix.
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+async fn reload_symbols(
+    State(token): State<Option<Arc<str>>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> StatusCode {
+    if !admin_allowed(peer, &headers, token.as_deref()) {
+        return StatusCode::FORBIDDEN;
+    }
+    crate::symbols::request_reload();
+    StatusCode::ACCEPTED
+}
+
+/// Serve health, readiness and admin endpoints, plus `/stream` when a
+/// [`StreamHub`] is given. Admin endpoints require `MD_ADMIN_TOKEN` when it
+/// is set and only answer loopback clients otherwise.
+pub fn serve(stream: Option<Arc<StreamHub>>) {
     let port: u16 = env::var("HEALTH_PORT")
         .ok()
         .and_then(|p| p.parse().ok())
         .unwrap_or(8080);
+    let token: Option<Arc<str>> = env::var("MD_ADMIN_TOKEN")
+        .ok()
+        .filter(|t| !t.is_empty())
+        .map(Into::into);
     let state = READY.clone();
     tokio::spawn(async move {
-        let app = Router::new()
+        let admin = Router::new()
+            .route("/admin/reload-symbols", post(reload_symbols))
+            .with_state(token);
+        let mut app = Router::new()
             .route("/healthz", get(|| async { StatusCode::OK }))
             .route("/readyz", get(readyz))
-            .with_state(state);
+            .with_state(state)
+            .merge(admin);
+        if let Some(hub) = stream {
+            app = app.merge(super::stream::router(hub));
+        }
         let addr = SocketAddr::from(([0, 0, 0, 0], port));
         if let Err(e) = axum::Server::bind(&addr)
-            .serve(app.into_make_service())
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>())
             .await
         {
             error!("health server error: {e}");
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn admin_needs_loopback_or_the_token() {
+        let local = SocketAddr::from(([127, 0, 0, 1], 40000));
+        let remote = SocketAddr::from(([10, 0, 0, 7], 40000));
+        let mut headers = HeaderMap::new();
+        assert!(admin_allowed(local, &headers, None));
+        assert!(!admin_allowed(remote, &headers, None));
+
+        assert!(!admin_allowed(local, &headers, Some("secret")));
+        headers.insert(AUTHORIZATION, "Bearer secreT".parse().unwrap());
+        assert!(!admin_allowed(remote, &headers, Some("secret")));
+        headers.insert(AUTHORIZATION, "Bearer secret".parse().unwrap());
+        assert!(admin_allowed(remote, &headers, Some("secret")));
+    }
+}
@@ -1,17 +1,20 @@
 use anyhow::Result;
+use std::sync::Arc;
 
 mod health;
 #[cfg(feature = "prometheus-exporter")]
 pub mod metrics;
 pub mod shutdown;
+pub mod stream;
+pub mod top_of_book;
 
 pub use health::set_ready;
 
-pub fn serve_all(metrics_enabled: bool) -> Result<()> {
+pub fn serve_all(metrics_enabled: bool, stream: Option<Arc<stream::StreamHub>>) -> Result<()> {
     if metrics_enabled {
         #[cfg(feature = "prometheus-exporter")]
         metrics::serve()?;
     }
-    health::serve();
+    health::serve(stream);
     Ok(())
 }
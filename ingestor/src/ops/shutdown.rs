@@ -1,13 +1,16 @@
 use reqwest::Client;
 use std::env;
+use std::sync::Arc;
 use tokio::signal::unix::{signal, SignalKind};
 use tracing::error;
 
 use agents::TaskSet;
 
+use crate::sink::Sink;
+
 /// Spawn a task to listen for SIGINT and SIGTERM and perform a graceful
 /// shutdown sequence when triggered.
-pub fn install(join_set: TaskSet) {
+pub fn install(join_set: TaskSet, sink: Arc<dyn Sink>) {
     tokio::spawn(async move {
         let mut sigint = signal(SignalKind::interrupt()).expect("sigint handler");
         let mut sigterm = signal(SignalKind::terminate()).expect("sigterm handler");
@@ -27,8 +30,11 @@ pub fn install(join_set: TaskSet) {
             while set.join_next().await.is_some() {}
         }
 
-        // Drain queues and flush sink: nothing explicit is required beyond
-        // awaiting task completion, but this block signifies intent.
+        // Flush the sink so buffered output, such as open Parquet files, is
+        // finalized before exiting.
+        if let Err(e) = sink.flush().await {
+            error!("sink flush failed: {e}");
+        }
 
         // Confirm health endpoint before exiting.
         let port: u16 = env::var("HEALTH_PORT")
@@ -0,0 +1,816 @@
+//! Live fan-out of normalized events over WebSocket.
+//!
+//! Clients connect to `/stream` on the health server, optionally with
+//! `?encoding=sbe`, and send `{"op": "subscribe", ...}` with the same
+//! exchange, symbol, channel and kind filters as sink routes. Each client has
+//! a bounded queue; a client that lets it fill up is disconnected rather
+//! than slowing down ingestion. On subscribe, the latest book ticker and
+//! depth book of every matching instrument are queued ahead of live events.
+//! A depth book is dropped on a gap in its update ids until the next
+//! snapshot.
+
+use anyhow::{anyhow, Result};
+use arb_core::events::Channel;
+use arb_core::ApplyResult;
+use async_trait::async_trait;
+use axum::{
+    extract::{
+        ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade},
+        Query, State,
+    },
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use canonical::{normalize_symbol, DepthSnapshot, Level, MdEvent, MdEventKind, SCHEMA_VERSION};
+use futures::{SinkExt, StreamExt};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::env;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+use super::top_of_book::TopOfBook;
+use crate::sink::{Encoding, EventEncoder, RouteFilter, Sink};
+
+const DEFAULT_QUEUE: usize = 1024;
+/// Locks the books are spread over, by instrument.
+const BOOK_SHARDS: usize = 16;
+
+/// Connected clients and the latest books, shared by the server and
+/// [`StreamingSink`].
+///
+/// Publishers share the client lock and only take the book lock of the
+/// event's instrument, so consumers of different partitions do not wait on
+/// each other. Subscribing takes the client lock exclusively, so a new
+/// client's books and its first live events line up.
+pub struct StreamHub {
+    clients: RwLock<Clients>,
+    books: Vec<Mutex<Books>>,
+    top_of_book: Mutex<Option<TopOfBook>>,
+    queue: usize,
+    metrics_enabled: bool,
+}
+
+#[derive(Default)]
+struct Clients {
+    next_id: u64,
+    map: HashMap<u64, Client>,
+}
+
+/// Books by exchange and venue symbol.
+type Books = HashMap<String, HashMap<String, Book>>;
+
+struct Client {
+    /// `None` until the client subscribes.
+    filter: Option<RouteFilter>,
+    tx: mpsc::Sender<Arc<MdEvent>>,
+    /// Set when the client was dropped for falling behind.
+    slow: Arc<AtomicBool>,
+}
+
+#[derive(Default)]
+struct Book {
+    ticker: Option<Arc<MdEvent>>,
+    depth: Option<DepthSnapshot>,
+}
+
+impl StreamHub {
+    pub fn new(queue: usize, metrics_enabled: bool) -> Self {
+        Self {
+            clients: RwLock::new(Clients::default()),
+            books: (0..BOOK_SHARDS).map(|_| Mutex::default()).collect(),
+            top_of_book: Mutex::new(None),
+            queue: queue.max(1),
+            metrics_enabled,
+        }
+    }
+
+    /// A hub when `MD_STREAM_ENABLED` is true, see [`StreamHub::with_env_queue`].
+    pub fn from_env(metrics_enabled: bool) -> Result<Option<Arc<Self>>> {
+        let enabled = match env::var("MD_STREAM_ENABLED") {
+            Ok(v) => v
+                .trim()
+                .parse()
+                .map_err(|_| anyhow!("MD_STREAM_ENABLED: {v}"))?,
+            Err(_) => false,
+        };
+        if !enabled {
+            return Ok(None);
+        }
+        Self::with_env_queue(metrics_enabled).map(Some)
+    }
+
+    /// A hub with `MD_STREAM_QUEUE` events buffered per client (default 1024).
+    pub fn with_env_queue(metrics_enabled: bool) -> Result<Arc<Self>> {
+        let queue = match env::var("MD_STREAM_QUEUE") {
+            Ok(v) => v
+                .trim()
+                .parse()
+                .map_err(|_| anyhow!("MD_STREAM_QUEUE: {v}"))?,
+            Err(_) => DEFAULT_QUEUE,
+        };
+        Ok(Arc::new(Self::new(queue, metrics_enabled)))
+    }
+
+    fn clients(&self) -> std::sync::RwLockReadGuard<'_, Clients> {
+        self.clients.read().expect("stream hub lock poisoned")
+    }
+
+    fn clients_mut(&self) -> std::sync::RwLockWriteGuard<'_, Clients> {
+        self.clients.write().expect("stream hub lock poisoned")
+    }
+
+    fn shard(&self, exchange: &str, symbol: &str) -> std::sync::MutexGuard<'_, Books> {
+        let mut hasher = DefaultHasher::new();
+        (exchange, symbol).hash(&mut hasher);
+        let shard = hasher.finish() as usize % self.books.len();
+        self.books[shard].lock().expect("stream hub mutex poisoned")
+    }
+
+    /// Register a client. It receives nothing until it subscribes, and is
+    /// removed when the returned [`Subscription`] is dropped.
+    pub fn connect(self: &Arc<Self>) -> Subscription {
+        let (tx, rx) = mpsc::channel(self.queue);
+        let slow = Arc::new(AtomicBool::new(false));
+        let mut clients = self.clients_mut();
+        let id = clients.next_id;
+        clients.next_id += 1;
+        clients.map.insert(
+            id,
+            Client {
+                filter: None,
+                tx,
+                slow: slow.clone(),
+            },
+        );
+        self.record_clients(clients.map.len());
+        Subscription {
+            hub: self.clone(),
+            id,
+            rx,
+            slow,
+        }
+    }
+
+    fn disconnect(&self, id: u64) {
+        let mut clients = self.clients_mut();
+        if clients.map.remove(&id).is_some() {
+            self.record_clients(clients.map.len());
+        }
+    }
+
+    /// Replace the client's filter and queue the books it matches. Returns
+    /// the number of snapshot events queued.
+    fn subscribe(&self, id: u64, filter: Option<RouteFilter>) -> usize {
+        let mut clients = self.clients_mut();
+        let Some(client) = clients.map.get_mut(&id) else {
+            return 0;
+        };
+        client.filter = filter;
+        let Some(filter) = &client.filter else {
+            return 0;
+        };
+        let mut queued = 0;
+        'shards: for shard in &self.books {
+            let books = shard.lock().expect("stream hub mutex poisoned");
+            for book in books.values().flat_map(HashMap::values) {
+                let depth = book.depth.as_ref().map(|d| {
+                    Arc::new(MdEvent {
+                        schema_version: SCHEMA_VERSION,
+                        event: MdEventKind::DepthSnapshot(d.clone()),
+                        validation: None,
+                    })
+                });
+                for ev in depth.iter().chain(book.ticker.as_ref()) {
+                    if !filter.matches(ev) {
+                        continue;
+                    }
+                    if client.tx.try_send(ev.clone()).is_err() {
+                        client.slow.store(true, Ordering::Relaxed);
+                        break 'shards;
+                    }
+                    queued += 1;
+                }
+            }
+        }
+        if client.slow.load(Ordering::Relaxed) {
+            clients.map.remove(&id);
+            self.record_slow(clients.map.len());
+        }
+        queued
+    }
+
+    /// Latest depth book and book ticker of an instrument, looked up by
+    /// venue-native symbol, ignoring case, or by canonical symbol.
+    #[cfg_attr(not(feature = "grpc"), allow(dead_code))]
+    pub fn book(
+        &self,
+        exchange: &str,
+        symbol: &str,
+    ) -> (Option<DepthSnapshot>, Option<Arc<MdEvent>>) {
+        let found = |book: &Book| (book.depth.clone(), book.ticker.clone());
+        if let Some(book) = self
+            .shard(exchange, symbol)
+            .get(exchange)
+            .and_then(|b| b.get(symbol))
+        {
+            return found(book);
+        }
+        for shard in &self.books {
+            let books = shard.lock().expect("stream hub mutex poisoned");
+            let book = books
+                .iter()
+                .filter(|(ex, _)| ex.eq_ignore_ascii_case(exchange))
+                .flat_map(|(ex, books)| books.iter().map(move |(sym, book)| (ex, sym, book)))
+                .find_map(|(ex, sym, book)| {
+                    (sym.eq_ignore_ascii_case(symbol) || normalize_symbol(ex, sym) == symbol)
+                        .then_some(book)
+                });
+            if let Some(book) = book {
+                return found(book);
+            }
+        }
+        (None, None)
+    }
+
+    /// Also publish the best bid and ask of every book on each change.
+    pub fn publish_top_of_book(&self, top_of_book: TopOfBook) {
+        *self.top_of_book.lock().expect("stream hub mutex poisoned") = Some(top_of_book);
+    }
+
+    /// Update the latest books and queue `event` for every matching client,
+    /// dropping clients whose queue is full.
+    pub fn broadcast(&self, event: &MdEvent) {
+        let clients = self.clients();
+        let mut shared = None;
+        match &event.event {
+            MdEventKind::BookTicker(ticker) => {
+                let ev = shared
+                    .get_or_insert_with(|| Arc::new(event.clone()))
+                    .clone();
+                let mut books = self.shard(&ticker.exchange, &ticker.symbol);
+                book_entry(&mut books, &ticker.exchange, &ticker.symbol).ticker = Some(ev);
+                if let Some(top) = &mut *self.top_of_book.lock().expect("stream hub mutex poisoned")
+                {
+                    top.ticker(ticker);
+                }
+            }
+            MdEventKind::DepthSnapshot(snapshot) => {
+                let mut books = self.shard(&snapshot.exchange, &snapshot.symbol);
+                book_entry(&mut books, &snapshot.exchange, &snapshot.symbol).depth =
+                    Some(snapshot.clone());
+                if let Some(top) = &mut *self.top_of_book.lock().expect("stream hub mutex poisoned")
+                {
+                    top.depth(snapshot);
+                }
+            }
+            MdEventKind::DepthL2Update(update) => {
+                let mut books = self.shard(&update.exchange, &update.symbol);
+                let book = books
+                    .get_mut(update.exchange.as_str())
+                    .and_then(|b| b.get_mut(update.symbol.as_str()));
+                if let Some(book) = book {
+                    if let Some(depth) = book.depth.as_mut() {
+                        match apply_update(depth, update) {
+                            ApplyResult::Applied => {
+                                let mut top =
+                                    self.top_of_book.lock().expect("stream hub mutex poisoned");
+                                if let Some(top) = &mut *top {
+                                    top.depth(depth);
+                                }
+                            }
+                            ApplyResult::Outdated => {}
+                            ApplyResult::Gap => {
+                                warn!(
+                                    exchange = %update.exchange,
+                                    symbol = %update.symbol,
+                                    last_update_id = depth.last_update_id,
+                                    first_update_id = ?update.first_update_id,
+                                    "gap in depth updates, dropping book until the next snapshot"
+                                );
+                                book.depth = None;
+                            }
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+        let mut slow = Vec::new();
+        for (id, client) in &clients.map {
+            if client.slow.load(Ordering::Relaxed)
+                || !client.filter.as_ref().is_some_and(|f| f.matches(event))
+            {
+                continue;
+            }
+            let ev = shared
+                .get_or_insert_with(|| Arc::new(event.clone()))
+                .clone();
+            if client.tx.try_send(ev).is_err() {
+                client.slow.store(true, Ordering::Relaxed);
+                slow.push(*id);
+            }
+        }
+        drop(clients);
+        if !slow.is_empty() {
+            let mut clients = self.clients_mut();
+            for id in slow {
+                if clients.map.remove(&id).is_some() {
+                    self.record_slow(clients.map.len());
+                }
+            }
+        }
+    }
+
+    fn record_clients(&self, clients: usize) {
+        if self.metrics_enabled {
+            metrics::gauge!("md_stream_clients").set(clients as f64);
+        }
+    }
+
+    fn record_slow(&self, clients: usize) {
+        if self.metrics_enabled {
+            metrics::counter!("md_stream_slow_disconnects_total").increment(1);
+        }
+        self.record_clients(clients);
+    }
+}
+
+/// A client registered with a [`StreamHub`].
+pub struct Subscription {
+    hub: Arc<StreamHub>,
+    id: u64,
+    rx: mpsc::Receiver<Arc<MdEvent>>,
+    slow: Arc<AtomicBool>,
+}
+
+impl Subscription {
+    /// Replace the filter, `None` to stop delivery, and queue the books it
+    /// matches. Returns the number of snapshot events queued.
+    pub fn subscribe(&self, filter: Option<RouteFilter>) -> usize {
+        self.hub.subscribe(self.id, filter)
+    }
+
+    /// Next event, or `None` once the client was dropped for falling behind.
+    pub async fn recv(&mut self) -> Option<Arc<MdEvent>> {
+        self.rx.recv().await
+    }
+
+    /// Whether the hub dropped this client because its queue was full.
+    pub fn is_slow(&self) -> bool {
+        self.slow.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.hub.disconnect(self.id);
+    }
+}
+
+fn book_entry<'a>(books: &'a mut Books, exchange: &str, symbol: &str) -> &'a mut Book {
+    // Only allocates the keys for a new instrument.
+    if !books.get(exchange).is_some_and(|b| b.contains_key(symbol)) {
+        books
+            .entry(exchange.to_string())
+            .or_default()
+            .insert(symbol.to_string(), Book::default());
+    }
+    books
+        .get_mut(exchange)
+        .and_then(|b| b.get_mut(symbol))
+        .expect("book inserted above")
+}
+
+/// Apply an incremental update to a snapshot, skipping updates it already
+/// contains. A zero quantity removes the level. Updates that do not follow
+/// on from the snapshot's last update id, by their first id or by the
+/// previous final id where the venue sends one, are reported as a gap.
+fn apply_update(book: &mut DepthSnapshot, update: &canonical::DepthL2Update) -> ApplyResult {
+    if let Some(last) = update.final_update_id {
+        if last <= book.last_update_id {
+            return ApplyResult::Outdated;
+        }
+        // Venues without a previous id leave it at zero.
+        let previous = update.previous_final_update_id.filter(|&id| id != 0);
+        let gap = match (update.first_update_id, previous) {
+            // Spans the last id, as the first update after a snapshot may.
+            (Some(first), _) if first <= book.last_update_id => false,
+            (_, Some(previous)) => previous != book.last_update_id,
+            (Some(first), None) => first > book.last_update_id + 1,
+            (None, None) => false,
+        };
+        if gap {
+            return ApplyResult::Gap;
+        }
+        book.last_update_id = last;
+    }
+    for level in &update.bids {
+        apply_level(&mut book.bids, level, |a, b| a > b);
+    }
+    for level in &update.asks {
+        apply_level(&mut book.asks, level, |a, b| a < b);
+    }
+    book.ts = update.ts;
+    book.ingest_ts_monotonic = update.ingest_ts_monotonic;
+    book.ingest_ts_utc = update.ingest_ts_utc;
+    book.seq_no = update.seq_no;
+    ApplyResult::Applied
+}
+
+/// Insert, replace or remove `level` in `levels`, kept ordered so that
+/// `better(a, b)` holds for every earlier price `a`.
+fn apply_level(levels: &mut Vec<Level>, level: &Level, better: impl Fn(f64, f64) -> bool) {
+    let pos = levels.partition_point(|l| better(l.price, level.price));
+    let exists = levels.get(pos).is_some_and(|l| l.price == level.price);
+    match (exists, level.quantity == 0.0) {
+        (true, true) => {
+            levels.remove(pos);
+        }
+        (true, false) => levels[pos].quantity = level.quantity,
+        (false, true) => {}
+        (false, false) => levels.insert(pos, level.clone()),
+    }
+}
+
+/// Sink publishing to `inner` and broadcasting every event to WebSocket
+/// clients.
+pub struct StreamingSink {
+    inner: Arc<dyn Sink>,
+    hub: Arc<StreamHub>,
+}
+
+impl StreamingSink {
+    pub fn new(inner: Arc<dyn Sink>, hub: Arc<StreamHub>) -> Self {
+        Self { inner, hub }
+    }
+}
+
+#[async_trait]
+impl Sink for StreamingSink {
+    async fn publish(&self, event: &MdEvent) -> Result<()> {
+        self.hub.broadcast(event);
+        self.inner.publish(event).await
+    }
+
+    async fn publish_batch(&self, events: &[MdEvent]) -> Result<()> {
+        for event in events {
+            self.hub.broadcast(event);
+        }
+        self.inner.publish_batch(events).await
+    }
+
+    async fn flush(&self) -> Result<()> {
+        self.inner.flush().await
+    }
+}
+
+/// Routes serving the hub, merged into the health server.
+pub fn router(hub: Arc<StreamHub>) -> Router {
+    Router::new().route("/stream", get(upgrade)).with_state(hub)
+}
+
+#[derive(Deserialize)]
+struct StreamParams {
+    encoding: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Request {
+    Subscribe {
+        #[serde(default)]
+        exchanges: Vec<String>,
+        #[serde(default)]
+        symbols: Vec<String>,
+        #[serde(default)]
+        channels: Vec<String>,
+        #[serde(default)]
+        kinds: Vec<String>,
+    },
+    Unsubscribe,
+}
+
+async fn upgrade(
+    ws: WebSocketUpgrade,
+    Query(params): Query<StreamParams>,
+    State(hub): State<Arc<StreamHub>>,
+) -> Response {
+    let encoding = match params.encoding.as_deref().map(str::parse::<Encoding>) {
+        None => Encoding::Json,
+        Some(Ok(encoding)) => encoding,
+        Some(Err(e)) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+    ws.on_upgrade(move |socket| serve_client(socket, hub, encoding))
+}
+
+async fn serve_client(socket: WebSocket, hub: Arc<StreamHub>, encoding: Encoding) {
+    let mut sub = hub.connect();
+    let (mut tx, mut incoming) = socket.split();
+    let mut encoder = EventEncoder::new(encoding);
+    loop {
+        tokio::select! {
+            msg = incoming.next() => {
+                let reply = match msg {
+                    Some(Ok(Message::Text(text))) => handle_request(&sub, &text),
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => continue,
+                };
+                if tx.send(Message::Text(reply.to_string())).await.is_err() {
+                    break;
+                }
+            }
+            ev = sub.recv() => {
+                let Some(ev) = ev else {
+                    if sub.is_slow() {
+                        debug!("disconnecting slow stream client");
+                        let _ = tx
+                            .send(Message::Close(Some(CloseFrame {
+                                code: close_code::POLICY,
+                                reason: "slow consumer".into(),
+                            })))
+                            .await;
+                    }
+                    break;
+                };
+                let mut buf = Vec::new();
+                let msg = match encoding {
+                    Encoding::Json => encoder
+                        .encode_event(&ev, &mut buf)
+                        .map(|_| Message::Text(String::from_utf8(buf).unwrap_or_default())),
+                    Encoding::Sbe => encoder.encode(&ev, &mut buf).map(|_| Message::Binary(buf)),
+                };
+                let msg = match msg {
+                    Ok(msg) => msg,
+                    Err(e) => {
+                        warn!(error = %e, "failed to encode stream event");
+                        continue;
+                    }
+                };
+                if tx.send(msg).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+fn handle_request(sub: &Subscription, text: &str) -> serde_json::Value {
+    let request = match serde_json::from_str::<Request>(text) {
+        Ok(request) => request,
+        Err(e) => return json!({"event": "error", "message": e.to_string()}),
+    };
+    match request {
+        Request::Subscribe {
+            exchanges,
+            symbols,
+            channels,
+            kinds,
+        } => {
+            let channels = match channels
+                .iter()
+                .map(|c| c.parse::<Channel>())
+                .collect::<Result<Vec<_>, _>>()
+            {
+                Ok(channels) => channels,
+                Err(e) => return json!({"event": "error", "message": e}),
+            };
+            let filter = RouteFilter {
+                exchanges,
+                symbols,
+                channels,
+                kinds,
+            };
+            let snapshots = sub.subscribe(Some(filter));
+            json!({"event": "subscribed", "snapshots": snapshots})
+        }
+        Request::Unsubscribe => {
+            sub.subscribe(None);
+            json!({"event": "unsubscribed"})
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::testing::event;
+    use canonical::{BookKind, BookTicker, DepthL2Update, Trade};
+    use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+    fn level(price: f64, quantity: f64, kind: BookKind) -> Level {
+        Level {
+            schema_version: SCHEMA_VERSION,
+            price,
+            quantity,
+            kind,
+        }
+    }
+
+    fn trade(exchange: &str, id: u64) -> MdEvent {
+        event(MdEventKind::Trade(Trade {
+            exchange: exchange.into(),
+            symbol: "BTCUSDT".into(),
+            trade_id: Some(id),
+            ..Default::default()
+        }))
+    }
+
+    fn snapshot() -> MdEvent {
+        event(MdEventKind::DepthSnapshot(DepthSnapshot {
+            exchange: "binance".into(),
+            symbol: "BTCUSDT".into(),
+            last_update_id: 10,
+            bids: vec![
+                level(100.0, 1.0, BookKind::Bid),
+                level(99.0, 1.0, BookKind::Bid),
+            ],
+            asks: vec![level(101.0, 1.0, BookKind::Ask)],
+            ..Default::default()
+        }))
+    }
+
+    fn update(id: u64, bids: Vec<Level>, asks: Vec<Level>) -> MdEvent {
+        event(MdEventKind::DepthL2Update(DepthL2Update {
+            exchange: "binance".into(),
+            symbol: "BTCUSDT".into(),
+            final_update_id: Some(id),
+            bids,
+            asks,
+            ..Default::default()
+        }))
+    }
+
+    #[test]
+    fn updates_keep_the_book_ordered() {
+        let hub = StreamHub::new(8, false);
+        hub.broadcast(&snapshot());
+        hub.broadcast(&update(
+            11,
+            vec![
+                level(100.5, 2.0, BookKind::Bid),
+                level(99.0, 0.0, BookKind::Bid),
+            ],
+            vec![
+                level(101.0, 3.0, BookKind::Ask),
+                level(102.0, 1.0, BookKind::Ask),
+            ],
+        ));
+        // Already applied, so ignored.
+        hub.broadcast(&update(11, vec![level(100.0, 0.0, BookKind::Bid)], vec![]));
+
+        let book = hub.book("binance", "BTCUSDT").0.unwrap();
+        let prices = |levels: &[Level]| {
+            levels
+                .iter()
+                .map(|l| (l.price, l.quantity))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(prices(&book.bids), vec![(100.5, 2.0), (100.0, 1.0)]);
+        assert_eq!(prices(&book.asks), vec![(101.0, 3.0), (102.0, 1.0)]);
+        assert_eq!(book.last_update_id, 11);
+    }
+
+    #[test]
+    fn gap_drops_the_book_until_the_next_snapshot() {
+        let hub = StreamHub::new(8, false);
+        let depth = || hub.book("binance", "BTCUSDT").0;
+        let gapped = |first, last| {
+            event(MdEventKind::DepthL2Update(DepthL2Update {
+                exchange: "binance".into(),
+                symbol: "BTCUSDT".into(),
+                first_update_id: Some(first),
+                final_update_id: Some(last),
+                ..Default::default()
+            }))
+        };
+
+        hub.broadcast(&snapshot());
+        // Spans the snapshot's id 10, then follows on.
+        hub.broadcast(&gapped(9, 12));
+        hub.broadcast(&gapped(13, 14));
+        assert_eq!(depth().unwrap().last_update_id, 14);
+
+        // 15 and 16 were missed.
+        hub.broadcast(&gapped(17, 18));
+        assert!(depth().is_none());
+        hub.broadcast(&gapped(19, 20));
+        assert!(depth().is_none());
+
+        hub.broadcast(&snapshot());
+        assert!(depth().is_some());
+    }
+
+    #[test]
+    fn slow_clients_are_dropped() {
+        let hub = Arc::new(StreamHub::new(2, false));
+        let slow = hub.connect();
+        let mut fast = hub.connect();
+        slow.subscribe(Some(RouteFilter::default()));
+        fast.subscribe(Some(RouteFilter::default()));
+        for id in 0..3 {
+            hub.broadcast(&trade("binance", id));
+            while fast.rx.try_recv().is_ok() {}
+        }
+        assert!(slow.is_slow());
+        assert!(!fast.is_slow());
+        {
+            let clients = hub.clients();
+            assert!(!clients.map.contains_key(&slow.id));
+            assert!(clients.map.contains_key(&fast.id));
+        }
+        drop(fast);
+        assert!(hub.clients().map.is_empty());
+    }
+
+    async fn next_text(
+        ws: &mut tokio_tungstenite::WebSocketStream<
+            tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+        >,
+    ) -> serde_json::Value {
+        let msg = tokio::time::timeout(std::time::Duration::from_secs(5), ws.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        match msg {
+            WsMessage::Text(text) => serde_json::from_str(&text).unwrap(),
+            other => panic!("unexpected message {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn subscribers_get_books_then_matching_events() {
+        let hub = Arc::new(StreamHub::new(64, false));
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = router(hub.clone());
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+
+        let sink = StreamingSink::new(Arc::new(NullSink), hub.clone());
+        sink.publish(&snapshot()).await.unwrap();
+        sink.publish(&update(11, vec![level(100.0, 0.0, BookKind::Bid)], vec![]))
+            .await
+            .unwrap();
+        sink.publish(&event(MdEventKind::BookTicker(BookTicker {
+            exchange: "okx".into(),
+            symbol: "BTC-USDT".into(),
+            ..Default::default()
+        })))
+        .await
+        .unwrap();
+
+        let (mut ws, _) = tokio_tungstenite::connect_async(format!("ws://{addr}/stream"))
+            .await
+            .unwrap();
+        ws.send(WsMessage::Text(
+            r#"{"op": "subscribe", "exchanges": ["binance"], "channels": ["trade", "depth"]}"#
+                .into(),
+        ))
+        .await
+        .unwrap();
+        let ack = next_text(&mut ws).await;
+        assert_eq!(ack["event"], "subscribed");
+        assert_eq!(ack["snapshots"], 1);
+        let book = next_text(&mut ws).await;
+        assert_eq!(book["DepthSnapshot"]["last_update_id"], 11);
+        assert_eq!(book["DepthSnapshot"]["bids"].as_array().unwrap().len(), 1);
+
+        sink.publish(&trade("okx", 1)).await.unwrap();
+        sink.publish(&trade("binance", 2)).await.unwrap();
+        let live = next_text(&mut ws).await;
+        assert_eq!(live["Trade"]["exchange"], "binance");
+        assert_eq!(live["Trade"]["trade_id"], 2);
+
+        ws.send(WsMessage::Text(
+            r#"{"op": "subscribe", "channels": ["bogus"]}"#.into(),
+        ))
+        .await
+        .unwrap();
+        assert_eq!(next_text(&mut ws).await["event"], "error");
+    }
+
+    struct NullSink;
+
+    #[async_trait]
+    impl Sink for NullSink {
+        async fn publish(&self, _: &MdEvent) -> Result<()> {
+            Ok(())
+        }
+
+        async fn flush(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+}
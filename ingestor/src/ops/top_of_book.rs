@@ -0,0 +1,185 @@
+//! Top-of-book published to shared memory for co-located readers, see the
+//! `md_shm` crate.
+
+use anyhow::{Context, Result};
+use canonical::{BookTicker, DepthSnapshot};
+use md_shm::{Quote, Writer};
+use std::env;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+use super::stream::StreamHub;
+
+const DEFAULT_CAPACITY: u32 = 4096;
+
+/// Publish the books of `hub` when `MD_SHM_PATH` is set, with room for
+/// `MD_SHM_CAPACITY` instruments (default 4096).
+///
+/// Reuses `hub` when the WebSocket stream or gRPC service is enabled and
+/// creates one otherwise; the returned hub must be fed by a
+/// [`StreamingSink`](super::stream::StreamingSink).
+pub fn publish_from_env(
+    hub: Option<Arc<StreamHub>>,
+    metrics_enabled: bool,
+) -> Result<Option<Arc<StreamHub>>> {
+    let Ok(path) = env::var("MD_SHM_PATH") else {
+        return Ok(hub);
+    };
+    let capacity = match env::var("MD_SHM_CAPACITY") {
+        Ok(v) => v.trim().parse().context("MD_SHM_CAPACITY")?,
+        Err(_) => DEFAULT_CAPACITY,
+    };
+    let writer = Writer::create(path.trim(), capacity)
+        .with_context(|| format!("creating top-of-book file {path}"))?;
+    info!(%path, capacity, "publishing top-of-book to shared memory");
+    let hub = match hub {
+        Some(hub) => hub,
+        None => StreamHub::with_env_queue(metrics_enabled)?,
+    };
+    hub.publish_top_of_book(TopOfBook::new(writer, metrics_enabled));
+    Ok(Some(hub))
+}
+
+/// Writer of the best bid and ask of every instrument.
+pub struct TopOfBook {
+    writer: Writer,
+    metrics_enabled: bool,
+    warned_full: bool,
+}
+
+impl TopOfBook {
+    pub fn new(writer: Writer, metrics_enabled: bool) -> Self {
+        Self {
+            writer,
+            metrics_enabled,
+            warned_full: false,
+        }
+    }
+
+    pub fn ticker(&mut self, t: &BookTicker) {
+        let quote = Quote {
+            bid_price: t.bid_price,
+            bid_quantity: t.bid_quantity,
+            ask_price: t.ask_price,
+            ask_quantity: t.ask_quantity,
+            ts: t.ts,
+            ingest_ts_utc: t.ingest_ts_utc,
+            ingest_ts_monotonic: t.ingest_ts_monotonic,
+        };
+        self.update(&t.exchange, &t.symbol, &quote);
+    }
+
+    /// Best levels of a maintained depth book; an empty side is published
+    /// as zero price and quantity.
+    pub fn depth(&mut self, book: &DepthSnapshot) {
+        let best = |levels: &[canonical::Level]| {
+            levels.first().map_or((0.0, 0.0), |l| (l.price, l.quantity))
+        };
+        let (bid_price, bid_quantity) = best(&book.bids);
+        let (ask_price, ask_quantity) = best(&book.asks);
+        let quote = Quote {
+            bid_price,
+            bid_quantity,
+            ask_price,
+            ask_quantity,
+            ts: book.ts,
+            ingest_ts_utc: book.ingest_ts_utc,
+            ingest_ts_monotonic: book.ingest_ts_monotonic,
+        };
+        self.update(&book.exchange, &book.symbol, &quote);
+    }
+
+    fn update(&mut self, exchange: &str, symbol: &str, quote: &Quote) {
+        if self.writer.update(exchange, symbol, quote) {
+            return;
+        }
+        if self.metrics_enabled {
+            metrics::counter!("md_shm_rejected_updates_total").increment(1);
+        }
+        if !self.warned_full {
+            self.warned_full = true;
+            warn!(
+                exchange,
+                symbol,
+                instruments = self.writer.len(),
+                "top-of-book file is full or the name is too long; raise MD_SHM_CAPACITY"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::testing::event;
+    use canonical::{BookKind, DepthL2Update, Level, MdEventKind, SCHEMA_VERSION};
+    use md_shm::Reader;
+
+    fn level(price: f64, quantity: f64, kind: BookKind) -> Level {
+        Level {
+            schema_version: SCHEMA_VERSION,
+            price,
+            quantity,
+            kind,
+        }
+    }
+
+    #[test]
+    fn publishes_tickers_and_book_changes() {
+        let path = env::temp_dir().join(format!("md_top_of_book_{}", std::process::id()));
+        let hub = StreamHub::new(8, false);
+        hub.publish_top_of_book(TopOfBook::new(Writer::create(&path, 8).unwrap(), false));
+        let reader = Reader::open(&path).unwrap();
+
+        hub.broadcast(&event(MdEventKind::BookTicker(BookTicker {
+            exchange: "okx".into(),
+            symbol: "BTC-USDT".into(),
+            bid_price: 100.0,
+            bid_quantity: 1.0,
+            ask_price: 101.0,
+            ask_quantity: 2.0,
+            ts: 5,
+            ..Default::default()
+        })));
+        hub.broadcast(&event(MdEventKind::DepthSnapshot(DepthSnapshot {
+            exchange: "binance".into(),
+            symbol: "BTCUSDT".into(),
+            last_update_id: 10,
+            bids: vec![level(100.0, 1.0, BookKind::Bid)],
+            asks: vec![level(101.0, 1.0, BookKind::Ask)],
+            ..Default::default()
+        })));
+        let update = |id, bids| {
+            event(MdEventKind::DepthL2Update(DepthL2Update {
+                exchange: "binance".into(),
+                symbol: "BTCUSDT".into(),
+                ts: id,
+                final_update_id: Some(id),
+                bids,
+                ..Default::default()
+            }))
+        };
+        hub.broadcast(&update(11, vec![level(100.5, 3.0, BookKind::Bid)]));
+        let binance = reader.find("binance", "BTCUSDT").unwrap();
+        let sequence = reader.sequence(binance);
+        // Already applied, so the slot is left alone.
+        hub.broadcast(&update(11, vec![level(100.5, 0.0, BookKind::Bid)]));
+        assert_eq!(reader.sequence(binance), sequence);
+
+        let okx = reader
+            .read(reader.find("okx", "BTC-USDT").unwrap())
+            .unwrap();
+        assert_eq!((okx.bid_price, okx.ask_quantity, okx.ts), (100.0, 2.0, 5));
+        let binance = reader.read(binance).unwrap();
+        assert_eq!(
+            (
+                binance.bid_price,
+                binance.bid_quantity,
+                binance.ask_price,
+                binance.ts
+            ),
+            (100.5, 3.0, 101.0, 11)
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,389 @@
+//! Size- and time-bounded batching with retries for sinks that write in
+//! bulk.
+//!
+//! Events are buffered per kind and written once `max_rows` are pending, once
+//! `max_delay` has passed, or on flush. A failed write is retried with
+//! exponential backoff by the timer and on flush and, if it still fails, the
+//! rows stay pending for the next attempt rather than being dropped, so
+//! `flush` only succeeds once everything published before it is written.
+//! Publishing never waits out a backoff: events are buffered without holding
+//! the writer, and a full batch is only attempted once from `push`. Behind a [`Wal`](super::Wal),
+//! this keeps the log from being checkpointed past rows the store has not
+//! taken.
+
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use canonical::MdEvent;
+use std::collections::BTreeMap;
+use std::env;
+use std::sync::{Arc, Mutex as StdMutex, Weak};
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+use tracing::warn;
+
+/// Batch limits and retries of a bulk-writing sink.
+#[derive(Debug, Clone, Copy)]
+pub struct BatchOptions {
+    /// Rows pending before a batch is written.
+    pub max_rows: usize,
+    /// Longest a row waits before its batch is written.
+    pub max_delay: Duration,
+    /// Attempts after the first before a write is reported as failed.
+    pub retries: u32,
+    /// Wait before the first retry, doubled for every further one.
+    pub backoff: Duration,
+    /// Rows kept while writes fail. Further events are rejected.
+    pub max_pending: usize,
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        Self {
+            max_rows: 10_000,
+            max_delay: Duration::from_secs(1),
+            retries: 3,
+            backoff: Duration::from_millis(200),
+            max_pending: 1_000_000,
+        }
+    }
+}
+
+impl BatchOptions {
+    /// Read `<prefix>_BATCH_SIZE`, `<prefix>_BATCH_MS`, `<prefix>_RETRIES` and
+    /// `<prefix>_MAX_PENDING`, falling back to the defaults.
+    pub fn from_env(prefix: &str) -> Result<Self> {
+        let mut opts = Self::default();
+        let var = |name: &str| {
+            let name = format!("{prefix}_{name}");
+            env::var(&name).ok().map(|v| (v, name))
+        };
+        if let Some((v, name)) = var("BATCH_SIZE") {
+            opts.max_rows = v.trim().parse().context(name)?;
+        }
+        if let Some((v, name)) = var("BATCH_MS") {
+            opts.max_delay = Duration::from_millis(v.trim().parse().context(name)?);
+        }
+        if let Some((v, name)) = var("RETRIES") {
+            opts.retries = v.trim().parse().context(name)?;
+        }
+        if let Some((v, name)) = var("MAX_PENDING") {
+            opts.max_pending = v.trim().parse().context(name)?;
+        }
+        Ok(opts)
+    }
+}
+
+/// Destination of a [`Batcher`].
+#[async_trait]
+pub(crate) trait BatchWriter: Send + 'static {
+    /// Write rows of one kind. A failed call is retried with the same rows.
+    async fn write(&mut self, kind: &'static str, rows: &[MdEvent]) -> Result<()>;
+}
+
+struct Pending {
+    rows: BTreeMap<&'static str, Vec<MdEvent>>,
+    /// Rows buffered or being written.
+    len: usize,
+    /// Set after a failed write; `push` leaves full batches to the timer
+    /// until then.
+    retry_at: Option<Instant>,
+}
+
+struct Shared<W> {
+    name: &'static str,
+    options: BatchOptions,
+    pending: StdMutex<Pending>,
+    writer: Mutex<W>,
+}
+
+/// Buffers events for a [`BatchWriter`]. `name` identifies the store in
+/// logs and errors.
+pub(crate) struct Batcher<W> {
+    shared: Arc<Shared<W>>,
+}
+
+impl<W: BatchWriter> Batcher<W> {
+    pub fn new(name: &'static str, writer: W, options: BatchOptions) -> Self {
+        let shared = Arc::new(Shared {
+            name,
+            options,
+            pending: StdMutex::new(Pending {
+                rows: BTreeMap::new(),
+                len: 0,
+                retry_at: None,
+            }),
+            writer: Mutex::new(writer),
+        });
+        tokio::spawn(run_timer(Arc::downgrade(&shared)));
+        Self { shared }
+    }
+
+    /// Buffer `ev`, writing the batch when it is full. Write failures keep
+    /// the rows pending; only an event that would exceed `max_pending` is
+    /// rejected.
+    pub async fn push(&self, ev: &MdEvent) -> Result<()> {
+        self.push_all(std::slice::from_ref(ev)).await
+    }
+
+    /// Buffer `events` like [`Batcher::push`]. The events are rejected
+    /// together if they would take the pending rows past `max_pending`.
+    ///
+    /// A full batch is written with a single attempt, and not at all while
+    /// another write is in progress or within `backoff` of a failed one;
+    /// retries are left to the timer and [`Batcher::flush`].
+    pub async fn push_all(&self, events: &[MdEvent]) -> Result<()> {
+        let shared = &self.shared;
+        let full = {
+            let mut pending = shared.pending.lock().expect("batch lock");
+            if pending.len + events.len() > shared.options.max_pending {
+                bail!(
+                    "{} events already waiting for {}, rejecting {}",
+                    pending.len,
+                    shared.name,
+                    events.len()
+                );
+            }
+            for ev in events {
+                pending.rows.entry(ev.kind()).or_default().push(ev.clone());
+            }
+            pending.len += events.len();
+            pending.len >= shared.options.max_rows
+                && pending.retry_at.is_none_or(|at| Instant::now() >= at)
+        };
+        if full {
+            if let Ok(mut writer) = shared.writer.try_lock() {
+                if let Err(e) = shared.write_pending(&mut writer, 0).await {
+                    warn!(error = %e, store = shared.name, "batch write failed");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Write everything pending, failing if any of it could not be written.
+    pub async fn flush(&self) -> Result<()> {
+        let shared = &self.shared;
+        let mut writer = shared.writer.lock().await;
+        shared
+            .write_pending(&mut writer, shared.options.retries)
+            .await
+    }
+}
+
+impl<W: BatchWriter> Shared<W> {
+    /// Write the rows pending now. Rows that could not be written go back in
+    /// front of any pushed in the meantime. The caller holds the writer
+    /// lock, but not the pending one, while writing.
+    async fn write_pending(&self, writer: &mut W, retries: u32) -> Result<()> {
+        let mut rows = std::mem::take(&mut self.pending.lock().expect("batch lock").rows);
+        while let Some((kind, batch)) = rows.pop_first() {
+            if let Err(e) = self.write(writer, kind, &batch, retries).await {
+                rows.insert(kind, batch);
+                let mut pending = self.pending.lock().expect("batch lock");
+                for (kind, mut batch) in rows {
+                    batch.append(pending.rows.entry(kind).or_default());
+                    pending.rows.insert(kind, batch);
+                }
+                pending.retry_at = Some(Instant::now() + self.options.backoff);
+                return Err(e.context(format!("writing {kind} rows to {}", self.name)));
+            }
+            self.pending.lock().expect("batch lock").len -= batch.len();
+        }
+        self.pending.lock().expect("batch lock").retry_at = None;
+        Ok(())
+    }
+
+    async fn write(
+        &self,
+        writer: &mut W,
+        kind: &'static str,
+        rows: &[MdEvent],
+        retries: u32,
+    ) -> Result<()> {
+        let mut backoff = self.options.backoff;
+        let mut attempt = 0;
+        loop {
+            match writer.write(kind, rows).await {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt < retries => {
+                    attempt += 1;
+                    warn!(error = %e, kind, rows = rows.len(), attempt, "retrying batch write");
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Write whatever is pending every `max_delay`, until the batcher is dropped.
+async fn run_timer<W: BatchWriter>(shared: Weak<Shared<W>>) {
+    let Some(max_delay) = shared.upgrade().map(|shared| shared.options.max_delay) else {
+        return;
+    };
+    let mut interval = tokio::time::interval(max_delay.max(Duration::from_millis(1)));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        let Some(shared) = shared.upgrade() else {
+            return;
+        };
+        if shared.pending.lock().expect("batch lock").rows.is_empty() {
+            continue;
+        }
+        let mut writer = shared.writer.lock().await;
+        if let Err(e) = shared
+            .write_pending(&mut writer, shared.options.retries)
+            .await
+        {
+            warn!(error = %e, store = shared.name, "batch write failed");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use canonical::{MdEventKind, Trade};
+
+    type Written = Arc<StdMutex<Vec<(&'static str, Vec<u64>)>>>;
+
+    #[derive(Clone, Default)]
+    struct Capture {
+        written: Written,
+        failures: Arc<StdMutex<u32>>,
+    }
+
+    #[async_trait]
+    impl BatchWriter for Capture {
+        async fn write(&mut self, kind: &'static str, rows: &[MdEvent]) -> Result<()> {
+            let mut failures = self.failures.lock().unwrap();
+            if *failures > 0 {
+                *failures -= 1;
+                bail!("unavailable");
+            }
+            let ids = rows
+                .iter()
+                .filter_map(|ev| match &ev.event {
+                    MdEventKind::Trade(t) => t.trade_id,
+                    _ => None,
+                })
+                .collect();
+            self.written.lock().unwrap().push((kind, ids));
+            Ok(())
+        }
+    }
+
+    fn trade(id: u64) -> MdEvent {
+        MdEvent {
+            schema_version: canonical::SCHEMA_VERSION,
+            event: MdEventKind::Trade(Trade {
+                trade_id: Some(id),
+                ..Default::default()
+            }),
+            validation: None,
+        }
+    }
+
+    fn options() -> BatchOptions {
+        BatchOptions {
+            max_rows: 3,
+            max_delay: Duration::from_secs(60),
+            retries: 1,
+            backoff: Duration::from_millis(1),
+            max_pending: 5,
+        }
+    }
+
+    #[tokio::test]
+    async fn writes_full_batches_and_keeps_failed_rows() {
+        let capture = Capture::default();
+        let batcher = Batcher::new("test", capture.clone(), options());
+        for id in 0..3 {
+            batcher.push(&trade(id)).await.unwrap();
+        }
+        assert_eq!(*capture.written.lock().unwrap(), [("Trade", vec![0, 1, 2])]);
+
+        // Every attempt fails, so the rows stay pending until the cap.
+        *capture.failures.lock().unwrap() = u32::MAX;
+        for id in 3..8 {
+            batcher.push(&trade(id)).await.unwrap();
+        }
+        assert!(batcher.push(&trade(8)).await.is_err());
+        assert_eq!(capture.written.lock().unwrap().len(), 1);
+
+        // One failure is absorbed by the retry.
+        *capture.failures.lock().unwrap() = 1;
+        batcher.flush().await.unwrap();
+        assert_eq!(
+            capture.written.lock().unwrap()[1],
+            ("Trade", vec![3, 4, 5, 6, 7])
+        );
+
+        *capture.failures.lock().unwrap() = 2;
+        batcher.push(&trade(9)).await.unwrap();
+        assert!(batcher.flush().await.is_err());
+        batcher.flush().await.unwrap();
+        assert_eq!(capture.written.lock().unwrap()[2], ("Trade", vec![9]));
+
+        batcher
+            .push_all(&[trade(10), trade(11), trade(12)])
+            .await
+            .unwrap();
+        assert_eq!(
+            capture.written.lock().unwrap()[3],
+            ("Trade", vec![10, 11, 12])
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn writes_pending_rows_after_the_delay() {
+        let capture = Capture::default();
+        let batcher = Batcher::new("test", capture.clone(), options());
+        batcher.push(&trade(1)).await.unwrap();
+        tokio::time::sleep(Duration::from_secs(30)).await;
+        assert!(capture.written.lock().unwrap().is_empty());
+        tokio::time::sleep(Duration::from_secs(31)).await;
+        assert_eq!(*capture.written.lock().unwrap(), [("Trade", vec![1])]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn push_leaves_retries_to_the_timer() {
+        let capture = Capture::default();
+        let options = BatchOptions {
+            max_rows: 1,
+            backoff: Duration::from_secs(10),
+            retries: 3,
+            ..options()
+        };
+        let batcher = Batcher::new("test", capture.clone(), options);
+        *capture.failures.lock().unwrap() = u32::MAX;
+        let attempts = || u32::MAX - *capture.failures.lock().unwrap();
+
+        // One attempt per full batch, none while backing off.
+        batcher.push(&trade(1)).await.unwrap();
+        assert_eq!(attempts(), 1);
+        batcher.push(&trade(2)).await.unwrap();
+        assert_eq!(attempts(), 1);
+        tokio::time::advance(Duration::from_secs(11)).await;
+        batcher.push(&trade(3)).await.unwrap();
+        assert_eq!(attempts(), 2);
+
+        // A batch that would exceed the cap is rejected whole.
+        assert!(batcher
+            .push_all(&[trade(4), trade(5), trade(6)])
+            .await
+            .is_err());
+        batcher.push_all(&[trade(4), trade(5)]).await.unwrap();
+
+        *capture.failures.lock().unwrap() = 0;
+        batcher.flush().await.unwrap();
+        assert_eq!(
+            *capture.written.lock().unwrap(),
+            [("Trade", vec![1, 2, 3, 4, 5])]
+        );
+    }
+}
@@ -0,0 +1,485 @@
+//! ClickHouse tables of events, one per kind.
+//!
+//! Batches are sent over the HTTP interface as `INSERT ... FORMAT RowBinary`,
+//! one request per kind, with the columns of [`table`](super::table). Tables
+//! are created on first use as `MergeTree`s ordered by exchange, symbol and
+//! ingest time unless that is disabled.
+
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use canonical::MdEvent;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::env;
+use std::time::Duration;
+
+use super::batch::{BatchOptions, BatchWriter, Batcher};
+use super::table::{columns, row, table_name, Column, ColumnType};
+use super::Sink;
+
+/// Connection and table settings of a [`ClickHouseSink`].
+#[derive(Debug, Clone)]
+pub struct ClickHouseOptions {
+    /// Database of the tables; the user's default when unset.
+    pub database: Option<String>,
+    pub user: Option<String>,
+    pub password: Option<String>,
+    /// Prepended to the snake-case kind to name each table.
+    pub table_prefix: String,
+    /// Create missing tables before the first insert.
+    pub create_tables: bool,
+    pub batch: BatchOptions,
+}
+
+impl Default for ClickHouseOptions {
+    fn default() -> Self {
+        Self {
+            database: None,
+            user: None,
+            password: None,
+            table_prefix: "md_".into(),
+            create_tables: true,
+            batch: BatchOptions::default(),
+        }
+    }
+}
+
+impl ClickHouseOptions {
+    /// Read `MD_SINK_CLICKHOUSE_DATABASE`, `MD_SINK_CLICKHOUSE_USER`,
+    /// `MD_SINK_CLICKHOUSE_PASSWORD`, `MD_SINK_CLICKHOUSE_TABLE_PREFIX`,
+    /// `MD_SINK_CLICKHOUSE_CREATE_TABLES` and the batch settings prefixed with
+    /// `MD_SINK_CLICKHOUSE`, falling back to the defaults.
+    pub fn from_env() -> Result<Self> {
+        let mut opts = Self {
+            batch: BatchOptions::from_env("MD_SINK_CLICKHOUSE")?,
+            ..Self::default()
+        };
+        let non_empty = |name| env::var(name).ok().filter(|v| !v.trim().is_empty());
+        opts.database = non_empty("MD_SINK_CLICKHOUSE_DATABASE");
+        opts.user = non_empty("MD_SINK_CLICKHOUSE_USER");
+        opts.password = env::var("MD_SINK_CLICKHOUSE_PASSWORD").ok();
+        if let Ok(v) = env::var("MD_SINK_CLICKHOUSE_TABLE_PREFIX") {
+            opts.table_prefix = v.trim().to_string();
+        }
+        if let Ok(v) = env::var("MD_SINK_CLICKHOUSE_CREATE_TABLES") {
+            opts.create_tables = v
+                .trim()
+                .parse()
+                .context("MD_SINK_CLICKHOUSE_CREATE_TABLES")?;
+        }
+        Ok(opts)
+    }
+}
+
+/// Sink inserting events into per-kind ClickHouse tables.
+pub struct ClickHouseSink {
+    batcher: Batcher<Writer>,
+}
+
+impl ClickHouseSink {
+    /// Insert through the HTTP interface at `url`, e.g.
+    /// `http://localhost:8123`.
+    pub fn new(url: &str, options: ClickHouseOptions) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(30))
+            .build()?;
+        let writer = Writer {
+            client,
+            url: url.trim_end_matches('/').to_string(),
+            tables: HashMap::new(),
+            options: options.clone(),
+        };
+        Ok(Self {
+            batcher: Batcher::new("clickhouse", writer, options.batch),
+        })
+    }
+}
+
+#[async_trait]
+impl Sink for ClickHouseSink {
+    /// Buffer the event; see [`Batcher`] for when it is written.
+    async fn publish(&self, event: &MdEvent) -> Result<()> {
+        self.batcher.push(event).await
+    }
+
+    async fn publish_batch(&self, events: &[MdEvent]) -> Result<()> {
+        self.batcher.push_all(events).await
+    }
+
+    async fn flush(&self) -> Result<()> {
+        self.batcher.flush().await
+    }
+}
+
+struct Table {
+    name: String,
+    columns: Vec<Column>,
+    created: bool,
+}
+
+struct Writer {
+    client: reqwest::Client,
+    url: String,
+    tables: HashMap<&'static str, Table>,
+    options: ClickHouseOptions,
+}
+
+impl Writer {
+    async fn query(&self, sql: &str, body: Vec<u8>) -> Result<()> {
+        let mut request = self.client.post(&self.url).query(&[("query", sql)]);
+        if let Some(database) = &self.options.database {
+            request = request.query(&[("database", database)]);
+        }
+        if let Some(user) = &self.options.user {
+            request = request.header("X-ClickHouse-User", user);
+        }
+        if let Some(password) = &self.options.password {
+            request = request.header("X-ClickHouse-Key", password);
+        }
+        let response = request.body(body).send().await?;
+        let status = response.status();
+        if !status.is_success() {
+            let text = response.text().await.unwrap_or_default();
+            bail!("clickhouse returned {status}: {}", text.trim());
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl BatchWriter for Writer {
+    async fn write(&mut self, kind: &'static str, rows: &[MdEvent]) -> Result<()> {
+        let prefix = &self.options.table_prefix;
+        let table = self.tables.entry(kind).or_insert_with(|| Table {
+            name: table_name(prefix, kind),
+            columns: columns(kind),
+            created: false,
+        });
+        let create = (self.options.create_tables && !table.created)
+            .then(|| create_table(&table.name, &table.columns));
+        let insert = insert(&table.name, &table.columns);
+        let body = row_binary(&table.columns, rows)?;
+        if let Some(sql) = create {
+            self.query(&sql, Vec::new())
+                .await
+                .context("creating table")?;
+            self.tables
+                .get_mut(kind)
+                .expect("table inserted above")
+                .created = true;
+        }
+        self.query(&insert, body).await
+    }
+}
+
+fn column_type(column: &Column) -> String {
+    let ty = match column.ty {
+        ColumnType::UInt32 => "UInt32",
+        ColumnType::UInt64 => "UInt64",
+        ColumnType::Float64 => "Float64",
+        ColumnType::String => "String",
+        ColumnType::Levels => "Array(Tuple(price Float64, quantity Float64, kind String))",
+    };
+    if column.nullable {
+        format!("Nullable({ty})")
+    } else {
+        ty.to_string()
+    }
+}
+
+fn create_table(name: &str, columns: &[Column]) -> String {
+    let columns: Vec<_> = columns
+        .iter()
+        .map(|c| format!("`{}` {}", c.name, column_type(c)))
+        .collect();
+    format!(
+        "CREATE TABLE IF NOT EXISTS `{name}` ({}) ENGINE = MergeTree ORDER BY (exchange, symbol, ingest_ts_utc)",
+        columns.join(", ")
+    )
+}
+
+fn insert(name: &str, columns: &[Column]) -> String {
+    let columns: Vec<_> = columns.iter().map(|c| format!("`{}`", c.name)).collect();
+    format!(
+        "INSERT INTO `{name}` ({}) FORMAT RowBinary",
+        columns.join(", ")
+    )
+}
+
+fn put_varint(out: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        out.push(v as u8 | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+fn put_string(out: &mut Vec<u8>, s: &str) {
+    put_varint(out, s.len() as u64);
+    out.extend_from_slice(s.as_bytes());
+}
+
+/// Encode one value of a column's type. Missing values of non-nullable
+/// columns are written as zero, an empty string or NaN.
+fn put_value(out: &mut Vec<u8>, ty: ColumnType, value: Option<&Value>) {
+    match ty {
+        ColumnType::UInt32 => {
+            let v = value.and_then(Value::as_u64).unwrap_or(0) as u32;
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+        ColumnType::UInt64 => {
+            let v = value.and_then(Value::as_u64).unwrap_or(0);
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+        ColumnType::Float64 => {
+            let v = value.and_then(Value::as_f64).unwrap_or(f64::NAN);
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+        ColumnType::String => match value {
+            Some(Value::String(s)) => put_string(out, s),
+            Some(other) => put_string(out, &other.to_string()),
+            None => put_string(out, ""),
+        },
+        ColumnType::Levels => {
+            let levels = value
+                .and_then(Value::as_array)
+                .map_or(&[][..], Vec::as_slice);
+            put_varint(out, levels.len() as u64);
+            for level in levels {
+                put_value(out, ColumnType::Float64, level.get("price"));
+                put_value(out, ColumnType::Float64, level.get("quantity"));
+                put_value(out, ColumnType::String, level.get("kind"));
+            }
+        }
+    }
+}
+
+fn row_binary(columns: &[Column], rows: &[MdEvent]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    for ev in rows {
+        let row = row(ev)?;
+        for column in columns {
+            let value = column.value(&row);
+            if column.nullable {
+                out.push(value.is_none() as u8);
+                if value.is_none() {
+                    continue;
+                }
+            }
+            put_value(&mut out, column.ty, value);
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::testing::{event, free_port, scratch_dir, Server};
+    use axum::extract::{Query, State};
+    use axum::http::StatusCode;
+    use axum::routing::post;
+    use canonical::{BookTicker, MdEventKind, Trade};
+    use std::net::TcpListener;
+    use std::process::Command;
+    use std::sync::{Arc, Mutex};
+
+    fn trade(id: u64) -> MdEvent {
+        event(MdEventKind::Trade(Trade {
+            exchange: "binance".into(),
+            symbol: "BTCUSDT".into(),
+            price: 1.5,
+            quantity: 2.0,
+            trade_id: Some(id),
+            ingest_ts_utc: 1_700_000_000_000_000_000,
+            ..Default::default()
+        }))
+    }
+
+    fn book(bid: f64) -> MdEvent {
+        event(MdEventKind::BookTicker(BookTicker {
+            exchange: "okx".into(),
+            symbol: "BTC-USDT".into(),
+            bid_price: bid,
+            ..Default::default()
+        }))
+    }
+
+    #[test]
+    fn encodes_rows_in_column_order() {
+        let columns = columns("Trade");
+        let names: Vec<_> = columns.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "exchange",
+                "schema_version",
+                "symbol",
+                "price",
+                "quantity",
+                "trade_id",
+                "buyer_order_id",
+                "seller_order_id",
+                "timestamp",
+                "side",
+                "ingest_ts_monotonic",
+                "ingest_ts_utc",
+                "seq_no",
+            ]
+        );
+        let mut expected = Vec::new();
+        expected.extend(b"\x07binance");
+        expected.extend(canonical::SCHEMA_VERSION.to_le_bytes());
+        expected.extend(b"\x07BTCUSDT");
+        expected.extend(1.5f64.to_le_bytes());
+        expected.extend(2.0f64.to_le_bytes());
+        expected.push(0);
+        expected.extend(7u64.to_le_bytes());
+        expected.extend([1, 1]);
+        expected.extend(0u64.to_le_bytes());
+        expected.push(1);
+        expected.extend(0u64.to_le_bytes());
+        expected.extend(1_700_000_000_000_000_000u64.to_le_bytes());
+        expected.extend(0u64.to_le_bytes());
+        assert_eq!(row_binary(&columns, &[trade(7)]).unwrap(), expected);
+
+        let sql = create_table("md_trade", &columns);
+        assert!(sql.starts_with("CREATE TABLE IF NOT EXISTS `md_trade` (`exchange` String, "));
+        assert!(sql.contains("`trade_id` Nullable(UInt64)"));
+        let depth = create_table("md_depth_snapshot", &super::columns("DepthSnapshot"));
+        assert!(depth.contains("`bids` Array(Tuple(price Float64, quantity Float64, kind String))"));
+
+        let mut varint = Vec::new();
+        put_varint(&mut varint, 300);
+        assert_eq!(varint, [0xac, 0x02]);
+    }
+
+    type Requests = Arc<Mutex<Vec<(String, usize)>>>;
+
+    /// HTTP server failing the first `failures` requests and recording the
+    /// queries of the rest.
+    fn mock_server(failures: usize) -> (String, Requests) {
+        let requests = Requests::default();
+        let failures = Arc::new(Mutex::new(failures));
+        let app = axum::Router::new()
+            .route(
+                "/",
+                post(
+                    |State((requests, failures)): State<(Requests, Arc<Mutex<usize>>)>,
+                     Query(params): Query<HashMap<String, String>>,
+                     body: axum::body::Bytes| async move {
+                        let mut failures = failures.lock().unwrap();
+                        if *failures > 0 {
+                            *failures -= 1;
+                            return StatusCode::SERVICE_UNAVAILABLE;
+                        }
+                        requests
+                            .lock()
+                            .unwrap()
+                            .push((params["query"].clone(), body.len()));
+                        StatusCode::OK
+                    },
+                ),
+            )
+            .with_state((requests.clone(), failures));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+        (url, requests)
+    }
+
+    #[tokio::test]
+    async fn creates_tables_once_and_retries_failed_inserts() {
+        let (url, requests) = mock_server(1);
+        let options = ClickHouseOptions {
+            batch: BatchOptions {
+                backoff: Duration::from_millis(1),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let sink = ClickHouseSink::new(&url, options).unwrap();
+        sink.publish(&trade(1)).await.unwrap();
+        sink.publish(&book(1.0)).await.unwrap();
+        sink.flush().await.unwrap();
+        sink.publish(&trade(2)).await.unwrap();
+        sink.flush().await.unwrap();
+
+        let queries: Vec<_> = requests
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(q, _)| q.clone())
+            .collect();
+        assert_eq!(queries.len(), 5);
+        assert!(queries[0].starts_with("CREATE TABLE IF NOT EXISTS `md_book_ticker`"));
+        assert!(queries[1].starts_with("INSERT INTO `md_book_ticker`"));
+        assert!(queries[2].starts_with("CREATE TABLE IF NOT EXISTS `md_trade`"));
+        assert!(queries[3].starts_with("INSERT INTO `md_trade`"));
+        assert_eq!(queries[3], queries[4]);
+    }
+
+    /// A throwaway server run by the single-binary `clickhouse` from `PATH`.
+    async fn spawn_server() -> Server {
+        let dir = scratch_dir("clickhouse_sink");
+        let http = free_port();
+        let mut command = Command::new("clickhouse");
+        command
+            .arg("server")
+            .arg("--")
+            .arg(format!("--path={}/", dir.display()))
+            .arg(format!("--http_port={http}"))
+            .arg(format!("--tcp_port={}", free_port()))
+            .arg("--listen_host=127.0.0.1");
+        let ping = format!("http://127.0.0.1:{http}/ping");
+        Server::spawn(command, http, dir, || async {
+            reqwest::get(&ping).await.is_ok()
+        })
+        .await
+    }
+
+    #[tokio::test]
+    #[ignore = "needs clickhouse on PATH"]
+    async fn inserts_into_a_local_server() {
+        let server = spawn_server().await;
+        let url = format!("http://127.0.0.1:{}", server.port);
+        let sink = ClickHouseSink::new(&url, ClickHouseOptions::default()).unwrap();
+        for id in 0..100 {
+            sink.publish(&trade(id)).await.unwrap();
+        }
+        sink.publish(&book(2.0)).await.unwrap();
+        sink.flush().await.unwrap();
+
+        let query = |sql: &str| {
+            let url = url.clone();
+            let sql = sql.to_string();
+            async move {
+                reqwest::Client::new()
+                    .post(url)
+                    .body(sql)
+                    .send()
+                    .await
+                    .unwrap()
+                    .text()
+                    .await
+                    .unwrap()
+            }
+        };
+        assert_eq!(
+            query("SELECT count(), sum(trade_id) FROM md_trade")
+                .await
+                .trim(),
+            "100\t4950"
+        );
+        assert_eq!(
+            query("SELECT exchange, symbol, bid_price FROM md_book_ticker")
+                .await
+                .trim(),
+            "okx\tBTC-USDT\t2"
+        );
+    }
+}
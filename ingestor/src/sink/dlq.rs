@@ -0,0 +1,198 @@
+use anyhow::{bail, Context, Result};
+use canonical::MdEvent;
+use serde::{Deserialize, Serialize};
+use std::fs::TryLockError;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+/// Append-only JSON lines file for events that could not be delivered or
+/// failed validation. The file is share-locked while open, so that
+/// [`DeadLetterLock`] keeps `ingestor dlq replay` from rewriting it under a
+/// running ingestor.
+pub struct DeadLetterQueue {
+    file: Mutex<tokio::fs::File>,
+}
+
+/// One line of a dead letter queue.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DeadLetter {
+    /// Milliseconds since the Unix epoch when the entry was written, `0` for
+    /// entries written before timestamps were recorded.
+    pub ts: u64,
+    /// Short failure category used for grouping, e.g. `publish`, `decode` or
+    /// `validation:crossed`.
+    pub reason: String,
+    /// Full error message.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event: Option<MdEvent>,
+    /// Hex-encoded bytes that could not be decoded as an event.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw: Option<String>,
+}
+
+impl DeadLetter {
+    /// Parse a queue line, accepting the bare event and raw lines written by
+    /// earlier versions.
+    pub fn parse(line: &[u8]) -> Self {
+        if let Ok(letter) = serde_json::from_slice::<Self>(line) {
+            return letter;
+        }
+        match serde_json::from_slice::<MdEvent>(line) {
+            Ok(ev) => Self {
+                ts: 0,
+                reason: ev.validation.as_ref().map_or_else(
+                    || "unknown".to_string(),
+                    |e| format!("validation:{}", e.reason()),
+                ),
+                error: ev.validation.as_ref().map(ToString::to_string),
+                event: Some(ev),
+                raw: None,
+            },
+            Err(_) => Self {
+                ts: 0,
+                reason: "decode".into(),
+                error: None,
+                event: None,
+                raw: Some(hex::encode(line)),
+            },
+        }
+    }
+}
+
+impl DeadLetterQueue {
+    pub async fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+            .with_context(|| format!("opening {}", path.display()))?
+            .into_std()
+            .await;
+        match file.try_lock_shared() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                bail!(
+                    "{} is being rewritten by `ingestor dlq replay`",
+                    path.display()
+                )
+            }
+            Err(TryLockError::Error(e)) => {
+                return Err(e).with_context(|| format!("locking {}", path.display()))
+            }
+        }
+        Ok(Self {
+            file: Mutex::new(tokio::fs::File::from_std(file)),
+        })
+    }
+
+    /// Record `event` with the category `reason` and the error behind it.
+    pub async fn push(&self, event: &MdEvent, reason: &str, error: &str) -> Result<()> {
+        self.append(DeadLetter {
+            ts: now_ms(),
+            reason: reason.to_string(),
+            error: Some(error.to_string()),
+            event: Some(event.clone()),
+            raw: None,
+        })
+        .await
+    }
+
+    /// Record bytes that could not be decoded as an event.
+    pub async fn push_raw(&self, line: &[u8]) -> Result<()> {
+        self.append(DeadLetter {
+            ts: now_ms(),
+            reason: "decode".into(),
+            error: None,
+            event: None,
+            raw: Some(hex::encode(line)),
+        })
+        .await
+    }
+
+    async fn append(&self, letter: DeadLetter) -> Result<()> {
+        let mut line = serde_json::to_vec(&letter)?;
+        line.push(b'\n');
+        let mut file = self.file.lock().await;
+        file.write_all(&line).await?;
+        file.sync_data().await?;
+        Ok(())
+    }
+}
+
+/// Exclusive lock on a queue file, held while it is read and rewritten.
+pub struct DeadLetterLock {
+    _file: std::fs::File,
+}
+
+impl DeadLetterLock {
+    /// Lock the queue at `path`, failing while a [`DeadLetterQueue`] has it
+    /// open.
+    pub fn acquire(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file =
+            std::fs::File::open(path).with_context(|| format!("opening {}", path.display()))?;
+        match file.try_lock() {
+            Ok(()) => Ok(Self { _file: file }),
+            Err(TryLockError::WouldBlock) => bail!(
+                "{} is in use by a running ingestor, stop it before replaying",
+                path.display()
+            ),
+            Err(TryLockError::Error(e)) => {
+                Err(e).with_context(|| format!("locking {}", path.display()))
+            }
+        }
+    }
+}
+
+/// Read every line of the queue at `path`, keeping the original bytes so
+/// untouched entries can be written back unchanged.
+pub async fn read_dead_letters(path: impl AsRef<Path>) -> Result<Vec<(Vec<u8>, DeadLetter)>> {
+    let path = path.as_ref();
+    let data = tokio::fs::read(path)
+        .await
+        .with_context(|| format!("reading {}", path.display()))?;
+    Ok(data
+        .split(|b| *b == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(|line| (line.to_vec(), DeadLetter::parse(line)))
+        .collect())
+}
+
+/// Replace the queue at `path` with `lines` by writing a temporary file next
+/// to it and renaming it into place. Callers hold a [`DeadLetterLock`] from
+/// before reading the queue.
+pub async fn rewrite_dead_letters<'a>(
+    path: impl AsRef<Path>,
+    lines: impl IntoIterator<Item = &'a [u8]>,
+) -> Result<()> {
+    let path = path.as_ref();
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let mut buf = Vec::new();
+    for line in lines {
+        buf.extend_from_slice(line);
+        buf.push(b'\n');
+    }
+    let mut file = tokio::fs::File::create(&tmp).await?;
+    file.write_all(&buf).await?;
+    file.sync_all().await?;
+    drop(file);
+    tokio::fs::rename(&tmp, path)
+        .await
+        .with_context(|| format!("replacing {}", path.display()))?;
+    Ok(())
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
@@ -0,0 +1,155 @@
+use anyhow::{anyhow, Result};
+use canonical::{sbe, MdEvent};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{env, str::FromStr};
+
+/// Wire format used by a sink when writing events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    /// One `serde_json` document per event, newline terminated in files.
+    #[default]
+    Json,
+    /// Length-prefixed fixed-layout frames from [`canonical::sbe`].
+    Sbe,
+}
+
+impl FromStr for Encoding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Ok(Encoding::Json),
+            "sbe" | "binary" => Ok(Encoding::Sbe),
+            other => Err(anyhow!("unknown sink encoding: {other}")),
+        }
+    }
+}
+
+impl Encoding {
+    /// Lowercase name accepted by [`Encoding::from_str`].
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Json => "json",
+            Encoding::Sbe => "sbe",
+        }
+    }
+
+    /// Read the encoding from `var`, defaulting to JSON when unset.
+    pub fn from_env(var: &str) -> Result<Self> {
+        match env::var(var) {
+            Ok(v) if !v.trim().is_empty() => v.trim().parse(),
+            _ => Ok(Encoding::Json),
+        }
+    }
+}
+
+/// Stateful encoder holding the symbol dictionary for binary output.
+pub(crate) struct EventEncoder {
+    encoding: Encoding,
+    sbe: sbe::Encoder,
+    epoch: u64,
+}
+
+impl EventEncoder {
+    pub(crate) fn new(encoding: Encoding) -> Self {
+        let epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        Self {
+            encoding,
+            sbe: sbe::Encoder::new(),
+            epoch,
+        }
+    }
+
+    /// Generation of the dictionary ids. Ids restart from zero with every
+    /// encoder and every [`EventEncoder::reset`], which both move to a new,
+    /// larger epoch, so consumers of a separate dictionary stream can tell
+    /// which announcements an event refers to.
+    pub(crate) fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Append `ev` to `out`, preceded by any dictionary frames it needs.
+    pub(crate) fn encode(&mut self, ev: &MdEvent, out: &mut Vec<u8>) -> Result<()> {
+        match self.encoding {
+            Encoding::Json => {
+                serde_json::to_writer(&mut *out, ev)?;
+                out.push(b'\n');
+            }
+            Encoding::Sbe => self.sbe.encode_with_dictionary(ev, out)?,
+        }
+        Ok(())
+    }
+
+    /// Append only the event frame, leaving new dictionary entries queued for
+    /// [`EventEncoder::take_dictionary`].
+    pub(crate) fn encode_event(&mut self, ev: &MdEvent, out: &mut Vec<u8>) -> Result<()> {
+        match self.encoding {
+            Encoding::Json => serde_json::to_writer(&mut *out, ev)?,
+            Encoding::Sbe => self.sbe.encode(ev, out)?,
+        }
+        Ok(())
+    }
+
+    pub(crate) fn take_dictionary(&mut self, out: &mut Vec<u8>) -> bool {
+        self.sbe.take_dictionary(out)
+    }
+
+    /// Forget interned names so the next output is self-describing.
+    pub(crate) fn reset(&mut self) {
+        self.sbe.reset();
+        self.epoch += 1;
+    }
+}
+
+/// Stateful decoder keeping the symbol dictionary across calls, so a log
+/// written in several pieces can be read back piece by piece.
+pub(crate) struct EventDecoder {
+    encoding: Encoding,
+    dict: sbe::Dictionary,
+}
+
+impl EventDecoder {
+    pub(crate) fn new(encoding: Encoding) -> Self {
+        Self {
+            encoding,
+            dict: sbe::Dictionary::new(),
+        }
+    }
+
+    /// Decode every event stored in `buf`.
+    ///
+    /// Undecodable JSON lines are returned as `Err` with the raw line so
+    /// callers can quarantine them. Binary input stops at the first corrupt
+    /// frame.
+    pub(crate) fn decode(&mut self, buf: &[u8]) -> Vec<Result<MdEvent, Vec<u8>>> {
+        match self.encoding {
+            Encoding::Json => buf
+                .split(|b| *b == b'\n')
+                .filter(|l| !l.iter().all(u8::is_ascii_whitespace))
+                .map(|line| serde_json::from_slice(line).map_err(|_| line.to_vec()))
+                .collect(),
+            Encoding::Sbe => {
+                let mut out = Vec::new();
+                for msg in sbe::frames(buf) {
+                    match msg.and_then(|m| self.dict.decode(&m)) {
+                        Ok(Some(ev)) => out.push(Ok(ev)),
+                        Ok(None) => {}
+                        Err(e) => {
+                            tracing::error!(error = %e, "corrupt binary frame, discarding remainder");
+                            break;
+                        }
+                    }
+                }
+                out
+            }
+        }
+    }
+}
+
+/// Decode every event stored in `buf` using `encoding`, see
+/// [`EventDecoder::decode`].
+pub(crate) fn decode_all(encoding: Encoding, buf: &[u8]) -> Vec<Result<MdEvent, Vec<u8>>> {
+    EventDecoder::new(encoding).decode(buf)
+}
@@ -0,0 +1,884 @@
+//! Local file output with rotation and retention.
+//!
+//! The output path is a template that may contain `{exchange}` and `{date}`
+//! (`YYYY-MM-DD` of the ingest time, UTC), giving each venue and day its own
+//! file. The active file is rotated to `<path>.<unix_ms>` once it exceeds a
+//! size or age limit; files that stop receiving events are rotated by a
+//! timer once they reach the age limit. Closed files are optionally compressed in the
+//! background, handed to an [`Archiver`] and pruned by age or total size.
+
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use canonical::{symbol::date_from_ms, MdEvent};
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::fs::OpenOptions;
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+use super::encoding::{Encoding, EventEncoder};
+use super::Sink;
+use crate::archive::Archiver;
+
+/// Compression applied to closed files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FileCompression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl FromStr for FileCompression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "" | "none" => Ok(Self::None),
+            "gzip" | "gz" => Ok(Self::Gzip),
+            "zstd" | "zst" => Ok(Self::Zstd),
+            other => bail!("unknown file compression {other}"),
+        }
+    }
+}
+
+impl FileCompression {
+    fn extension(self) -> Option<&'static str> {
+        match self {
+            Self::None => None,
+            Self::Gzip => Some("gz"),
+            Self::Zstd => Some("zst"),
+        }
+    }
+}
+
+/// Rotation, compression and retention of a [`FileSink`]. Every limit is
+/// off by default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FileSinkOptions {
+    /// Rotate once the active file reaches this many bytes.
+    pub max_bytes: Option<u64>,
+    /// Rotate once the active file has been open this long.
+    pub max_age: Option<Duration>,
+    pub compression: FileCompression,
+    /// Delete closed files last modified longer ago than this.
+    pub retention_age: Option<Duration>,
+    /// Delete the oldest closed files once those in a directory exceed this
+    /// many bytes.
+    pub retention_bytes: Option<u64>,
+}
+
+impl FileSinkOptions {
+    /// Read the `MD_SINK_FILE_*` rotation and retention variables.
+    pub fn from_env() -> Result<Self> {
+        fn var<T: FromStr>(name: &str) -> Result<Option<T>>
+        where
+            T::Err: std::error::Error + Send + Sync + 'static,
+        {
+            match env::var(name) {
+                Ok(v) if !v.trim().is_empty() => Ok(Some(
+                    v.trim()
+                        .parse()
+                        .with_context(|| format!("invalid {name}"))?,
+                )),
+                _ => Ok(None),
+            }
+        }
+        let secs = |name| var::<u64>(name).map(|v| v.map(Duration::from_secs));
+        Ok(Self {
+            max_bytes: var("MD_SINK_FILE_MAX_BYTES")?,
+            max_age: secs("MD_SINK_FILE_MAX_AGE_SECS")?,
+            compression: env::var("MD_SINK_FILE_COMPRESSION")
+                .unwrap_or_default()
+                .parse()?,
+            retention_age: secs("MD_SINK_FILE_RETENTION_SECS")?,
+            retention_bytes: var("MD_SINK_FILE_RETENTION_BYTES")?,
+        })
+    }
+}
+
+pub struct FileSink {
+    template: String,
+    encoding: Encoding,
+    options: FileSinkOptions,
+    inner: Arc<Mutex<Files>>,
+    batch_size: usize,
+    archiver: Option<Arc<Archiver>>,
+}
+
+struct Files {
+    /// Active file per exchange, or under `""` when the template has no
+    /// `{exchange}` placeholder.
+    open: HashMap<String, State>,
+    /// Background compression and retention tasks.
+    closing: Vec<JoinHandle<()>>,
+    /// Whether the task rotating idle files runs, started with the first
+    /// event when there is an age limit.
+    idle_timer: bool,
+}
+
+struct State {
+    path: PathBuf,
+    writer: BufWriter<tokio::fs::File>,
+    encoder: EventEncoder,
+    buf: Vec<u8>,
+    pending: usize,
+    bytes: u64,
+    opened: Instant,
+}
+
+impl FileSink {
+    const DEFAULT_BATCH_SIZE: usize = 1024;
+
+    /// Create a sink writing to the path `template`.
+    ///
+    /// A template without placeholders is opened right away. Existing files
+    /// are appended to.
+    pub async fn new(
+        template: impl Into<String>,
+        encoding: Encoding,
+        options: FileSinkOptions,
+    ) -> Result<Self> {
+        let template = template.into();
+        let mut open = HashMap::new();
+        if !template.contains("{exchange}") && !template.contains("{date}") {
+            open.insert(
+                String::new(),
+                State::open(PathBuf::from(&template), encoding).await?,
+            );
+        }
+        Ok(Self {
+            template,
+            encoding,
+            options,
+            inner: Arc::new(Mutex::new(Files {
+                open,
+                closing: Vec::new(),
+                idle_timer: false,
+            })),
+            batch_size: Self::DEFAULT_BATCH_SIZE,
+            archiver: None,
+        })
+    }
+
+    /// Upload closed files with `archiver` once they are compressed,
+    /// starting with those already on disk.
+    pub fn with_archiver(mut self, archiver: Arc<Archiver>) -> Result<Self> {
+        let root = archive_root(&self.template);
+        for path in closed_files(&root, &self.template, self.options.compression)? {
+            archiver.submit(path.clone(), archive_name(&root, &path));
+        }
+        self.archiver = Some(archiver);
+        Ok(self)
+    }
+
+    fn render(&self, ev: &MdEvent) -> PathBuf {
+        let mut path = self.template.replace("{exchange}", ev.exchange());
+        if path.contains("{date}") {
+            let ns = match ev.ingest_ts_utc() {
+                0 => now_ms() * 1_000_000,
+                ns => ns,
+            };
+            let date = date_from_ms(ns / 1_000_000);
+            let date = format!(
+                "{:04}-{:02}-{:02}",
+                date / 10_000,
+                date / 100 % 100,
+                date % 100
+            );
+            path = path.replace("{date}", &date);
+        }
+        PathBuf::from(path)
+    }
+
+    async fn flush_locked(state: &mut State) -> Result<()> {
+        state.writer.write_all(&state.buf).await?;
+        state.bytes += state.buf.len() as u64;
+        state.buf.clear();
+        state.pending = 0;
+        state.writer.flush().await?;
+        Ok(())
+    }
+
+    fn due_for_rotation(&self, state: &State) -> bool {
+        let size = state.bytes + state.buf.len() as u64;
+        self.options.max_bytes.is_some_and(|max| size >= max)
+            || self
+                .options
+                .max_age
+                .is_some_and(|max| state.opened.elapsed() >= max)
+    }
+
+    /// Flush `state` and rename it aside to `<path>.<unix_ms>`, returning
+    /// the new name. Closed files always get a unique name, so a path that is
+    /// reopened later never overwrites an earlier file.
+    async fn close(mut state: State) -> Result<PathBuf> {
+        Self::flush_locked(&mut state).await?;
+        drop(state.writer);
+        let mut ms = now_ms();
+        loop {
+            let mut closed = state.path.clone().into_os_string();
+            closed.push(format!(".{ms}"));
+            let taken = ["", ".gz", ".zst"].iter().any(|ext| {
+                let mut name = closed.clone();
+                name.push(ext);
+                Path::new(&name).exists()
+            });
+            let closed = PathBuf::from(closed);
+            if !taken {
+                tokio::fs::rename(&state.path, &closed).await?;
+                return Ok(closed);
+            }
+            ms += 1;
+        }
+    }
+
+    fn finalize_in_background(&self, files: &mut Files, path: PathBuf) {
+        finalize_in_background(
+            files,
+            path,
+            &self.template,
+            self.options,
+            self.archiver.clone(),
+        );
+    }
+}
+
+/// Compress `path` and apply retention on a blocking thread.
+fn finalize_in_background(
+    files: &mut Files,
+    path: PathBuf,
+    template: &str,
+    options: FileSinkOptions,
+    archiver: Option<Arc<Archiver>>,
+) {
+    let active: HashSet<PathBuf> = files.open.values().map(|s| s.path.clone()).collect();
+    let template = template.to_string();
+    files.closing.retain(|task| !task.is_finished());
+    files.closing.push(tokio::task::spawn_blocking(move || {
+        match finalize(&path, &template, &options, &active, archiver.as_deref()) {
+            Ok(closed) => {
+                if let Some(archiver) = archiver {
+                    let root = archive_root(&template);
+                    archiver.submit(closed.clone(), archive_name(&root, &closed));
+                }
+            }
+            Err(e) => {
+                warn!(error = %e, path = %path.display(), "failed to finalize output file");
+            }
+        }
+    }));
+}
+
+#[async_trait]
+impl Sink for FileSink {
+    async fn publish(&self, event: &MdEvent) -> Result<()> {
+        let path = self.render(event);
+        let key = if self.template.contains("{exchange}") {
+            event.exchange()
+        } else {
+            ""
+        };
+        let mut files = self.inner.lock().await;
+        let files = &mut *files;
+        if let (Some(max_age), false) = (self.options.max_age, files.idle_timer) {
+            files.idle_timer = true;
+            tokio::spawn(rotate_idle(
+                Arc::downgrade(&self.inner),
+                max_age,
+                self.template.clone(),
+                self.options,
+                self.archiver.clone(),
+            ));
+        }
+        let rollover = match files.open.get(key) {
+            // The rendered path changed (e.g. a new day) or a limit was hit.
+            Some(state) => state.path != path || self.due_for_rotation(state),
+            None => {
+                let state = State::open(path.clone(), self.encoding).await?;
+                files.open.insert(key.to_string(), state);
+                false
+            }
+        };
+        if rollover {
+            let old = files.open.remove(key).expect("checked above");
+            let closed = Self::close(old).await?;
+            let state = State::open(path, self.encoding).await?;
+            files.open.insert(key.to_string(), state);
+            self.finalize_in_background(files, closed);
+        }
+
+        let state = files.open.get_mut(key).expect("opened above");
+        state.encoder.encode(event, &mut state.buf)?;
+        state.pending += 1;
+        if state.pending >= self.batch_size {
+            Self::flush_locked(state).await?;
+        }
+        Ok(())
+    }
+
+    async fn flush(&self) -> Result<()> {
+        let mut files = self.inner.lock().await;
+        for state in files.open.values_mut() {
+            if state.pending == 0 {
+                state.writer.flush().await?;
+                continue;
+            }
+            Self::flush_locked(state).await?;
+        }
+        for task in std::mem::take(&mut files.closing) {
+            task.await?;
+        }
+        Ok(())
+    }
+}
+
+/// Close files that reached `max_age` without an event arriving to rotate
+/// them, until the sink is dropped. The next event for such a file opens a
+/// new one.
+async fn rotate_idle(
+    files: Weak<Mutex<Files>>,
+    max_age: Duration,
+    template: String,
+    options: FileSinkOptions,
+    archiver: Option<Arc<Archiver>>,
+) {
+    let period = (max_age / 4).clamp(Duration::from_millis(10), Duration::from_secs(1));
+    let mut interval = tokio::time::interval(period);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        let Some(files) = files.upgrade() else {
+            return;
+        };
+        let mut files = files.lock().await;
+        let idle: Vec<String> = files
+            .open
+            .iter()
+            .filter(|(_, state)| state.opened.elapsed() >= max_age)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in idle {
+            let state = files.open.remove(&key).expect("listed above");
+            let path = state.path.clone();
+            match FileSink::close(state).await {
+                Ok(closed) => {
+                    finalize_in_background(&mut files, closed, &template, options, archiver.clone())
+                }
+                Err(e) => {
+                    warn!(error = %e, path = %path.display(), "failed to rotate idle output file")
+                }
+            }
+        }
+    }
+}
+
+impl State {
+    async fn open(path: PathBuf, encoding: Encoding) -> Result<Self> {
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await
+            .with_context(|| format!("opening {}", path.display()))?;
+        let bytes = file.metadata().await?.len();
+        // Binary output must be self-describing from the point we start
+        // appending, so every new writer re-announces its dictionary.
+        Ok(Self {
+            path,
+            writer: BufWriter::new(file),
+            encoder: EventEncoder::new(encoding),
+            buf: Vec::new(),
+            pending: 0,
+            bytes,
+            opened: Instant::now(),
+        })
+    }
+}
+
+/// Compress a closed file and apply retention to its directory, returning
+/// the final path of the file. With an `archiver`, retention keeps files it
+/// has not uploaded yet.
+fn finalize(
+    path: &Path,
+    template: &str,
+    options: &FileSinkOptions,
+    active: &HashSet<PathBuf>,
+    archiver: Option<&Archiver>,
+) -> Result<PathBuf> {
+    let mut closed = path.to_path_buf();
+    if let Some(ext) = options.compression.extension() {
+        let mut target = path.as_os_str().to_owned();
+        target.push(format!(".{ext}"));
+        let mut tmp = target.clone();
+        tmp.push(".tmp");
+        let mut input = fs::File::open(path)?;
+        let output = fs::File::create(&tmp)?;
+        match options.compression {
+            FileCompression::Gzip => {
+                let mut enc = flate2::write::GzEncoder::new(output, flate2::Compression::default());
+                io::copy(&mut input, &mut enc)?;
+                enc.finish()?.sync_all()?;
+            }
+            FileCompression::Zstd => {
+                let mut enc = zstd::Encoder::new(output, 0)?;
+                io::copy(&mut input, &mut enc)?;
+                enc.finish()?.sync_all()?;
+            }
+            FileCompression::None => unreachable!(),
+        }
+        fs::rename(&tmp, &target)?;
+        fs::remove_file(path)?;
+        closed = PathBuf::from(target);
+    }
+    if options.retention_age.is_some() || options.retention_bytes.is_some() {
+        if let Some(dir) = path.parent() {
+            let dir = if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir
+            };
+            let pending = |path: &Path| {
+                let path = path.strip_prefix(".").unwrap_or(path);
+                archiver.is_some_and(|a| !a.is_archived(path))
+            };
+            apply_retention(dir, template, options, active, pending)?;
+        }
+    }
+    Ok(closed)
+}
+
+/// Delete closed files in `dir` written from `template` that are too old or
+/// push the directory over its size budget, oldest first. Files for which
+/// `pending` holds are kept, but count towards the budget.
+fn apply_retention(
+    dir: &Path,
+    template: &str,
+    options: &FileSinkOptions,
+    active: &HashSet<PathBuf>,
+    pending: impl Fn(&Path) -> bool,
+) -> Result<()> {
+    let name = Path::new(template)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or(template);
+    let mut closed = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let Some(file_name) = file_name.to_str() else {
+            continue;
+        };
+        let path = entry.path();
+        // Files that were never rotated are appended to when reopened, so
+        // only rotated ones are candidates.
+        if !matches_template(name, file_name)
+            || !is_closed(file_name, FileCompression::None)
+            || active.iter().any(|a| same_file(a, &path))
+        {
+            continue;
+        }
+        let meta = entry.metadata()?;
+        closed.push((meta.modified()?, meta.len(), path));
+    }
+    // Newest first, so the byte budget keeps the most recent files.
+    closed.sort_by_key(|c| std::cmp::Reverse(c.0));
+    let now = SystemTime::now();
+    let mut kept = 0u64;
+    for (modified, len, path) in closed {
+        if pending(&path) {
+            kept += len;
+            continue;
+        }
+        let expired = options
+            .retention_age
+            .is_some_and(|max| now.duration_since(modified).unwrap_or_default() > max);
+        let over_budget = options.retention_bytes.is_some_and(|max| kept + len > max);
+        if expired || over_budget {
+            if let Err(e) = fs::remove_file(&path) {
+                warn!(error = %e, path = %path.display(), "failed to remove expired output file");
+            }
+        } else {
+            kept += len;
+        }
+    }
+    Ok(())
+}
+
+/// Whether `a` and `b` name the same file, ignoring a leading `./`.
+fn same_file(a: &Path, b: &Path) -> bool {
+    let parts = |p| Path::components(p).filter(|c| *c != Component::CurDir);
+    parts(a).eq(parts(b))
+}
+
+/// Whether `name` is a file produced from the template file name `pattern`,
+/// possibly closed (`.<unix_ms>`) and compressed.
+fn matches_template(pattern: &str, name: &str) -> bool {
+    let name = name
+        .strip_suffix(".gz")
+        .or_else(|| name.strip_suffix(".zst"))
+        .unwrap_or(name);
+    let name = match name.rsplit_once('.') {
+        Some((stem, ms)) if !ms.is_empty() && ms.bytes().all(|b| b.is_ascii_digit()) => stem,
+        _ => name,
+    };
+    let parts: Vec<&str> = pattern
+        .split("{exchange}")
+        .flat_map(|p| p.split("{date}"))
+        .collect();
+    let [first, middle @ .., last] = parts.as_slice() else {
+        return name == pattern;
+    };
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// Whether `name` is a closed file: rotated to `.<unix_ms>` and, when
+/// `compression` is on, compressed, since an uncompressed one is about to be
+/// replaced.
+fn is_closed(name: &str, compression: FileCompression) -> bool {
+    let name = match name
+        .strip_suffix(".gz")
+        .or_else(|| name.strip_suffix(".zst"))
+    {
+        Some(name) => name,
+        None if compression == FileCompression::None => name,
+        None => return false,
+    };
+    name.rsplit_once('.')
+        .is_some_and(|(_, ms)| !ms.is_empty() && ms.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// Deepest directory of `template` without placeholders; archived files are
+/// named relative to it.
+fn archive_root(template: &str) -> PathBuf {
+    let mut root = PathBuf::new();
+    let path = Path::new(template);
+    let Some(dir) = path.parent() else {
+        return root;
+    };
+    for part in dir.components() {
+        if part.as_os_str().to_string_lossy().contains('{') {
+            break;
+        }
+        root.push(part);
+    }
+    root
+}
+
+fn archive_name(root: &Path, path: &Path) -> String {
+    let rel = path.strip_prefix(root).unwrap_or(path);
+    rel.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Closed files written from `template` anywhere below `root`, sorted by
+/// path.
+fn closed_files(root: &Path, template: &str, compression: FileCompression) -> Result<Vec<PathBuf>> {
+    let name = Path::new(template)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or(template);
+    let mut dirs = vec![if root.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        root.to_path_buf()
+    }];
+    let mut closed = Vec::new();
+    while let Some(dir) = dirs.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e).with_context(|| format!("listing {}", dir.display())),
+        };
+        for entry in entries {
+            let entry = entry?;
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+            let Some(file_name) = path.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            if matches_template(name, file_name) && is_closed(file_name, compression) {
+                // Keep names relative to the root as written in the template.
+                closed.push(match root.as_os_str().is_empty() {
+                    true => path.strip_prefix(".").unwrap_or(&path).to_path_buf(),
+                    false => path,
+                });
+            }
+        }
+    }
+    closed.sort();
+    Ok(closed)
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use canonical::{MdEventKind, Trade};
+    use std::io::Read;
+
+    fn trade(exchange: &str, ingest_ms: u64) -> MdEvent {
+        MdEvent {
+            schema_version: canonical::SCHEMA_VERSION,
+            event: MdEventKind::Trade(Trade {
+                exchange: exchange.into(),
+                symbol: "BTCUSDT".into(),
+                ingest_ts_utc: ingest_ms * 1_000_000,
+                ..Default::default()
+            }),
+            validation: None,
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("{name}_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn template_matching_covers_rotated_and_compressed_files() {
+        assert!(matches_template("out.jsonl", "out.jsonl.1717000000000.gz"));
+        assert!(matches_template("out.jsonl", "out.jsonl.1717000000000"));
+        assert!(matches_template("out.jsonl", "out.jsonl"));
+        assert!(!matches_template("out.jsonl", "other.jsonl"));
+        assert!(matches_template(
+            "{exchange}-{date}.jsonl",
+            "binance-2024-06-01.jsonl.zst"
+        ));
+        assert!(!matches_template(
+            "{exchange}-{date}.jsonl",
+            "binance-2024-06-01.parquet"
+        ));
+    }
+
+    #[test]
+    fn finds_closed_files_to_archive() {
+        assert!(is_closed("out.jsonl.1717000000000", FileCompression::None));
+        assert!(!is_closed("out.jsonl.1717000000000", FileCompression::Gzip));
+        assert!(is_closed(
+            "out.jsonl.1717000000000.zst",
+            FileCompression::Gzip
+        ));
+        assert!(!is_closed(
+            "out.jsonl.1717000000000.gz.tmp",
+            FileCompression::Gzip
+        ));
+        assert!(!is_closed("out.jsonl", FileCompression::None));
+
+        let dir = temp_dir("file_sink_archive");
+        let template = dir.join("{exchange}/{date}.jsonl");
+        let template = template.to_str().unwrap();
+        assert_eq!(archive_root(template), dir);
+        assert_eq!(archive_root("out.jsonl"), PathBuf::new());
+        for name in [
+            "binance/2024-06-01.jsonl",
+            "binance/2024-06-01.jsonl.1717000000000",
+            "binance/2024-06-01.jsonl.1717000000001.gz",
+            "gateio/2024-06-01.jsonl.1717000000002.gz",
+            "gateio/2024-06-01.jsonl.1717000000003.gz.tmp",
+        ] {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"").unwrap();
+        }
+        let names: Vec<_> = closed_files(&dir, template, FileCompression::Gzip)
+            .unwrap()
+            .iter()
+            .map(|p| archive_name(&dir, p))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "binance/2024-06-01.jsonl.1717000000001.gz",
+                "gateio/2024-06-01.jsonl.1717000000002.gz"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn routes_by_template_and_rotates_compressed() {
+        let dir = temp_dir("file_sink_rotate");
+        let template = dir.join("{exchange}-{date}.jsonl");
+        let options = FileSinkOptions {
+            max_bytes: Some(1),
+            compression: FileCompression::Gzip,
+            ..Default::default()
+        };
+        let mut sink = FileSink::new(template.to_str().unwrap(), Encoding::Json, options)
+            .await
+            .unwrap();
+        sink.batch_size = 1;
+        // 2024-06-01 and 2024-06-02 UTC.
+        let day = 1_717_200_000_000;
+        sink.publish(&trade("binance", day)).await.unwrap();
+        sink.publish(&trade("gateio", day)).await.unwrap();
+        // Over the size limit, so the first binance file is rotated.
+        sink.publish(&trade("binance", day)).await.unwrap();
+        // A new day closes the active binance file too.
+        sink.publish(&trade("binance", day + 86_400_000))
+            .await
+            .unwrap();
+        sink.flush().await.unwrap();
+
+        let names = names(&dir);
+        assert_eq!(names.len(), 4, "{names:?}");
+        assert!(names.contains(&"binance-2024-06-02.jsonl".to_string()));
+        assert!(names.contains(&"gateio-2024-06-01.jsonl".to_string()));
+        let closed: Vec<_> = names
+            .iter()
+            .filter(|n| n.starts_with("binance-2024-06-01.jsonl.") && n.ends_with(".gz"))
+            .collect();
+        assert_eq!(closed.len(), 2);
+        for name in closed {
+            let mut text = String::new();
+            flate2::read::GzDecoder::new(fs::File::open(dir.join(name)).unwrap())
+                .read_to_string(&mut text)
+                .unwrap();
+            assert_eq!(text.lines().count(), 1);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn retention_keeps_newest_within_budget() {
+        let dir = temp_dir("file_sink_retention");
+        let template = dir.join("out.jsonl");
+        let options = FileSinkOptions {
+            max_bytes: Some(1),
+            retention_bytes: Some(1),
+            ..Default::default()
+        };
+        let mut sink = FileSink::new(template.to_str().unwrap(), Encoding::Json, options)
+            .await
+            .unwrap();
+        sink.batch_size = 1;
+        for _ in 0..4 {
+            sink.publish(&trade("binance", 0)).await.unwrap();
+        }
+        sink.flush().await.unwrap();
+
+        // Every closed file exceeds the budget; only the active one is left.
+        assert_eq!(names(&dir), vec!["out.jsonl".to_string()]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn retention_keeps_files_not_yet_archived() {
+        let dir = temp_dir("file_sink_retention_archive");
+        for name in ["out.jsonl.1", "out.jsonl.2", "out.jsonl.3"] {
+            fs::write(dir.join(name), b"{}").unwrap();
+        }
+        let template = dir.join("out.jsonl");
+        let options = FileSinkOptions {
+            retention_bytes: Some(1),
+            ..Default::default()
+        };
+        let pending = |path: &Path| path.ends_with("out.jsonl.2");
+        apply_retention(
+            &dir,
+            template.to_str().unwrap(),
+            &options,
+            &HashSet::new(),
+            pending,
+        )
+        .unwrap();
+        assert_eq!(names(&dir), vec!["out.jsonl.2".to_string()]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn retention_only_skips_the_active_file_of_its_directory() {
+        let dir = temp_dir("file_sink_retention_dirs");
+        let okx = dir.join("okx");
+        fs::create_dir_all(&okx).unwrap();
+        for name in ["out.jsonl", "out.jsonl.1", "out.jsonl.2"] {
+            fs::write(okx.join(name), b"{}").unwrap();
+        }
+        let template = dir.join("{exchange}").join("out.jsonl");
+        let options = FileSinkOptions {
+            retention_bytes: Some(1),
+            ..Default::default()
+        };
+        let active = HashSet::from([dir.join("binance").join("out.jsonl")]);
+        apply_retention(&okx, template.to_str().unwrap(), &options, &active, |_| {
+            false
+        })
+        .unwrap();
+        // The unrotated file is left for the next writer to append to.
+        assert_eq!(names(&okx), vec!["out.jsonl".to_string()]);
+        assert!(same_file(Path::new("./out.jsonl"), Path::new("out.jsonl")));
+        assert!(!same_file(
+            &dir.join("binance").join("out.jsonl"),
+            &okx.join("out.jsonl")
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rotates_idle_files_once_they_reach_max_age() {
+        let dir = temp_dir("file_sink_idle");
+        let template = dir.join("{exchange}").join("out.jsonl");
+        let options = FileSinkOptions {
+            max_age: Some(Duration::from_millis(50)),
+            ..Default::default()
+        };
+        let sink = FileSink::new(template.to_str().unwrap(), Encoding::Json, options)
+            .await
+            .unwrap();
+        sink.publish(&trade("binance", 0)).await.unwrap();
+        sink.publish(&trade("okx", 0)).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        sink.flush().await.unwrap();
+
+        for exchange in ["binance", "okx"] {
+            let names = names(&dir.join(exchange));
+            assert_eq!(names.len(), 1, "{names:?}");
+            assert!(names[0].starts_with("out.jsonl."), "{names:?}");
+            let text = fs::read_to_string(dir.join(exchange).join(&names[0])).unwrap();
+            assert_eq!(text.lines().count(), 1);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,42 +1,271 @@
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use async_trait::async_trait;
 use canonical::MdEvent;
+use rdkafka::message::{Header, OwnedHeaders};
 use rdkafka::producer::{FutureProducer, FutureRecord, Producer};
 use rdkafka::ClientConfig;
+use std::env;
 use std::time::Duration;
+use tokio::sync::Mutex;
 
-use super::Sink;
+use super::encoding::{Encoding, EventEncoder};
+use super::{check_placeholders, Sink};
+
+const DEFAULT_TOPIC: &str = "md_events";
+
+/// Topic layout and producer settings of a [`KafkaSink`].
+#[derive(Debug, Clone)]
+pub struct KafkaOptions {
+    /// Topic each event is published to. May contain `{exchange}`,
+    /// `{channel}` and `{kind}`, e.g. `md.{exchange}.{channel}`.
+    pub topic: String,
+    /// Topic for SBE dictionary frames. Defaults to `<topic>.dictionary`, or
+    /// `md_events.dictionary` when the topic contains placeholders.
+    pub dictionary_topic: Option<String>,
+    /// Enable the idempotent producer, so retries cannot duplicate or reorder
+    /// messages within a partition.
+    pub idempotent: bool,
+    /// Extra librdkafka properties, applied after the sink's own so they can
+    /// override them.
+    pub config: Vec<(String, String)>,
+}
+
+impl Default for KafkaOptions {
+    fn default() -> Self {
+        Self {
+            topic: DEFAULT_TOPIC.into(),
+            dictionary_topic: None,
+            idempotent: false,
+            config: Vec::new(),
+        }
+    }
+}
+
+impl KafkaOptions {
+    /// Read `MD_SINK_KAFKA_TOPIC`, `MD_SINK_KAFKA_DICTIONARY_TOPIC`,
+    /// `MD_SINK_KAFKA_IDEMPOTENT` and `MD_SINK_KAFKA_CONFIG`, falling back to
+    /// the defaults.
+    pub fn from_env() -> Result<Self> {
+        let mut opts = Self::default();
+        if let Ok(v) = env::var("MD_SINK_KAFKA_TOPIC") {
+            if !v.trim().is_empty() {
+                opts.topic = v.trim().to_string();
+            }
+        }
+        if let Ok(v) = env::var("MD_SINK_KAFKA_DICTIONARY_TOPIC") {
+            if !v.trim().is_empty() {
+                opts.dictionary_topic = Some(v.trim().to_string());
+            }
+        }
+        if let Ok(v) = env::var("MD_SINK_KAFKA_IDEMPOTENT") {
+            opts.idempotent = v.trim().parse().context("MD_SINK_KAFKA_IDEMPOTENT")?;
+        }
+        if let Ok(v) = env::var("MD_SINK_KAFKA_CONFIG") {
+            opts.config = parse_config(&v).context("MD_SINK_KAFKA_CONFIG")?;
+        }
+        Ok(opts)
+    }
+}
+
+/// Parse comma-separated `key=value` pairs.
+fn parse_config(value: &str) -> Result<Vec<(String, String)>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| anyhow!("expected key=value, got {pair}"))?;
+            Ok((key.trim().to_string(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+/// Topic name with `{exchange}`, `{channel}` and `{kind}` placeholders.
+#[derive(Debug)]
+struct TopicTemplate {
+    template: String,
+    literal: bool,
+}
+
+impl TopicTemplate {
+    fn new(template: &str) -> Result<Self> {
+        check_placeholders(template, &["exchange", "channel", "kind"], "topic")?;
+        Ok(Self {
+            template: template.to_string(),
+            literal: !template.contains('{'),
+        })
+    }
+
+    fn render(&self, event: &MdEvent) -> String {
+        if self.literal {
+            return self.template.clone();
+        }
+        self.template
+            .replace("{exchange}", event.exchange())
+            .replace("{channel}", event.channel().as_str())
+            .replace("{kind}", event.kind())
+    }
+}
 
 pub struct KafkaSink {
     producer: FutureProducer,
-    topic: String,
+    topic: TopicTemplate,
+    dictionary_topic: String,
+    encoding: Encoding,
+    encoder: Mutex<EventEncoder>,
 }
 
 impl KafkaSink {
-    pub fn new(brokers: &str, topic: &str) -> Result<Self> {
-        let producer: FutureProducer = ClientConfig::new()
+    /// Create a sink publishing with `encoding`.
+    ///
+    /// Each message is keyed by `<exchange>:<symbol>`, so every instrument
+    /// keeps its order within one partition, and carries `schema_version`,
+    /// `kind`, `exchange`, `channel`, `encoding`, `ingest_ts_utc` and
+    /// `ingest_ts_monotonic` headers so consumers can filter without decoding
+    /// the payload. With [`Encoding::Sbe`], dictionary frames are published to
+    /// the dictionary topic, and delivered, before any event that references
+    /// them is sent. Both carry a `dictionary_epoch` header, which changes
+    /// whenever ids restart, e.g. after a restart of the ingestor.
+    pub fn new(brokers: &str, encoding: Encoding, options: KafkaOptions) -> Result<Self> {
+        let topic = TopicTemplate::new(&options.topic)?;
+        let dictionary_topic = options.dictionary_topic.unwrap_or_else(|| {
+            if topic.literal {
+                format!("{}.dictionary", options.topic)
+            } else {
+                format!("{DEFAULT_TOPIC}.dictionary")
+            }
+        });
+        let mut config = ClientConfig::new();
+        config
             .set("bootstrap.servers", brokers)
-            .set("message.timeout.ms", "5000")
-            .create()?;
+            .set("message.timeout.ms", "5000");
+        if options.idempotent {
+            config.set("enable.idempotence", "true");
+        }
+        for (key, value) in &options.config {
+            config.set(key, value);
+        }
+        let producer: FutureProducer = config.create()?;
         Ok(Self {
             producer,
-            topic: topic.to_string(),
+            topic,
+            dictionary_topic,
+            encoding,
+            encoder: Mutex::new(EventEncoder::new(encoding)),
         })
     }
+
+    fn headers(&self, event: &MdEvent, epoch: u64) -> OwnedHeaders {
+        let schema_version = event.schema_version.to_string();
+        let ingest_ts_utc = event.ingest_ts_utc().to_string();
+        let ingest_ts_monotonic = event.ingest_ts_monotonic().to_string();
+        let epoch = epoch.to_string();
+        let headers = [
+            ("schema_version", schema_version.as_str()),
+            ("kind", event.kind()),
+            ("exchange", event.exchange()),
+            ("channel", event.channel().as_str()),
+            ("encoding", self.encoding.as_str()),
+            ("ingest_ts_utc", ingest_ts_utc.as_str()),
+            ("ingest_ts_monotonic", ingest_ts_monotonic.as_str()),
+        ]
+        .into_iter()
+        .fold(OwnedHeaders::new_with_capacity(8), |headers, (key, value)| {
+            headers.insert(Header {
+                key,
+                value: Some(value),
+            })
+        });
+        if self.encoding == Encoding::Sbe {
+            return headers.insert(Header {
+                key: "dictionary_epoch",
+                value: Some(epoch.as_str()),
+            });
+        }
+        headers
+    }
+
+    /// Publish the names interned since the last call and wait for their
+    /// delivery. Callers hold the encoder lock throughout, so no event using
+    /// the new ids can be sent before them.
+    async fn announce(&self, encoder: &mut EventEncoder) -> Result<()> {
+        let mut dictionary = Vec::new();
+        if !encoder.take_dictionary(&mut dictionary) {
+            return Ok(());
+        }
+        let epoch = encoder.epoch().to_string();
+        let headers = OwnedHeaders::new_with_capacity(1).insert(Header {
+            key: "dictionary_epoch",
+            value: Some(epoch.as_str()),
+        });
+        let record = FutureRecord::to(&self.dictionary_topic)
+            .payload(&dictionary[..])
+            .headers(headers);
+        if let Err(e) = self.send(record).await {
+            // Re-announce every name, under a new epoch, rather than leave
+            // consumers with ids they cannot resolve.
+            encoder.reset();
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    async fn send(&self, record: FutureRecord<'_, str, [u8]>) -> Result<()> {
+        self.producer
+            .send(record, Duration::from_secs(0))
+            .await
+            .map_err(|(e, _)| e)?;
+        Ok(())
+    }
 }
 
 #[async_trait]
 impl Sink for KafkaSink {
     async fn publish(&self, event: &MdEvent) -> Result<()> {
-        let payload = serde_json::to_vec(event)?;
-        self
-            .producer
-            .send(
-                FutureRecord::<(), _>::to(&self.topic).payload(&payload),
-                Duration::from_secs(0),
-            )
-            .await
-            .map_err(|(e, _)| e)?;
+        let mut payload = Vec::new();
+        let epoch = {
+            let mut encoder = self.encoder.lock().await;
+            encoder.encode_event(event, &mut payload)?;
+            self.announce(&mut encoder).await?;
+            encoder.epoch()
+        };
+        let topic = self.topic.render(event);
+        let key = format!("{}:{}", event.exchange(), event.symbol());
+        let record = FutureRecord::to(&topic)
+            .key(&key[..])
+            .payload(&payload[..])
+            .headers(self.headers(event, epoch));
+        self.send(record).await
+    }
+
+    /// Send the dictionary frame for the whole batch first, then every event
+    /// without waiting for the previous one's delivery.
+    async fn publish_batch(&self, events: &[MdEvent]) -> Result<()> {
+        let mut payloads = Vec::with_capacity(events.len());
+        let epoch = {
+            let mut encoder = self.encoder.lock().await;
+            for event in events {
+                let mut payload = Vec::new();
+                encoder.encode_event(event, &mut payload)?;
+                payloads.push(payload);
+            }
+            self.announce(&mut encoder).await?;
+            encoder.epoch()
+        };
+        let keys: Vec<_> = events
+            .iter()
+            .map(|ev| (self.topic.render(ev), format!("{}:{}", ev.exchange(), ev.symbol())))
+            .collect();
+        let sends = events.iter().zip(&keys).zip(&payloads).map(|((event, (topic, key)), payload)| {
+            let record = FutureRecord::to(topic)
+                .key(&key[..])
+                .payload(&payload[..])
+                .headers(self.headers(event, epoch));
+            self.send(record)
+        });
+        futures::future::try_join_all(sends).await?;
         Ok(())
     }
 
@@ -46,3 +275,169 @@ impl Sink for KafkaSink {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::testing::event;
+    use canonical::{BookTicker, MdEventKind, Trade};
+    use rdkafka::consumer::{BaseConsumer, Consumer};
+    use rdkafka::message::Headers;
+    use rdkafka::mocking::MockCluster;
+    use rdkafka::Message;
+    use std::collections::HashMap;
+
+    fn trade(exchange: &str, symbol: &str) -> MdEvent {
+        event(MdEventKind::Trade(Trade {
+            exchange: exchange.into(),
+            symbol: symbol.into(),
+            ingest_ts_utc: 1_700_000_000_000_000_000,
+            ingest_ts_monotonic: 42,
+            ..Default::default()
+        }))
+    }
+
+    #[test]
+    fn rejects_unknown_placeholders() {
+        assert!(TopicTemplate::new("md.{exchange}.{channel}.{kind}").is_ok());
+        assert!(TopicTemplate::new("md.{venue}").is_err());
+        assert!(TopicTemplate::new("md.{exchange").is_err());
+        assert_eq!(
+            parse_config("acks=all, linger.ms = 5,").unwrap(),
+            vec![
+                ("acks".to_string(), "all".to_string()),
+                ("linger.ms".to_string(), "5".to_string())
+            ]
+        );
+        assert!(parse_config("acks").is_err());
+    }
+
+    #[tokio::test]
+    async fn publishes_keyed_events_with_headers_to_templated_topics() {
+        let cluster = MockCluster::new(1).unwrap();
+        for topic in ["md.binance.trade", "md.okx.book"] {
+            cluster.create_topic(topic, 3, 1).unwrap();
+        }
+        let brokers = cluster.bootstrap_servers();
+        let sink = KafkaSink::new(
+            &brokers,
+            Encoding::Json,
+            KafkaOptions {
+                topic: "md.{exchange}.{channel}".into(),
+                idempotent: true,
+                config: vec![("linger.ms".into(), "0".into())],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        sink.publish(&trade("binance", "BTCUSDT")).await.unwrap();
+        sink.publish(&trade("binance", "ETHUSDT")).await.unwrap();
+        sink.publish_batch(&[event(MdEventKind::BookTicker(BookTicker {
+            exchange: "okx".into(),
+            symbol: "BTC-USDT".into(),
+            ..Default::default()
+        }))])
+        .await
+        .unwrap();
+        sink.flush().await.unwrap();
+
+        let consumer: BaseConsumer = ClientConfig::new()
+            .set("bootstrap.servers", &brokers)
+            .set("group.id", "test")
+            .set("auto.offset.reset", "earliest")
+            .create()
+            .unwrap();
+        consumer
+            .subscribe(&["md.binance.trade", "md.okx.book"])
+            .unwrap();
+        let mut received = HashMap::new();
+        for _ in 0..100 {
+            if received.len() == 3 {
+                break;
+            }
+            let Some(message) = consumer.poll(Duration::from_millis(100)) else {
+                continue;
+            };
+            let message = message.unwrap();
+            let key = String::from_utf8(message.key().unwrap().to_vec()).unwrap();
+            let headers = message
+                .headers()
+                .unwrap()
+                .iter()
+                .map(|h| (h.key.to_string(), String::from_utf8(h.value.unwrap().to_vec()).unwrap()))
+                .collect::<HashMap<_, _>>();
+            let decoded: MdEvent = serde_json::from_slice(message.payload().unwrap()).unwrap();
+            assert_eq!(key, format!("{}:{}", decoded.exchange(), decoded.symbol()));
+            received.insert(key, (message.topic().to_string(), headers));
+        }
+        assert_eq!(received.len(), 3);
+        assert_eq!(received["binance:ETHUSDT"].0, "md.binance.trade");
+        assert_eq!(received["okx:BTC-USDT"].0, "md.okx.book");
+        let headers = &received["binance:BTCUSDT"].1;
+        assert_eq!(headers["schema_version"], canonical::SCHEMA_VERSION.to_string());
+        assert_eq!(headers["kind"], "Trade");
+        assert_eq!(headers["channel"], "trade");
+        assert_eq!(headers["encoding"], "json");
+        assert_eq!(headers["ingest_ts_utc"], "1700000000000000000");
+        assert_eq!(headers["ingest_ts_monotonic"], "42");
+        assert_eq!(received["okx:BTC-USDT"].1["kind"], "BookTicker");
+    }
+
+    #[tokio::test]
+    async fn announces_dictionary_with_the_epoch_of_its_events() {
+        let cluster = MockCluster::new(1).unwrap();
+        for topic in ["md_events", "md_events.dictionary"] {
+            cluster.create_topic(topic, 1, 1).unwrap();
+        }
+        let brokers = cluster.bootstrap_servers();
+        let sink = KafkaSink::new(&brokers, Encoding::Sbe, KafkaOptions::default()).unwrap();
+        sink.publish_batch(&[trade("binance", "BTCUSDT"), trade("okx", "BTC-USDT")])
+            .await
+            .unwrap();
+        sink.publish(&trade("binance", "BTCUSDT")).await.unwrap();
+        sink.flush().await.unwrap();
+
+        let consumer: BaseConsumer = ClientConfig::new()
+            .set("bootstrap.servers", &brokers)
+            .set("group.id", "test")
+            .set("auto.offset.reset", "earliest")
+            .create()
+            .unwrap();
+        consumer
+            .subscribe(&["md_events", "md_events.dictionary"])
+            .unwrap();
+        let mut dictionary = Vec::new();
+        let mut events = Vec::new();
+        for _ in 0..100 {
+            if dictionary.len() == 1 && events.len() == 3 {
+                break;
+            }
+            let Some(message) = consumer.poll(Duration::from_millis(100)) else {
+                continue;
+            };
+            let message = message.unwrap();
+            let epoch = message
+                .headers()
+                .unwrap()
+                .iter()
+                .find(|h| h.key == "dictionary_epoch")
+                .map(|h| String::from_utf8(h.value.unwrap().to_vec()).unwrap())
+                .unwrap();
+            let payload = message.payload().unwrap().to_vec();
+            match message.topic() {
+                "md_events.dictionary" => dictionary.push((epoch, payload)),
+                _ => events.push((epoch, payload)),
+            }
+        }
+        assert_eq!(dictionary.len(), 1);
+        assert_eq!(events.len(), 3);
+        let mut dict = canonical::sbe::Dictionary::new();
+        for msg in canonical::sbe::frames(&dictionary[0].1) {
+            assert!(dict.decode(&msg.unwrap()).unwrap().is_none());
+        }
+        for (epoch, payload) in &events {
+            assert_eq!(epoch, &dictionary[0].0);
+            let msg = canonical::sbe::frames(payload).next().unwrap().unwrap();
+            assert!(dict.decode(&msg).unwrap().is_some());
+        }
+    }
+}
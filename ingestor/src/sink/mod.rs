@@ -1,156 +1,151 @@
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use async_trait::async_trait;
 use canonical::MdEvent;
-use std::io::SeekFrom;
-use std::path::Path;
-use tokio::fs::OpenOptions;
-use tokio::io::{AsyncSeekExt, AsyncWriteExt, BufWriter};
-use tokio::sync::Mutex;
 
+mod batch;
+mod clickhouse;
+mod dlq;
+mod encoding;
+mod file;
 mod kafka;
-pub use kafka::KafkaSink;
+mod multicast;
+mod nats;
+mod parquet;
+mod pipeline;
+mod postgres;
+mod questdb;
+mod redis_stream;
+mod router;
+mod table;
+mod wal;
+pub use clickhouse::{ClickHouseOptions, ClickHouseSink};
+pub use dlq::{
+    read_dead_letters, rewrite_dead_letters, DeadLetter, DeadLetterLock, DeadLetterQueue,
+};
+pub use encoding::Encoding;
+pub(crate) use encoding::EventEncoder;
+pub use file::{FileSink, FileSinkOptions};
+pub use kafka::{KafkaOptions, KafkaSink};
+pub use multicast::{MulticastOptions, MulticastSink};
+pub use nats::{NatsOptions, NatsSink};
+pub use parquet::{ParquetOptions, ParquetSink};
+pub use pipeline::{MetricNames, Overflow, Pipeline, PipelineOptions};
+pub use postgres::{PostgresOptions, PostgresSink};
+pub use questdb::{QuestDbOptions, QuestDbSink};
+pub use redis_stream::{RedisOptions, RedisStreamSink};
+pub use router::{RouteFilter, RouterSink};
+pub use wal::{Wal, WalOptions};
 
 #[async_trait]
 pub trait Sink: Send + Sync {
     async fn publish(&self, event: &MdEvent) -> Result<()>;
-    async fn flush(&self) -> Result<()>;
-}
 
-pub struct FileSink {
-    inner: Mutex<State>,
-    batch_size: usize,
-}
+    /// Publish events in order. Sinks that can write several events at once
+    /// override this; the default publishes them one by one. On error, some
+    /// of the events may already have been published.
+    async fn publish_batch(&self, events: &[MdEvent]) -> Result<()> {
+        for event in events {
+            self.publish(event).await?;
+        }
+        Ok(())
+    }
 
-struct State {
-    writer: BufWriter<tokio::fs::File>,
-    buf: Vec<String>,
+    async fn flush(&self) -> Result<()>;
 }
 
-impl FileSink {
-    const DEFAULT_BATCH_SIZE: usize = 1024;
-
-    pub async fn new(path: impl AsRef<Path>) -> Result<Self> {
-        let file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(path)
-            .await?;
-        Ok(Self {
-            inner: Mutex::new(State {
-                writer: BufWriter::new(file),
-                buf: Vec::with_capacity(Self::DEFAULT_BATCH_SIZE),
-            }),
-            batch_size: Self::DEFAULT_BATCH_SIZE,
-        })
-    }
-
-    async fn flush_locked(state: &mut State) -> Result<()> {
-        for line in state.buf.drain(..) {
-            state.writer.write_all(line.as_bytes()).await?;
-            state.writer.write_all(b"\n").await?;
+/// Check that every `{placeholder}` in `template` is one of `allowed`.
+/// `what` names the template in errors, e.g. `topic`.
+pub(crate) fn check_placeholders(template: &str, allowed: &[&str], what: &str) -> Result<()> {
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| anyhow!("unterminated placeholder in {what} {template}"))?;
+        let name = &rest[start + 1..start + end];
+        if !allowed.contains(&name) {
+            bail!("unknown placeholder {{{name}}} in {what} {template}");
         }
-        state.writer.flush().await?;
-        Ok(())
+        rest = &rest[start + end + 1..];
     }
+    Ok(())
 }
 
-#[async_trait]
-impl Sink for FileSink {
-    async fn publish(&self, event: &MdEvent) -> Result<()> {
-        let mut inner = self.inner.lock().await;
-        let json = serde_json::to_string(event)?;
-        inner.buf.push(json);
-        if inner.buf.len() >= self.batch_size {
-            Self::flush_locked(&mut inner).await?;
+/// Fixtures shared by the sink tests.
+#[cfg(test)]
+pub(crate) mod testing {
+    use canonical::{MdEvent, MdEventKind, SCHEMA_VERSION};
+    use std::future::Future;
+    use std::net::TcpListener;
+    use std::path::PathBuf;
+    use std::process::{Child, Command, Stdio};
+    use std::time::Duration;
+
+    pub(crate) fn event(kind: MdEventKind) -> MdEvent {
+        MdEvent {
+            schema_version: SCHEMA_VERSION,
+            event: kind,
+            validation: None,
         }
-        Ok(())
     }
 
-    async fn flush(&self) -> Result<()> {
-        let mut inner = self.inner.lock().await;
-        if inner.buf.is_empty() {
-            inner.writer.flush().await?;
-            return Ok(());
-        }
-        Self::flush_locked(&mut inner).await
+    pub(crate) fn free_port() -> u16 {
+        TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
     }
-}
 
-pub struct Wal<T: Sink> {
-    inner: T,
-    wal: Mutex<tokio::fs::File>,
-    dlq: Mutex<tokio::fs::File>,
-}
+    /// Empty directory for the data of a test server.
+    pub(crate) fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("{name}_{}_{}", std::process::id(), free_port()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
 
-impl<T: Sink> Wal<T> {
-    pub async fn new(path: impl AsRef<Path>, inner: T) -> Result<Self> {
-        let path = path.as_ref();
-        let mut dlq_path = path.to_path_buf();
-        dlq_path.set_extension("dlq");
+    /// Whether something accepts connections on `port`.
+    pub(crate) async fn listening(port: u16) -> bool {
+        tokio::net::TcpStream::connect(("127.0.0.1", port)).await.is_ok()
+    }
 
-        let mut dlq = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&dlq_path)
-            .await?;
+    /// A server process started by a test, killed on drop together with its
+    /// data directory.
+    pub(crate) struct Server {
+        child: Child,
+        pub port: u16,
+        pub dir: PathBuf,
+    }
 
-        if let Ok(existing) = tokio::fs::read_to_string(path).await {
-            for line in existing.lines().filter(|l| !l.trim().is_empty()) {
-                if let Ok(ev) = serde_json::from_str::<MdEvent>(line) {
-                    if inner.publish(&ev).await.is_err() {
-                        dlq.write_all(line.as_bytes()).await?;
-                        dlq.write_all(b"\n").await?;
-                    }
-                } else {
-                    dlq.write_all(line.as_bytes()).await?;
-                    dlq.write_all(b"\n").await?;
-                }
-            }
-            inner.flush().await?;
+    impl Drop for Server {
+        fn drop(&mut self) {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+            let _ = std::fs::remove_dir_all(&self.dir);
         }
-
-        let wal_file = OpenOptions::new()
-            .create(true)
-            .truncate(true)
-            .read(true)
-            .write(true)
-            .open(path)
-            .await?;
-        wal_file.sync_data().await?;
-
-        Ok(Self {
-            inner,
-            wal: Mutex::new(wal_file),
-            dlq: Mutex::new(dlq),
-        })
     }
-}
 
-#[async_trait]
-impl<T: Sink> Sink for Wal<T> {
-    async fn publish(&self, event: &MdEvent) -> Result<()> {
-        let json = serde_json::to_string(event)?;
+    impl Server {
+        /// Run `command` and wait for `ready`. Panics when the program is
+        /// missing or does not come up, so tests that need one are marked
+        /// `#[ignore]` and run with `cargo test -- --ignored` where it is
+        /// installed.
+        pub(crate) async fn spawn<F, Fut>(mut command: Command, port: u16, dir: PathBuf, ready: F) -> Self
+        where
+            F: Fn() -> Fut,
+            Fut: Future<Output = bool>,
         {
-            let mut wal = self.wal.lock().await;
-            wal.write_all(json.as_bytes()).await?;
-            wal.write_all(b"\n").await?;
-            wal.sync_data().await?;
-        }
-        if let Err(e) = self.inner.publish(event).await {
-            let mut dlq = self.dlq.lock().await;
-            dlq.write_all(json.as_bytes()).await?;
-            dlq.write_all(b"\n").await?;
-            dlq.sync_data().await?;
-            return Err(e);
+            let program = command.get_program().to_string_lossy().into_owned();
+            let child = command
+                .current_dir(&dir)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+                .unwrap_or_else(|e| panic!("cannot run {program}: {e}"));
+            let server = Self { child, port, dir };
+            for _ in 0..100 {
+                if ready().await {
+                    return server;
+                }
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+            panic!("{program} did not start");
         }
-        let mut wal = self.wal.lock().await;
-        wal.set_len(0).await?;
-        wal.seek(SeekFrom::Start(0)).await?;
-        wal.sync_data().await?;
-        Ok(())
-    }
-
-    async fn flush(&self) -> Result<()> {
-        self.inner.flush().await
     }
 }
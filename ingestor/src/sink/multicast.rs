@@ -0,0 +1,446 @@
+use agents::ChannelRegistry;
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use canonical::{sbe, MdEvent};
+use md_multicast::{sender_socket, write_frame, PacketBuilder, PacketKind, Request, REQUEST_LEN};
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::sync::{Arc, Mutex};
+use tokio::io::AsyncReadExt;
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, info, warn};
+
+use super::Sink;
+
+/// Network settings of a [`MulticastSink`].
+#[derive(Debug, Clone)]
+pub struct MulticastOptions {
+    /// Local interface multicast is sent through.
+    pub interface: Ipv4Addr,
+    pub ttl: u32,
+    /// TCP address of the recovery server. Defaults to all interfaces on the
+    /// group's port.
+    pub recovery_addr: Option<SocketAddr>,
+    /// Largest datagram payload, in bytes.
+    pub packet_size: usize,
+    /// Messages kept per channel for retransmission.
+    pub retransmit: usize,
+    pub queue: usize,
+}
+
+impl Default for MulticastOptions {
+    fn default() -> Self {
+        Self {
+            interface: Ipv4Addr::UNSPECIFIED,
+            ttl: 1,
+            recovery_addr: None,
+            packet_size: md_multicast::DEFAULT_PACKET_SIZE,
+            retransmit: 65_536,
+            queue: 8192,
+        }
+    }
+}
+
+impl MulticastOptions {
+    /// Read `MD_SINK_MULTICAST_INTERFACE`, `MD_SINK_MULTICAST_TTL`,
+    /// `MD_SINK_MULTICAST_RECOVERY_ADDR`, `MD_SINK_MULTICAST_PACKET_SIZE` and
+    /// `MD_SINK_MULTICAST_RETRANSMIT`, falling back to the defaults.
+    pub fn from_env() -> Result<Self> {
+        let mut opts = Self::default();
+        if let Ok(v) = env::var("MD_SINK_MULTICAST_INTERFACE") {
+            opts.interface = v.trim().parse().context("MD_SINK_MULTICAST_INTERFACE")?;
+        }
+        if let Ok(v) = env::var("MD_SINK_MULTICAST_TTL") {
+            opts.ttl = v.trim().parse().context("MD_SINK_MULTICAST_TTL")?;
+        }
+        if let Ok(v) = env::var("MD_SINK_MULTICAST_RECOVERY_ADDR") {
+            opts.recovery_addr = Some(
+                v.trim()
+                    .parse()
+                    .context("MD_SINK_MULTICAST_RECOVERY_ADDR")?,
+            );
+        }
+        if let Ok(v) = env::var("MD_SINK_MULTICAST_PACKET_SIZE") {
+            opts.packet_size = v.trim().parse().context("MD_SINK_MULTICAST_PACKET_SIZE")?;
+        }
+        if let Ok(v) = env::var("MD_SINK_MULTICAST_RETRANSMIT") {
+            opts.retransmit = v.trim().parse().context("MD_SINK_MULTICAST_RETRANSMIT")?;
+        }
+        Ok(opts)
+    }
+}
+
+/// Published messages of one channel.
+#[derive(Default)]
+struct ChannelLog {
+    encoder: sbe::Encoder,
+    /// Every dictionary announcement so far, in order.
+    dictionary: Vec<Arc<[u8]>>,
+    /// The last `retransmit` messages with their sequence numbers.
+    history: VecDeque<(u64, Arc<[u8]>)>,
+    /// Event frame of the last message of every instrument.
+    latest: HashMap<(String, String), Arc<[u8]>>,
+    next: u64,
+}
+
+struct Shared {
+    channels: Mutex<HashMap<u8, ChannelLog>>,
+    sequences: ChannelRegistry,
+    retransmit: usize,
+}
+
+enum Message {
+    Packet {
+        channel: u8,
+        seq: u64,
+        msg: Arc<[u8]>,
+    },
+    Flush(oneshot::Sender<Result<()>>),
+}
+
+/// Sink multicasting events as sequenced datagrams, see [`md_multicast`],
+/// with a TCP server for retransmissions and snapshots.
+pub struct MulticastSink {
+    shared: Arc<Shared>,
+    tx: mpsc::Sender<Message>,
+    metrics_enabled: bool,
+}
+
+impl MulticastSink {
+    /// Publish to `group` and serve recovery requests.
+    ///
+    /// Datagrams are sent by a background task that packs whatever is queued
+    /// for a channel into as few packets as fit. When its queue is full,
+    /// messages are not sent live but remain available for retransmission.
+    pub async fn new(
+        group: SocketAddrV4,
+        options: MulticastOptions,
+        metrics_enabled: bool,
+    ) -> Result<Self> {
+        let socket = sender_socket(options.interface, options.ttl)
+            .with_context(|| format!("multicast socket on {}", options.interface))?;
+        let socket = UdpSocket::from_std(socket)?;
+        let recovery_addr = options
+            .recovery_addr
+            .unwrap_or_else(|| SocketAddr::from((Ipv4Addr::UNSPECIFIED, group.port())));
+        let listener = TcpListener::bind(recovery_addr)
+            .await
+            .with_context(|| format!("binding multicast recovery server on {recovery_addr}"))?;
+        let shared = Arc::new(Shared {
+            channels: Mutex::new(HashMap::new()),
+            sequences: ChannelRegistry::new(1),
+            retransmit: options.retransmit.max(1),
+        });
+        let (tx, rx) = mpsc::channel(options.queue.max(1));
+        tokio::spawn(run_sender(socket, group.into(), rx, options.packet_size));
+        tokio::spawn(serve_recovery(listener, shared.clone()));
+        info!(%group, %recovery_addr, "multicasting events");
+        Ok(Self {
+            shared,
+            tx,
+            metrics_enabled,
+        })
+    }
+}
+
+#[async_trait]
+impl Sink for MulticastSink {
+    async fn publish(&self, event: &MdEvent) -> Result<()> {
+        let channel = event.channel();
+        let (seq, msg) = {
+            let mut channels = self
+                .shared
+                .channels
+                .lock()
+                .expect("multicast mutex poisoned");
+            let log = channels.entry(channel as u8).or_default();
+            let mut frame = Vec::new();
+            log.encoder.encode(event, &mut frame)?;
+            let mut msg = Vec::with_capacity(frame.len());
+            if log.encoder.take_dictionary(&mut msg) {
+                log.dictionary.push(msg.as_slice().into());
+            }
+            msg.extend_from_slice(&frame);
+            let msg: Arc<[u8]> = msg.into();
+            let seq = self.shared.sequences.next_seq_no(channel.as_str());
+            log.next = seq + 1;
+            log.latest.insert(
+                (event.exchange().to_string(), event.symbol().to_string()),
+                frame.into(),
+            );
+            log.history.push_back((seq, msg.clone()));
+            if log.history.len() > self.shared.retransmit {
+                log.history.pop_front();
+            }
+            (seq, msg)
+        };
+        let packet = Message::Packet {
+            channel: channel as u8,
+            seq,
+            msg,
+        };
+        if self.tx.try_send(packet).is_err() && self.metrics_enabled {
+            metrics::counter!("md_multicast_dropped_total").increment(1);
+        }
+        Ok(())
+    }
+
+    async fn flush(&self) -> Result<()> {
+        let (done, rx) = oneshot::channel();
+        self.tx
+            .send(Message::Flush(done))
+            .await
+            .map_err(|_| anyhow!("multicast sender stopped"))?;
+        rx.await.map_err(|_| anyhow!("multicast sender stopped"))?
+    }
+}
+
+async fn run_sender(
+    socket: UdpSocket,
+    group: SocketAddr,
+    mut rx: mpsc::Receiver<Message>,
+    packet_size: usize,
+) {
+    let mut failed = 0u64;
+    let mut pending: HashMap<u8, PacketBuilder> = HashMap::new();
+    let mut flushes = Vec::new();
+    while let Some(msg) = rx.recv().await {
+        let mut next = Some(msg);
+        while let Some(msg) = next.take() {
+            match msg {
+                Message::Packet { channel, seq, msg } => {
+                    let builder = pending.entry(channel).or_insert_with(|| {
+                        PacketBuilder::new(channel, PacketKind::Live, seq, packet_size)
+                    });
+                    // Messages skipped while the queue was full break the
+                    // run of consecutive sequence numbers a packet needs.
+                    if builder.next_seq() != seq || !builder.push(&msg) {
+                        failed += send(&socket, group, builder).await;
+                        *builder = PacketBuilder::new(channel, PacketKind::Live, seq, packet_size);
+                        if !builder.push(&msg) {
+                            warn!(
+                                channel,
+                                seq,
+                                len = msg.len(),
+                                "message too large for a datagram"
+                            );
+                            *builder =
+                                PacketBuilder::new(channel, PacketKind::Live, seq + 1, packet_size);
+                        }
+                    }
+                }
+                Message::Flush(done) => flushes.push(done),
+            }
+            next = rx.try_recv().ok();
+        }
+        for builder in pending.values() {
+            failed += send(&socket, group, builder).await;
+        }
+        pending.clear();
+        for done in flushes.drain(..) {
+            let res = match std::mem::take(&mut failed) {
+                0 => Ok(()),
+                n => Err(anyhow!("{n} multicast datagrams not sent")),
+            };
+            let _ = done.send(res);
+        }
+    }
+}
+
+/// Send a packet unless it is empty, returning the number of failed sends.
+async fn send(socket: &UdpSocket, group: SocketAddr, packet: &PacketBuilder) -> u64 {
+    if packet.is_empty() {
+        return 0;
+    }
+    match socket.send_to(packet.as_bytes(), group).await {
+        Ok(_) => 0,
+        Err(e) => {
+            debug!(error = %e, "failed to send multicast datagram");
+            1
+        }
+    }
+}
+
+async fn serve_recovery(listener: TcpListener, shared: Arc<Shared>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                let shared = shared.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = serve_client(stream, &shared).await {
+                        debug!(%peer, error = %e, "multicast recovery client disconnected");
+                    }
+                });
+            }
+            Err(e) => warn!(error = %e, "multicast recovery server accept failed"),
+        }
+    }
+}
+
+async fn serve_client(mut stream: TcpStream, shared: &Shared) -> Result<()> {
+    stream.set_nodelay(true)?;
+    let mut request = [0u8; REQUEST_LEN];
+    loop {
+        stream.read_exact(&mut request).await?;
+        let packet = respond(shared, Request::decode(&request)?);
+        write_frame(&mut stream, packet.as_bytes()).await?;
+    }
+}
+
+fn respond(shared: &Shared, request: Request) -> PacketBuilder {
+    let channels = shared.channels.lock().expect("multicast mutex poisoned");
+    match request {
+        Request::Retransmit {
+            channel,
+            from_seq,
+            count,
+        } => {
+            let history = channels.get(&channel).map(|log| &log.history);
+            let oldest = history
+                .and_then(|h| h.front())
+                .map_or(from_seq, |(seq, _)| *seq);
+            // Messages no longer held are skipped, which tells the receiver
+            // to fall back to a snapshot.
+            let start = from_seq.max(oldest);
+            let end = from_seq.saturating_add(count as u64);
+            let mut packet = PacketBuilder::new(channel, PacketKind::Retransmit, start, usize::MAX);
+            if let Some(history) = history {
+                let skip = (start - oldest) as usize;
+                for (seq, msg) in history.iter().skip(skip) {
+                    if *seq >= end || !packet.push(msg) {
+                        break;
+                    }
+                }
+            }
+            packet
+        }
+        Request::Snapshot { channel } => {
+            let log = channels.get(&channel);
+            let next = log.map_or(0, |log| log.next);
+            let mut packet = PacketBuilder::new(channel, PacketKind::Snapshot, next, usize::MAX);
+            if let Some(log) = log {
+                for msg in log.dictionary.iter().chain(log.latest.values()) {
+                    if !packet.push(msg) {
+                        warn!(channel, "multicast snapshot truncated");
+                        break;
+                    }
+                }
+            }
+            packet
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::testing::event;
+    use canonical::{BookTicker, MdEventKind, Trade};
+    use md_multicast::Receiver;
+    use std::time::Duration;
+
+    fn trade(id: u64) -> MdEvent {
+        event(MdEventKind::Trade(Trade {
+            exchange: "binance".into(),
+            symbol: "BTCUSDT".into(),
+            trade_id: Some(id),
+            ..Default::default()
+        }))
+    }
+
+    fn book(bid: f64) -> MdEvent {
+        event(MdEventKind::BookTicker(BookTicker {
+            exchange: "okx".into(),
+            symbol: "BTC-USDT".into(),
+            bid_price: bid,
+            ..Default::default()
+        }))
+    }
+
+    fn free_port() -> u16 {
+        let udp = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        udp.local_addr().unwrap().port()
+    }
+
+    async fn recv(receiver: &mut Receiver) -> MdEvent {
+        tokio::time::timeout(Duration::from_secs(5), receiver.recv())
+            .await
+            .expect("multicast event")
+            .unwrap()
+    }
+
+    fn trade_id(ev: &MdEvent) -> Option<u64> {
+        match &ev.event {
+            MdEventKind::Trade(t) => t.trade_id,
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn receivers_fill_gaps_and_join_from_snapshots() {
+        let group = SocketAddrV4::new(Ipv4Addr::new(239, 255, 77, 1), free_port());
+        let recovery = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let loopback = Ipv4Addr::LOCALHOST;
+        let mut receiver = Receiver::join(group, loopback, recovery).unwrap();
+        let sink = MulticastSink::new(
+            group,
+            MulticastOptions {
+                interface: loopback,
+                recovery_addr: Some(recovery),
+                // Only the first message fits, so the rest of the burst
+                // below is never sent live.
+                queue: 1,
+                ..Default::default()
+            },
+            false,
+        )
+        .await
+        .unwrap();
+
+        for id in 0..100 {
+            sink.publish(&trade(id)).await.unwrap();
+        }
+        sink.publish(&book(1.0)).await.unwrap();
+        sink.publish(&book(2.0)).await.unwrap();
+        sink.flush().await.unwrap();
+        sink.publish(&trade(100)).await.unwrap();
+        sink.flush().await.unwrap();
+
+        let mut trades = Vec::new();
+        while trades.len() < 101 {
+            let ev = recv(&mut receiver).await;
+            trades.extend(trade_id(&ev));
+        }
+        assert_eq!(trades, (0..=100).collect::<Vec<_>>());
+        let stats = receiver.stats();
+        assert!(stats.gaps >= 1, "{stats:?}");
+        assert!(stats.retransmitted >= 99, "{stats:?}");
+        assert_eq!(stats.snapshots, 0);
+
+        // A receiver joining mid-stream starts from a snapshot holding the
+        // latest message of every instrument, which already covers the
+        // live packets that prompted it.
+        let mut late = Receiver::join(group, loopback, recovery).unwrap();
+        sink.publish(&trade(101)).await.unwrap();
+        sink.flush().await.unwrap();
+        sink.publish(&book(3.0)).await.unwrap();
+        sink.flush().await.unwrap();
+        let first = recv(&mut late).await;
+        assert_eq!(trade_id(&first), Some(101));
+        match recv(&mut late).await.event {
+            MdEventKind::BookTicker(b) => assert_eq!(b.bid_price, 3.0),
+            other => panic!("expected a book ticker, got {other:?}"),
+        }
+        assert_eq!(late.stats().snapshots, 2);
+        sink.publish(&trade(102)).await.unwrap();
+        sink.flush().await.unwrap();
+        assert_eq!(trade_id(&recv(&mut late).await), Some(102));
+        assert_eq!(late.stats().snapshots, 2);
+        assert_eq!(trade_id(&recv(&mut receiver).await), Some(101));
+    }
+}
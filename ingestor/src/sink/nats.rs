@@ -0,0 +1,393 @@
+use anyhow::{anyhow, Context, Result};
+use async_nats::jetstream::{self, stream};
+use async_nats::HeaderMap;
+use async_trait::async_trait;
+use canonical::MdEvent;
+use std::env;
+use tokio::sync::Mutex;
+
+use super::encoding::{Encoding, EventEncoder};
+use super::{check_placeholders, Sink};
+
+const DEFAULT_SUBJECT: &str = "md.{exchange}.{channel}.{symbol}";
+
+/// Subject layout and delivery mode of a [`NatsSink`].
+#[derive(Debug, Clone)]
+pub struct NatsOptions {
+    /// Subject each event is published to. May contain `{exchange}`,
+    /// `{channel}`, `{kind}` and `{symbol}`.
+    pub subject: String,
+    /// Subject for SBE dictionary frames. Defaults to `md.dictionary`.
+    pub dictionary_subject: Option<String>,
+    /// Publish through JetStream and wait for the server's acknowledgement
+    /// instead of fire-and-forget core NATS.
+    pub jetstream: bool,
+    /// JetStream stream to create, if missing, capturing every subject the
+    /// sink publishes to.
+    pub stream: Option<String>,
+}
+
+impl Default for NatsOptions {
+    fn default() -> Self {
+        Self {
+            subject: DEFAULT_SUBJECT.into(),
+            dictionary_subject: None,
+            jetstream: false,
+            stream: None,
+        }
+    }
+}
+
+impl NatsOptions {
+    /// Read `MD_SINK_NATS_SUBJECT`, `MD_SINK_NATS_DICTIONARY_SUBJECT`,
+    /// `MD_SINK_NATS_JETSTREAM` and `MD_SINK_NATS_STREAM`, falling back to the
+    /// defaults.
+    pub fn from_env() -> Result<Self> {
+        let mut opts = Self::default();
+        if let Ok(v) = env::var("MD_SINK_NATS_SUBJECT") {
+            if !v.trim().is_empty() {
+                opts.subject = v.trim().to_string();
+            }
+        }
+        if let Ok(v) = env::var("MD_SINK_NATS_DICTIONARY_SUBJECT") {
+            if !v.trim().is_empty() {
+                opts.dictionary_subject = Some(v.trim().to_string());
+            }
+        }
+        if let Ok(v) = env::var("MD_SINK_NATS_JETSTREAM") {
+            opts.jetstream = v.trim().parse().context("MD_SINK_NATS_JETSTREAM")?;
+        }
+        if let Ok(v) = env::var("MD_SINK_NATS_STREAM") {
+            if !v.trim().is_empty() {
+                opts.stream = Some(v.trim().to_string());
+                opts.jetstream = true;
+            }
+        }
+        Ok(opts)
+    }
+}
+
+/// Subject with `{exchange}`, `{channel}`, `{kind}` and `{symbol}`
+/// placeholders.
+#[derive(Debug)]
+struct SubjectTemplate {
+    template: String,
+}
+
+impl SubjectTemplate {
+    fn new(template: &str) -> Result<Self> {
+        check_placeholders(
+            template,
+            &["exchange", "channel", "kind", "symbol"],
+            "subject",
+        )?;
+        Ok(Self {
+            template: template.to_string(),
+        })
+    }
+
+    fn render(&self, event: &MdEvent) -> String {
+        self.template
+            .replace("{exchange}", &token(event.exchange()))
+            .replace("{channel}", event.channel().as_str())
+            .replace("{kind}", event.kind())
+            .replace("{symbol}", &token(event.symbol()))
+    }
+
+    /// Subjects a stream needs to capture everything rendered from this
+    /// template: the literal prefix followed by `>`.
+    fn stream_subject(&self) -> String {
+        match self.template.find('{') {
+            None => self.template.clone(),
+            Some(start) => {
+                let prefix = &self.template[..start];
+                match prefix.rfind('.') {
+                    Some(dot) => format!("{}.>", &prefix[..dot]),
+                    None => ">".into(),
+                }
+            }
+        }
+    }
+}
+
+/// Make `value` a single subject token. Dots separate tokens and `*`, `>`
+/// and whitespace are reserved.
+fn token(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            '.' | '*' | '>' => '_',
+            c if c.is_whitespace() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// Whether `pattern`, which may contain `*` and `>` wildcards, matches
+/// `subject`.
+fn matches_subject(pattern: &str, subject: &str) -> bool {
+    let mut tokens = subject.split('.');
+    for wanted in pattern.split('.') {
+        match (wanted, tokens.next()) {
+            (">", Some(_)) => return true,
+            ("*", Some(_)) => {}
+            (wanted, Some(token)) if wanted == token => {}
+            _ => return false,
+        }
+    }
+    tokens.next().is_none()
+}
+
+enum Publisher {
+    Core(async_nats::Client),
+    JetStream(jetstream::Context),
+}
+
+pub struct NatsSink {
+    client: async_nats::Client,
+    publisher: Publisher,
+    subject: SubjectTemplate,
+    dictionary_subject: String,
+    encoding: Encoding,
+    encoder: Mutex<EventEncoder>,
+}
+
+impl NatsSink {
+    /// Connect to `url` and publish with `encoding`.
+    ///
+    /// Messages carry the same `schema_version`, `kind`, `exchange`,
+    /// `channel`, `encoding`, `ingest_ts_utc` and `ingest_ts_monotonic`
+    /// headers as the Kafka sink, including `dictionary_epoch` with
+    /// [`Encoding::Sbe`]. With JetStream, `publish` returns once the
+    /// server has stored the event, so the sink can sit behind a
+    /// [`Wal`](super::Wal).
+    pub async fn new(url: &str, encoding: Encoding, options: NatsOptions) -> Result<Self> {
+        let subject = SubjectTemplate::new(&options.subject)?;
+        let client = async_nats::connect(url)
+            .await
+            .with_context(|| format!("connecting to nats at {url}"))?;
+        let dictionary_subject = options
+            .dictionary_subject
+            .unwrap_or_else(|| "md.dictionary".into());
+        let publisher = if options.jetstream {
+            let context = jetstream::new(client.clone());
+            if let Some(name) = options.stream {
+                let mut subjects = vec![subject.stream_subject()];
+                if encoding == Encoding::Sbe && !matches_subject(&subjects[0], &dictionary_subject)
+                {
+                    subjects.push(dictionary_subject.clone());
+                }
+                context
+                    .get_or_create_stream(stream::Config {
+                        name: name.clone(),
+                        subjects,
+                        ..Default::default()
+                    })
+                    .await
+                    .map_err(|e| anyhow!("creating stream {name}: {e}"))?;
+            }
+            Publisher::JetStream(context)
+        } else {
+            Publisher::Core(client.clone())
+        };
+        Ok(Self {
+            client,
+            publisher,
+            subject,
+            dictionary_subject,
+            encoding,
+            encoder: Mutex::new(EventEncoder::new(encoding)),
+        })
+    }
+
+    fn headers(&self, event: &MdEvent, epoch: u64) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("schema_version", event.schema_version.to_string());
+        headers.insert("kind", event.kind());
+        headers.insert("exchange", event.exchange());
+        headers.insert("channel", event.channel().as_str());
+        headers.insert("encoding", self.encoding.as_str());
+        headers.insert("ingest_ts_utc", event.ingest_ts_utc().to_string());
+        headers.insert(
+            "ingest_ts_monotonic",
+            event.ingest_ts_monotonic().to_string(),
+        );
+        if self.encoding == Encoding::Sbe {
+            headers.insert("dictionary_epoch", epoch.to_string());
+        }
+        headers
+    }
+
+    async fn send(&self, subject: String, headers: HeaderMap, payload: Vec<u8>) -> Result<()> {
+        match &self.publisher {
+            Publisher::Core(client) => client
+                .publish_with_headers(subject, headers, payload.into())
+                .await
+                .map_err(|e| anyhow!("nats publish: {e}")),
+            Publisher::JetStream(context) => {
+                context
+                    .publish_with_headers(subject, headers, payload.into())
+                    .await
+                    .map_err(|e| anyhow!("jetstream publish: {e}"))?
+                    .await
+                    .map_err(|e| anyhow!("jetstream ack: {e}"))?;
+                Ok(())
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Sink for NatsSink {
+    async fn publish(&self, event: &MdEvent) -> Result<()> {
+        let mut payload = Vec::new();
+        let mut dictionary = Vec::new();
+        let epoch = {
+            // Held until the dictionary is sent, so no event using the new
+            // ids can overtake it.
+            let mut encoder = self.encoder.lock().await;
+            encoder.encode_event(event, &mut payload)?;
+            if encoder.take_dictionary(&mut dictionary) {
+                let subject = self.dictionary_subject.clone();
+                let mut headers = HeaderMap::new();
+                headers.insert("dictionary_epoch", encoder.epoch().to_string());
+                if let Err(e) = self.send(subject, headers, dictionary).await {
+                    // Re-announce every name, under a new epoch, rather than
+                    // leave consumers with ids they cannot resolve.
+                    encoder.reset();
+                    return Err(e);
+                }
+            }
+            encoder.epoch()
+        };
+        self.send(
+            self.subject.render(event),
+            self.headers(event, epoch),
+            payload,
+        )
+        .await
+    }
+
+    async fn flush(&self) -> Result<()> {
+        self.client
+            .flush()
+            .await
+            .map_err(|e| anyhow!("nats flush: {e}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::testing::{event, free_port, listening, scratch_dir, Server};
+    use canonical::{MdEventKind, Trade};
+    use futures::StreamExt;
+    use std::process::Command;
+    use std::time::Duration;
+
+    fn trade(symbol: &str) -> MdEvent {
+        event(MdEventKind::Trade(Trade {
+            exchange: "binance".into(),
+            symbol: symbol.into(),
+            ingest_ts_monotonic: 7,
+            ..Default::default()
+        }))
+    }
+
+    /// A `nats-server` from `PATH` with JetStream enabled.
+    async fn spawn_server() -> Server {
+        let port = free_port();
+        let dir = scratch_dir("nats_sink");
+        let mut command = Command::new("nats-server");
+        command
+            .args(["-a", "127.0.0.1", "-p", &port.to_string(), "-js", "-sd"])
+            .arg(&dir);
+        Server::spawn(command, port, dir, || listening(port)).await
+    }
+
+    fn url(server: &Server) -> String {
+        format!("nats://127.0.0.1:{}", server.port)
+    }
+
+    #[test]
+    fn renders_subjects() {
+        let template = SubjectTemplate::new(DEFAULT_SUBJECT).unwrap();
+        assert_eq!(
+            template.render(&trade("BTC.USDT")),
+            "md.binance.trade.BTC_USDT"
+        );
+        assert_eq!(template.stream_subject(), "md.>");
+        assert_eq!(
+            SubjectTemplate::new("{exchange}.x")
+                .unwrap()
+                .stream_subject(),
+            ">"
+        );
+        assert_eq!(
+            SubjectTemplate::new("md.all").unwrap().stream_subject(),
+            "md.all"
+        );
+        assert!(SubjectTemplate::new("md.{venue}").is_err());
+        assert!(matches_subject("md.>", "md.dictionary"));
+        assert!(matches_subject("md.*.trade", "md.okx.trade"));
+        assert!(!matches_subject("md.>", "md"));
+        assert!(!matches_subject("md.okx", "md.okx.trade"));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs nats-server on PATH"]
+    async fn publishes_to_core_subjects() {
+        let server = spawn_server().await;
+        let url = url(&server);
+        let client = async_nats::connect(&url).await.unwrap();
+        let mut sub = client.subscribe("md.binance.>").await.unwrap();
+        client.flush().await.unwrap();
+
+        let sink = NatsSink::new(&url, Encoding::Json, NatsOptions::default())
+            .await
+            .unwrap();
+        sink.publish(&trade("BTCUSDT")).await.unwrap();
+        sink.flush().await.unwrap();
+
+        let msg = tokio::time::timeout(Duration::from_secs(5), sub.next())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(msg.subject.as_str(), "md.binance.trade.BTCUSDT");
+        let headers = msg.headers.unwrap();
+        assert_eq!(headers.get("kind").unwrap().as_str(), "Trade");
+        assert_eq!(headers.get("ingest_ts_monotonic").unwrap().as_str(), "7");
+        let event: MdEvent = serde_json::from_slice(&msg.payload).unwrap();
+        assert_eq!(event.symbol(), "BTCUSDT");
+    }
+
+    #[tokio::test]
+    #[ignore = "needs nats-server on PATH"]
+    async fn jetstream_acknowledges_and_stores_events() {
+        let server = spawn_server().await;
+        let url = url(&server);
+        let options = NatsOptions {
+            stream: Some("MD".into()),
+            jetstream: true,
+            ..Default::default()
+        };
+        let sink = NatsSink::new(&url, Encoding::Json, options).await.unwrap();
+        for symbol in ["BTCUSDT", "ETHUSDT"] {
+            sink.publish(&trade(symbol)).await.unwrap();
+        }
+        sink.flush().await.unwrap();
+
+        let client = async_nats::connect(&url).await.unwrap();
+        let mut stream = jetstream::new(client).get_stream("MD").await.unwrap();
+        assert_eq!(stream.info().await.unwrap().state.messages, 2);
+
+        // Without a stream covering the subject the publish is not acked.
+        let options = NatsOptions {
+            subject: "other.{symbol}".into(),
+            jetstream: true,
+            ..Default::default()
+        };
+        let sink = NatsSink::new(&url, Encoding::Json, options).await.unwrap();
+        assert!(sink.publish(&trade("BTCUSDT")).await.is_err());
+    }
+}
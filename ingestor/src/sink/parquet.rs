@@ -0,0 +1,519 @@
+//! Columnar output for research datasets.
+//!
+//! Every event kind gets its own Arrow schema, and files are laid out as
+//! Hive-style partitions `exchange=<name>/kind=<kind>/date=YYYY-MM-DD/hour=HH`
+//! keyed on the ingest time, so DuckDB and Polars can prune by partition.
+//! Files are written under a hidden `.inprogress` name and renamed once their
+//! footer is written, so readers never see a partial file.
+
+use anyhow::{anyhow, Context, Result};
+use arrow_array::RecordBatch;
+use arrow_json::ReaderBuilder;
+use arrow_schema::{DataType, Field, Fields, Schema, SchemaRef};
+use async_trait::async_trait;
+use canonical::{symbol::date_from_ms, MdEvent, MdEventKind};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+use serde::{Serialize, Serializer};
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+
+use super::Sink;
+
+const NANOS_PER_HOUR: u64 = 3_600_000_000_000;
+
+/// Row-group size and compression of a [`ParquetSink`].
+#[derive(Debug, Clone, Copy)]
+pub struct ParquetOptions {
+    /// Rows buffered per partition before a row group is written.
+    pub row_group_size: usize,
+    /// Zstd compression level.
+    pub zstd_level: i32,
+}
+
+impl Default for ParquetOptions {
+    fn default() -> Self {
+        Self {
+            row_group_size: 65_536,
+            zstd_level: 3,
+        }
+    }
+}
+
+impl ParquetOptions {
+    /// Read `MD_SINK_PARQUET_ROW_GROUP_SIZE` and `MD_SINK_PARQUET_ZSTD_LEVEL`,
+    /// falling back to the defaults.
+    pub fn from_env() -> Result<Self> {
+        let mut opts = Self::default();
+        if let Ok(v) = env::var("MD_SINK_PARQUET_ROW_GROUP_SIZE") {
+            opts.row_group_size = v.trim().parse().context("MD_SINK_PARQUET_ROW_GROUP_SIZE")?;
+        }
+        if let Ok(v) = env::var("MD_SINK_PARQUET_ZSTD_LEVEL") {
+            opts.zstd_level = v.trim().parse().context("MD_SINK_PARQUET_ZSTD_LEVEL")?;
+        }
+        Ok(opts)
+    }
+}
+
+/// Sink writing partitioned Parquet files under a root directory.
+pub struct ParquetSink {
+    state: Arc<Mutex<State>>,
+}
+
+struct State {
+    root: PathBuf,
+    row_group_size: usize,
+    props: WriterProperties,
+    /// Open partitions by exchange and kind.
+    partitions: HashMap<String, HashMap<&'static str, Partition>>,
+    /// Partitions whose hour has passed, waiting to be finalized.
+    closing: Vec<Partition>,
+    files: u64,
+}
+
+/// Rows of one exchange and kind for one hour.
+struct Partition {
+    dir: PathBuf,
+    hour: u64,
+    kind: &'static str,
+    schema: SchemaRef,
+    rows: Vec<MdEvent>,
+    file: Option<OpenFile>,
+}
+
+struct OpenFile {
+    writer: ArrowWriter<File>,
+    tmp: PathBuf,
+    path: PathBuf,
+}
+
+impl ParquetSink {
+    pub fn new(root: impl AsRef<Path>, options: ParquetOptions) -> Result<Self> {
+        let root = root.as_ref().to_path_buf();
+        fs::create_dir_all(&root)?;
+        let props = WriterProperties::builder()
+            .set_compression(Compression::ZSTD(ZstdLevel::try_new(options.zstd_level)?))
+            .set_max_row_group_size(options.row_group_size.max(1))
+            .build();
+        Ok(Self {
+            state: Arc::new(Mutex::new(State {
+                root,
+                row_group_size: options.row_group_size.max(1),
+                props,
+                partitions: HashMap::new(),
+                closing: Vec::new(),
+                files: 0,
+            })),
+        })
+    }
+}
+
+#[async_trait]
+impl Sink for ParquetSink {
+    async fn publish(&self, event: &MdEvent) -> Result<()> {
+        let mut state = self.state.clone().lock_owned().await;
+        if !state.buffer(event.clone()) {
+            return Ok(());
+        }
+        tokio::task::spawn_blocking(move || state.write_due()).await?
+    }
+
+    /// Write all buffered rows and finalize every open file.
+    async fn flush(&self) -> Result<()> {
+        let mut state = self.state.clone().lock_owned().await;
+        tokio::task::spawn_blocking(move || state.finish_all()).await?
+    }
+}
+
+impl State {
+    /// Queue `ev` in its partition, returning whether file work is due.
+    fn buffer(&mut self, ev: MdEvent) -> bool {
+        let ts = match ev.ingest_ts_utc() {
+            0 => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos() as u64,
+            ts => ts,
+        };
+        let hour = ts / NANOS_PER_HOUR;
+        let kind = ev.kind();
+        if !self.partitions.contains_key(ev.exchange()) {
+            self.partitions
+                .insert(ev.exchange().to_string(), HashMap::new());
+        }
+        let by_kind = self
+            .partitions
+            .get_mut(ev.exchange())
+            .expect("partition inserted above");
+        let partition = match by_kind.get_mut(kind) {
+            Some(p) if p.hour == hour => p,
+            existing => {
+                let fresh = Partition::new(ev.exchange(), kind, hour);
+                match existing {
+                    Some(p) => {
+                        self.closing.push(std::mem::replace(p, fresh));
+                        p
+                    }
+                    None => by_kind.entry(kind).or_insert(fresh),
+                }
+            }
+        };
+        partition.rows.push(ev);
+        partition.rows.len() >= self.row_group_size || !self.closing.is_empty()
+    }
+
+    /// Finalize partitions whose hour has passed and write full row groups.
+    fn write_due(&mut self) -> Result<()> {
+        for mut partition in std::mem::take(&mut self.closing) {
+            partition.finish(&self.root, &self.props, &mut self.files)?;
+        }
+        for partition in self.partitions.values_mut().flat_map(|m| m.values_mut()) {
+            if partition.rows.len() >= self.row_group_size {
+                partition.write_rows(&self.root, &self.props, &mut self.files)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish_all(&mut self) -> Result<()> {
+        self.write_due()?;
+        for partition in self.partitions.values_mut().flat_map(|m| m.values_mut()) {
+            partition.finish(&self.root, &self.props, &mut self.files)?;
+        }
+        Ok(())
+    }
+}
+
+impl Partition {
+    fn new(exchange: &str, kind: &'static str, hour: u64) -> Self {
+        let date = date_from_ms(hour * 3_600_000);
+        let dir = PathBuf::from(format!(
+            "exchange={exchange}/kind={kind}/date={:04}-{:02}-{:02}/hour={:02}",
+            date / 10_000,
+            date / 100 % 100,
+            date % 100,
+            hour % 24,
+        ));
+        Self {
+            dir,
+            hour,
+            kind,
+            schema: schema(kind),
+            rows: Vec::new(),
+            file: None,
+        }
+    }
+
+    fn write_rows(&mut self, root: &Path, props: &WriterProperties, files: &mut u64) -> Result<()> {
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+        let batch = to_batch(&self.schema, &self.rows)
+            .with_context(|| format!("converting {} rows", self.kind))?;
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => {
+                let dir = root.join(&self.dir);
+                fs::create_dir_all(&dir)?;
+                let started = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis();
+                *files += 1;
+                let name = format!("part-{started}-{files:06}.parquet");
+                let tmp = dir.join(format!(".{name}.inprogress"));
+                let writer = ArrowWriter::try_new(
+                    File::create(&tmp)?,
+                    self.schema.clone(),
+                    Some(props.clone()),
+                )?;
+                self.file.insert(OpenFile {
+                    writer,
+                    tmp,
+                    path: dir.join(name),
+                })
+            }
+        };
+        file.writer.write(&batch)?;
+        // End the row group here so buffered rows are bounded by the
+        // configured size.
+        file.writer.flush()?;
+        self.rows.clear();
+        Ok(())
+    }
+
+    /// Write remaining rows, close the file and move it into place.
+    fn finish(&mut self, root: &Path, props: &WriterProperties, files: &mut u64) -> Result<()> {
+        self.write_rows(root, props, files)?;
+        if let Some(file) = self.file.take() {
+            file.writer.close()?;
+            fs::rename(&file.tmp, &file.path)
+                .with_context(|| format!("finalizing {}", file.path.display()))?;
+        }
+        Ok(())
+    }
+}
+
+/// Serializes the payload of an event without its kind tag.
+pub(super) struct Row<'a>(pub(super) &'a MdEvent);
+
+impl Serialize for Row<'_> {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        match &self.0.event {
+            MdEventKind::Trade(e) => e.serialize(s),
+            MdEventKind::DepthL2Update(e) => e.serialize(s),
+            MdEventKind::BookTicker(e) => e.serialize(s),
+            MdEventKind::MiniTicker(e) => e.serialize(s),
+            MdEventKind::Kline(e) => e.serialize(s),
+            MdEventKind::DepthSnapshot(e) => e.serialize(s),
+            MdEventKind::AvgPrice(e) => e.serialize(s),
+            MdEventKind::MarkPrice(e) => e.serialize(s),
+            MdEventKind::IndexPrice(e) => e.serialize(s),
+            MdEventKind::FundingRate(e) => e.serialize(s),
+            MdEventKind::OpenInterest(e) => e.serialize(s),
+            MdEventKind::Liquidation(e) => e.serialize(s),
+            MdEventKind::InstrumentUpdate(e) => e.serialize(s),
+        }
+    }
+}
+
+fn to_batch(schema: &SchemaRef, rows: &[MdEvent]) -> Result<RecordBatch> {
+    let mut decoder = ReaderBuilder::new(schema.clone())
+        .with_batch_size(rows.len())
+        .build_decoder()?;
+    let rows: Vec<Row<'_>> = rows.iter().map(Row).collect();
+    decoder.serialize(&rows)?;
+    decoder.flush()?.ok_or_else(|| anyhow!("no rows decoded"))
+}
+
+fn levels() -> DataType {
+    let level = Fields::from(vec![
+        Field::new("price", DataType::Float64, false),
+        Field::new("quantity", DataType::Float64, false),
+        Field::new("kind", DataType::Utf8, false),
+    ]);
+    DataType::List(Arc::new(Field::new("item", DataType::Struct(level), false)))
+}
+
+/// Columns of `kind`. The exchange is only carried by the partition path.
+pub(super) fn schema(kind: &str) -> SchemaRef {
+    use DataType::{Float64, UInt64, Utf8};
+    let f64_cols = |names: &[&str]| -> Vec<Field> {
+        names
+            .iter()
+            .map(|n| Field::new(*n, Float64, false))
+            .collect()
+    };
+    let mut fields = vec![
+        Field::new("schema_version", DataType::UInt32, false),
+        Field::new("symbol", Utf8, false),
+    ];
+    match kind {
+        "Trade" => {
+            fields.extend(f64_cols(&["price", "quantity"]));
+            fields.extend([
+                Field::new("trade_id", UInt64, true),
+                Field::new("buyer_order_id", UInt64, true),
+                Field::new("seller_order_id", UInt64, true),
+                Field::new("timestamp", UInt64, false),
+                Field::new("side", Utf8, true),
+            ]);
+        }
+        "DepthL2Update" => fields.extend([
+            Field::new("ts", UInt64, false),
+            Field::new("bids", levels(), false),
+            Field::new("asks", levels(), false),
+            Field::new("first_update_id", UInt64, true),
+            Field::new("final_update_id", UInt64, true),
+            Field::new("previous_final_update_id", UInt64, true),
+        ]),
+        "DepthSnapshot" => fields.extend([
+            Field::new("ts", UInt64, false),
+            Field::new("last_update_id", UInt64, false),
+            Field::new("bids", levels(), false),
+            Field::new("asks", levels(), false),
+        ]),
+        "InstrumentUpdate" => {
+            let spec = Fields::from(vec![
+                Field::new("venue", Utf8, false),
+                Field::new("base", Utf8, false),
+                Field::new("quote", Utf8, false),
+                Field::new("settle", Utf8, false),
+                Field::new("lot_step", Float64, true),
+                Field::new("price_step", Float64, true),
+                Field::new("contract_size", Float64, true),
+                Field::new("expiry", UInt64, true),
+            ]);
+            fields.extend([
+                Field::new("instrument", Utf8, false),
+                Field::new("change", Utf8, false),
+                Field::new("spec", DataType::Struct(spec), true),
+                Field::new("ts", UInt64, false),
+            ]);
+        }
+        other => {
+            fields.push(Field::new("ts", UInt64, false));
+            fields.extend(f64_cols(match other {
+                "BookTicker" => &["bid_price", "bid_quantity", "ask_price", "ask_quantity"],
+                "MiniTicker" => &["open", "high", "low", "close", "volume", "quote_volume"],
+                "Kline" => &["open", "close", "high", "low", "volume"],
+                "FundingRate" => &["rate"],
+                "OpenInterest" => &["open_interest"],
+                "Liquidation" => &["price", "quantity"],
+                // AvgPrice, MarkPrice and IndexPrice.
+                _ => &["price"],
+            }));
+        }
+    }
+    fields.extend([
+        Field::new("ingest_ts_monotonic", UInt64, false),
+        Field::new("ingest_ts_utc", UInt64, false),
+        Field::new("seq_no", UInt64, false),
+    ]);
+    Arc::new(Schema::new(fields))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use canonical::{
+        BookKind, ContractSpec, DepthL2Update, InstrumentChange, InstrumentUpdate, Level, Trade,
+    };
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    const HOUR: u64 = NANOS_PER_HOUR;
+
+    fn trade(ingest: u64) -> MdEvent {
+        MdEvent {
+            schema_version: canonical::SCHEMA_VERSION,
+            event: MdEventKind::Trade(Trade {
+                exchange: "binance".into(),
+                symbol: "BTCUSDT".into(),
+                price: 1.5,
+                quantity: 2.0,
+                ingest_ts_utc: ingest,
+                ..Default::default()
+            }),
+            validation: None,
+        }
+    }
+
+    fn depth(ingest: u64) -> MdEvent {
+        let level = |price, kind| Level {
+            schema_version: canonical::SCHEMA_VERSION,
+            price,
+            quantity: 1.0,
+            kind,
+        };
+        MdEvent {
+            schema_version: canonical::SCHEMA_VERSION,
+            event: MdEventKind::DepthL2Update(DepthL2Update {
+                exchange: "gateio".into(),
+                symbol: "BTC_USDT".into(),
+                bids: vec![level(10.0, BookKind::Bid), level(9.0, BookKind::Bid)],
+                asks: vec![level(11.0, BookKind::Ask)],
+                ingest_ts_utc: ingest,
+                ..Default::default()
+            }),
+            validation: None,
+        }
+    }
+
+    fn listing(ingest: u64) -> MdEvent {
+        MdEvent {
+            schema_version: canonical::SCHEMA_VERSION,
+            event: MdEventKind::InstrumentUpdate(InstrumentUpdate {
+                schema_version: canonical::SCHEMA_VERSION,
+                exchange: "binance".into(),
+                symbol: "BTCUSDT".into(),
+                instrument: "BTC-USDT".parse().unwrap(),
+                change: InstrumentChange::Added,
+                spec: Some(ContractSpec {
+                    base: "BTC".into(),
+                    quote: "USDT".into(),
+                    price_step: Some(0.01),
+                    ..Default::default()
+                }),
+                ts: 0,
+                ingest_ts_monotonic: 0,
+                ingest_ts_utc: ingest,
+                seq_no: 0,
+            }),
+            validation: None,
+        }
+    }
+
+    fn parquet_files(root: &Path) -> Vec<PathBuf> {
+        let mut out = Vec::new();
+        let mut stack = vec![root.to_path_buf()];
+        while let Some(dir) = stack.pop() {
+            for entry in fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    stack.push(path);
+                } else {
+                    out.push(path);
+                }
+            }
+        }
+        out.sort();
+        out
+    }
+
+    fn rows(path: &Path) -> usize {
+        ParquetRecordBatchReaderBuilder::try_new(File::open(path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap()
+            .map(|b| b.unwrap().num_rows())
+            .sum()
+    }
+
+    #[tokio::test]
+    async fn partitions_by_exchange_kind_and_hour() {
+        let root = std::env::temp_dir().join(format!("parquet_sink_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let options = ParquetOptions {
+            row_group_size: 2,
+            ..Default::default()
+        };
+        let sink = ParquetSink::new(&root, options).unwrap();
+        // 2024-06-01 13:00 UTC.
+        let start = 477_013 * HOUR;
+        for i in 0..3 {
+            sink.publish(&trade(start + i)).await.unwrap();
+        }
+        sink.publish(&depth(start)).await.unwrap();
+        sink.publish(&listing(start)).await.unwrap();
+        // The next hour finalizes the previous trade file.
+        sink.publish(&trade(start + HOUR)).await.unwrap();
+
+        let trades = root.join("exchange=binance/kind=Trade/date=2024-06-01/hour=13");
+        let done: Vec<_> = parquet_files(&trades)
+            .into_iter()
+            .filter(|p| p.extension().is_some_and(|e| e == "parquet"))
+            .collect();
+        assert_eq!(done.len(), 1);
+        assert_eq!(rows(&done[0]), 3);
+
+        sink.flush().await.unwrap();
+        let files = parquet_files(&root);
+        assert!(files
+            .iter()
+            .all(|p| p.extension().is_some_and(|e| e == "parquet")));
+        assert_eq!(files.len(), 4);
+        let depth = root.join("exchange=gateio/kind=DepthL2Update/date=2024-06-01/hour=13");
+        assert_eq!(rows(&parquet_files(&depth)[0]), 1);
+        let listings = root.join("exchange=binance/kind=InstrumentUpdate/date=2024-06-01/hour=13");
+        assert_eq!(rows(&parquet_files(&listings)[0]), 1);
+        let later = root.join("exchange=binance/kind=Trade/date=2024-06-01/hour=14");
+        assert_eq!(rows(&parquet_files(&later)[0]), 1);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,1217 @@
+//! Bounded, batched delivery of events to a sink.
+//!
+//! A [`Pipeline`] decouples publishers from a sink: publishing only queues
+//! the event, and a task drains the queue in batches through
+//! [`Sink::publish_batch`], retrying a failed batch with backoff. A slow sink
+//! therefore backs up its own queue rather than the consumers, and what
+//! happens once the queue is full is set by its [`Overflow`] policy.
+//!
+//! Spilled events are written and read back by a thread of their own, so
+//! publishers never wait on the disk. The spill is split into segment files
+//! that are deleted once read, and capped in size.
+
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use canonical::{MdEvent, MdEventKind};
+use metrics::{Counter, Gauge, Histogram};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, TryRecvError};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+use tokio::sync::{oneshot, Notify};
+use tracing::{error, warn};
+
+use super::Sink;
+
+/// What a full [`Pipeline`] does with a new event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// Wait for room, pushing back on the publisher.
+    Block,
+    /// Reject the new event.
+    DropNewest,
+    /// Drop the oldest queued event to make room.
+    DropOldest,
+    /// Append further events to a file, read back in order once the queue
+    /// has drained.
+    Spill,
+    /// Replace the queued event of the same stream for book tickers, mini
+    /// tickers and price, funding and open interest updates. Other events
+    /// wait for room.
+    Conflate,
+}
+
+impl FromStr for Overflow {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().replace('-', "_").as_str() {
+            "block" => Ok(Self::Block),
+            "drop_newest" => Ok(Self::DropNewest),
+            "drop_oldest" => Ok(Self::DropOldest),
+            "spill" => Ok(Self::Spill),
+            "conflate" => Ok(Self::Conflate),
+            other => bail!("unknown overflow policy {other}"),
+        }
+    }
+}
+
+/// Queue size, batching, overflow and retries of a [`Pipeline`].
+#[derive(Debug, Clone)]
+pub struct PipelineOptions {
+    /// Events queued before the overflow policy applies.
+    pub capacity: usize,
+    /// Most events handed to the sink at once.
+    pub batch_size: usize,
+    pub overflow: Overflow,
+    /// Directory of the spill files of [`Overflow::Spill`].
+    pub spill_dir: PathBuf,
+    /// Size at which a new spill file is started.
+    pub spill_segment_bytes: u64,
+    /// Spilled bytes not read back yet beyond which events are dropped.
+    pub spill_max_bytes: u64,
+    /// Attempts after the first before a batch is given up.
+    pub retries: u32,
+    /// Wait before the first retry, doubled for every further one up to a
+    /// second.
+    pub backoff: Duration,
+}
+
+impl Default for PipelineOptions {
+    fn default() -> Self {
+        Self {
+            capacity: 65_536,
+            batch_size: 512,
+            overflow: Overflow::Block,
+            spill_dir: PathBuf::from("md.spill"),
+            spill_segment_bytes: 64 << 20,
+            spill_max_bytes: 1 << 30,
+            retries: 3,
+            backoff: Duration::from_millis(100),
+        }
+    }
+}
+
+impl PipelineOptions {
+    /// Read `MD_SINK_QUEUE`, `MD_SINK_BATCH_SIZE`, `MD_SINK_OVERFLOW`,
+    /// `MD_SINK_SPILL_DIR`, `MD_SINK_SPILL_MAX_BYTES` and `MD_SINK_RETRIES`,
+    /// falling back to the defaults.
+    pub fn from_env() -> Result<Self> {
+        let mut opts = Self::default();
+        let var = |name: &str| env::var(name).ok().filter(|v| !v.trim().is_empty());
+        if let Some(v) = var("MD_SINK_QUEUE") {
+            opts.capacity = v.trim().parse().context("MD_SINK_QUEUE")?;
+        }
+        if let Some(v) = var("MD_SINK_BATCH_SIZE") {
+            opts.batch_size = v.trim().parse().context("MD_SINK_BATCH_SIZE")?;
+        }
+        if let Some(v) = var("MD_SINK_OVERFLOW") {
+            opts.overflow = v.parse().context("MD_SINK_OVERFLOW")?;
+        }
+        if let Some(v) = var("MD_SINK_SPILL_DIR") {
+            opts.spill_dir = PathBuf::from(v.trim());
+        }
+        if let Some(v) = var("MD_SINK_SPILL_MAX_BYTES") {
+            opts.spill_max_bytes = v.trim().parse().context("MD_SINK_SPILL_MAX_BYTES")?;
+        }
+        if let Some(v) = var("MD_SINK_RETRIES") {
+            opts.retries = v.trim().parse().context("MD_SINK_RETRIES")?;
+        }
+        Ok(opts)
+    }
+}
+
+// Nearly every entry is an event, so boxing them would only add an
+// allocation each.
+#[allow(clippy::large_enum_variant)]
+enum Entry {
+    Event(MdEvent),
+    Flush(oneshot::Sender<Result<()>>),
+}
+
+struct Queue {
+    entries: VecDeque<Entry>,
+    /// Sequence number of the front entry.
+    head: u64,
+    /// Events in `entries`, not counting flushes.
+    events: usize,
+    /// Sequence number of the newest queued event of each conflatable
+    /// stream, by [`stream_hash`]. Only kept for [`Overflow::Conflate`].
+    latest: HashMap<u64, u64>,
+    /// Events spilled and not read back yet. While there are any, new events
+    /// are spilled too so they stay in order.
+    spilled: u64,
+    closed: bool,
+}
+
+impl Queue {
+    fn push(&mut self, ev: MdEvent, conflate: bool) {
+        if conflate && conflatable(&ev) {
+            let seq = self.head + self.entries.len() as u64;
+            self.latest.insert(stream_hash(&ev), seq);
+        }
+        self.entries.push_back(Entry::Event(ev));
+        self.events += 1;
+    }
+
+    fn pop(&mut self) -> Option<Entry> {
+        let entry = self.entries.pop_front()?;
+        if let Entry::Event(ev) = &entry {
+            self.events -= 1;
+            if !self.latest.is_empty() && conflatable(ev) {
+                let hash = stream_hash(ev);
+                if self.latest.get(&hash) == Some(&self.head) {
+                    self.latest.remove(&hash);
+                }
+            }
+        }
+        self.head += 1;
+        Some(entry)
+    }
+
+    /// Replace the queued event of `ev`'s stream, returning `false` when
+    /// there is none.
+    fn conflate(&mut self, ev: &MdEvent) -> bool {
+        if !conflatable(ev) {
+            return false;
+        }
+        let Some(&seq) = self.latest.get(&stream_hash(ev)) else {
+            return false;
+        };
+        match self.entries.get_mut((seq - self.head) as usize) {
+            Some(Entry::Event(queued)) if same_stream(queued, ev) => {
+                *queued = ev.clone();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Drop the oldest queued event.
+    fn drop_oldest(&mut self) -> bool {
+        match self
+            .entries
+            .iter()
+            .position(|e| matches!(e, Entry::Event(_)))
+        {
+            Some(i) => {
+                self.entries.remove(i);
+                self.events -= 1;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Whether a newer event of the same stream makes `ev` obsolete.
+fn conflatable(ev: &MdEvent) -> bool {
+    matches!(
+        ev.event,
+        MdEventKind::BookTicker(_)
+            | MdEventKind::MiniTicker(_)
+            | MdEventKind::AvgPrice(_)
+            | MdEventKind::MarkPrice(_)
+            | MdEventKind::IndexPrice(_)
+            | MdEventKind::FundingRate(_)
+            | MdEventKind::OpenInterest(_)
+    )
+}
+
+fn same_stream(a: &MdEvent, b: &MdEvent) -> bool {
+    a.kind() == b.kind() && a.exchange() == b.exchange() && a.symbol() == b.symbol()
+}
+
+fn stream_hash(ev: &MdEvent) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    (ev.kind(), ev.exchange(), ev.symbol()).hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Metric handles, labelled with the pipeline's name.
+struct Metrics {
+    events: Counter,
+    errors: Counter,
+    dropped: Counter,
+    conflated: Counter,
+    spilled: Counter,
+    depth: Gauge,
+    batch: Histogram,
+}
+
+impl Metrics {
+    fn new(names: MetricNames, name: &str, enabled: bool) -> Self {
+        if !enabled {
+            return Self {
+                events: Counter::noop(),
+                errors: Counter::noop(),
+                dropped: Counter::noop(),
+                conflated: Counter::noop(),
+                spilled: Counter::noop(),
+                depth: Gauge::noop(),
+                batch: Histogram::noop(),
+            };
+        }
+        let (prefix, label) = (names.prefix, names.label);
+        let counter = |what: &str| metrics::counter!(format!("{prefix}_{what}_total"), label => name.to_string());
+        Self {
+            events: counter("events"),
+            errors: counter("errors"),
+            dropped: counter("dropped"),
+            conflated: counter("conflated"),
+            spilled: counter("spilled"),
+            depth: metrics::gauge!(format!("{prefix}_queue_depth"), label => name.to_string()),
+            batch: metrics::histogram!(format!("{prefix}_batch_size"), label => name.to_string()),
+        }
+    }
+}
+
+/// Prefix and label of the metrics a [`Pipeline`] reports, e.g. `md_route`
+/// and `route` for `md_route_events_total{route}`.
+#[derive(Debug, Clone, Copy)]
+pub struct MetricNames {
+    pub prefix: &'static str,
+    pub label: &'static str,
+}
+
+struct Shared {
+    name: String,
+    options: PipelineOptions,
+    queue: Mutex<Queue>,
+    /// Signalled when entries are queued or the pipeline closes.
+    data: Notify,
+    /// Signalled when queued events are taken.
+    space: Notify,
+    /// Events dropped or not delivered since the last flush.
+    failed: AtomicU64,
+    /// The spill thread, started on first use.
+    spiller: OnceLock<mpsc::Sender<SpillOp>>,
+    /// Spilled bytes not read back yet.
+    spill_bytes: Arc<AtomicU64>,
+    metrics: Metrics,
+}
+
+impl Shared {
+    fn spiller(&self) -> &mpsc::Sender<SpillOp> {
+        self.spiller
+            .get_or_init(|| Spiller::new(self, Vec::new()).spawn())
+    }
+
+    /// Hand `ev` to the spill thread, or reject it once the spill is full.
+    fn spill(&self, queue: &mut Queue, ev: &MdEvent) -> Result<Offer> {
+        let mut line = serde_json::to_vec(ev)?;
+        line.push(b'\n');
+        let len = line.len() as u64;
+        if self.spill_bytes.load(Ordering::Relaxed) + len > self.options.spill_max_bytes {
+            self.failed.fetch_add(1, Ordering::Relaxed);
+            self.metrics.dropped.increment(1);
+            return Ok(Offer::Rejected);
+        }
+        self.spill_bytes.fetch_add(len, Ordering::Relaxed);
+        if self.spiller().send(SpillOp::Write(line)).is_err() {
+            self.spill_bytes.fetch_sub(len, Ordering::Relaxed);
+            bail!("{} spill thread stopped", self.name);
+        }
+        queue.spilled += 1;
+        self.metrics.spilled.increment(1);
+        Ok(Offer::Queued)
+    }
+
+    /// Read back the next `count` spilled events.
+    async fn read_spill(&self, count: u64) -> Result<Vec<MdEvent>> {
+        let (reply, rx) = oneshot::channel();
+        let stopped = || anyhow!("{} spill thread stopped", self.name);
+        self.spiller()
+            .send(SpillOp::Read(count, reply))
+            .map_err(|_| stopped())?;
+        rx.await.map_err(|_| stopped())?
+    }
+}
+
+enum SpillOp {
+    /// Append a JSON line.
+    Write(Vec<u8>),
+    /// Read back up to this many events, oldest first.
+    Read(u64, oneshot::Sender<Result<Vec<MdEvent>>>),
+    /// Delete everything spilled so far.
+    Reset,
+}
+
+/// Spill file, named `{pipeline}.{seq}.spill`.
+struct Segment {
+    path: PathBuf,
+    len: u64,
+    /// Bytes read back so far.
+    read: u64,
+}
+
+/// State of the spill thread. Events are appended to the newest segment and
+/// read from the oldest, which is deleted once read to the end.
+struct Spiller {
+    dir: PathBuf,
+    /// File name prefix, the pipeline's name made file name safe.
+    prefix: String,
+    segment_bytes: u64,
+    bytes: Arc<AtomicU64>,
+    segments: VecDeque<Segment>,
+    next_seq: u64,
+    /// Appends to the last segment.
+    writer: Option<BufWriter<fs::File>>,
+    /// Reads the first segment.
+    reader: Option<BufReader<fs::File>>,
+}
+
+impl Spiller {
+    fn new(shared: &Shared, segments: Vec<Segment>) -> Self {
+        let prefix = spill_prefix(&shared.name);
+        let next_seq = segments
+            .last()
+            .and_then(|seg| segment_seq(&seg.path, &prefix))
+            .map_or(0, |seq| seq + 1);
+        Self {
+            dir: shared.options.spill_dir.clone(),
+            prefix,
+            segment_bytes: shared.options.spill_segment_bytes,
+            bytes: shared.spill_bytes.clone(),
+            segments: segments.into(),
+            next_seq,
+            writer: None,
+            reader: None,
+        }
+    }
+
+    fn spawn(self) -> mpsc::Sender<SpillOp> {
+        let (tx, rx) = mpsc::channel();
+        thread::Builder::new()
+            .name(format!("spill-{}", self.prefix))
+            .spawn(move || self.run(rx))
+            .expect("failed to start spill thread");
+        tx
+    }
+
+    /// Serve requests until the pipeline is gone, flushing whenever none
+    /// are waiting.
+    fn run(mut self, ops: mpsc::Receiver<SpillOp>) {
+        loop {
+            let op = match ops.try_recv() {
+                Ok(op) => op,
+                Err(TryRecvError::Empty) => {
+                    self.flush();
+                    match ops.recv() {
+                        Ok(op) => op,
+                        Err(_) => break,
+                    }
+                }
+                Err(TryRecvError::Disconnected) => break,
+            };
+            match op {
+                SpillOp::Write(line) => {
+                    if let Err(e) = self.write(&line) {
+                        // Counted as lost once reading comes up short.
+                        error!(error = %e, dir = %self.dir.display(), "failed to write spill file");
+                        self.bytes.fetch_sub(line.len() as u64, Ordering::Relaxed);
+                    }
+                }
+                SpillOp::Read(count, reply) => {
+                    let _ = reply.send(self.read(count));
+                }
+                SpillOp::Reset => self.reset(),
+            }
+        }
+        self.flush();
+    }
+
+    fn flush(&mut self) {
+        if let Some(writer) = &mut self.writer {
+            if let Err(e) = writer.flush() {
+                error!(error = %e, dir = %self.dir.display(), "failed to write spill file");
+            }
+        }
+    }
+
+    fn write(&mut self, line: &[u8]) -> Result<()> {
+        let len = line.len() as u64;
+        let fits = self
+            .segments
+            .back()
+            .is_some_and(|seg| seg.len == 0 || seg.len + len <= self.segment_bytes);
+        let writer = match &mut self.writer {
+            Some(writer) if fits => writer,
+            _ => {
+                if let Some(mut writer) = self.writer.take() {
+                    writer.flush()?;
+                }
+                fs::create_dir_all(&self.dir)
+                    .with_context(|| format!("creating {}", self.dir.display()))?;
+                let path = self
+                    .dir
+                    .join(format!("{}.{}.spill", self.prefix, self.next_seq));
+                let file = fs::File::create(&path)
+                    .with_context(|| format!("creating {}", path.display()))?;
+                self.next_seq += 1;
+                self.segments.push_back(Segment {
+                    path,
+                    len: 0,
+                    read: 0,
+                });
+                self.writer.insert(BufWriter::new(file))
+            }
+        };
+        writer.write_all(line)?;
+        if let Some(seg) = self.segments.back_mut() {
+            seg.len += len;
+        }
+        Ok(())
+    }
+
+    /// Read up to `count` events. Fewer come back if writes failed.
+    fn read(&mut self, count: u64) -> Result<Vec<MdEvent>> {
+        if let Some(writer) = &mut self.writer {
+            writer.flush()?;
+        }
+        let mut events = Vec::new();
+        let mut line = String::new();
+        while (events.len() as u64) < count {
+            self.remove_read();
+            let Some(seg) = self.segments.front_mut() else {
+                break;
+            };
+            let reader = match &mut self.reader {
+                Some(reader) => reader,
+                None => {
+                    let file = fs::File::open(&seg.path)
+                        .with_context(|| format!("opening {}", seg.path.display()))?;
+                    self.reader.insert(BufReader::new(file))
+                }
+            };
+            line.clear();
+            let n = reader.read_line(&mut line)? as u64;
+            if n == 0 {
+                bail!("{} ended early", seg.path.display());
+            }
+            seg.read += n;
+            self.bytes.fetch_sub(n, Ordering::Relaxed);
+            events.push(serde_json::from_str(&line)?);
+        }
+        self.remove_read();
+        Ok(events)
+    }
+
+    /// Delete the leading segments read to the end.
+    fn remove_read(&mut self) {
+        while let Some(seg) = self.segments.front() {
+            if seg.read < seg.len {
+                break;
+            }
+            self.reader = None;
+            if self.segments.len() == 1 {
+                self.writer = None;
+            }
+            if let Err(e) = fs::remove_file(&seg.path) {
+                warn!(error = %e, path = %seg.path.display(), "failed to delete spill file");
+            }
+            self.segments.pop_front();
+        }
+    }
+
+    fn reset(&mut self) {
+        self.reader = None;
+        self.writer = None;
+        for seg in self.segments.drain(..) {
+            self.bytes.fetch_sub(seg.len - seg.read, Ordering::Relaxed);
+            let _ = fs::remove_file(&seg.path);
+        }
+    }
+}
+
+/// The pipeline's name made safe to use in file names.
+fn spill_prefix(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn segment_seq(path: &std::path::Path, prefix: &str) -> Option<u64> {
+    path.file_name()?
+        .to_str()?
+        .strip_prefix(prefix)?
+        .strip_prefix('.')?
+        .strip_suffix(".spill")?
+        .parse()
+        .ok()
+}
+
+/// Spill files left by a previous run, oldest first, and the events in them.
+fn leftover_segments(shared: &Shared) -> Result<(Vec<Segment>, u64)> {
+    let dir = &shared.options.spill_dir;
+    let prefix = spill_prefix(&shared.name);
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok((Vec::new(), 0)),
+        Err(e) => return Err(e).with_context(|| format!("reading {}", dir.display())),
+    };
+    let mut found = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if let Some(seq) = segment_seq(&path, &prefix) {
+            found.push((seq, path));
+        }
+    }
+    found.sort();
+    let mut segments = Vec::new();
+    let mut events = 0;
+    for (_, path) in found {
+        let file = fs::File::open(&path).with_context(|| format!("opening {}", path.display()))?;
+        let len = file.metadata()?.len();
+        events += BufReader::new(file).lines().count() as u64;
+        shared.spill_bytes.fetch_add(len, Ordering::Relaxed);
+        segments.push(Segment { path, len, read: 0 });
+    }
+    Ok((segments, events))
+}
+
+/// Sink queueing events for another sink, see the [module docs](self).
+pub struct Pipeline {
+    shared: Arc<Shared>,
+}
+
+impl Pipeline {
+    /// Start draining into `sink`. `name` labels metrics and logs and names
+    /// the spill file; events spilled before a restart are delivered first.
+    pub fn new(
+        name: impl Into<String>,
+        sink: Arc<dyn Sink>,
+        options: PipelineOptions,
+        metric_names: MetricNames,
+        metrics_enabled: bool,
+    ) -> Self {
+        let name = name.into();
+        let metrics = Metrics::new(metric_names, &name, metrics_enabled);
+        let shared = Arc::new(Shared {
+            name,
+            options,
+            queue: Mutex::new(Queue {
+                entries: VecDeque::new(),
+                head: 0,
+                events: 0,
+                latest: HashMap::new(),
+                spilled: 0,
+                closed: false,
+            }),
+            data: Notify::new(),
+            space: Notify::new(),
+            failed: AtomicU64::new(0),
+            spiller: OnceLock::new(),
+            spill_bytes: Arc::new(AtomicU64::new(0)),
+            metrics,
+        });
+        match leftover_segments(&shared) {
+            Ok((_, 0)) => {}
+            Ok((segments, left)) => {
+                warn!(sink = %shared.name, events = left, "delivering events spilled before restart");
+                shared
+                    .queue
+                    .lock()
+                    .expect("pipeline mutex poisoned")
+                    .spilled = left;
+                let _ = shared.spiller.set(Spiller::new(&shared, segments).spawn());
+            }
+            Err(e) => warn!(error = %e, sink = %shared.name, "failed to read spill files"),
+        }
+        tokio::spawn(run(shared.clone(), sink));
+        Self { shared }
+    }
+}
+
+impl Drop for Pipeline {
+    fn drop(&mut self) {
+        self.shared
+            .queue
+            .lock()
+            .expect("pipeline mutex poisoned")
+            .closed = true;
+        self.shared.data.notify_one();
+    }
+}
+
+/// What became of an event offered to a [`Queue`].
+enum Offer {
+    Queued,
+    Rejected,
+    /// No room; the publisher waits for some.
+    Full,
+}
+
+impl Shared {
+    /// Queue `event` or apply the overflow policy to it.
+    fn offer(&self, queue: &mut Queue, event: &MdEvent) -> Result<Offer> {
+        let options = &self.options;
+        let full = queue.events >= options.capacity.max(1);
+        if queue.spilled > 0 || (full && options.overflow == Overflow::Spill) {
+            return self.spill(queue, event);
+        }
+        match options.overflow {
+            _ if !full => {}
+            Overflow::DropNewest => {
+                self.failed.fetch_add(1, Ordering::Relaxed);
+                self.metrics.dropped.increment(1);
+                return Ok(Offer::Rejected);
+            }
+            Overflow::DropOldest => {
+                if queue.drop_oldest() {
+                    self.failed.fetch_add(1, Ordering::Relaxed);
+                    self.metrics.dropped.increment(1);
+                }
+            }
+            Overflow::Conflate if queue.conflate(event) => {
+                self.metrics.conflated.increment(1);
+                return Ok(Offer::Queued);
+            }
+            Overflow::Conflate | Overflow::Block | Overflow::Spill => return Ok(Offer::Full),
+        }
+        queue.push(event.clone(), options.overflow == Overflow::Conflate);
+        Ok(Offer::Queued)
+    }
+}
+
+#[async_trait]
+impl Sink for Pipeline {
+    async fn publish(&self, event: &MdEvent) -> Result<()> {
+        self.publish_batch(std::slice::from_ref(event)).await
+    }
+
+    /// Queue the events in order. Only [`Overflow::Block`] and
+    /// [`Overflow::Conflate`] wait when the queue is full. Events that
+    /// [`Overflow::DropNewest`] rejects, or that [`Overflow::Spill`] rejects
+    /// once `spill_max_bytes` is reached, make it fail after the rest are
+    /// queued. It also fails if an event cannot be spilled at all.
+    async fn publish_batch(&self, events: &[MdEvent]) -> Result<()> {
+        let shared = &*self.shared;
+        let mut rest = events;
+        let mut rejected = 0;
+        while !rest.is_empty() {
+            let space = shared.space.notified();
+            tokio::pin!(space);
+            space.as_mut().enable();
+            {
+                let mut queue = shared.queue.lock().expect("pipeline mutex poisoned");
+                if queue.closed {
+                    bail!("{} pipeline stopped", shared.name);
+                }
+                let before = rest.len();
+                while let Some((event, tail)) = rest.split_first() {
+                    match shared.offer(&mut queue, event)? {
+                        Offer::Queued => {}
+                        Offer::Rejected => rejected += 1,
+                        Offer::Full => break,
+                    }
+                    rest = tail;
+                }
+                if rest.len() < before {
+                    shared.metrics.depth.set(queue.events as f64);
+                    shared.data.notify_one();
+                }
+                if rest.is_empty() {
+                    break;
+                }
+            }
+            space.await;
+        }
+        match rejected {
+            0 => Ok(()),
+            1 => bail!("{} queue is full", shared.name),
+            n => bail!("{} queue is full, {n} events rejected", shared.name),
+        }
+    }
+
+    /// Wait for everything queued so far to be delivered and flush the sink.
+    /// Fails if events were dropped or not delivered since the last flush.
+    async fn flush(&self) -> Result<()> {
+        let (done, rx) = oneshot::channel();
+        {
+            let mut queue = self.shared.queue.lock().expect("pipeline mutex poisoned");
+            if queue.closed {
+                bail!("{} pipeline stopped", self.shared.name);
+            }
+            queue.entries.push_back(Entry::Flush(done));
+        }
+        self.shared.data.notify_one();
+        let res = rx
+            .await
+            .unwrap_or_else(|_| Err(anyhow!("{} pipeline stopped", self.shared.name)));
+        let failed = self.shared.failed.swap(0, Ordering::Relaxed);
+        match (res, failed) {
+            (res, 0) => res,
+            (Ok(()), failed) => Err(anyhow!("{failed} events not delivered")),
+            (Err(e), failed) => Err(anyhow!("{failed} events not delivered; {e}")),
+        }
+    }
+}
+
+/// Deliver queued and spilled events in order until the pipeline is dropped
+/// and everything is delivered.
+async fn run(shared: Arc<Shared>, sink: Arc<dyn Sink>) {
+    let options = &shared.options;
+    let batch_size = options.batch_size.max(1);
+    // Flushes queued behind spilled events, answered once those are
+    // delivered.
+    let mut deferred = Vec::new();
+    loop {
+        let mut batch = Vec::new();
+        let mut flush = None;
+        let mut from_spill = 0;
+        let idle = {
+            let mut queue = shared.queue.lock().expect("pipeline mutex poisoned");
+            while batch.len() < batch_size {
+                match queue.entries.front() {
+                    Some(Entry::Flush(_)) if !batch.is_empty() => break,
+                    None => break,
+                    _ => {}
+                }
+                match queue.pop() {
+                    Some(Entry::Event(ev)) => batch.push(ev),
+                    Some(Entry::Flush(done)) if queue.spilled > 0 => deferred.push(done),
+                    Some(Entry::Flush(done)) => {
+                        flush = Some(done);
+                        break;
+                    }
+                    None => break,
+                }
+            }
+            if batch.is_empty() && flush.is_none() && queue.spilled > 0 {
+                from_spill = queue.spilled.min(batch_size as u64);
+            }
+            let idle = batch.is_empty() && flush.is_none() && from_spill == 0;
+            if idle && queue.closed {
+                return;
+            }
+            shared.metrics.depth.set(queue.events as f64);
+            idle
+        };
+        if idle {
+            shared.data.notified().await;
+            continue;
+        }
+        shared.space.notify_waiters();
+
+        let mut spill_drained = false;
+        if from_spill > 0 {
+            let read = shared.read_spill(from_spill).await;
+            let mut queue = shared.queue.lock().expect("pipeline mutex poisoned");
+            match read {
+                Ok(events) => {
+                    let lost = from_spill - events.len() as u64;
+                    if lost > 0 {
+                        error!(sink = %shared.name, events = lost, "spilled events lost");
+                        shared.failed.fetch_add(lost, Ordering::Relaxed);
+                    }
+                    batch = events;
+                    queue.spilled -= from_spill;
+                }
+                Err(e) => {
+                    // Events that cannot be read back are lost; start over
+                    // rather than retry forever.
+                    error!(error = %e, sink = %shared.name, "failed to read spill file");
+                    shared.failed.fetch_add(queue.spilled, Ordering::Relaxed);
+                    queue.spilled = 0;
+                    let _ = shared.spiller().send(SpillOp::Reset);
+                }
+            }
+            spill_drained = queue.spilled == 0;
+        }
+
+        if !batch.is_empty() {
+            deliver(&shared, sink.as_ref(), &batch).await;
+        }
+        if let Some(done) = flush {
+            let _ = done.send(sink.flush().await);
+        }
+        if spill_drained {
+            for done in deferred.drain(..) {
+                let _ = done.send(sink.flush().await);
+            }
+        }
+    }
+}
+
+/// Publish `batch`, retrying with backoff, and count it as failed if every
+/// attempt fails.
+async fn deliver(shared: &Shared, sink: &dyn Sink, batch: &[MdEvent]) {
+    let options = &shared.options;
+    shared.metrics.batch.record(batch.len() as f64);
+    let mut delay = options.backoff;
+    let mut attempt = 0;
+    loop {
+        match sink.publish_batch(batch).await {
+            Ok(()) => {
+                shared.metrics.events.increment(batch.len() as u64);
+                return;
+            }
+            Err(e) if attempt < options.retries => {
+                attempt += 1;
+                warn!(error = %e, sink = %shared.name, events = batch.len(), attempt, "retrying batch");
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(Duration::from_secs(1));
+            }
+            Err(e) => {
+                shared
+                    .failed
+                    .fetch_add(batch.len() as u64, Ordering::Relaxed);
+                shared.metrics.errors.increment(batch.len() as u64);
+                error!(error = %e, sink = %shared.name, events = batch.len(), "failed to deliver batch");
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use canonical::{BookTicker, Trade};
+    use std::sync::atomic::AtomicUsize;
+    use tokio::sync::Semaphore;
+
+    /// Records the batches it is given. Delivery waits for a permit of
+    /// `gate`, which has none until the test opens it.
+    struct Capture {
+        batches: Mutex<Vec<Vec<MdEvent>>>,
+        started: AtomicUsize,
+        gate: Semaphore,
+    }
+
+    impl Capture {
+        fn new() -> Arc<Self> {
+            Arc::new(Self {
+                batches: Mutex::new(Vec::new()),
+                started: AtomicUsize::new(0),
+                gate: Semaphore::new(0),
+            })
+        }
+
+        fn open(&self) {
+            self.gate.add_permits(1);
+        }
+
+        /// Wait until the pipeline hands over its first batch.
+        async fn wait_started(&self) {
+            while self.started.load(Ordering::SeqCst) == 0 {
+                tokio::task::yield_now().await;
+            }
+        }
+
+        fn seen(&self) -> Vec<MdEvent> {
+            self.batches.lock().unwrap().concat()
+        }
+    }
+
+    #[async_trait]
+    impl Sink for Capture {
+        async fn publish(&self, event: &MdEvent) -> Result<()> {
+            self.publish_batch(std::slice::from_ref(event)).await
+        }
+
+        async fn publish_batch(&self, events: &[MdEvent]) -> Result<()> {
+            self.started.fetch_add(1, Ordering::SeqCst);
+            let _permit = self.gate.acquire().await?;
+            self.batches.lock().unwrap().push(events.to_vec());
+            Ok(())
+        }
+
+        async fn flush(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn trade(id: u64) -> MdEvent {
+        MdEvent {
+            schema_version: canonical::SCHEMA_VERSION,
+            event: MdEventKind::Trade(Trade {
+                exchange: "binance".into(),
+                symbol: "BTCUSDT".into(),
+                trade_id: Some(id),
+                ..Default::default()
+            }),
+            validation: None,
+        }
+    }
+
+    fn book(symbol: &str, bid: f64) -> MdEvent {
+        MdEvent {
+            schema_version: canonical::SCHEMA_VERSION,
+            event: MdEventKind::BookTicker(BookTicker {
+                exchange: "binance".into(),
+                symbol: symbol.into(),
+                bid_price: bid,
+                ..Default::default()
+            }),
+            validation: None,
+        }
+    }
+
+    fn trade_ids(events: &[MdEvent]) -> Vec<u64> {
+        events
+            .iter()
+            .filter_map(|ev| match &ev.event {
+                MdEventKind::Trade(t) => t.trade_id,
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn pipeline(sink: Arc<Capture>, capacity: usize, overflow: Overflow) -> Pipeline {
+        let options = PipelineOptions {
+            capacity,
+            overflow,
+            ..Default::default()
+        };
+        let names = MetricNames {
+            prefix: "md_test",
+            label: "test",
+        };
+        Pipeline::new("test", sink, options, names, false)
+    }
+
+    #[tokio::test]
+    async fn delivers_queued_events_in_batches() {
+        let sink = Capture::new();
+        let pipeline = pipeline(sink.clone(), 16, Overflow::Block);
+        pipeline.publish(&trade(0)).await.unwrap();
+        sink.wait_started().await;
+        for id in 1..6 {
+            pipeline.publish(&trade(id)).await.unwrap();
+        }
+        sink.open();
+        pipeline.flush().await.unwrap();
+        let batches = sink.batches.lock().unwrap().clone();
+        assert_eq!(batches.len(), 2);
+        assert_eq!(trade_ids(&batches[1]), [1, 2, 3, 4, 5]);
+    }
+
+    #[tokio::test]
+    async fn drops_oldest_or_newest_when_full() {
+        let sink = Capture::new();
+        let pipeline = pipeline(sink.clone(), 2, Overflow::DropOldest);
+        pipeline.publish(&trade(0)).await.unwrap();
+        sink.wait_started().await;
+        for id in 1..5 {
+            pipeline.publish(&trade(id)).await.unwrap();
+        }
+        sink.open();
+        let err = pipeline.flush().await.unwrap_err();
+        assert_eq!(err.to_string(), "2 events not delivered");
+        assert_eq!(trade_ids(&sink.seen()), [0, 3, 4]);
+        pipeline.flush().await.unwrap();
+
+        let sink = Capture::new();
+        let pipeline = self::pipeline(sink.clone(), 1, Overflow::DropNewest);
+        pipeline.publish(&trade(0)).await.unwrap();
+        sink.wait_started().await;
+        pipeline.publish(&trade(1)).await.unwrap();
+        assert!(pipeline.publish(&trade(2)).await.is_err());
+        sink.open();
+        assert!(pipeline.flush().await.is_err());
+        assert_eq!(trade_ids(&sink.seen()), [0, 1]);
+    }
+
+    #[tokio::test]
+    async fn batch_keeps_what_fits_when_dropping_newest() {
+        let sink = Capture::new();
+        let pipeline = pipeline(sink.clone(), 2, Overflow::DropNewest);
+        pipeline.publish(&trade(0)).await.unwrap();
+        sink.wait_started().await;
+        let batch: Vec<_> = (1..5).map(trade).collect();
+        let err = pipeline.publish_batch(&batch).await.unwrap_err();
+        assert_eq!(err.to_string(), "test queue is full, 2 events rejected");
+        sink.open();
+        assert!(pipeline.flush().await.is_err());
+        assert_eq!(trade_ids(&sink.seen()), [0, 1, 2]);
+    }
+
+    #[tokio::test]
+    async fn conflates_tickers_and_blocks_trades() {
+        let sink = Capture::new();
+        let pipeline = Arc::new(pipeline(sink.clone(), 2, Overflow::Conflate));
+        pipeline.publish(&trade(0)).await.unwrap();
+        sink.wait_started().await;
+        pipeline.publish(&book("BTCUSDT", 1.0)).await.unwrap();
+        pipeline.publish(&book("ETHUSDT", 2.0)).await.unwrap();
+        pipeline.publish(&book("BTCUSDT", 3.0)).await.unwrap();
+
+        // A trade is never conflated, so it waits for room.
+        let blocked = tokio::spawn({
+            let pipeline = pipeline.clone();
+            async move { pipeline.publish(&trade(1)).await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!blocked.is_finished());
+
+        sink.open();
+        blocked.await.unwrap().unwrap();
+        pipeline.flush().await.unwrap();
+        let bids: Vec<_> = sink
+            .seen()
+            .iter()
+            .filter_map(|ev| match &ev.event {
+                MdEventKind::BookTicker(b) => Some((b.symbol.clone(), b.bid_price)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(bids, [("BTCUSDT".into(), 3.0), ("ETHUSDT".into(), 2.0)]);
+        assert_eq!(trade_ids(&sink.seen()), [0, 1]);
+    }
+
+    fn spill_files(dir: &std::path::Path) -> usize {
+        fs::read_dir(dir)
+            .map(|entries| {
+                entries
+                    .filter(|e| {
+                        e.as_ref()
+                            .is_ok_and(|e| e.path().extension().is_some_and(|x| x == "spill"))
+                    })
+                    .count()
+            })
+            .unwrap_or(0)
+    }
+
+    #[tokio::test]
+    async fn spills_to_disk_and_replays_in_order() {
+        let dir = env::temp_dir().join(format!("md_pipeline_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let options = PipelineOptions {
+            capacity: 1,
+            overflow: Overflow::Spill,
+            spill_dir: dir.clone(),
+            ..Default::default()
+        };
+        let names = MetricNames {
+            prefix: "md_test",
+            label: "test",
+        };
+
+        let sink = Capture::new();
+        let pipeline = Pipeline::new("spill/test", sink.clone(), options.clone(), names, false);
+        pipeline.publish(&trade(0)).await.unwrap();
+        sink.wait_started().await;
+        for id in 1..6 {
+            pipeline.publish(&trade(id)).await.unwrap();
+        }
+        sink.open();
+        pipeline.flush().await.unwrap();
+        assert_eq!(trade_ids(&sink.seen()), [0, 1, 2, 3, 4, 5]);
+        assert_eq!(spill_files(&dir), 0);
+
+        // Events left by a previous run go first.
+        for (seq, id) in [(3, 6), (4, 7)] {
+            let mut line = serde_json::to_vec(&trade(id)).unwrap();
+            line.push(b'\n');
+            fs::write(dir.join(format!("spill_test.{seq}.spill")), line).unwrap();
+        }
+        let sink = Capture::new();
+        sink.open();
+        let pipeline = Pipeline::new("spill/test", sink.clone(), options, names, false);
+        pipeline.publish(&trade(8)).await.unwrap();
+        pipeline.flush().await.unwrap();
+        assert_eq!(trade_ids(&sink.seen()), [6, 7, 8]);
+        assert_eq!(spill_files(&dir), 0);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn spill_rotates_files_and_drops_beyond_cap() {
+        let dir = env::temp_dir().join(format!("md_pipeline_cap_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let line = serde_json::to_vec(&trade(1)).unwrap().len() as u64 + 1;
+        let options = PipelineOptions {
+            capacity: 1,
+            overflow: Overflow::Spill,
+            spill_dir: dir.clone(),
+            spill_segment_bytes: 2 * line,
+            spill_max_bytes: 5 * line,
+            ..Default::default()
+        };
+        let names = MetricNames {
+            prefix: "md_test",
+            label: "test",
+        };
+
+        let sink = Capture::new();
+        let pipeline = Pipeline::new("cap", sink.clone(), options, names, false);
+        pipeline.publish(&trade(0)).await.unwrap();
+        sink.wait_started().await;
+        // 1 is queued and 2 to 6 spilled, two to a file.
+        for id in 1..7 {
+            pipeline.publish(&trade(id)).await.unwrap();
+        }
+        assert!(pipeline.publish(&trade(7)).await.is_err());
+        for _ in 0..100 {
+            if spill_files(&dir) == 3 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(spill_files(&dir), 3);
+
+        sink.open();
+        let err = pipeline.flush().await.unwrap_err();
+        assert_eq!(err.to_string(), "1 events not delivered");
+        assert_eq!(trade_ids(&sink.seen()), [0, 1, 2, 3, 4, 5, 6]);
+        assert_eq!(spill_files(&dir), 0);
+
+        // Room again once read back.
+        pipeline.publish(&trade(8)).await.unwrap();
+        pipeline.flush().await.unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn spill_rejects_batch_events_beyond_cap() {
+        let dir = env::temp_dir().join(format!("md_pipeline_batch_cap_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let line = serde_json::to_vec(&trade(1)).unwrap().len() as u64 + 1;
+        let options = PipelineOptions {
+            capacity: 1,
+            overflow: Overflow::Spill,
+            spill_dir: dir.clone(),
+            spill_max_bytes: 2 * line,
+            ..Default::default()
+        };
+        let names = MetricNames {
+            prefix: "md_test",
+            label: "test",
+        };
+
+        let sink = Capture::new();
+        let pipeline = Pipeline::new("batch_cap", sink.clone(), options, names, false);
+        pipeline.publish(&trade(0)).await.unwrap();
+        sink.wait_started().await;
+        // 1 is queued, 2 and 3 spilled, and 4 and 5 rejected.
+        let events: Vec<_> = (1..6).map(trade).collect();
+        let err = pipeline.publish_batch(&events).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "batch_cap queue is full, 2 events rejected"
+        );
+
+        sink.open();
+        let err = pipeline.flush().await.unwrap_err();
+        assert_eq!(err.to_string(), "2 events not delivered");
+        assert_eq!(trade_ids(&sink.seen()), [0, 1, 2, 3]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,529 @@
+//! PostgreSQL tables of events, one per kind, optionally TimescaleDB
+//! hypertables.
+//!
+//! Batches are written with binary `COPY`, one transaction per kind, into
+//! tables with the columns of [`table`](super::table) that are created on
+//! first use. When the TimescaleDB extension is installed they are turned
+//! into hypertables chunked by day of ingest time. `Kline` bars are updated
+//! until they close, so their table is keyed by exchange, symbol and bar
+//! time, and each batch is copied into a temporary table and upserted from
+//! there.
+//!
+//! Connections come from a pool. A connection that fails is discarded and
+//! replaced on the next attempt, which with several hosts and
+//! `target_session_attrs=read-write` in the URL follows a failover to the new
+//! primary.
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use canonical::MdEvent;
+use deadpool_postgres::{Manager, ManagerConfig, Pool, RecyclingMethod};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::env;
+use tokio_postgres::binary_copy::BinaryCopyInWriter;
+use tokio_postgres::types::{ToSql, Type};
+use tokio_postgres::NoTls;
+
+use super::batch::{BatchOptions, BatchWriter, Batcher};
+use super::table::{columns, row, table_name, Column, ColumnType};
+use super::Sink;
+
+const NANOS_PER_DAY: u64 = 86_400_000_000_000;
+const MILLIS_PER_DAY: u64 = 86_400_000;
+
+/// Table layout, pool size and batching of a [`PostgresSink`].
+#[derive(Debug, Clone)]
+pub struct PostgresOptions {
+    /// Schema of the tables, created if missing; the search path when unset.
+    pub schema: Option<String>,
+    /// Prepended to the snake-case kind to name each table.
+    pub table_prefix: String,
+    /// Make new tables hypertables when TimescaleDB is installed.
+    pub hypertables: bool,
+    pub pool_size: usize,
+    pub batch: BatchOptions,
+}
+
+impl Default for PostgresOptions {
+    fn default() -> Self {
+        Self {
+            schema: None,
+            table_prefix: "md_".into(),
+            hypertables: true,
+            pool_size: 4,
+            batch: BatchOptions::default(),
+        }
+    }
+}
+
+impl PostgresOptions {
+    /// Read `MD_SINK_POSTGRES_SCHEMA`, `MD_SINK_POSTGRES_TABLE_PREFIX`,
+    /// `MD_SINK_POSTGRES_HYPERTABLES`, `MD_SINK_POSTGRES_POOL_SIZE` and the
+    /// batch settings prefixed with `MD_SINK_POSTGRES`, falling back to the
+    /// defaults.
+    pub fn from_env() -> Result<Self> {
+        let mut opts = Self {
+            batch: BatchOptions::from_env("MD_SINK_POSTGRES")?,
+            ..Self::default()
+        };
+        if let Ok(v) = env::var("MD_SINK_POSTGRES_SCHEMA") {
+            opts.schema = Some(v.trim().to_string()).filter(|s| !s.is_empty());
+        }
+        if let Ok(v) = env::var("MD_SINK_POSTGRES_TABLE_PREFIX") {
+            opts.table_prefix = v.trim().to_string();
+        }
+        if let Ok(v) = env::var("MD_SINK_POSTGRES_HYPERTABLES") {
+            opts.hypertables = v.trim().parse().context("MD_SINK_POSTGRES_HYPERTABLES")?;
+        }
+        if let Ok(v) = env::var("MD_SINK_POSTGRES_POOL_SIZE") {
+            opts.pool_size = v.trim().parse().context("MD_SINK_POSTGRES_POOL_SIZE")?;
+        }
+        Ok(opts)
+    }
+}
+
+/// Sink copying events into per-kind PostgreSQL tables.
+pub struct PostgresSink {
+    batcher: Batcher<Writer>,
+}
+
+impl PostgresSink {
+    /// Connect to `url`, a `postgres://` URL or key-value connection string.
+    /// The pool connects lazily; the first connection is checked here.
+    pub async fn new(url: &str, options: PostgresOptions) -> Result<Self> {
+        let mut config: tokio_postgres::Config = url.parse().context("postgres url")?;
+        if config.get_application_name().is_none() {
+            config.application_name("md_ingestor");
+        }
+        let manager = Manager::from_config(
+            config,
+            NoTls,
+            ManagerConfig {
+                recycling_method: RecyclingMethod::Fast,
+            },
+        );
+        let pool = Pool::builder(manager)
+            .max_size(options.pool_size.max(1))
+            .build()?;
+        drop(pool.get().await.context("connecting to postgres")?);
+        let writer = Writer {
+            pool,
+            tables: HashMap::new(),
+            options: options.clone(),
+        };
+        Ok(Self {
+            batcher: Batcher::new("postgres", writer, options.batch),
+        })
+    }
+}
+
+#[async_trait]
+impl Sink for PostgresSink {
+    /// Buffer the event; see [`Batcher`] for when it is written.
+    async fn publish(&self, event: &MdEvent) -> Result<()> {
+        self.batcher.push(event).await
+    }
+
+    async fn publish_batch(&self, events: &[MdEvent]) -> Result<()> {
+        self.batcher.push_all(events).await
+    }
+
+    async fn flush(&self) -> Result<()> {
+        self.batcher.flush().await
+    }
+}
+
+fn quote(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('"', "\"\""))
+}
+
+struct Table {
+    /// Schema-qualified, quoted name.
+    name: String,
+    /// Name without the schema, unquoted.
+    base: String,
+    /// Quoted name of the temporary table upserts are copied through.
+    staging: String,
+    columns: Vec<Column>,
+    /// Columns identifying a row that later events update.
+    key: Option<&'static [&'static str]>,
+    created: bool,
+}
+
+impl Table {
+    fn new(kind: &str, options: &PostgresOptions) -> Self {
+        let table = table_name(&options.table_prefix, kind);
+        let name = match &options.schema {
+            Some(schema) => format!("{}.{}", quote(schema), quote(&table)),
+            None => quote(&table),
+        };
+        Self {
+            name,
+            staging: quote(&format!("{table}_staging")),
+            base: table,
+            columns: columns(kind),
+            key: (kind == "Kline").then_some(&["exchange", "symbol", "ts"][..]),
+            created: false,
+        }
+    }
+
+    /// Time column and chunk interval of the hypertable.
+    fn time_column(&self) -> (&'static str, u64) {
+        match self.key {
+            Some(_) => ("ts", MILLIS_PER_DAY),
+            None => ("ingest_ts_utc", NANOS_PER_DAY),
+        }
+    }
+
+    fn create(&self) -> String {
+        let mut defs: Vec<_> = self
+            .columns
+            .iter()
+            .map(|c| {
+                let null = if c.nullable { "" } else { " NOT NULL" };
+                format!("{} {}{null}", quote(&c.name), sql_type(c.ty))
+            })
+            .collect();
+        if let Some(key) = self.key {
+            let key: Vec<_> = key.iter().map(|c| quote(c)).collect();
+            defs.push(format!("PRIMARY KEY ({})", key.join(", ")));
+        }
+        let mut sql = format!(
+            "CREATE TABLE IF NOT EXISTS {} ({});",
+            self.name,
+            defs.join(", ")
+        );
+        if self.key.is_none() {
+            let (time, _) = self.time_column();
+            sql += &format!(
+                " CREATE INDEX IF NOT EXISTS {} ON {} (\"exchange\", \"symbol\", \"{time}\");",
+                quote(&format!("{}_instrument", self.base)),
+                self.name
+            );
+        }
+        sql
+    }
+
+    fn hypertable(&self) -> String {
+        let (time, chunk) = self.time_column();
+        format!(
+            "SELECT create_hypertable('{}', '{time}', chunk_time_interval => {chunk}::bigint, \
+             if_not_exists => TRUE, migrate_data => TRUE)",
+            self.name.replace('\'', "''")
+        )
+    }
+
+    fn column_list(&self) -> String {
+        let names: Vec<_> = self.columns.iter().map(|c| quote(&c.name)).collect();
+        names.join(", ")
+    }
+
+    fn copy(&self, target: &str) -> String {
+        format!("COPY {target} ({}) FROM STDIN BINARY", self.column_list())
+    }
+
+    /// Move the staged rows into the table, replacing rows with the same
+    /// key.
+    fn upsert(&self, key: &[&str]) -> String {
+        let key: Vec<_> = key.iter().map(|c| quote(c)).collect();
+        let updates: Vec<_> = self
+            .columns
+            .iter()
+            .map(|c| quote(&c.name))
+            .filter(|c| !key.contains(c))
+            .map(|c| format!("{c} = EXCLUDED.{c}"))
+            .collect();
+        format!(
+            "INSERT INTO {} ({cols}) SELECT {cols} FROM {} ON CONFLICT ({}) DO UPDATE SET {}",
+            self.name,
+            self.staging,
+            key.join(", "),
+            updates.join(", "),
+            cols = self.column_list(),
+        )
+    }
+}
+
+fn sql_type(ty: ColumnType) -> &'static str {
+    match ty {
+        ColumnType::UInt32 => "INTEGER",
+        ColumnType::UInt64 => "BIGINT",
+        ColumnType::Float64 => "DOUBLE PRECISION",
+        ColumnType::String => "TEXT",
+        ColumnType::Levels => "JSONB",
+    }
+}
+
+fn copy_type(ty: ColumnType) -> Type {
+    match ty {
+        ColumnType::UInt32 => Type::INT4,
+        ColumnType::UInt64 => Type::INT8,
+        ColumnType::Float64 => Type::FLOAT8,
+        ColumnType::String => Type::TEXT,
+        ColumnType::Levels => Type::JSONB,
+    }
+}
+
+/// Value of a column for `COPY`. Unsigned integers are stored as their
+/// signed counterparts, and missing values of non-null columns as zero, an
+/// empty string or NaN.
+fn cell(column: &Column, row: &Value) -> Box<dyn ToSql + Sync + Send> {
+    let value = column.value(row);
+    if value.is_none() && column.nullable {
+        return match column.ty {
+            ColumnType::UInt32 => Box::new(None::<i32>),
+            ColumnType::UInt64 => Box::new(None::<i64>),
+            ColumnType::Float64 => Box::new(None::<f64>),
+            ColumnType::String => Box::new(None::<String>),
+            ColumnType::Levels => Box::new(None::<Value>),
+        };
+    }
+    match column.ty {
+        ColumnType::UInt32 => Box::new(value.and_then(Value::as_u64).unwrap_or(0) as i32),
+        ColumnType::UInt64 => Box::new(value.and_then(Value::as_u64).unwrap_or(0) as i64),
+        ColumnType::Float64 => Box::new(value.and_then(Value::as_f64).unwrap_or(f64::NAN)),
+        ColumnType::String => Box::new(match value {
+            Some(Value::String(s)) => s.clone(),
+            Some(other) => other.to_string(),
+            None => String::new(),
+        }),
+        ColumnType::Levels => Box::new(value.cloned().unwrap_or_else(|| Value::Array(Vec::new()))),
+    }
+}
+
+struct Writer {
+    pool: Pool,
+    tables: HashMap<&'static str, Table>,
+    options: PostgresOptions,
+}
+
+#[async_trait]
+impl BatchWriter for Writer {
+    async fn write(&mut self, kind: &'static str, rows: &[MdEvent]) -> Result<()> {
+        let options = &self.options;
+        let table = self
+            .tables
+            .entry(kind)
+            .or_insert_with(|| Table::new(kind, options));
+        let mut client = self.pool.get().await.context("connecting to postgres")?;
+        if !table.created {
+            if let Some(schema) = &options.schema {
+                let sql = format!("CREATE SCHEMA IF NOT EXISTS {}", quote(schema));
+                client.batch_execute(&sql).await?;
+            }
+            client.batch_execute(&table.create()).await?;
+            let timescale = client
+                .query_opt(
+                    "SELECT 1 FROM pg_extension WHERE extname = 'timescaledb'",
+                    &[],
+                )
+                .await?
+                .is_some();
+            if options.hypertables && timescale {
+                client.batch_execute(&table.hypertable()).await?;
+            }
+            table.created = true;
+        }
+
+        let mut values = Vec::with_capacity(rows.len());
+        for ev in rows {
+            values.push(row(ev)?);
+        }
+        if let Some(key) = table.key {
+            // Only the last update of a bar may reach the upsert, which
+            // cannot touch the same row twice.
+            let mut last = HashMap::new();
+            for (i, v) in values.iter().enumerate() {
+                let id: Vec<_> = key.iter().map(|k| v.get(k).map(Value::to_string)).collect();
+                last.insert(id, i);
+            }
+            let mut keep: Vec<_> = last.into_values().collect();
+            keep.sort_unstable();
+            values = keep.into_iter().map(|i| values[i].take()).collect();
+        }
+
+        let types: Vec<_> = table.columns.iter().map(|c| copy_type(c.ty)).collect();
+        let tx = client.transaction().await?;
+        let target = match table.key {
+            Some(_) => {
+                let sql = format!(
+                    "CREATE TEMP TABLE IF NOT EXISTS {} (LIKE {} INCLUDING DEFAULTS) ON COMMIT DELETE ROWS",
+                    table.staging, table.name
+                );
+                tx.batch_execute(&sql).await?;
+                &table.staging
+            }
+            None => &table.name,
+        };
+        let sink = tx.copy_in(&table.copy(target)).await?;
+        let writer = BinaryCopyInWriter::new(sink, &types);
+        futures::pin_mut!(writer);
+        for row in &values {
+            let cells: Vec<_> = table.columns.iter().map(|c| cell(c, row)).collect();
+            let refs: Vec<&(dyn ToSql + Sync)> = cells
+                .iter()
+                .map(|c| c.as_ref() as &(dyn ToSql + Sync))
+                .collect();
+            writer.as_mut().write(&refs).await?;
+        }
+        writer.finish().await?;
+        if let Some(key) = table.key {
+            tx.execute(&table.upsert(key), &[]).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::testing::{event, free_port, scratch_dir, Server};
+    use canonical::{Kline, MdEventKind, Trade};
+    use std::process::{Command, Stdio};
+    use std::time::Duration;
+
+    fn trade(id: u64) -> MdEvent {
+        event(MdEventKind::Trade(Trade {
+            exchange: "binance".into(),
+            symbol: "BTCUSDT".into(),
+            price: 1.5,
+            trade_id: Some(id),
+            ingest_ts_utc: 1_700_000_000_000_000_000 + id,
+            ..Default::default()
+        }))
+    }
+
+    fn kline(ts: u64, close: f64) -> MdEvent {
+        event(MdEventKind::Kline(Kline {
+            exchange: "binance".into(),
+            symbol: "BTCUSDT".into(),
+            ts,
+            close,
+            ..Default::default()
+        }))
+    }
+
+    #[test]
+    fn generates_tables_and_upserts() {
+        let options = PostgresOptions {
+            schema: Some("md".into()),
+            ..Default::default()
+        };
+        let trades = Table::new("Trade", &options);
+        assert_eq!(trades.name, "\"md\".\"md_trade\"");
+        let create = trades.create();
+        assert!(create.starts_with(
+            "CREATE TABLE IF NOT EXISTS \"md\".\"md_trade\" (\"exchange\" TEXT NOT NULL, \
+             \"schema_version\" INTEGER NOT NULL, \"symbol\" TEXT NOT NULL, "
+        ));
+        assert!(create.contains("\"trade_id\" BIGINT, "));
+        assert!(create.ends_with(
+            "CREATE INDEX IF NOT EXISTS \"md_trade_instrument\" ON \"md\".\"md_trade\" \
+             (\"exchange\", \"symbol\", \"ingest_ts_utc\");"
+        ));
+        assert!(trades.hypertable().contains(
+            "'\"md\".\"md_trade\"', 'ingest_ts_utc', chunk_time_interval => 86400000000000::bigint"
+        ));
+
+        let klines = Table::new("Kline", &PostgresOptions::default());
+        assert!(klines
+            .create()
+            .ends_with("PRIMARY KEY (\"exchange\", \"symbol\", \"ts\"));"));
+        let upsert = klines.upsert(klines.key.unwrap());
+        assert!(upsert.starts_with("INSERT INTO \"md_kline\" (\"exchange\", "));
+        assert!(upsert.contains(" FROM \"md_kline_staging\" ON CONFLICT (\"exchange\", \"symbol\", \"ts\") DO UPDATE SET \"schema_version\" = EXCLUDED.\"schema_version\", "));
+        assert!(!upsert.contains("\"ts\" = EXCLUDED"));
+    }
+
+    /// A throwaway cluster run with `initdb` and `postgres` from `PATH`,
+    /// which refuse to run as root.
+    async fn spawn_server() -> Server {
+        let dir = scratch_dir("postgres_sink");
+        let status = Command::new("initdb")
+            .args(["-U", "postgres", "--auth=trust", "-D"])
+            .arg(&dir)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .expect("cannot run initdb");
+        assert!(status.success(), "initdb failed");
+        let port = free_port();
+        let url = format!("host=127.0.0.1 port={port} user=postgres dbname=postgres");
+        let mut command = Command::new("postgres");
+        command
+            .arg("-D")
+            .arg(&dir)
+            .args(["-p", &port.to_string(), "-k"])
+            .arg(&dir)
+            .args(["-c", "listen_addresses=127.0.0.1", "-c", "fsync=off"]);
+        Server::spawn(command, port, dir, || async {
+            tokio_postgres::connect(&url, NoTls).await.is_ok()
+        })
+        .await
+    }
+
+    #[tokio::test]
+    #[ignore = "needs initdb and postgres on PATH and a non-root user"]
+    async fn copies_rows_upserts_bars_and_survives_lost_connections() {
+        let server = spawn_server().await;
+        // The first host is down, so connections fail over to the second.
+        let url = format!(
+            "host=127.0.0.1,127.0.0.1 port={},{} user=postgres dbname=postgres",
+            free_port(),
+            server.port
+        );
+        let options = PostgresOptions {
+            schema: Some("md".into()),
+            batch: BatchOptions {
+                backoff: Duration::from_millis(10),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let sink = PostgresSink::new(&url, options).await.unwrap();
+        for id in 0..100 {
+            sink.publish(&trade(id)).await.unwrap();
+        }
+        sink.publish(&kline(60_000, 1.0)).await.unwrap();
+        sink.publish(&kline(60_000, 2.0)).await.unwrap();
+        sink.publish(&kline(120_000, 3.0)).await.unwrap();
+        sink.flush().await.unwrap();
+
+        let direct = format!(
+            "host=127.0.0.1 port={} user=postgres dbname=postgres",
+            server.port
+        );
+        let (client, conn) = tokio_postgres::connect(&direct, NoTls).await.unwrap();
+        tokio::spawn(conn);
+        client
+            .execute(
+                "SELECT pg_terminate_backend(pid) FROM pg_stat_activity \
+                 WHERE application_name = 'md_ingestor'",
+                &[],
+            )
+            .await
+            .unwrap();
+        sink.publish(&kline(60_000, 4.0)).await.unwrap();
+        sink.publish(&trade(100)).await.unwrap();
+        sink.flush().await.unwrap();
+
+        let row = client
+            .query_one(
+                "SELECT count(*), sum(trade_id)::bigint FROM md.md_trade",
+                &[],
+            )
+            .await
+            .unwrap();
+        assert_eq!((row.get::<_, i64>(0), row.get::<_, i64>(1)), (101, 5050));
+        let bars: Vec<(i64, f64)> = client
+            .query("SELECT ts, close FROM md.md_kline ORDER BY ts", &[])
+            .await
+            .unwrap()
+            .iter()
+            .map(|r| (r.get(0), r.get(1)))
+            .collect();
+        assert_eq!(bars, [(60_000, 4.0), (120_000, 3.0)]);
+    }
+}
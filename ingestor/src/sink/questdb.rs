@@ -0,0 +1,404 @@
+//! QuestDB tables of events over the InfluxDB line protocol.
+//!
+//! Each event is one line in the table of its kind, named as for ClickHouse,
+//! with the exchange and symbol as tags (QuestDB `SYMBOL` columns), the other
+//! columns of [`table`](super::table) as fields and the ingest time as the
+//! designated timestamp. QuestDB creates tables and columns as lines arrive.
+//! Book levels are written as a JSON string of `[price, quantity]` pairs, and
+//! the trade's `timestamp` field as `ts`, since QuestDB names the designated
+//! timestamp `timestamp`.
+//!
+//! The TCP protocol has no acknowledgements: a batch counts as written once
+//! it is sent, and the server reports bad lines only by closing the
+//! connection. The connection is checked before every batch and reopened
+//! when the server has closed it.
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use canonical::MdEvent;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Write as _;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tracing::warn;
+
+use super::batch::{BatchOptions, BatchWriter, Batcher};
+use super::table::{columns, row, table_name, Column, ColumnType};
+use super::Sink;
+
+/// Table naming and batching of a [`QuestDbSink`].
+#[derive(Debug, Clone)]
+pub struct QuestDbOptions {
+    /// Prepended to the snake-case kind to name each table.
+    pub table_prefix: String,
+    pub batch: BatchOptions,
+}
+
+impl Default for QuestDbOptions {
+    fn default() -> Self {
+        Self {
+            table_prefix: "md_".into(),
+            batch: BatchOptions::default(),
+        }
+    }
+}
+
+impl QuestDbOptions {
+    /// Read `MD_SINK_QUESTDB_TABLE_PREFIX` and the batch settings prefixed
+    /// with `MD_SINK_QUESTDB`, falling back to the defaults.
+    pub fn from_env() -> Result<Self> {
+        let mut opts = Self {
+            batch: BatchOptions::from_env("MD_SINK_QUESTDB")?,
+            ..Self::default()
+        };
+        if let Ok(v) = env::var("MD_SINK_QUESTDB_TABLE_PREFIX") {
+            opts.table_prefix = v.trim().to_string();
+        }
+        Ok(opts)
+    }
+}
+
+/// Sink writing events to QuestDB as InfluxDB line protocol over TCP.
+pub struct QuestDbSink {
+    batcher: Batcher<Writer>,
+}
+
+impl QuestDbSink {
+    /// Connect to the line protocol port at `addr`, e.g. `localhost:9009`.
+    pub async fn new(addr: &str, options: QuestDbOptions) -> Result<Self> {
+        let conn = connect(addr).await?;
+        let writer = Writer {
+            addr: addr.to_string(),
+            conn: Some(conn),
+            tables: HashMap::new(),
+            table_prefix: options.table_prefix,
+        };
+        Ok(Self {
+            batcher: Batcher::new("questdb", writer, options.batch),
+        })
+    }
+}
+
+#[async_trait]
+impl Sink for QuestDbSink {
+    /// Buffer the event; see [`Batcher`] for when it is written.
+    async fn publish(&self, event: &MdEvent) -> Result<()> {
+        self.batcher.push(event).await
+    }
+
+    async fn publish_batch(&self, events: &[MdEvent]) -> Result<()> {
+        self.batcher.push_all(events).await
+    }
+
+    async fn flush(&self) -> Result<()> {
+        self.batcher.flush().await
+    }
+}
+
+async fn connect(addr: &str) -> Result<TcpStream> {
+    let conn = TcpStream::connect(addr)
+        .await
+        .with_context(|| format!("connecting to questdb at {addr}"))?;
+    conn.set_nodelay(true)?;
+    Ok(conn)
+}
+
+/// Whether the server closed `conn`. It never sends anything otherwise.
+fn closed(conn: &TcpStream) -> bool {
+    let mut buf = [0u8; 64];
+    match conn.try_read(&mut buf) {
+        Ok(n) => n == 0,
+        Err(e) => e.kind() != std::io::ErrorKind::WouldBlock,
+    }
+}
+
+struct Writer {
+    addr: String,
+    conn: Option<TcpStream>,
+    tables: HashMap<&'static str, (String, Vec<Column>)>,
+    table_prefix: String,
+}
+
+#[async_trait]
+impl BatchWriter for Writer {
+    async fn write(&mut self, kind: &'static str, rows: &[MdEvent]) -> Result<()> {
+        let prefix = &self.table_prefix;
+        let (table, columns) = self
+            .tables
+            .entry(kind)
+            .or_insert_with(|| (table_name(prefix, kind), columns(kind)));
+        let mut lines = String::new();
+        for ev in rows {
+            line(&mut lines, table, columns, &row(ev)?);
+        }
+
+        if self.conn.as_ref().is_some_and(closed) {
+            warn!(addr = %self.addr, "questdb closed the connection, reconnecting");
+            self.conn = None;
+        }
+        let conn = match &mut self.conn {
+            Some(conn) => conn,
+            None => self.conn.insert(connect(&self.addr).await?),
+        };
+        let res = async {
+            conn.write_all(lines.as_bytes()).await?;
+            conn.flush().await
+        }
+        .await;
+        if res.is_err() {
+            self.conn = None;
+        }
+        Ok(res?)
+    }
+}
+
+/// Escape `,`, ` ` and, for tags, `=` with a backslash.
+fn escape_name(out: &mut String, s: &str, tag: bool) {
+    for c in s.chars() {
+        match c {
+            ',' | ' ' => out.push('\\'),
+            '=' if tag => out.push('\\'),
+            '\n' => {
+                out.push_str("\\n");
+                continue;
+            }
+            _ => {}
+        }
+        out.push(c);
+    }
+}
+
+fn escape_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' | '\\' => out.push('\\'),
+            '\n' => {
+                out.push_str("\\n");
+                continue;
+            }
+            _ => {}
+        }
+        out.push(c);
+    }
+    out.push('"');
+}
+
+/// Append the field for `value`, returning `false` when it has no
+/// line-protocol representation and was skipped.
+fn field(out: &mut String, ty: ColumnType, value: &Value) -> bool {
+    match ty {
+        ColumnType::UInt32 | ColumnType::UInt64 => {
+            match value.as_u64().and_then(|v| i64::try_from(v).ok()) {
+                Some(v) => write!(out, "{v}i").is_ok(),
+                None => false,
+            }
+        }
+        ColumnType::Float64 => match value.as_f64() {
+            Some(v) if v.is_finite() => write!(out, "{v}").is_ok(),
+            _ => {
+                out.push_str("NaN");
+                true
+            }
+        },
+        ColumnType::String => {
+            match value {
+                Value::String(s) => escape_string(out, s),
+                other => escape_string(out, &other.to_string()),
+            }
+            true
+        }
+        ColumnType::Levels => {
+            let pairs: Vec<_> = value
+                .as_array()
+                .map_or(&[][..], Vec::as_slice)
+                .iter()
+                .map(|l| [l.get("price"), l.get("quantity")])
+                .collect();
+            escape_string(out, &serde_json::to_string(&pairs).unwrap_or_default());
+            true
+        }
+    }
+}
+
+fn line(out: &mut String, table: &str, columns: &[Column], row: &Value) {
+    escape_name(out, table, false);
+    let mut fields = 0;
+    let mut ts = None;
+    for column in columns {
+        let Some(value) = column.value(row) else {
+            continue;
+        };
+        match column.name.as_str() {
+            "exchange" | "symbol" => {
+                out.push(',');
+                out.push_str(&column.name);
+                out.push('=');
+                escape_name(out, value.as_str().unwrap_or_default(), true);
+            }
+            "ingest_ts_utc" => ts = value.as_u64().filter(|&ts| ts > 0),
+            _ => {}
+        }
+    }
+    for column in columns {
+        let name = match column.name.as_str() {
+            "exchange" | "symbol" | "ingest_ts_utc" => continue,
+            "timestamp" => "ts",
+            name => name,
+        };
+        let Some(value) = column.value(row) else {
+            continue;
+        };
+        let start = out.len();
+        out.push(if fields == 0 { ' ' } else { ',' });
+        out.push_str(name);
+        out.push('=');
+        if field(out, column.ty, value) {
+            fields += 1;
+        } else {
+            out.truncate(start);
+        }
+    }
+    if let Some(ts) = ts {
+        let _ = write!(out, " {ts}");
+    }
+    out.push('\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::testing::event;
+    use canonical::{BookKind, DepthSnapshot, Level, MdEventKind, Trade};
+    use std::time::Duration;
+    use tokio::io::AsyncBufReadExt;
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    fn trade(id: u64) -> MdEvent {
+        event(MdEventKind::Trade(Trade {
+            exchange: "binance".into(),
+            symbol: "BTCUSDT".into(),
+            price: 1.5,
+            quantity: 2.0,
+            trade_id: Some(id),
+            timestamp: 1_700_000_000_000,
+            ingest_ts_utc: 1_700_000_000_000_000_000,
+            ..Default::default()
+        }))
+    }
+
+    fn lines_of(events: &[MdEvent]) -> String {
+        let mut out = String::new();
+        for ev in events {
+            line(
+                &mut out,
+                &table_name("md_", ev.kind()),
+                &columns(ev.kind()),
+                &row(ev).unwrap(),
+            );
+        }
+        out
+    }
+
+    #[test]
+    fn formats_lines_with_tags_fields_and_timestamp() {
+        assert_eq!(
+            lines_of(&[trade(7)]),
+            format!(
+                "md_trade,exchange=binance,symbol=BTCUSDT schema_version={}i,price=1.5,quantity=2,\
+                 trade_id=7i,ts=1700000000000i,ingest_ts_monotonic=0i,seq_no=0i \
+                 1700000000000000000\n",
+                canonical::SCHEMA_VERSION
+            )
+        );
+        let level = |price, kind| Level {
+            schema_version: canonical::SCHEMA_VERSION,
+            price,
+            quantity: 1.0,
+            kind,
+        };
+        let snapshot = event(MdEventKind::DepthSnapshot(DepthSnapshot {
+            exchange: "my venue".into(),
+            symbol: "A,B=C".into(),
+            bids: vec![level(10.0, BookKind::Bid), level(9.5, BookKind::Bid)],
+            asks: vec![],
+            ..Default::default()
+        }));
+        let line = lines_of(&[snapshot]);
+        assert!(
+            line.starts_with("md_depth_snapshot,exchange=my\\ venue,symbol=A\\,B\\=C "),
+            "{line}"
+        );
+        assert!(
+            line.contains(r#"bids="[[10.0,1.0],[9.5,1.0]]",asks="[]""#),
+            "{line}"
+        );
+        // Without an ingest time QuestDB assigns the timestamp.
+        assert!(line.ends_with("seq_no=0i\n"), "{line}");
+    }
+
+    /// Line-protocol server forwarding every received line. Each connection
+    /// is closed after `per_conn` lines.
+    async fn mock_server(per_conn: usize) -> (String, mpsc::UnboundedReceiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok((conn, _)) = listener.accept().await {
+                let mut lines = tokio::io::BufReader::new(conn).lines();
+                for _ in 0..per_conn {
+                    match lines.next_line().await {
+                        Ok(Some(line)) => tx.send(line).unwrap(),
+                        _ => break,
+                    }
+                }
+            }
+        });
+        (addr, rx)
+    }
+
+    #[tokio::test]
+    async fn reconnects_after_the_server_closes_the_connection() {
+        let (addr, mut rx) = mock_server(2).await;
+        let options = QuestDbOptions {
+            batch: BatchOptions {
+                backoff: Duration::from_millis(10),
+                retries: 10,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let sink = QuestDbSink::new(&addr, options).await.unwrap();
+        let mut received = Vec::new();
+        for id in 0..6 {
+            sink.publish(&trade(id)).await.unwrap();
+            sink.flush().await.unwrap();
+            // The second line fills the connection, so wait for the server to
+            // drop it before the next write.
+            let line = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            received.push(line);
+            if id % 2 == 1 {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        }
+        let ids: Vec<_> = received
+            .iter()
+            .map(|l| {
+                l.split("trade_id=")
+                    .nth(1)
+                    .unwrap()
+                    .split('i')
+                    .next()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect();
+        assert_eq!(ids, ["0", "1", "2", "3", "4", "5"]);
+    }
+}
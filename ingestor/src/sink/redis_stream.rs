@@ -0,0 +1,368 @@
+//! Capped Redis Streams of recent events per instrument.
+//!
+//! Events are queued to a writer task that drains whatever is waiting, up to
+//! a batch, into one pipeline of `XADD ... MAXLEN ~` commands. Book tickers
+//! also overwrite the instrument's field in a per-exchange hash, so the
+//! latest top of book can be read with a single `HGETALL`.
+
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use canonical::{MdEvent, MdEventKind};
+use redis::aio::ConnectionLike;
+use std::env;
+use tokio::sync::{mpsc, oneshot};
+use tracing::warn;
+
+use super::{check_placeholders, Sink};
+
+/// Stream layout and batching of a [`RedisStreamSink`].
+#[derive(Debug, Clone)]
+pub struct RedisOptions {
+    /// Stream key of each event. May contain `{exchange}`, `{symbol}`,
+    /// `{channel}` and `{kind}`.
+    pub stream_key: String,
+    /// Approximate number of entries kept per stream.
+    pub max_len: usize,
+    /// Hash holding the latest book ticker per symbol, keyed by symbol. May
+    /// contain `{exchange}`. `None` disables the hash.
+    pub book_key: Option<String>,
+    /// Commands sent per pipeline.
+    pub batch_size: usize,
+    /// Events buffered for the writer before `publish` waits.
+    pub queue: usize,
+}
+
+impl Default for RedisOptions {
+    fn default() -> Self {
+        Self {
+            stream_key: "md:{exchange}:{symbol}".into(),
+            max_len: 10_000,
+            book_key: Some("md:book:{exchange}".into()),
+            batch_size: 512,
+            queue: 8192,
+        }
+    }
+}
+
+impl RedisOptions {
+    /// Read `MD_SINK_REDIS_STREAM_KEY`, `MD_SINK_REDIS_MAXLEN`,
+    /// `MD_SINK_REDIS_BOOK_KEY` and `MD_SINK_REDIS_BATCH_SIZE`, falling back to
+    /// the defaults. An empty `MD_SINK_REDIS_BOOK_KEY` disables the hash.
+    pub fn from_env() -> Result<Self> {
+        let mut opts = Self::default();
+        if let Ok(v) = env::var("MD_SINK_REDIS_STREAM_KEY") {
+            if !v.trim().is_empty() {
+                opts.stream_key = v.trim().to_string();
+            }
+        }
+        if let Ok(v) = env::var("MD_SINK_REDIS_MAXLEN") {
+            opts.max_len = v.trim().parse().context("MD_SINK_REDIS_MAXLEN")?;
+        }
+        if let Ok(v) = env::var("MD_SINK_REDIS_BOOK_KEY") {
+            opts.book_key = Some(v.trim().to_string()).filter(|k| !k.is_empty());
+        }
+        if let Ok(v) = env::var("MD_SINK_REDIS_BATCH_SIZE") {
+            opts.batch_size = v.trim().parse().context("MD_SINK_REDIS_BATCH_SIZE")?;
+        }
+        Ok(opts)
+    }
+}
+
+enum Message {
+    Event(Box<MdEvent>),
+    Flush(oneshot::Sender<Result<()>>),
+}
+
+/// Sink appending JSON events to capped Redis Streams.
+pub struct RedisStreamSink {
+    tx: mpsc::Sender<Message>,
+}
+
+impl RedisStreamSink {
+    /// Connect to `url`, e.g. `redis://localhost:6379`.
+    pub async fn new(url: &str, options: RedisOptions) -> Result<Self> {
+        check_placeholders(
+            &options.stream_key,
+            &["exchange", "symbol", "channel", "kind"],
+            "stream key",
+        )?;
+        if let Some(key) = &options.book_key {
+            check_placeholders(key, &["exchange"], "book key")?;
+        }
+        let client = redis::Client::open(url).with_context(|| format!("redis url {url}"))?;
+        let conn = client
+            .get_connection_manager()
+            .await
+            .with_context(|| format!("connecting to redis at {url}"))?;
+        Ok(Self::spawn(conn, options))
+    }
+
+    fn spawn<C>(conn: C, options: RedisOptions) -> Self
+    where
+        C: ConnectionLike + Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel(options.queue.max(1));
+        tokio::spawn(run_writer(conn, rx, options));
+        Self { tx }
+    }
+}
+
+#[async_trait]
+impl Sink for RedisStreamSink {
+    /// Queue the event for the next pipeline. Write errors are reported by
+    /// [`flush`](Sink::flush).
+    async fn publish(&self, event: &MdEvent) -> Result<()> {
+        self.tx
+            .send(Message::Event(Box::new(event.clone())))
+            .await
+            .map_err(|_| anyhow!("redis writer stopped"))
+    }
+
+    /// Wait for queued events to be written. Fails if any were not written
+    /// since the previous flush.
+    async fn flush(&self) -> Result<()> {
+        let (done, rx) = oneshot::channel();
+        self.tx
+            .send(Message::Flush(done))
+            .await
+            .map_err(|_| anyhow!("redis writer stopped"))?;
+        rx.await.map_err(|_| anyhow!("redis writer stopped"))?
+    }
+}
+
+async fn run_writer<C: ConnectionLike>(
+    mut conn: C,
+    mut rx: mpsc::Receiver<Message>,
+    options: RedisOptions,
+) {
+    let batch_size = options.batch_size.max(1);
+    let mut failed = 0u64;
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut flushes = Vec::new();
+    while let Some(msg) = rx.recv().await {
+        let mut next = Some(msg);
+        while let Some(msg) = next.take() {
+            match msg {
+                Message::Event(ev) => batch.push(ev),
+                Message::Flush(done) => flushes.push(done),
+            }
+            if batch.len() < batch_size {
+                next = rx.try_recv().ok();
+            }
+        }
+
+        if !batch.is_empty() {
+            let res = match pipeline(&batch, &options) {
+                Ok(pipe) => pipe.exec_async(&mut conn).await.map_err(Into::into),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = res {
+                failed += batch.len() as u64;
+                warn!(error = %e, events = batch.len(), "failed to write events to redis");
+            }
+            batch.clear();
+        }
+        for done in flushes.drain(..) {
+            let res = match std::mem::take(&mut failed) {
+                0 => Ok(()),
+                n => Err(anyhow!("{n} events not written to redis")),
+            };
+            let _ = done.send(res);
+        }
+    }
+}
+
+/// One `XADD` per event, plus an `HSET` per book ticker.
+fn pipeline(batch: &[Box<MdEvent>], options: &RedisOptions) -> Result<redis::Pipeline> {
+    let mut pipe = redis::pipe();
+    for ev in batch {
+        let json = serde_json::to_string(ev)?;
+        let key = options
+            .stream_key
+            .replace("{exchange}", ev.exchange())
+            .replace("{symbol}", ev.symbol())
+            .replace("{channel}", ev.channel().as_str())
+            .replace("{kind}", ev.kind());
+        pipe.cmd("XADD")
+            .arg(key)
+            .arg("MAXLEN")
+            .arg("~")
+            .arg(options.max_len)
+            .arg("*")
+            .arg("kind")
+            .arg(ev.kind())
+            .arg("ingest_ts_utc")
+            .arg(ev.ingest_ts_utc())
+            .arg("event")
+            .arg(&json)
+            .ignore();
+        if let (MdEventKind::BookTicker(_), Some(book_key)) = (&ev.event, &options.book_key) {
+            pipe.cmd("HSET")
+                .arg(book_key.replace("{exchange}", ev.exchange()))
+                .arg(ev.symbol())
+                .arg(&json)
+                .ignore();
+        }
+    }
+    Ok(pipe)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::testing::{event, free_port, listening, scratch_dir, Server};
+    use canonical::{BookTicker, Trade};
+    use redis_test::{MockCmd, MockRedisConnection};
+    use std::process::Command;
+
+    /// A throwaway `redis-server` from `PATH`.
+    async fn spawn_server() -> Server {
+        let port = free_port();
+        let mut command = Command::new("redis-server");
+        command
+            .args(["--port", &port.to_string(), "--bind", "127.0.0.1"])
+            .args(["--save", "", "--appendonly", "no"]);
+        Server::spawn(command, port, scratch_dir("redis_sink"), || listening(port)).await
+    }
+
+    fn url(server: &Server) -> String {
+        format!("redis://127.0.0.1:{}", server.port)
+    }
+
+    fn trade(id: u64) -> MdEvent {
+        event(MdEventKind::Trade(Trade {
+            exchange: "binance".into(),
+            symbol: "BTCUSDT".into(),
+            trade_id: Some(id),
+            ..Default::default()
+        }))
+    }
+
+    fn book(bid: f64) -> MdEvent {
+        event(MdEventKind::BookTicker(BookTicker {
+            exchange: "binance".into(),
+            symbol: "BTCUSDT".into(),
+            bid_price: bid,
+            ..Default::default()
+        }))
+    }
+
+    #[tokio::test]
+    #[ignore = "needs redis-server on PATH"]
+    async fn caps_streams_and_keeps_latest_book() {
+        let server = spawn_server().await;
+        let url = url(&server);
+        let options = RedisOptions {
+            max_len: 10,
+            batch_size: 16,
+            ..Default::default()
+        };
+        let sink = RedisStreamSink::new(&url, options).await.unwrap();
+        for id in 0..1000 {
+            sink.publish(&trade(id)).await.unwrap();
+        }
+        sink.publish(&book(1.0)).await.unwrap();
+        sink.publish(&book(2.0)).await.unwrap();
+        sink.flush().await.unwrap();
+
+        let client = redis::Client::open(url.as_str()).unwrap();
+        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+        let len: usize = redis::cmd("XLEN")
+            .arg("md:binance:BTCUSDT")
+            .query_async(&mut conn)
+            .await
+            .unwrap();
+        // MAXLEN ~ trims whole macro nodes, so the stream may run over.
+        assert!((10..1000).contains(&len), "{len} entries");
+        let last: Vec<(String, Vec<(String, String)>)> = redis::cmd("XREVRANGE")
+            .arg("md:binance:BTCUSDT")
+            .arg("+")
+            .arg("-")
+            .arg("COUNT")
+            .arg(1)
+            .query_async(&mut conn)
+            .await
+            .unwrap();
+        let fields = &last[0].1;
+        assert_eq!(fields[0], ("kind".into(), "BookTicker".into()));
+        let latest: String = redis::cmd("HGET")
+            .arg("md:book:binance")
+            .arg("BTCUSDT")
+            .query_async(&mut conn)
+            .await
+            .unwrap();
+        let latest: MdEvent = serde_json::from_str(&latest).unwrap();
+        assert!(matches!(latest.event, MdEventKind::BookTicker(b) if b.bid_price == 2.0));
+    }
+
+    #[test]
+    fn builds_capped_xadds_and_book_hash() {
+        let options = RedisOptions {
+            max_len: 100,
+            ..Default::default()
+        };
+        let batch = vec![Box::new(trade(1)), Box::new(book(2.0))];
+        let json: Vec<_> = batch
+            .iter()
+            .map(|ev| serde_json::to_string(ev).unwrap())
+            .collect();
+        let mut expected = redis::pipe();
+        for (ev, json) in batch.iter().zip(&json) {
+            expected
+                .cmd("XADD")
+                .arg("md:binance:BTCUSDT")
+                .arg("MAXLEN")
+                .arg("~")
+                .arg(100)
+                .arg("*")
+                .arg("kind")
+                .arg(ev.kind())
+                .arg("ingest_ts_utc")
+                .arg(0)
+                .arg("event")
+                .arg(json)
+                .ignore();
+        }
+        expected
+            .cmd("HSET")
+            .arg("md:book:binance")
+            .arg("BTCUSDT")
+            .arg(&json[1])
+            .ignore();
+        assert_eq!(
+            pipeline(&batch, &options).unwrap().get_packed_pipeline(),
+            expected.get_packed_pipeline()
+        );
+
+        let options = RedisOptions {
+            stream_key: "{kind}:{symbol}".into(),
+            book_key: None,
+            ..Default::default()
+        };
+        let packed = pipeline(&batch[1..], &options)
+            .unwrap()
+            .get_packed_pipeline();
+        let packed = String::from_utf8(packed).unwrap();
+        assert!(packed.contains("BookTicker:BTCUSDT"));
+        assert!(!packed.contains("HSET"));
+    }
+
+    #[tokio::test]
+    async fn flush_reports_failed_writes_once() {
+        let options = RedisOptions::default();
+        let first = vec![Box::new(trade(1))];
+        let second = vec![Box::new(trade(2))];
+        let conn = MockRedisConnection::new([
+            MockCmd::new::<_, &str>(
+                pipeline(&first, &options).unwrap(),
+                Err((redis::ErrorKind::Io, "connection reset").into()),
+            ),
+            MockCmd::with_values(pipeline(&second, &options).unwrap(), Ok(vec!["1-0"])),
+        ]);
+        let sink = RedisStreamSink::spawn(conn, options);
+        sink.publish(&trade(1)).await.unwrap();
+        assert!(sink.flush().await.is_err());
+        sink.publish(&trade(2)).await.unwrap();
+        sink.flush().await.unwrap();
+    }
+}
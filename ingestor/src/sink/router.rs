@@ -0,0 +1,675 @@
+//! Fan-out of events to several sinks.
+//!
+//! Each route pairs a filter on exchange, symbol, channel and event kind with
+//! a sink behind its own [`Pipeline`]. Publishing only enqueues, so a slow or
+//! failing sink backs up its own queue and nothing else; what happens to
+//! events for a full queue is the route's [`Overflow`] policy, dropping them
+//! by default.
+
+use anyhow::{anyhow, bail, Context, Result};
+use arb_core::events::Channel;
+use async_trait::async_trait;
+use canonical::symbol::{resolve, InstrumentId};
+use canonical::MdEvent;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::path::Path;
+use std::sync::Arc;
+
+use super::{
+    ClickHouseOptions, ClickHouseSink, Encoding, FileSink, FileSinkOptions, KafkaOptions,
+    KafkaSink, MetricNames, MulticastOptions, MulticastSink, NatsOptions, NatsSink, Overflow,
+    ParquetOptions, ParquetSink, Pipeline, PipelineOptions, PostgresOptions, PostgresSink,
+    QuestDbOptions, QuestDbSink, RedisOptions, RedisStreamSink, Sink, Wal, WalOptions,
+};
+
+const DEFAULT_QUEUE: usize = 8192;
+
+/// Events a route accepts. Empty lists match everything.
+#[derive(Debug, Default, Clone)]
+pub struct RouteFilter {
+    pub exchanges: Vec<String>,
+    /// Venue-native or canonical symbols.
+    pub symbols: Vec<String>,
+    pub channels: Vec<Channel>,
+    /// Event kinds as returned by [`MdEvent::kind`].
+    pub kinds: Vec<String>,
+}
+
+impl RouteFilter {
+    pub fn matches(&self, ev: &MdEvent) -> bool {
+        let any = |list: &[String], value: &str| {
+            list.is_empty() || list.iter().any(|v| v.eq_ignore_ascii_case(value))
+        };
+        any(&self.exchanges, ev.exchange())
+            && any(&self.kinds, ev.kind())
+            && (self.channels.is_empty() || self.channels.contains(&ev.channel()))
+            && (self.symbols.is_empty()
+                || any(&self.symbols, ev.symbol())
+                || match resolve(ev.exchange(), ev.symbol()) {
+                    Some(resolved) => any(&self.symbols, &resolved.name),
+                    // Only symbols missing from the table build their id.
+                    None => any(
+                        &self.symbols,
+                        &InstrumentId::unresolved(ev.symbol()).to_string(),
+                    ),
+                })
+    }
+}
+
+/// A named sink and the events sent to it.
+pub struct Route {
+    pub name: String,
+    pub filter: RouteFilter,
+    pub sink: Arc<dyn Sink>,
+    /// Queueing of events for the sink.
+    pub pipeline: PipelineOptions,
+}
+
+struct RouteHandle {
+    name: String,
+    filter: RouteFilter,
+    pipeline: Pipeline,
+}
+
+/// Sink publishing each event to every route whose filter matches.
+pub struct RouterSink {
+    routes: Vec<RouteHandle>,
+}
+
+impl RouterSink {
+    pub fn new(routes: Vec<Route>, metrics_enabled: bool) -> Self {
+        let names = MetricNames {
+            prefix: "md_route",
+            label: "route",
+        };
+        let routes = routes
+            .into_iter()
+            .map(|route| RouteHandle {
+                pipeline: Pipeline::new(
+                    route.name.clone(),
+                    route.sink,
+                    route.pipeline,
+                    names,
+                    metrics_enabled,
+                ),
+                name: route.name,
+                filter: route.filter,
+            })
+            .collect();
+        Self { routes }
+    }
+
+    /// Build the routes described in the JSON or TOML file at `path`.
+    ///
+    /// Kafka, NATS, ClickHouse, QuestDB and PostgreSQL routes with a `wal`
+    /// directory are written through a [`Wal`] unless `wal` is false.
+    pub async fn from_file(
+        path: impl AsRef<Path>,
+        wal: bool,
+        metrics_enabled: bool,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let buf = tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("reading {}", path.display()))?;
+        let config: RoutesConfig = match serde_json::from_str(&buf) {
+            Ok(config) => config,
+            Err(_) => {
+                toml::from_str(&buf).with_context(|| format!("parsing {}", path.display()))?
+            }
+        };
+        if config.routes.is_empty() {
+            bail!("{} defines no routes", path.display());
+        }
+        let mut routes = Vec::with_capacity(config.routes.len());
+        for route in config.routes {
+            routes.push(route.build(wal, metrics_enabled).await?);
+        }
+        Ok(Self::new(routes, metrics_enabled))
+    }
+}
+
+#[async_trait]
+impl Sink for RouterSink {
+    /// Queue the event on every matching route. Returns an error only if no
+    /// matching route accepted it.
+    async fn publish(&self, event: &MdEvent) -> Result<()> {
+        let mut matched = 0;
+        let mut accepted = false;
+        let mut last_err = None;
+        for route in &self.routes {
+            if !route.filter.matches(event) {
+                continue;
+            }
+            matched += 1;
+            match route.pipeline.publish(event).await {
+                Ok(()) => accepted = true,
+                Err(e) => last_err = Some(e),
+            }
+        }
+        match last_err {
+            Some(e) if !accepted => {
+                Err(e.context(format!("all {matched} matching routes rejected the event")))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Wait for every route to drain and flush its sink. Fails if any route
+    /// dropped or failed to deliver events since the previous flush.
+    async fn flush(&self) -> Result<()> {
+        let mut errors = Vec::new();
+        for route in &self.routes {
+            if let Err(e) = route.pipeline.flush().await {
+                errors.push(format!("{}: {e}", route.name));
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!(errors.join("; ")))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct RoutesConfig {
+    #[serde(default)]
+    routes: Vec<RouteConfig>,
+}
+
+#[derive(Deserialize)]
+struct RouteConfig {
+    name: String,
+    #[serde(default)]
+    exchanges: Vec<String>,
+    #[serde(default)]
+    symbols: Vec<String>,
+    #[serde(default)]
+    channels: Vec<String>,
+    #[serde(default)]
+    kinds: Vec<String>,
+    #[serde(default)]
+    queue: Option<usize>,
+    /// Overflow policy of the route's queue, `drop_newest` by default.
+    #[serde(default)]
+    overflow: Option<String>,
+    sink: SinkConfig,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum SinkConfig {
+    Kafka {
+        brokers: String,
+        #[serde(default = "default_topic")]
+        topic: String,
+        #[serde(default)]
+        dictionary_topic: Option<String>,
+        #[serde(default)]
+        idempotent: bool,
+        /// librdkafka properties passed through to the producer.
+        #[serde(default)]
+        config: BTreeMap<String, String>,
+        #[serde(default)]
+        encoding: Option<String>,
+        /// WAL directory; the Kafka sink is used directly when unset.
+        #[serde(default)]
+        wal: Option<String>,
+    },
+    Nats {
+        url: String,
+        #[serde(default)]
+        subject: Option<String>,
+        #[serde(default)]
+        dictionary_subject: Option<String>,
+        #[serde(default)]
+        jetstream: bool,
+        /// JetStream stream to create if missing; implies `jetstream`.
+        #[serde(default)]
+        stream: Option<String>,
+        #[serde(default)]
+        encoding: Option<String>,
+        /// WAL directory; the NATS sink is used directly when unset.
+        #[serde(default)]
+        wal: Option<String>,
+    },
+    Redis {
+        url: String,
+        #[serde(default)]
+        stream_key: Option<String>,
+        #[serde(default)]
+        max_len: Option<usize>,
+        /// Latest book ticker hash; an empty string disables it.
+        #[serde(default)]
+        book_key: Option<String>,
+        #[serde(default)]
+        batch_size: Option<usize>,
+    },
+    Multicast {
+        /// Multicast group and port, or a unicast address.
+        addr: SocketAddrV4,
+        #[serde(default)]
+        interface: Option<Ipv4Addr>,
+        #[serde(default)]
+        ttl: Option<u32>,
+        #[serde(default)]
+        recovery_addr: Option<SocketAddr>,
+    },
+    ClickHouse {
+        url: String,
+        #[serde(default)]
+        database: Option<String>,
+        #[serde(default)]
+        user: Option<String>,
+        #[serde(default)]
+        password: Option<String>,
+        #[serde(default)]
+        table_prefix: Option<String>,
+        #[serde(default)]
+        batch_size: Option<usize>,
+        /// WAL directory; the ClickHouse sink is used directly when unset.
+        #[serde(default)]
+        wal: Option<String>,
+    },
+    QuestDb {
+        addr: String,
+        #[serde(default)]
+        table_prefix: Option<String>,
+        #[serde(default)]
+        batch_size: Option<usize>,
+        /// WAL directory; the QuestDB sink is used directly when unset.
+        #[serde(default)]
+        wal: Option<String>,
+    },
+    Postgres {
+        url: String,
+        #[serde(default)]
+        schema: Option<String>,
+        #[serde(default)]
+        table_prefix: Option<String>,
+        #[serde(default)]
+        batch_size: Option<usize>,
+        /// WAL directory; the PostgreSQL sink is used directly when unset.
+        #[serde(default)]
+        wal: Option<String>,
+    },
+    File {
+        path: String,
+        #[serde(default)]
+        encoding: Option<String>,
+    },
+    Parquet {
+        dir: String,
+    },
+}
+
+fn default_topic() -> String {
+    "md_events".into()
+}
+
+fn encoding(value: Option<&str>) -> Result<Encoding> {
+    value.map_or(Ok(Encoding::Json), str::parse)
+}
+
+/// Put `sink` behind a WAL in `path`, if given.
+async fn with_wal<T: Sink + 'static>(sink: T, path: Option<String>) -> Result<Arc<dyn Sink>> {
+    Ok(match path {
+        Some(path) => {
+            let wal_encoding = Encoding::from_env("MD_SINK_WAL_ENCODING")?;
+            Arc::new(Wal::new(path, sink, wal_encoding, WalOptions::from_env()?).await?)
+        }
+        None => Arc::new(sink),
+    })
+}
+
+impl RouteConfig {
+    async fn build(self, wal: bool, metrics_enabled: bool) -> Result<Route> {
+        let channels = self
+            .channels
+            .iter()
+            .map(|c| c.parse::<Channel>().map_err(|e| anyhow!(e)))
+            .collect::<Result<_>>()
+            .with_context(|| format!("route {}", self.name))?;
+        let overflow = match &self.overflow {
+            Some(overflow) => overflow
+                .parse()
+                .with_context(|| format!("route {}", self.name))?,
+            None => Overflow::DropNewest,
+        };
+        let sink: Arc<dyn Sink> = match self.sink {
+            SinkConfig::Kafka {
+                brokers,
+                topic,
+                dictionary_topic,
+                idempotent,
+                config,
+                encoding: enc,
+                wal: wal_path,
+            } => {
+                let options = KafkaOptions {
+                    topic,
+                    dictionary_topic,
+                    idempotent,
+                    config: config.into_iter().collect(),
+                };
+                let kafka = KafkaSink::new(&brokers, encoding(enc.as_deref())?, options)?;
+                with_wal(kafka, wal_path.filter(|_| wal)).await?
+            }
+            SinkConfig::Nats {
+                url,
+                subject,
+                dictionary_subject,
+                jetstream,
+                stream,
+                encoding: enc,
+                wal: wal_path,
+            } => {
+                let mut options = NatsOptions {
+                    dictionary_subject,
+                    jetstream: jetstream || stream.is_some(),
+                    stream,
+                    ..Default::default()
+                };
+                if let Some(subject) = subject {
+                    options.subject = subject;
+                }
+                let nats = NatsSink::new(&url, encoding(enc.as_deref())?, options).await?;
+                with_wal(nats, wal_path.filter(|_| wal)).await?
+            }
+            SinkConfig::Redis {
+                url,
+                stream_key,
+                max_len,
+                book_key,
+                batch_size,
+            } => {
+                let defaults = RedisOptions::default();
+                let options = RedisOptions {
+                    stream_key: stream_key.unwrap_or(defaults.stream_key),
+                    max_len: max_len.unwrap_or(defaults.max_len),
+                    book_key: match book_key {
+                        Some(key) => Some(key).filter(|k| !k.is_empty()),
+                        None => defaults.book_key,
+                    },
+                    batch_size: batch_size.unwrap_or(defaults.batch_size),
+                    queue: defaults.queue,
+                };
+                Arc::new(RedisStreamSink::new(&url, options).await?)
+            }
+            SinkConfig::Multicast {
+                addr,
+                interface,
+                ttl,
+                recovery_addr,
+            } => {
+                let defaults = MulticastOptions::from_env()?;
+                let options = MulticastOptions {
+                    interface: interface.unwrap_or(defaults.interface),
+                    ttl: ttl.unwrap_or(defaults.ttl),
+                    recovery_addr: recovery_addr.or(defaults.recovery_addr),
+                    ..defaults
+                };
+                Arc::new(MulticastSink::new(addr, options, metrics_enabled).await?)
+            }
+            SinkConfig::ClickHouse {
+                url,
+                database,
+                user,
+                password,
+                table_prefix,
+                batch_size,
+                wal: wal_path,
+            } => {
+                let mut options = ClickHouseOptions::from_env()?;
+                options.database = database.or(options.database);
+                options.user = user.or(options.user);
+                options.password = password.or(options.password);
+                options.table_prefix = table_prefix.unwrap_or(options.table_prefix);
+                options.batch.max_rows = batch_size.unwrap_or(options.batch.max_rows);
+                let clickhouse = ClickHouseSink::new(&url, options)?;
+                with_wal(clickhouse, wal_path.filter(|_| wal)).await?
+            }
+            SinkConfig::QuestDb {
+                addr,
+                table_prefix,
+                batch_size,
+                wal: wal_path,
+            } => {
+                let mut options = QuestDbOptions::from_env()?;
+                options.table_prefix = table_prefix.unwrap_or(options.table_prefix);
+                options.batch.max_rows = batch_size.unwrap_or(options.batch.max_rows);
+                let questdb = QuestDbSink::new(&addr, options).await?;
+                with_wal(questdb, wal_path.filter(|_| wal)).await?
+            }
+            SinkConfig::Postgres {
+                url,
+                schema,
+                table_prefix,
+                batch_size,
+                wal: wal_path,
+            } => {
+                let mut options = PostgresOptions::from_env()?;
+                options.schema = schema.or(options.schema);
+                options.table_prefix = table_prefix.unwrap_or(options.table_prefix);
+                options.batch.max_rows = batch_size.unwrap_or(options.batch.max_rows);
+                let postgres = PostgresSink::new(&url, options).await?;
+                with_wal(postgres, wal_path.filter(|_| wal)).await?
+            }
+            SinkConfig::File {
+                path,
+                encoding: enc,
+            } => Arc::new(
+                FileSink::new(
+                    path,
+                    encoding(enc.as_deref())?,
+                    FileSinkOptions::from_env()?,
+                )
+                .await?,
+            ),
+            SinkConfig::Parquet { dir } => {
+                Arc::new(ParquetSink::new(dir, ParquetOptions::from_env()?)?)
+            }
+        };
+        Ok(Route {
+            name: self.name,
+            filter: RouteFilter {
+                exchanges: self.exchanges,
+                symbols: self.symbols,
+                channels,
+                kinds: self.kinds,
+            },
+            sink,
+            pipeline: PipelineOptions {
+                capacity: self.queue.unwrap_or(DEFAULT_QUEUE),
+                overflow,
+                ..PipelineOptions::from_env()?
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use canonical::{BookTicker, MdEventKind, Trade};
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use tokio::sync::Notify;
+
+    #[derive(Default)]
+    struct Capture {
+        seen: Mutex<Vec<MdEvent>>,
+        /// When set, publishing blocks until notified.
+        gate: Option<Arc<Notify>>,
+    }
+
+    #[async_trait]
+    impl Sink for Capture {
+        async fn publish(&self, event: &MdEvent) -> Result<()> {
+            if let Some(gate) = &self.gate {
+                gate.notified().await;
+            }
+            self.seen.lock().unwrap().push(event.clone());
+            Ok(())
+        }
+
+        async fn flush(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn trade(exchange: &str) -> MdEvent {
+        MdEvent {
+            schema_version: canonical::SCHEMA_VERSION,
+            event: MdEventKind::Trade(Trade {
+                exchange: exchange.into(),
+                symbol: "BTCUSDT".into(),
+                ..Default::default()
+            }),
+            validation: None,
+        }
+    }
+
+    fn book(exchange: &str) -> MdEvent {
+        MdEvent {
+            schema_version: canonical::SCHEMA_VERSION,
+            event: MdEventKind::BookTicker(BookTicker {
+                exchange: exchange.into(),
+                symbol: "BTCUSDT".into(),
+                ..Default::default()
+            }),
+            validation: None,
+        }
+    }
+
+    fn route(name: &str, filter: RouteFilter, sink: Arc<Capture>, queue: usize) -> Route {
+        Route {
+            name: name.into(),
+            filter,
+            sink,
+            pipeline: PipelineOptions {
+                capacity: queue,
+                overflow: Overflow::DropNewest,
+                ..Default::default()
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn routes_by_filter_and_isolates_slow_sinks() {
+        let trades = Arc::new(Capture::default());
+        let binance_books = Arc::new(Capture::default());
+        let gate = Arc::new(Notify::new());
+        let slow = Arc::new(Capture {
+            gate: Some(gate.clone()),
+            ..Default::default()
+        });
+        let router = RouterSink::new(
+            vec![
+                route(
+                    "trades",
+                    RouteFilter {
+                        channels: vec![Channel::Trade],
+                        ..Default::default()
+                    },
+                    trades.clone(),
+                    16,
+                ),
+                route(
+                    "books",
+                    RouteFilter {
+                        exchanges: vec!["binance".into()],
+                        kinds: vec!["bookticker".into()],
+                        ..Default::default()
+                    },
+                    binance_books.clone(),
+                    16,
+                ),
+                route("all", RouteFilter::default(), slow.clone(), 1),
+            ],
+            false,
+        );
+
+        router.publish(&trade("binance")).await.unwrap();
+        router.publish(&book("binance")).await.unwrap();
+        // Only the full catch-all route matches a gateio book.
+        assert!(router.publish(&book("gateio")).await.is_err());
+        router.publish(&trade("gateio")).await.unwrap();
+        // The blocked route does not hold up the others.
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while trades.seen.lock().unwrap().len() < 2 {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .expect("trades delivered");
+        assert_eq!(binance_books.seen.lock().unwrap().len(), 1);
+
+        // The slow route's queue held one event, so the other three were
+        // dropped; flush reports them once it drains.
+        gate.notify_one();
+        let err = router.flush().await.unwrap_err();
+        assert!(
+            err.to_string().contains("all: 3 events not delivered"),
+            "{err}"
+        );
+        assert_eq!(slow.seen.lock().unwrap().len(), 1);
+        router.flush().await.unwrap();
+    }
+
+    #[test]
+    fn parses_route_config() {
+        let config: RoutesConfig = toml::from_str(
+            r#"
+            [[routes]]
+            name = "trades"
+            channels = ["trade"]
+            sink = { type = "kafka", brokers = "localhost:9092", wal = "trades.wal" }
+
+            [[routes]]
+            name = "books"
+            overflow = "conflate"
+            sink = { type = "nats", url = "nats://localhost:4222", stream = "MD" }
+
+            [[routes]]
+            name = "archive"
+            sink = { type = "parquet", dir = "data" }
+
+            [[routes]]
+            name = "lan"
+            sink = { type = "multicast", addr = "239.1.1.1:30001", ttl = 2 }
+
+            [[routes]]
+            name = "ticks"
+            sink = { type = "questdb", addr = "localhost:9009", wal = "ticks.wal" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.routes.len(), 5);
+        assert!(matches!(
+            &config.routes[0].sink,
+            SinkConfig::Kafka { topic, wal: Some(_), .. } if topic == "md_events"
+        ));
+        assert_eq!(config.routes[1].overflow.as_deref(), Some("conflate"));
+        assert!(matches!(
+            &config.routes[1].sink,
+            SinkConfig::Nats { stream: Some(s), subject: None, .. } if s == "MD"
+        ));
+        assert!(matches!(config.routes[2].sink, SinkConfig::Parquet { .. }));
+        assert!(matches!(
+            config.routes[3].sink,
+            SinkConfig::Multicast {
+                ttl: Some(2),
+                interface: None,
+                ..
+            }
+        ));
+        assert!(matches!(
+            &config.routes[4].sink,
+            SinkConfig::QuestDb { wal: Some(w), batch_size: None, .. } if w == "ticks.wal"
+        ));
+    }
+}
@@ -0,0 +1,167 @@
+//! Table-per-kind layout of the column-store sinks.
+//!
+//! Columns follow the Parquet schema of each event kind, so every columnar
+//! output agrees on names and types, with the exchange added as a column and
+//! optional structs flattened into nullable `<struct>_<field>` columns.
+
+use anyhow::Result;
+use arrow_schema::DataType;
+use canonical::MdEvent;
+use serde_json::Value;
+
+use super::parquet::{schema, Row};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ColumnType {
+    UInt32,
+    UInt64,
+    Float64,
+    String,
+    /// Book levels, each a price, a quantity and a side.
+    Levels,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Column {
+    pub name: String,
+    pub ty: ColumnType,
+    pub nullable: bool,
+    /// Struct holding the field, for flattened columns.
+    parent: Option<String>,
+    field: String,
+}
+
+impl Column {
+    fn new(name: &str, ty: &DataType, nullable: bool, parent: Option<&str>) -> Option<Self> {
+        let ty = match ty {
+            DataType::UInt32 => ColumnType::UInt32,
+            DataType::UInt64 => ColumnType::UInt64,
+            DataType::Float64 => ColumnType::Float64,
+            DataType::Utf8 => ColumnType::String,
+            DataType::List(_) => ColumnType::Levels,
+            _ => return None,
+        };
+        Some(Self {
+            name: match parent {
+                Some(parent) => format!("{parent}_{name}"),
+                None => name.to_string(),
+            },
+            ty,
+            nullable,
+            parent: parent.map(str::to_string),
+            field: name.to_string(),
+        })
+    }
+
+    /// The column's value in a [`row`], or `None` when it is null.
+    pub fn value<'a>(&self, row: &'a Value) -> Option<&'a Value> {
+        let parent = match &self.parent {
+            Some(parent) => row.get(parent)?,
+            None => row,
+        };
+        parent.get(&self.field).filter(|v| !v.is_null())
+    }
+}
+
+/// Columns of `kind`, starting with the exchange and symbol.
+pub(crate) fn columns(kind: &str) -> Vec<Column> {
+    let schema = schema(kind);
+    let mut columns = vec![Column::new("exchange", &DataType::Utf8, false, None).expect("utf8")];
+    for field in schema.fields() {
+        match field.data_type() {
+            DataType::Struct(children) => columns.extend(
+                children
+                    .iter()
+                    .filter_map(|c| Column::new(c.name(), c.data_type(), true, Some(field.name()))),
+            ),
+            ty => columns.extend(Column::new(field.name(), ty, field.is_nullable(), None)),
+        }
+    }
+    columns
+}
+
+/// Payload of `ev` as a JSON object, read through [`Column::value`].
+pub(crate) fn row(ev: &MdEvent) -> Result<Value> {
+    Ok(serde_json::to_value(Row(ev))?)
+}
+
+/// Table of `kind`: `prefix` followed by the kind in snake case, e.g.
+/// `md_depth_l2_update`.
+pub(crate) fn table_name(prefix: &str, kind: &str) -> String {
+    let mut name = prefix.to_string();
+    let mut prev_lower = false;
+    for c in kind.chars() {
+        if c.is_ascii_uppercase() && prev_lower {
+            name.push('_');
+        }
+        prev_lower = c.is_ascii_lowercase() || c.is_ascii_digit();
+        name.push(c.to_ascii_lowercase());
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use canonical::{ContractSpec, InstrumentChange, InstrumentUpdate, MdEventKind};
+
+    #[test]
+    fn flattens_structs_and_names_tables() {
+        let names = |kind| {
+            columns(kind)
+                .into_iter()
+                .map(|c| c.name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names("BookTicker"),
+            [
+                "exchange",
+                "schema_version",
+                "symbol",
+                "ts",
+                "bid_price",
+                "bid_quantity",
+                "ask_price",
+                "ask_quantity",
+                "ingest_ts_monotonic",
+                "ingest_ts_utc",
+                "seq_no",
+            ]
+        );
+        let listing = columns("InstrumentUpdate");
+        let base = listing.iter().find(|c| c.name == "spec_base").unwrap();
+        assert_eq!((base.ty, base.nullable), (ColumnType::String, true));
+        assert!(columns("DepthSnapshot")
+            .iter()
+            .any(|c| c.name == "bids" && c.ty == ColumnType::Levels));
+
+        let ev = MdEvent {
+            schema_version: canonical::SCHEMA_VERSION,
+            event: MdEventKind::InstrumentUpdate(InstrumentUpdate {
+                schema_version: canonical::SCHEMA_VERSION,
+                exchange: "binance".into(),
+                symbol: "BTCUSDT".into(),
+                instrument: "BTC-USDT".parse().unwrap(),
+                change: InstrumentChange::Added,
+                spec: Some(ContractSpec {
+                    base: "BTC".into(),
+                    ..Default::default()
+                }),
+                ts: 0,
+                ingest_ts_monotonic: 0,
+                ingest_ts_utc: 0,
+                seq_no: 0,
+            }),
+            validation: None,
+        };
+        let row = row(&ev).unwrap();
+        assert_eq!(base.value(&row), Some(&Value::from("BTC")));
+        let expiry = listing.iter().find(|c| c.name == "spec_expiry").unwrap();
+        assert_eq!(expiry.value(&row), None);
+        assert_eq!(listing[0].value(&row), Some(&Value::from("binance")));
+
+        assert_eq!(table_name("md_", "DepthL2Update"), "md_depth_l2_update");
+        assert_eq!(table_name("", "InstrumentUpdate"), "instrument_update");
+    }
+}
@@ -0,0 +1,1019 @@
+//! Segmented write-ahead log in front of a sink.
+//!
+//! Events are appended to numbered segment files in the log directory by a
+//! single writer task, which commits whatever has queued up with one
+//! `sync_data` (group commit) before `publish` returns. A second task
+//! forwards committed events to the inner sink in batches, in log order, and
+//! checkpoints the last acknowledged offset into the segment's `.ack` file
+//! when flushed, when a segment can be released, or periodically. Segments whose
+//! records are all acknowledged are deleted, and appends wait while the live
+//! segments exceed the configured size, which bounds disk usage.
+//!
+//! Record layout: `u32` payload length, `u64` offset, then the payload in the
+//! log's [`Encoding`]. Binary segments restart their dictionary so each file
+//! decodes on its own. Delivery is at least once: records appended after the
+//! last checkpoint are replayed on restart.
+
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use canonical::MdEvent;
+use std::collections::VecDeque;
+use std::env;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::fs::{self, OpenOptions};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::{mpsc, oneshot, Notify};
+use tokio::time::{self, Instant};
+use tracing::{error, warn};
+
+use super::dlq::DeadLetterQueue;
+use super::encoding::{decode_all, Encoding, EventDecoder, EventEncoder};
+use super::Sink;
+
+const HEADER_LEN: usize = 12;
+
+/// Size limits, batching and retries of a [`Wal`].
+#[derive(Debug, Clone, Copy)]
+pub struct WalOptions {
+    /// Roll to a new segment once the active one reaches this many bytes.
+    pub segment_bytes: u64,
+    /// Appends wait while the live segments hold more than this many bytes.
+    /// Must exceed `segment_bytes`, since the active segment is only released
+    /// once sealed.
+    pub max_bytes: u64,
+    /// Most events committed or forwarded in one batch.
+    pub batch_size: usize,
+    /// Attempts after the first before a forwarded batch is dead-lettered.
+    pub retries: u32,
+    /// Wait before the first retry, doubled for every further one up to a
+    /// second.
+    pub backoff: Duration,
+    /// Longest time forwarded events go without a checkpoint.
+    pub checkpoint_interval: Duration,
+}
+
+impl Default for WalOptions {
+    fn default() -> Self {
+        Self {
+            segment_bytes: 64 * 1024 * 1024,
+            max_bytes: 1024 * 1024 * 1024,
+            batch_size: 1024,
+            retries: 3,
+            backoff: Duration::from_millis(100),
+            checkpoint_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+impl WalOptions {
+    /// Read `MD_SINK_WAL_SEGMENT_BYTES` and `MD_SINK_WAL_MAX_BYTES`, falling
+    /// back to the defaults.
+    pub fn from_env() -> Result<Self> {
+        let mut opts = Self::default();
+        if let Ok(v) = env::var("MD_SINK_WAL_SEGMENT_BYTES") {
+            opts.segment_bytes = v.trim().parse().context("MD_SINK_WAL_SEGMENT_BYTES")?;
+        }
+        if let Ok(v) = env::var("MD_SINK_WAL_MAX_BYTES") {
+            opts.max_bytes = v.trim().parse().context("MD_SINK_WAL_MAX_BYTES")?;
+        }
+        opts.validate()?;
+        Ok(opts)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.max_bytes <= self.segment_bytes {
+            bail!(
+                "wal max bytes ({}) must exceed the segment size ({})",
+                self.max_bytes,
+                self.segment_bytes
+            );
+        }
+        Ok(())
+    }
+}
+
+enum Command {
+    Append(Box<MdEvent>, oneshot::Sender<Result<()>>),
+    Flush(oneshot::Sender<Result<()>>),
+}
+
+enum Forward {
+    Event(u64, Box<MdEvent>),
+    Flush(oneshot::Sender<Result<()>>),
+}
+
+/// A segment no longer written to.
+struct Sealed {
+    base: u64,
+    last: u64,
+    bytes: u64,
+}
+
+/// State shared by the writer and the forwarder.
+struct Shared {
+    dir: PathBuf,
+    sealed: Mutex<VecDeque<Sealed>>,
+    active_base: AtomicU64,
+    live_bytes: AtomicU64,
+    space: Notify,
+}
+
+impl Shared {
+    fn log_path(&self, base: u64) -> PathBuf {
+        self.dir.join(format!("{base:020}.log"))
+    }
+
+    fn ack_path(&self, base: u64) -> PathBuf {
+        self.dir.join(format!("{base:020}.ack"))
+    }
+
+    /// Base offset of the segment holding `offset`.
+    fn segment_of(&self, offset: u64) -> u64 {
+        let active = self.active_base.load(Ordering::Acquire);
+        if offset >= active {
+            return active;
+        }
+        let sealed = self.sealed.lock().expect("wal mutex poisoned");
+        sealed
+            .iter()
+            .rev()
+            .find(|s| s.base <= offset)
+            .map_or(active, |s| s.base)
+    }
+
+    /// Whether acknowledging `offset` would delete a sealed segment.
+    fn releases(&self, offset: u64) -> bool {
+        let sealed = self.sealed.lock().expect("wal mutex poisoned");
+        sealed.front().is_some_and(|s| s.last <= offset)
+    }
+
+    async fn wait_for_space(&self, max_bytes: u64) {
+        loop {
+            let notified = self.space.notified();
+            if self.live_bytes.load(Ordering::Acquire) < max_bytes {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// Sink that makes events durable in a write-ahead log before handing them to
+/// `inner`.
+pub struct Wal<T: Sink> {
+    commands: mpsc::Sender<Command>,
+    dlq: Arc<DeadLetterQueue>,
+    _inner: std::marker::PhantomData<fn() -> T>,
+}
+
+impl<T: Sink + 'static> Wal<T> {
+    /// Create a WAL in the directory `path`.
+    ///
+    /// Unacknowledged records left by a previous run are replayed into
+    /// `inner` first, using the same encoding. Entries routed to the `.dlq`
+    /// file next to the directory are always JSON lines.
+    pub async fn new(
+        path: impl AsRef<Path>,
+        inner: T,
+        encoding: Encoding,
+        options: WalOptions,
+    ) -> Result<Self> {
+        options.validate()?;
+        let path = path.as_ref();
+        let mut dlq_path = path.to_path_buf();
+        dlq_path.set_extension("dlq");
+        let dlq = Arc::new(DeadLetterQueue::open(&dlq_path).await?);
+
+        replay_legacy(path, &inner, &dlq, encoding, &options).await?;
+        fs::create_dir_all(path).await?;
+        let next = recover(path, &inner, &dlq, encoding, &options).await?;
+
+        let shared = Arc::new(Shared {
+            dir: path.to_path_buf(),
+            sealed: Mutex::new(VecDeque::new()),
+            active_base: AtomicU64::new(next),
+            live_bytes: AtomicU64::new(0),
+            space: Notify::new(),
+        });
+        let writer = Writer::open(shared.clone(), encoding, options, next).await?;
+
+        let (commands, command_rx) = mpsc::channel(options.batch_size);
+        let (forward, forward_rx) = mpsc::channel(options.batch_size);
+        tokio::spawn(writer.run(command_rx, forward));
+        tokio::spawn(run_forwarder(
+            Arc::new(inner),
+            dlq.clone(),
+            shared,
+            forward_rx,
+            options,
+        ));
+
+        Ok(Self {
+            commands,
+            dlq,
+            _inner: std::marker::PhantomData,
+        })
+    }
+
+    /// Dead letter queue next to the log, shared so other stages can
+    /// quarantine events into it.
+    pub fn dead_letters(&self) -> Arc<DeadLetterQueue> {
+        self.dlq.clone()
+    }
+
+    async fn send(
+        &self,
+        command: impl FnOnce(oneshot::Sender<Result<()>>) -> Command,
+    ) -> Result<()> {
+        let (done, rx) = oneshot::channel();
+        self.commands
+            .send(command(done))
+            .await
+            .map_err(|_| anyhow!("wal writer stopped"))?;
+        rx.await.map_err(|_| anyhow!("wal writer stopped"))?
+    }
+}
+
+#[async_trait]
+impl<T: Sink + 'static> Sink for Wal<T> {
+    /// Returns once the event is durable in the log; delivery to the inner
+    /// sink happens in the background.
+    async fn publish(&self, event: &MdEvent) -> Result<()> {
+        let event = Box::new(event.clone());
+        self.send(|done| Command::Append(event, done)).await
+    }
+
+    /// Queue every event before waiting, so they share the writer's commits.
+    async fn publish_batch(&self, events: &[MdEvent]) -> Result<()> {
+        let mut pending = Vec::with_capacity(events.len());
+        for event in events {
+            let (done, rx) = oneshot::channel();
+            self.commands
+                .send(Command::Append(Box::new(event.clone()), done))
+                .await
+                .map_err(|_| anyhow!("wal writer stopped"))?;
+            pending.push(rx);
+        }
+        for rx in pending {
+            rx.await.map_err(|_| anyhow!("wal writer stopped"))??;
+        }
+        Ok(())
+    }
+
+    /// Wait until everything published so far has been acknowledged by the
+    /// inner sink and checkpointed.
+    async fn flush(&self) -> Result<()> {
+        self.send(Command::Flush).await
+    }
+}
+
+/// Owner of the active segment.
+struct Writer {
+    shared: Arc<Shared>,
+    options: WalOptions,
+    encoder: EventEncoder,
+    file: fs::File,
+    base: u64,
+    next: u64,
+    size: u64,
+    buf: Vec<u8>,
+    payload: Vec<u8>,
+}
+
+impl Writer {
+    async fn open(
+        shared: Arc<Shared>,
+        encoding: Encoding,
+        options: WalOptions,
+        base: u64,
+    ) -> Result<Self> {
+        let file = create_segment(&shared.log_path(base)).await?;
+        Ok(Self {
+            shared,
+            options,
+            encoder: EventEncoder::new(encoding),
+            file,
+            base,
+            next: base,
+            size: 0,
+            buf: Vec::new(),
+            payload: Vec::new(),
+        })
+    }
+
+    async fn run(mut self, mut commands: mpsc::Receiver<Command>, forward: mpsc::Sender<Forward>) {
+        while let Some(first) = commands.recv().await {
+            let mut batch = vec![first];
+            while batch.len() < self.options.batch_size {
+                match commands.try_recv() {
+                    Ok(cmd) => batch.push(cmd),
+                    Err(_) => break,
+                }
+            }
+            // Seal a full segment before waiting, so that acknowledging it
+            // can free space.
+            if self.size >= self.options.segment_bytes && self.next > self.base {
+                if let Err(e) = self.roll().await {
+                    error!(error = %e, "failed to roll wal segment");
+                }
+            }
+            self.shared.wait_for_space(self.options.max_bytes).await;
+
+            let mut appended = Vec::new();
+            let mut flushes = Vec::new();
+            for cmd in batch {
+                match cmd {
+                    Command::Append(ev, done) => match self.append(&ev).await {
+                        Ok(offset) => appended.push((offset, ev, done)),
+                        Err(e) => {
+                            let _ = done.send(Err(e));
+                        }
+                    },
+                    Command::Flush(done) => flushes.push(done),
+                }
+            }
+
+            match self.commit().await {
+                Ok(()) => {
+                    for (offset, ev, done) in appended {
+                        let _ = done.send(Ok(()));
+                        if forward.send(Forward::Event(offset, ev)).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+                Err(e) => {
+                    error!(error = %e, "failed to commit wal batch");
+                    for (_, _, done) in appended {
+                        let _ = done.send(Err(anyhow!("wal commit failed: {e}")));
+                    }
+                }
+            }
+            for done in flushes {
+                if forward.send(Forward::Flush(done)).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Buffer one record, rolling to a new segment when the active one is
+    /// full.
+    async fn append(&mut self, ev: &MdEvent) -> Result<u64> {
+        if self.size + self.buf.len() as u64 >= self.options.segment_bytes && self.next > self.base
+        {
+            self.roll().await?;
+        }
+        self.payload.clear();
+        self.encoder.encode(ev, &mut self.payload)?;
+        let offset = self.next;
+        self.buf
+            .extend_from_slice(&(self.payload.len() as u32).to_le_bytes());
+        self.buf.extend_from_slice(&offset.to_le_bytes());
+        self.buf.extend_from_slice(&self.payload);
+        self.next += 1;
+        Ok(offset)
+    }
+
+    /// Write buffered records and sync them to disk. On failure the buffered
+    /// records are discarded and the segment cut back to the last commit.
+    async fn commit(&mut self) -> Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let written = self.buf.len() as u64;
+        let res = async {
+            self.file.write_all(&self.buf).await?;
+            // Surfaces the write's error, which `sync_data` would not.
+            self.file.flush().await?;
+            self.file.sync_data().await
+        }
+        .await;
+        self.buf.clear();
+        if let Err(e) = res {
+            if let Err(e) = self.rollback().await {
+                error!(error = %e, base = self.base, "failed to truncate wal segment");
+            }
+            return Err(e.into());
+        }
+        self.size += written;
+        self.shared.live_bytes.fetch_add(written, Ordering::AcqRel);
+        Ok(())
+    }
+
+    /// Truncate the segment to the records committed so far. Dictionary
+    /// frames may have been lost with the rest, so the encoder starts over.
+    async fn rollback(&mut self) -> Result<()> {
+        self.file.set_len(self.size).await?;
+        self.file.seek(SeekFrom::Start(self.size)).await?;
+        self.encoder.reset();
+        Ok(())
+    }
+
+    async fn roll(&mut self) -> Result<()> {
+        self.commit().await?;
+        let base = self.next;
+        let file = create_segment(&self.shared.log_path(base)).await?;
+        let sealed = Sealed {
+            base: self.base,
+            last: self.next - 1,
+            bytes: self.size,
+        };
+        self.shared
+            .sealed
+            .lock()
+            .expect("wal mutex poisoned")
+            .push_back(sealed);
+        self.shared.active_base.store(base, Ordering::Release);
+        self.file = file;
+        self.base = base;
+        self.size = 0;
+        // Each segment carries its own dictionary frames.
+        self.encoder.reset();
+        Ok(())
+    }
+}
+
+async fn create_segment(path: &Path) -> Result<fs::File> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(true)
+        .write(true)
+        .open(path)
+        .await
+        .with_context(|| format!("creating wal segment {}", path.display()))?;
+    file.sync_all().await?;
+    Ok(file)
+}
+
+async fn run_forwarder<T: Sink>(
+    inner: Arc<T>,
+    dlq: Arc<DeadLetterQueue>,
+    shared: Arc<Shared>,
+    mut forward: mpsc::Receiver<Forward>,
+    options: WalOptions,
+) {
+    let mut batch = Vec::with_capacity(options.batch_size);
+    let mut events = Vec::with_capacity(options.batch_size);
+    // Newest forwarded offset past the checkpoint, and when it is due.
+    let mut unchecked = None;
+    let mut deadline = Instant::now();
+    let limit = options.batch_size.max(1);
+    loop {
+        let open = match unchecked {
+            Some(_) => time::timeout_at(deadline, forward.recv_many(&mut batch, limit))
+                .await
+                .map_or(true, |n| n > 0),
+            None => forward.recv_many(&mut batch, limit).await > 0,
+        };
+
+        let mut flushes = Vec::new();
+        for item in batch.drain(..) {
+            match item {
+                Forward::Event(offset, ev) => {
+                    if unchecked.is_none() {
+                        deadline = Instant::now() + options.checkpoint_interval;
+                    }
+                    unchecked = Some(offset);
+                    events.push(*ev);
+                }
+                Forward::Flush(done) => flushes.push(done),
+            }
+        }
+        if !events.is_empty() {
+            forward_batch(inner.as_ref(), &dlq, &events, &options).await;
+            events.clear();
+        }
+
+        let due = !open
+            || !flushes.is_empty()
+            || Instant::now() >= deadline
+            || unchecked.is_some_and(|offset| shared.releases(offset));
+        if !due {
+            continue;
+        }
+        let res = async {
+            inner.flush().await?;
+            if let Some(offset) = unchecked {
+                checkpoint(&shared, offset).await?;
+            }
+            Ok::<_, anyhow::Error>(())
+        }
+        .await;
+        match &res {
+            Ok(()) => unchecked = None,
+            Err(e) => {
+                error!(error = %e, "failed to checkpoint wal");
+                deadline = Instant::now() + options.checkpoint_interval;
+            }
+        }
+        for done in flushes {
+            let _ = done.send(res.as_ref().map(|_| ()).map_err(|e| anyhow!("{e}")));
+        }
+        if !open {
+            return;
+        }
+    }
+}
+
+/// Publish `events` to the inner sink, retrying with backoff, and dead-letter
+/// them if every attempt fails.
+async fn forward_batch<T: Sink + ?Sized>(
+    inner: &T,
+    dlq: &DeadLetterQueue,
+    events: &[MdEvent],
+    options: &WalOptions,
+) {
+    let mut delay = options.backoff;
+    let mut attempt = 0;
+    loop {
+        match inner.publish_batch(events).await {
+            Ok(()) => return,
+            Err(e) if attempt < options.retries => {
+                attempt += 1;
+                warn!(error = %e, events = events.len(), attempt, "inner sink rejected batch, retrying");
+                time::sleep(delay).await;
+                delay = (delay * 2).min(Duration::from_secs(1));
+            }
+            Err(e) => {
+                warn!(error = %e, events = events.len(), "inner sink rejected batch, dead-lettering");
+                for ev in events {
+                    if let Err(e) = dlq.push(ev, "publish", &e.to_string()).await {
+                        error!(error = %e, "failed to dead-letter event");
+                    }
+                }
+                return;
+            }
+        }
+    }
+}
+
+/// Record `offset` as acknowledged and delete segments it fully covers.
+async fn checkpoint(shared: &Shared, offset: u64) -> Result<()> {
+    let base = shared.segment_of(offset);
+    fs::write(shared.ack_path(base), offset.to_le_bytes()).await?;
+
+    let released: Vec<Sealed> = {
+        let mut sealed = shared.sealed.lock().expect("wal mutex poisoned");
+        let mut released = Vec::new();
+        while sealed.front().is_some_and(|s| s.last <= offset) {
+            released.extend(sealed.pop_front());
+        }
+        released
+    };
+    for segment in released {
+        remove_segment(shared, segment.base).await;
+        shared.live_bytes.fetch_sub(segment.bytes, Ordering::AcqRel);
+    }
+    shared.space.notify_waiters();
+    Ok(())
+}
+
+async fn remove_segment(shared: &Shared, base: u64) {
+    for path in [shared.log_path(base), shared.ack_path(base)] {
+        if let Err(e) = fs::remove_file(&path).await {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                warn!(error = %e, path = %path.display(), "failed to remove wal segment");
+            }
+        }
+    }
+}
+
+/// Replay a WAL written by the earlier single-file format, then remove it so
+/// the directory layout can take its place.
+async fn replay_legacy<T: Sink>(
+    path: &Path,
+    inner: &T,
+    dlq: &DeadLetterQueue,
+    encoding: Encoding,
+    options: &WalOptions,
+) -> Result<()> {
+    match fs::metadata(path).await {
+        Ok(meta) if meta.is_file() => {}
+        _ => return Ok(()),
+    }
+    let existing = fs::read(path).await?;
+    let mut replay = Replay::new(inner, dlq, options);
+    for record in decode_all(encoding, &existing) {
+        replay.push(record).await?;
+    }
+    replay.finish().await;
+    inner.flush().await?;
+    fs::remove_file(path).await?;
+    Ok(())
+}
+
+/// Replay records past each segment's checkpoint and delete the segments.
+/// Returns the next offset to assign.
+async fn recover<T: Sink>(
+    dir: &Path,
+    inner: &T,
+    dlq: &DeadLetterQueue,
+    encoding: Encoding,
+    options: &WalOptions,
+) -> Result<u64> {
+    let mut bases = Vec::new();
+    let mut entries = fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if let Some(base) = name
+            .strip_suffix(".log")
+            .and_then(|b| b.parse::<u64>().ok())
+        {
+            bases.push(base);
+        }
+    }
+    bases.sort_unstable();
+
+    let mut next = 0;
+    for base in bases {
+        let log_path = dir.join(format!("{base:020}.log"));
+        let ack_path = dir.join(format!("{base:020}.ack"));
+        let acked = fs::read(&ack_path)
+            .await
+            .ok()
+            .and_then(|b| b.try_into().ok())
+            .map(u64::from_le_bytes);
+        let data = fs::read(&log_path).await?;
+        let mut decoder = EventDecoder::new(encoding);
+        let mut replay = Replay::new(inner, dlq, options);
+        let mut rest = data.as_slice();
+        while rest.len() >= HEADER_LEN {
+            let len = u32::from_le_bytes(rest[..4].try_into().expect("4 bytes")) as usize;
+            let offset = u64::from_le_bytes(rest[4..HEADER_LEN].try_into().expect("8 bytes"));
+            let Some(payload) = rest.get(HEADER_LEN..HEADER_LEN + len) else {
+                warn!(segment = %log_path.display(), offset, "truncated wal record, discarding");
+                break;
+            };
+            rest = &rest[HEADER_LEN + len..];
+            next = next.max(offset + 1);
+            // Acknowledged records are still decoded for their dictionary
+            // frames.
+            let records = decoder.decode(payload);
+            if acked.is_some_and(|a| offset <= a) {
+                continue;
+            }
+            for record in records {
+                replay.push(record).await?;
+            }
+        }
+        replay.finish().await;
+        inner.flush().await?;
+        fs::remove_file(&log_path).await?;
+        let _ = fs::remove_file(&ack_path).await;
+    }
+    Ok(next)
+}
+
+/// Recovered records, forwarded in batches like live ones.
+struct Replay<'a, T> {
+    inner: &'a T,
+    dlq: &'a DeadLetterQueue,
+    options: &'a WalOptions,
+    events: Vec<MdEvent>,
+}
+
+impl<'a, T: Sink> Replay<'a, T> {
+    fn new(inner: &'a T, dlq: &'a DeadLetterQueue, options: &'a WalOptions) -> Self {
+        Self {
+            inner,
+            dlq,
+            options,
+            events: Vec::new(),
+        }
+    }
+
+    async fn push(&mut self, record: Result<MdEvent, Vec<u8>>) -> Result<()> {
+        match record {
+            Ok(ev) => {
+                self.events.push(ev);
+                if self.events.len() >= self.options.batch_size {
+                    self.finish().await;
+                }
+            }
+            Err(line) => self.dlq.push_raw(&line).await?,
+        }
+        Ok(())
+    }
+
+    /// Forward what is left.
+    async fn finish(&mut self) {
+        if !self.events.is_empty() {
+            forward_batch(self.inner, self.dlq, &self.events, self.options).await;
+            self.events.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::read_dead_letters;
+    use canonical::{MdEventKind, Trade};
+
+    #[derive(Default, Clone)]
+    struct Capture(Arc<Mutex<Vec<MdEvent>>>);
+
+    #[async_trait]
+    impl Sink for Capture {
+        async fn publish(&self, event: &MdEvent) -> Result<()> {
+            self.0.lock().unwrap().push(event.clone());
+            Ok(())
+        }
+
+        async fn flush(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Fails the first `fail` batches and counts batches and flushes.
+    #[derive(Default)]
+    struct Flaky {
+        fail: usize,
+        batches: AtomicU64,
+        flushes: AtomicU64,
+        seen: Mutex<Vec<MdEvent>>,
+    }
+
+    #[async_trait]
+    impl Sink for Arc<Flaky> {
+        async fn publish(&self, event: &MdEvent) -> Result<()> {
+            self.publish_batch(std::slice::from_ref(event)).await
+        }
+
+        async fn publish_batch(&self, events: &[MdEvent]) -> Result<()> {
+            let n = self.batches.fetch_add(1, Ordering::SeqCst);
+            if (n as usize) < self.fail {
+                return Err(anyhow!("unavailable"));
+            }
+            self.seen.lock().unwrap().extend_from_slice(events);
+            Ok(())
+        }
+
+        async fn flush(&self) -> Result<()> {
+            self.flushes.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    fn trade(id: u64) -> MdEvent {
+        MdEvent {
+            schema_version: canonical::SCHEMA_VERSION,
+            event: MdEventKind::Trade(Trade {
+                exchange: "binance".into(),
+                symbol: "BTCUSDT".into(),
+                trade_id: Some(id),
+                ..Default::default()
+            }),
+            validation: None,
+        }
+    }
+
+    fn trade_ids(events: &[MdEvent]) -> Vec<u64> {
+        events
+            .iter()
+            .filter_map(|ev| match &ev.event {
+                MdEventKind::Trade(t) => t.trade_id,
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let mut path = env::temp_dir();
+        path.push(format!(
+            "{name}_{}_{}.wal",
+            std::process::id(),
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        ));
+        path
+    }
+
+    fn small_segments() -> WalOptions {
+        WalOptions {
+            segment_bytes: 512,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn forwards_in_order_and_releases_segments() {
+        let path = temp_dir("wal_forward");
+        let capture = Capture::default();
+        let wal = Wal::new(&path, capture.clone(), Encoding::Json, small_segments())
+            .await
+            .unwrap();
+        for id in 0..50 {
+            wal.publish(&trade(id)).await.unwrap();
+        }
+        wal.flush().await.unwrap();
+
+        let seen = capture.0.lock().unwrap().clone();
+        assert_eq!(trade_ids(&seen), (0..50).collect::<Vec<_>>());
+
+        // Only the active segment and its checkpoint remain.
+        let mut entries = fs::read_dir(&path).await.unwrap();
+        let mut logs = 0;
+        while let Some(entry) = entries.next_entry().await.unwrap() {
+            if entry.file_name().to_string_lossy().ends_with(".log") {
+                logs += 1;
+            }
+        }
+        assert_eq!(logs, 1);
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    #[tokio::test]
+    async fn forwards_batches_and_checkpoints_when_flushed() {
+        let path = temp_dir("wal_batches");
+        let sink = Arc::new(Flaky::default());
+        let options = WalOptions {
+            checkpoint_interval: Duration::from_secs(60),
+            ..Default::default()
+        };
+        let wal = Wal::new(&path, sink.clone(), Encoding::Json, options)
+            .await
+            .unwrap();
+        let events: Vec<_> = (0..200).map(trade).collect();
+        wal.publish_batch(&events).await.unwrap();
+        wal.flush().await.unwrap();
+
+        assert_eq!(
+            trade_ids(&sink.seen.lock().unwrap()),
+            (0..200).collect::<Vec<_>>()
+        );
+        assert!(sink.batches.load(Ordering::SeqCst) < 200);
+        assert_eq!(sink.flushes.load(Ordering::SeqCst), 1);
+        assert!(path.join(format!("{:020}.ack", 0)).exists());
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    #[tokio::test]
+    async fn retries_batches_before_dead_lettering() {
+        let options = WalOptions {
+            retries: 2,
+            backoff: Duration::from_millis(1),
+            ..Default::default()
+        };
+
+        // Two failures are retried away.
+        let path = temp_dir("wal_retry");
+        let sink = Arc::new(Flaky {
+            fail: 2,
+            ..Default::default()
+        });
+        let wal = Wal::new(&path, sink.clone(), Encoding::Json, options)
+            .await
+            .unwrap();
+        wal.publish(&trade(0)).await.unwrap();
+        wal.flush().await.unwrap();
+        assert_eq!(trade_ids(&sink.seen.lock().unwrap()), vec![0]);
+        assert!(read_dead_letters(path.with_extension("dlq"))
+            .await
+            .unwrap()
+            .is_empty());
+        let _ = std::fs::remove_dir_all(&path);
+        let _ = std::fs::remove_file(path.with_extension("dlq"));
+
+        // A third is not.
+        let path = temp_dir("wal_dead_letter");
+        let sink = Arc::new(Flaky {
+            fail: 3,
+            ..Default::default()
+        });
+        let wal = Wal::new(&path, sink.clone(), Encoding::Json, options)
+            .await
+            .unwrap();
+        wal.publish(&trade(0)).await.unwrap();
+        wal.flush().await.unwrap();
+        assert_eq!(sink.batches.load(Ordering::SeqCst), 3);
+        let letters = read_dead_letters(path.with_extension("dlq")).await.unwrap();
+        assert_eq!(letters.len(), 1);
+        assert_eq!(letters[0].1.reason, "publish");
+        let _ = std::fs::remove_dir_all(&path);
+        let _ = std::fs::remove_file(path.with_extension("dlq"));
+    }
+
+    #[tokio::test]
+    async fn rejects_max_bytes_within_one_segment() {
+        let path = temp_dir("wal_limits");
+        let options = WalOptions {
+            segment_bytes: 1024,
+            max_bytes: 1024,
+            ..Default::default()
+        };
+        let err = Wal::new(&path, Capture::default(), Encoding::Json, options)
+            .await
+            .err()
+            .expect("limits rejected");
+        assert!(err.to_string().contains("must exceed the segment size"));
+
+        // Room for more than a segment never stalls appends.
+        let options = WalOptions {
+            segment_bytes: 512,
+            max_bytes: 1024,
+            ..Default::default()
+        };
+        let capture = Capture::default();
+        let wal = Wal::new(&path, capture.clone(), Encoding::Json, options)
+            .await
+            .unwrap();
+        for id in 0..50 {
+            wal.publish(&trade(id)).await.unwrap();
+        }
+        wal.flush().await.unwrap();
+        assert_eq!(capture.0.lock().unwrap().len(), 50);
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    #[tokio::test]
+    async fn failed_commit_truncates_to_last_record() {
+        let path = temp_dir("wal_rollback");
+        std::fs::create_dir_all(&path).unwrap();
+        let shared = Arc::new(Shared {
+            dir: path.clone(),
+            sealed: Mutex::new(VecDeque::new()),
+            active_base: AtomicU64::new(0),
+            live_bytes: AtomicU64::new(0),
+            space: Notify::new(),
+        });
+        let mut writer = Writer::open(shared.clone(), Encoding::Sbe, WalOptions::default(), 0)
+            .await
+            .unwrap();
+        writer.append(&trade(0)).await.unwrap();
+        writer.commit().await.unwrap();
+        let log = shared.log_path(0);
+        let committed = std::fs::metadata(&log).unwrap().len();
+
+        // Part of a batch reached the file before the write failed.
+        writer.file.write_all(b"torn").await.unwrap();
+        writer.rollback().await.unwrap();
+        assert_eq!(std::fs::metadata(&log).unwrap().len(), committed);
+
+        // A read-only handle makes the next commit fail.
+        let file = std::mem::replace(&mut writer.file, fs::File::open(&log).await.unwrap());
+        writer.append(&trade(1)).await.unwrap();
+        assert!(writer.commit().await.is_err());
+        assert!(writer.buf.is_empty());
+        writer.file = file;
+
+        writer.append(&trade(2)).await.unwrap();
+        writer.commit().await.unwrap();
+        drop(writer);
+        let capture = Capture::default();
+        let dlq = DeadLetterQueue::open(path.with_extension("dlq"))
+            .await
+            .unwrap();
+        recover(&path, &capture, &dlq, Encoding::Sbe, &WalOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(trade_ids(&capture.0.lock().unwrap()), vec![0, 2]);
+        let _ = std::fs::remove_dir_all(&path);
+        let _ = std::fs::remove_file(path.with_extension("dlq"));
+    }
+
+    #[tokio::test]
+    async fn recovery_replays_only_unacknowledged_records() {
+        let path = temp_dir("wal_recover");
+        std::fs::create_dir_all(&path).unwrap();
+
+        // Two segments as left by a crash: the first fully acknowledged, the
+        // second acknowledged up to offset 3.
+        for (base, ids, acked) in [(0u64, 0..2u64, 1u64), (2, 2..5, 3)] {
+            let mut encoder = EventEncoder::new(Encoding::Sbe);
+            let mut data = Vec::new();
+            for id in ids {
+                let mut payload = Vec::new();
+                encoder.encode(&trade(id), &mut payload).unwrap();
+                data.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+                data.extend_from_slice(&id.to_le_bytes());
+                data.extend_from_slice(&payload);
+            }
+            // A torn record at the tail is ignored.
+            data.extend_from_slice(&[9, 0, 0]);
+            std::fs::write(path.join(format!("{base:020}.log")), data).unwrap();
+            std::fs::write(path.join(format!("{base:020}.ack")), acked.to_le_bytes()).unwrap();
+        }
+
+        let capture = Capture::default();
+        let wal = Wal::new(&path, capture.clone(), Encoding::Sbe, WalOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(trade_ids(&capture.0.lock().unwrap()), vec![4]);
+
+        // New records continue after the recovered offsets.
+        wal.publish(&trade(5)).await.unwrap();
+        wal.flush().await.unwrap();
+        assert_eq!(trade_ids(&capture.0.lock().unwrap()), vec![4, 5]);
+        assert!(path.join(format!("{:020}.log", 5)).exists());
+        let _ = std::fs::remove_dir_all(&path);
+    }
+}
@@ -0,0 +1,125 @@
+//! Loading of the canonical symbol table and its reloads at runtime.
+
+use anyhow::{Context, Result};
+use canonical::{MdEvent, MdEventKind, SCHEMA_VERSION};
+use once_cell::sync::Lazy;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{env, path::Path};
+use tokio::signal::unix::{signal, Signal, SignalKind};
+use tokio::sync::Notify;
+use tokio::time::{interval_at, Duration, Instant, Interval};
+use tracing::{debug, error, info};
+
+use crate::sink::Sink;
+
+static RELOAD: Lazy<Notify> = Lazy::new(Notify::new);
+static SEQ_NO: AtomicU64 = AtomicU64::new(0);
+
+/// Override file layered on top of the fetched instruments:
+/// `MD_SYMBOLS_FILE`, or `streams/symbols.json` when present.
+fn overrides_path() -> Option<String> {
+    env::var("MD_SYMBOLS_FILE").ok().or_else(|| {
+        let default = "streams/symbols.json";
+        Path::new(default).exists().then(|| default.to_string())
+    })
+}
+
+/// Build the symbol table from the instruments reported by the adapters,
+/// overlaid with the override file.
+pub fn load() -> Result<()> {
+    let overrides = overrides_path();
+    let instruments = agents::instruments::snapshot();
+    debug!(count = instruments.len(), "loaded instrument metadata");
+    canonical::symbol::init(instruments, overrides.as_deref())
+        .with_context(|| format!("loading symbol overrides from {overrides:?}"))
+}
+
+/// Ask the reloader task to re-read the override file.
+pub fn request_reload() {
+    RELOAD.notify_one();
+}
+
+/// Rebuild the symbol table from the last fetched instruments and the
+/// override file, publishing an `InstrumentUpdate` event for every change.
+pub async fn reload(sink: &dyn Sink, metrics_enabled: bool) -> Result<usize> {
+    let overrides = overrides_path();
+    let updates = canonical::symbol::reload(agents::instruments::snapshot(), overrides.as_deref())
+        .with_context(|| format!("loading symbol overrides from {overrides:?}"))?;
+    let count = updates.len();
+    for mut update in updates {
+        update.ingest_ts_monotonic = crate::START.elapsed().as_nanos() as u64;
+        update.ingest_ts_utc = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        update.seq_no = SEQ_NO.fetch_add(1, Ordering::Relaxed);
+        if metrics_enabled {
+            let change = format!("{:?}", update.change).to_lowercase();
+            metrics::counter!("md_instrument_updates_total", "change" => change).increment(1);
+        }
+        let ev = MdEvent {
+            schema_version: SCHEMA_VERSION,
+            event: MdEventKind::InstrumentUpdate(update),
+            validation: None,
+        };
+        sink.publish(&ev).await?;
+    }
+    info!(updates = count, "reloaded symbol table");
+    Ok(count)
+}
+
+async fn tick(refresh: &mut Option<Interval>) {
+    match refresh {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+async fn hangup(sighup: &mut Option<Signal>) {
+    match sighup {
+        Some(sighup) => {
+            sighup.recv().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Spawn the task reloading the symbol table on SIGHUP, on
+/// [`request_reload`] and, every `MD_SYMBOLS_REFRESH_SECS` (default 3600,
+/// `0` disables), after re-fetching exchange instrument metadata.
+pub fn spawn_reloader(sink: Arc<dyn Sink>, metrics_enabled: bool) {
+    let period = env::var("MD_SYMBOLS_REFRESH_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(3600);
+    let mut refresh = (period > 0).then(|| {
+        let period = Duration::from_secs(period);
+        interval_at(Instant::now() + period, period)
+    });
+    tokio::spawn(async move {
+        let mut sighup = match signal(SignalKind::hangup()) {
+            Ok(sighup) => Some(sighup),
+            Err(e) => {
+                error!(error = %e, "cannot listen for SIGHUP; symbol reloads need the admin endpoint");
+                None
+            }
+        };
+        loop {
+            let refetch = tokio::select! {
+                _ = hangup(&mut sighup) => false,
+                _ = RELOAD.notified() => false,
+                _ = tick(&mut refresh) => true,
+            };
+            if refetch {
+                agents::instruments::refresh().await;
+            }
+            if let Err(e) = reload(sink.as_ref(), metrics_enabled).await {
+                error!(error = %e, "failed to reload symbol table");
+            }
+        }
+    });
+}
@@ -0,0 +1,226 @@
+use anyhow::{anyhow, Context, Result};
+use canonical::{MdEvent, ValidationRules};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use std::{env, str::FromStr, sync::Arc};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{error, warn};
+
+use crate::sink::DeadLetterQueue;
+
+/// Quarantined events waiting to be written to the dead letter queue.
+const QUARANTINE_QUEUE: usize = 4096;
+/// Shortest gap between two validation warnings; failures in between are
+/// only counted.
+const WARN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What to do with an event that fails validation.
+#[derive(Clone)]
+pub enum ValidationAction {
+    /// Discard the event.
+    Drop,
+    /// Write the flagged event to a dead letter queue instead of the sink.
+    Quarantine(Arc<DeadLetterQueue>),
+    /// Forward the event with [`MdEvent::validation`] set.
+    Flag,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ActionKind {
+    Drop,
+    Quarantine,
+    Flag,
+}
+
+impl FromStr for ActionKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "drop" => Ok(ActionKind::Drop),
+            "quarantine" | "dlq" => Ok(ActionKind::Quarantine),
+            "flag" | "pass" => Ok(ActionKind::Flag),
+            other => Err(anyhow!("unknown validation action: {other}")),
+        }
+    }
+}
+
+enum Quarantined {
+    Event(Box<MdEvent>),
+    Flush(oneshot::Sender<()>),
+}
+
+/// Pipeline stage run on every normalized event before it reaches the sink.
+pub struct Validator {
+    action: ValidationAction,
+    rules: ValidationRules,
+    /// Feeds the task writing quarantined events, so consumers never wait
+    /// on the dead letter queue.
+    quarantine: Option<mpsc::Sender<Quarantined>>,
+    next_warn: Mutex<Instant>,
+    suppressed: AtomicU64,
+}
+
+impl Validator {
+    /// Quarantining spawns the dead letter writer, so it must be called
+    /// within a Tokio runtime.
+    pub fn new(action: ValidationAction, rules: ValidationRules) -> Self {
+        let quarantine = match &action {
+            ValidationAction::Quarantine(dlq) => {
+                let (tx, rx) = mpsc::channel(QUARANTINE_QUEUE);
+                tokio::spawn(write_quarantined(dlq.clone(), rx));
+                Some(tx)
+            }
+            _ => None,
+        };
+        Self {
+            action,
+            rules,
+            quarantine,
+            next_warn: Mutex::new(Instant::now()),
+            suppressed: AtomicU64::new(0),
+        }
+    }
+
+    /// Build the stage from `MD_VALIDATION_*` variables. Quarantined events go
+    /// to `dlq` when the sink already has one, otherwise to
+    /// `MD_VALIDATION_DLQ_FILE`.
+    pub async fn from_env(dlq: Option<Arc<DeadLetterQueue>>) -> Result<Self> {
+        let kind = match env::var("MD_VALIDATION_ACTION") {
+            Ok(v) if !v.trim().is_empty() => v.trim().parse()?,
+            _ => ActionKind::Quarantine,
+        };
+        let action = match kind {
+            ActionKind::Drop => ValidationAction::Drop,
+            ActionKind::Flag => ValidationAction::Flag,
+            ActionKind::Quarantine => {
+                let dlq = match (env::var("MD_VALIDATION_DLQ_FILE"), dlq) {
+                    (Ok(path), _) => Arc::new(DeadLetterQueue::open(path).await?),
+                    (Err(_), Some(dlq)) => dlq,
+                    (Err(_), None) => Arc::new(DeadLetterQueue::open("md.dlq").await?),
+                };
+                ValidationAction::Quarantine(dlq)
+            }
+        };
+
+        let defaults = ValidationRules::default();
+        let rules = ValidationRules {
+            max_future_skew: parse_ms("MD_VALIDATION_MAX_FUTURE_MS")?
+                .unwrap_or(defaults.max_future_skew),
+            max_age: parse_ms("MD_VALIDATION_MAX_AGE_MS")?.unwrap_or(defaults.max_age),
+            check_steps: match env::var("MD_VALIDATION_CHECK_STEPS") {
+                Ok(v) => !matches!(v.trim().to_ascii_lowercase().as_str(), "0" | "false"),
+                Err(_) => defaults.check_steps,
+            },
+            missing_ts_allowed: match env::var("MD_VALIDATION_MISSING_TS_OK") {
+                Ok(v) => v
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|entry| !entry.is_empty())
+                    .map(String::from)
+                    .collect(),
+                Err(_) => defaults.missing_ts_allowed,
+            },
+        };
+        Ok(Self::new(action, rules))
+    }
+
+    /// Validate `ev` against the configured rules, returning whether it
+    /// should be forwarded to the sink. Failures are counted in
+    /// `md_validation_failures_total` and logged at most once per
+    /// [`WARN_INTERVAL`]. Quarantined events are queued for the dead letter
+    /// writer and dropped, counted in `md_validation_quarantine_dropped_total`,
+    /// while its queue is full.
+    pub fn check(&self, ev: &mut MdEvent, now_ns: u64, metrics_enabled: bool) -> bool {
+        let err = match ev.validate_with(&self.rules, now_ns) {
+            Ok(()) => return true,
+            Err(e) => e,
+        };
+        if metrics_enabled {
+            metrics::counter!(
+                "md_validation_failures_total",
+                "exchange" => ev.exchange().to_string(),
+                "reason" => err.reason(),
+            )
+            .increment(1);
+        }
+        if let Some(suppressed) = self.may_warn() {
+            warn!(
+                exchange = ev.exchange(),
+                symbol = ev.symbol(),
+                error = %err,
+                suppressed,
+                "event failed validation",
+            );
+        }
+        ev.validation = Some(err);
+        match &self.action {
+            ValidationAction::Drop => false,
+            ValidationAction::Flag => true,
+            ValidationAction::Quarantine(_) => {
+                let queued = self.quarantine.as_ref().is_some_and(|tx| {
+                    tx.try_send(Quarantined::Event(Box::new(ev.clone())))
+                        .is_ok()
+                });
+                if !queued && metrics_enabled {
+                    metrics::counter!("md_validation_quarantine_dropped_total").increment(1);
+                }
+                false
+            }
+        }
+    }
+
+    /// Wait until the events quarantined so far are written.
+    pub async fn flush(&self) {
+        if let Some(tx) = &self.quarantine {
+            let (done, rx) = oneshot::channel();
+            if tx.send(Quarantined::Flush(done)).await.is_ok() {
+                let _ = rx.await;
+            }
+        }
+    }
+
+    /// Whether a failure may be logged now, with the number of failures
+    /// not logged since the last one.
+    fn may_warn(&self) -> Option<u64> {
+        let now = Instant::now();
+        let mut next = self.next_warn.lock().expect("validator mutex poisoned");
+        if now < *next {
+            self.suppressed.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        *next = now + WARN_INTERVAL;
+        Some(self.suppressed.swap(0, Ordering::Relaxed))
+    }
+}
+
+async fn write_quarantined(dlq: Arc<DeadLetterQueue>, mut rx: mpsc::Receiver<Quarantined>) {
+    while let Some(item) = rx.recv().await {
+        match item {
+            Quarantined::Event(ev) => {
+                let Some(err) = &ev.validation else {
+                    continue;
+                };
+                let reason = format!("validation:{}", err.reason());
+                if let Err(e) = dlq.push(&ev, &reason, &err.to_string()).await {
+                    error!(error = %e, "failed to quarantine event");
+                }
+            }
+            Quarantined::Flush(done) => {
+                let _ = done.send(());
+            }
+        }
+    }
+}
+
+/// `Some(None)` disables the check when the variable is `0`.
+fn parse_ms(var: &str) -> Result<Option<Option<Duration>>> {
+    match env::var(var) {
+        Ok(v) if !v.trim().is_empty() => {
+            let ms: u64 = v.trim().parse().with_context(|| format!("invalid {var}"))?;
+            Ok(Some((ms > 0).then(|| Duration::from_millis(ms))))
+        }
+        _ => Ok(None),
+    }
+}
@@ -0,0 +1,280 @@
+//! Sequenced UDP multicast of market data with TCP gap recovery.
+//!
+//! Events are split into channels, one per [`Channel`](canonical::events::Channel),
+//! each with its own sequence numbers starting at zero. Every message carries
+//! the `canonical::sbe` frames of one event, preceded by the dictionary
+//! frames that first announce its exchange and symbol ids, so a receiver that
+//! saw every message of a channel can decode it. Dictionaries are per
+//! channel.
+//!
+//! A datagram is a [`PacketHeader`] followed by `count` messages, each a
+//! little-endian `u16` length and its bytes, numbered consecutively from
+//! `first_seq`:
+//!
+//! ```text
+//! channel: u8 | kind: u8 | count: u16 | first_seq: u64 | (len: u16, bytes)*
+//! ```
+//!
+//! Receivers that miss datagrams ask the publisher's recovery server over
+//! TCP, sending fixed-size [`Request`]s and reading back packets framed by a
+//! little-endian `u32` length. A retransmission returns the requested
+//! messages the publisher still holds; a snapshot returns the channel's
+//! dictionary and the last message of every instrument, with `first_seq` set
+//! to the next live sequence number. [`Receiver`] is a reference
+//! implementation of both sides of the protocol.
+
+use socket2::{Domain, Protocol, Socket, Type};
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+mod receiver;
+pub use receiver::{Receiver, ReceiverStats};
+
+/// Size of a packet header in bytes.
+pub const HEADER_LEN: usize = 12;
+/// Size of a recovery request in bytes.
+pub const REQUEST_LEN: usize = 16;
+/// Largest datagram payload that avoids IP fragmentation on Ethernet.
+pub const DEFAULT_PACKET_SIZE: usize = 1400;
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// How a packet was produced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum PacketKind {
+    Live = 0,
+    Retransmit = 1,
+    Snapshot = 2,
+}
+
+impl PacketKind {
+    fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            0 => Some(PacketKind::Live),
+            1 => Some(PacketKind::Retransmit),
+            2 => Some(PacketKind::Snapshot),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PacketHeader {
+    /// `Channel as u8` of every message in the packet.
+    pub channel: u8,
+    pub kind: PacketKind,
+    pub count: u16,
+    /// Sequence number of the first message. For snapshots, the sequence
+    /// number live messages continue at.
+    pub first_seq: u64,
+}
+
+/// Packs consecutive messages of one channel into a packet.
+#[derive(Debug)]
+pub struct PacketBuilder {
+    buf: Vec<u8>,
+    max_len: usize,
+    count: u16,
+    first_seq: u64,
+}
+
+impl PacketBuilder {
+    /// Start a packet whose messages are numbered from `first_seq`. The
+    /// packet grows up to `max_len` bytes, except that a single message is
+    /// always accepted.
+    pub fn new(channel: u8, kind: PacketKind, first_seq: u64, max_len: usize) -> Self {
+        let mut buf = Vec::with_capacity(max_len.min(64 * 1024));
+        buf.extend_from_slice(&[channel, kind as u8, 0, 0]);
+        buf.extend_from_slice(&first_seq.to_le_bytes());
+        Self {
+            buf,
+            max_len,
+            count: 0,
+            first_seq,
+        }
+    }
+
+    /// Append a message, returning `false` when it does not fit.
+    pub fn push(&mut self, msg: &[u8]) -> bool {
+        let Ok(len) = u16::try_from(msg.len()) else {
+            return false;
+        };
+        if self.count == u16::MAX
+            || (self.count > 0 && self.buf.len() + 2 + msg.len() > self.max_len)
+        {
+            return false;
+        }
+        self.buf.extend_from_slice(&len.to_le_bytes());
+        self.buf.extend_from_slice(msg);
+        self.count += 1;
+        self.buf[2..4].copy_from_slice(&self.count.to_le_bytes());
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.count as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Sequence number the next pushed message gets.
+    pub fn next_seq(&self) -> u64 {
+        self.first_seq + self.count as u64
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf
+    }
+}
+
+/// A received packet, borrowing the datagram.
+#[derive(Debug, Clone, Copy)]
+pub struct Packet<'a> {
+    pub header: PacketHeader,
+    body: &'a [u8],
+}
+
+impl<'a> Packet<'a> {
+    /// Parse and validate a packet, checking that its messages fill it
+    /// exactly.
+    pub fn parse(buf: &'a [u8]) -> io::Result<Self> {
+        if buf.len() < HEADER_LEN {
+            return Err(invalid("packet shorter than its header"));
+        }
+        let kind = PacketKind::from_u8(buf[1])
+            .ok_or_else(|| invalid(format!("unknown packet kind {}", buf[1])))?;
+        let header = PacketHeader {
+            channel: buf[0],
+            kind,
+            count: u16::from_le_bytes([buf[2], buf[3]]),
+            first_seq: u64::from_le_bytes(buf[4..12].try_into().unwrap()),
+        };
+        let body = &buf[HEADER_LEN..];
+        let mut rest = body;
+        for _ in 0..header.count {
+            if rest.len() < 2 {
+                return Err(invalid("truncated message length"));
+            }
+            let len = u16::from_le_bytes([rest[0], rest[1]]) as usize;
+            rest = rest
+                .get(2 + len..)
+                .ok_or_else(|| invalid("truncated message"))?;
+        }
+        if !rest.is_empty() {
+            return Err(invalid("trailing bytes after the last message"));
+        }
+        Ok(Self { header, body })
+    }
+
+    /// Messages with their sequence numbers.
+    pub fn messages(&self) -> impl Iterator<Item = (u64, &'a [u8])> + 'a {
+        let mut rest = self.body;
+        let first_seq = self.header.first_seq;
+        (0..self.header.count as u64).map(move |i| {
+            let len = u16::from_le_bytes([rest[0], rest[1]]) as usize;
+            let msg = &rest[2..2 + len];
+            rest = &rest[2 + len..];
+            (first_seq + i, msg)
+        })
+    }
+}
+
+/// Request sent to the recovery server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Request {
+    /// Messages `from_seq..from_seq + count` of a channel.
+    Retransmit {
+        channel: u8,
+        from_seq: u64,
+        count: u32,
+    },
+    /// Dictionary and latest message per instrument of a channel.
+    Snapshot { channel: u8 },
+}
+
+impl Request {
+    pub fn encode(&self) -> [u8; REQUEST_LEN] {
+        let mut out = [0u8; REQUEST_LEN];
+        match *self {
+            Request::Retransmit {
+                channel,
+                from_seq,
+                count,
+            } => {
+                out[0] = 1;
+                out[1] = channel;
+                out[4..8].copy_from_slice(&count.to_le_bytes());
+                out[8..16].copy_from_slice(&from_seq.to_le_bytes());
+            }
+            Request::Snapshot { channel } => {
+                out[0] = 2;
+                out[1] = channel;
+            }
+        }
+        out
+    }
+
+    pub fn decode(buf: &[u8; REQUEST_LEN]) -> io::Result<Self> {
+        match buf[0] {
+            1 => Ok(Request::Retransmit {
+                channel: buf[1],
+                count: u32::from_le_bytes(buf[4..8].try_into().unwrap()),
+                from_seq: u64::from_le_bytes(buf[8..16].try_into().unwrap()),
+            }),
+            2 => Ok(Request::Snapshot { channel: buf[1] }),
+            op => Err(invalid(format!("unknown request {op}"))),
+        }
+    }
+}
+
+/// Write a packet to a recovery stream.
+pub async fn write_frame<W: AsyncWrite + Unpin>(w: &mut W, packet: &[u8]) -> io::Result<()> {
+    let len = u32::try_from(packet.len()).map_err(|_| invalid("packet too large"))?;
+    w.write_all(&len.to_le_bytes()).await?;
+    w.write_all(packet).await?;
+    w.flush().await
+}
+
+/// Read a packet written by [`write_frame`] into `buf`.
+pub async fn read_frame<R: AsyncRead + Unpin>(r: &mut R, buf: &mut Vec<u8>) -> io::Result<()> {
+    let mut len = [0u8; 4];
+    r.read_exact(&mut len).await?;
+    buf.resize(u32::from_le_bytes(len) as usize, 0);
+    r.read_exact(buf).await?;
+    Ok(())
+}
+
+/// Socket for publishing, sending multicast through `interface` with the
+/// given TTL. Multicast loopback stays enabled so receivers on the same host
+/// see the traffic.
+pub fn sender_socket(interface: Ipv4Addr, ttl: u32) -> io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_multicast_if_v4(&interface)?;
+    socket.set_multicast_ttl_v4(ttl)?;
+    socket.set_multicast_loop_v4(true)?;
+    socket.bind(&SocketAddr::from((interface, 0)).into())?;
+    socket.set_nonblocking(true)?;
+    Ok(socket.into())
+}
+
+/// Socket receiving `addr`: joins the group on `interface` when `addr` is a
+/// multicast address, and binds it directly otherwise. The port may be shared
+/// with other receivers on the host.
+pub fn receiver_socket(addr: SocketAddrV4, interface: Ipv4Addr) -> io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    if addr.ip().is_multicast() {
+        socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, addr.port())).into())?;
+        socket.join_multicast_v4(addr.ip(), &interface)?;
+    } else {
+        socket.bind(&SocketAddr::V4(addr).into())?;
+    }
+    socket.set_nonblocking(true)?;
+    Ok(socket.into())
+}
@@ -0,0 +1,207 @@
+use anyhow::{bail, Context, Result};
+use canonical::{sbe, MdEvent};
+use std::collections::{HashMap, VecDeque};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpStream, UdpSocket};
+
+use crate::{read_frame, receiver_socket, Packet, PacketKind, Request};
+
+/// Counters of a [`Receiver`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReceiverStats {
+    pub packets: u64,
+    /// Times a sequence gap was detected.
+    pub gaps: u64,
+    /// Messages filled in by retransmission.
+    pub retransmitted: u64,
+    pub snapshots: u64,
+}
+
+#[derive(Default)]
+struct ChannelState {
+    /// Next expected sequence number, unknown until the first packet.
+    next: Option<u64>,
+    dictionary: sbe::Dictionary,
+}
+
+/// Reference receiver: delivers every channel's events in sequence order,
+/// filling gaps from the recovery server and resynchronising from a
+/// snapshot when the gap is no longer held there.
+pub struct Receiver {
+    socket: UdpSocket,
+    recovery: SocketAddr,
+    conn: Option<TcpStream>,
+    channels: HashMap<u8, ChannelState>,
+    ready: VecDeque<MdEvent>,
+    datagram: Vec<u8>,
+    frame: Vec<u8>,
+    stats: ReceiverStats,
+}
+
+impl Receiver {
+    /// Receive datagrams sent to `addr`, joining the group on `interface`
+    /// when it is a multicast address, and recover from `recovery`.
+    pub fn join(addr: SocketAddrV4, interface: Ipv4Addr, recovery: SocketAddr) -> Result<Self> {
+        let socket = receiver_socket(addr, interface)
+            .with_context(|| format!("joining multicast group {addr}"))?;
+        Ok(Self {
+            socket: UdpSocket::from_std(socket)?,
+            recovery,
+            conn: None,
+            channels: HashMap::new(),
+            ready: VecDeque::new(),
+            datagram: vec![0; 64 * 1024],
+            frame: Vec::new(),
+            stats: ReceiverStats::default(),
+        })
+    }
+
+    pub fn stats(&self) -> ReceiverStats {
+        self.stats
+    }
+
+    /// Next event, in sequence order within its channel.
+    pub async fn recv(&mut self) -> Result<MdEvent> {
+        loop {
+            if let Some(ev) = self.ready.pop_front() {
+                return Ok(ev);
+            }
+            let len = self.socket.recv(&mut self.datagram).await?;
+            let datagram = std::mem::take(&mut self.datagram);
+            let res = self.on_datagram(&datagram[..len]).await;
+            self.datagram = datagram;
+            res?;
+        }
+    }
+
+    async fn on_datagram(&mut self, datagram: &[u8]) -> Result<()> {
+        let packet = Packet::parse(datagram)?;
+        self.stats.packets += 1;
+        let channel = packet.header.channel;
+        let first_seq = packet.header.first_seq;
+        let next = match self.channels.get(&channel).and_then(|c| c.next) {
+            Some(next) => next,
+            // Joined mid-stream, so earlier dictionary entries are unknown.
+            None if first_seq > 0 => self.snapshot(channel).await?,
+            None => 0,
+        };
+        if first_seq > next {
+            self.stats.gaps += 1;
+            self.retransmit(channel, next, first_seq - next).await?;
+            if self.next(channel) < first_seq {
+                self.snapshot(channel).await?;
+            }
+        }
+        self.apply(&packet)?;
+        Ok(())
+    }
+
+    fn next(&self, channel: u8) -> u64 {
+        self.channels
+            .get(&channel)
+            .and_then(|c| c.next)
+            .unwrap_or(0)
+    }
+
+    /// Decode the messages of `packet` that continue the channel's sequence.
+    fn apply(&mut self, packet: &Packet<'_>) -> Result<usize> {
+        let state = self.channels.entry(packet.header.channel).or_default();
+        let mut applied = 0;
+        for (seq, msg) in packet.messages() {
+            let next = state.next.unwrap_or(0);
+            if seq < next {
+                continue;
+            }
+            if seq > next {
+                break;
+            }
+            decode(&mut state.dictionary, msg, &mut self.ready)?;
+            state.next = Some(seq + 1);
+            applied += 1;
+        }
+        Ok(applied)
+    }
+
+    async fn request(&mut self, request: Request) -> Result<Vec<u8>> {
+        if self.conn.is_none() {
+            let conn = TcpStream::connect(self.recovery)
+                .await
+                .with_context(|| format!("connecting to recovery server {}", self.recovery))?;
+            conn.set_nodelay(true)?;
+            self.conn = Some(conn);
+        }
+        let conn = self.conn.as_mut().expect("connected above");
+        let mut frame = std::mem::take(&mut self.frame);
+        let res = async {
+            conn.write_all(&request.encode()).await?;
+            read_frame(conn, &mut frame).await
+        }
+        .await;
+        if res.is_err() {
+            self.conn = None;
+        }
+        res.context("recovery request")?;
+        Ok(frame)
+    }
+
+    async fn retransmit(&mut self, channel: u8, from_seq: u64, count: u64) -> Result<()> {
+        let frame = self
+            .request(Request::Retransmit {
+                channel,
+                from_seq,
+                count: count.min(u32::MAX as u64) as u32,
+            })
+            .await?;
+        let res = Packet::parse(&frame)
+            .map_err(Into::into)
+            .and_then(|packet| {
+                if packet.header.kind != PacketKind::Retransmit || packet.header.channel != channel
+                {
+                    bail!(
+                        "unexpected {:?} packet for a retransmission",
+                        packet.header.kind
+                    );
+                }
+                self.apply(&packet)
+            });
+        self.frame = frame;
+        self.stats.retransmitted += res? as u64;
+        Ok(())
+    }
+
+    /// Replace the channel's dictionary and position from a snapshot,
+    /// queueing the latest event of every instrument. Returns the next
+    /// sequence number.
+    async fn snapshot(&mut self, channel: u8) -> Result<u64> {
+        let frame = self.request(Request::Snapshot { channel }).await?;
+        let res = Packet::parse(&frame)
+            .map_err(Into::into)
+            .and_then(|packet| {
+                if packet.header.kind != PacketKind::Snapshot || packet.header.channel != channel {
+                    bail!("unexpected {:?} packet for a snapshot", packet.header.kind);
+                }
+                let mut state = ChannelState {
+                    next: Some(packet.header.first_seq),
+                    dictionary: sbe::Dictionary::new(),
+                };
+                for (_, msg) in packet.messages() {
+                    decode(&mut state.dictionary, msg, &mut self.ready)?;
+                }
+                self.channels.insert(channel, state);
+                Ok(packet.header.first_seq)
+            });
+        self.frame = frame;
+        self.stats.snapshots += 1;
+        res
+    }
+}
+
+fn decode(dictionary: &mut sbe::Dictionary, msg: &[u8], out: &mut VecDeque<MdEvent>) -> Result<()> {
+    for frame in sbe::frames(msg) {
+        if let Some(ev) = dictionary.decode(&frame?)? {
+            out.push_back(ev);
+        }
+    }
+    Ok(())
+}
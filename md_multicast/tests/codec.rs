@@ -0,0 +1,90 @@
+use md_multicast::{
+    read_frame, write_frame, Packet, PacketBuilder, PacketKind, Request, HEADER_LEN, REQUEST_LEN,
+};
+
+#[test]
+fn packets_roundtrip_their_messages() {
+    let mut builder = PacketBuilder::new(3, PacketKind::Live, 41, 64);
+    assert!(builder.is_empty());
+    assert!(builder.push(b"first"));
+    assert!(builder.push(b""));
+    assert!(builder.push(&[7; 20]));
+    assert_eq!(builder.len(), 3);
+    assert_eq!(builder.next_seq(), 44);
+    // Header 12 + (2 + 5) + 2 + (2 + 20) = 43, so 20 more bytes overflow.
+    assert!(!builder.push(&[0; 20]));
+    assert_eq!(builder.as_bytes().len(), HEADER_LEN + 31);
+
+    let packet = Packet::parse(builder.as_bytes()).unwrap();
+    assert_eq!(packet.header.channel, 3);
+    assert_eq!(packet.header.kind, PacketKind::Live);
+    assert_eq!(packet.header.count, 3);
+    assert_eq!(packet.header.first_seq, 41);
+    let messages: Vec<_> = packet.messages().collect();
+    assert_eq!(
+        messages,
+        vec![(41, &b"first"[..]), (42, &b""[..]), (43, &[7u8; 20][..])]
+    );
+}
+
+#[test]
+fn oversized_messages_still_get_a_packet_of_their_own() {
+    let mut builder = PacketBuilder::new(0, PacketKind::Retransmit, 0, 16);
+    assert!(builder.push(&[1; 100]));
+    assert!(!builder.push(b"x"));
+    let packet = Packet::parse(builder.as_bytes()).unwrap();
+    assert_eq!(packet.messages().next().unwrap().1.len(), 100);
+
+    let mut builder = PacketBuilder::new(0, PacketKind::Live, 0, usize::MAX);
+    assert!(!builder.push(&vec![0; u16::MAX as usize + 1]));
+}
+
+#[test]
+fn rejects_malformed_packets() {
+    let mut builder = PacketBuilder::new(1, PacketKind::Snapshot, 9, 1400);
+    builder.push(b"abc");
+    let bytes = builder.as_bytes().to_vec();
+
+    assert!(Packet::parse(&bytes[..HEADER_LEN - 1]).is_err());
+    assert!(Packet::parse(&bytes[..bytes.len() - 1]).is_err());
+    let mut trailing = bytes.clone();
+    trailing.push(0);
+    assert!(Packet::parse(&trailing).is_err());
+    let mut kind = bytes.clone();
+    kind[1] = 9;
+    assert!(Packet::parse(&kind).is_err());
+    let mut count = bytes;
+    count[2] = 2;
+    assert!(Packet::parse(&count).is_err());
+}
+
+#[test]
+fn requests_roundtrip() {
+    for request in [
+        Request::Retransmit {
+            channel: 4,
+            from_seq: u64::MAX - 1,
+            count: 17,
+        },
+        Request::Snapshot { channel: 11 },
+    ] {
+        assert_eq!(Request::decode(&request.encode()).unwrap(), request);
+    }
+    assert!(Request::decode(&[0; REQUEST_LEN]).is_err());
+}
+
+#[tokio::test]
+async fn frames_roundtrip_over_a_stream() {
+    let (mut a, mut b) = tokio::io::duplex(64);
+    let writer = tokio::spawn(async move {
+        write_frame(&mut a, b"hello").await.unwrap();
+        write_frame(&mut a, &[5; 300]).await.unwrap();
+    });
+    let mut buf = Vec::new();
+    read_frame(&mut b, &mut buf).await.unwrap();
+    assert_eq!(buf, b"hello");
+    read_frame(&mut b, &mut buf).await.unwrap();
+    assert_eq!(buf, vec![5; 300]);
+    writer.await.unwrap();
+    assert!(read_frame(&mut b, &mut buf).await.is_err());
+}
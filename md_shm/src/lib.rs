@@ -0,0 +1,321 @@
+//! Top-of-book in shared memory.
+//!
+//! The ingestor publishes the best bid and ask of every instrument into a
+//! memory-mapped file; co-located processes map the same file with
+//! [`Reader`] and read quotes without locks, allocations or system calls.
+//!
+//! The file is a 64-byte header followed by fixed 128-byte slots, one per
+//! exchange and symbol, assigned in order of first update and never reused.
+//! Each slot is guarded by a seqlock: the writer makes the sequence odd while
+//! it updates the values and even again afterwards, and readers retry until
+//! they see the same even sequence before and after copying the values.
+
+use memmap2::{Mmap, MmapMut};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{fence, AtomicU32, AtomicU64, Ordering};
+
+const MAGIC: u64 = u64::from_le_bytes(*b"MDSHMTOB");
+/// Bumped whenever the layout changes.
+pub const VERSION: u32 = 1;
+/// Longest exchange name, in bytes, a slot can hold.
+pub const MAX_EXCHANGE_LEN: usize = 16;
+/// Longest symbol, in bytes, a slot can hold.
+pub const MAX_SYMBOL_LEN: usize = 32;
+
+const HEADER_SIZE: usize = std::mem::size_of::<Header>();
+const SLOT_SIZE: usize = std::mem::size_of::<Slot>();
+
+#[repr(C, align(64))]
+struct Header {
+    magic: u64,
+    version: u32,
+    capacity: u32,
+    /// Slots in use. Published with release ordering after the slot's names
+    /// are written.
+    len: AtomicU32,
+}
+
+#[repr(C, align(64))]
+struct Slot {
+    seq: AtomicU64,
+    exchange: [u8; MAX_EXCHANGE_LEN],
+    symbol: [u8; MAX_SYMBOL_LEN],
+    /// [`Quote`] fields in declaration order, prices and quantities as
+    /// `f64` bits.
+    values: [AtomicU64; 7],
+}
+
+const _: () = assert!(HEADER_SIZE == 64 && SLOT_SIZE == 128);
+
+/// Best bid and ask of one instrument.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Quote {
+    pub bid_price: f64,
+    pub bid_quantity: f64,
+    pub ask_price: f64,
+    pub ask_quantity: f64,
+    /// Exchange timestamp in milliseconds.
+    pub ts: u64,
+    /// Ingest wall-clock time in nanoseconds since the Unix epoch.
+    pub ingest_ts_utc: u64,
+    /// Ingest monotonic time in nanoseconds.
+    pub ingest_ts_monotonic: u64,
+}
+
+impl Quote {
+    fn to_values(self) -> [u64; 7] {
+        [
+            self.bid_price.to_bits(),
+            self.bid_quantity.to_bits(),
+            self.ask_price.to_bits(),
+            self.ask_quantity.to_bits(),
+            self.ts,
+            self.ingest_ts_utc,
+            self.ingest_ts_monotonic,
+        ]
+    }
+
+    fn from_values(v: [u64; 7]) -> Self {
+        Self {
+            bid_price: f64::from_bits(v[0]),
+            bid_quantity: f64::from_bits(v[1]),
+            ask_price: f64::from_bits(v[2]),
+            ask_quantity: f64::from_bits(v[3]),
+            ts: v[4],
+            ingest_ts_utc: v[5],
+            ingest_ts_monotonic: v[6],
+        }
+    }
+}
+
+fn file_len(capacity: u32) -> usize {
+    HEADER_SIZE + capacity as usize * SLOT_SIZE
+}
+
+fn name(bytes: &[u8]) -> &str {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    std::str::from_utf8(&bytes[..end]).unwrap_or("")
+}
+
+/// Single writer of a top-of-book file.
+pub struct Writer {
+    map: MmapMut,
+    capacity: u32,
+    /// Slot of every instrument by exchange, then symbol, so lookups need
+    /// no allocation.
+    slots: HashMap<String, HashMap<String, u32>>,
+    len: u32,
+}
+
+impl Writer {
+    /// Create the file at `path` with room for `capacity` instruments.
+    ///
+    /// An existing file is unlinked first, so readers still mapping it keep a
+    /// consistent, if stale, view until they reopen the path.
+    pub fn create(path: impl AsRef<Path>, capacity: u32) -> io::Result<Self> {
+        let path = path.as_ref();
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(path)?;
+        file.set_len(file_len(capacity) as u64)?;
+        // SAFETY: the file was just created by this process; readers only
+        // ever map it read-only.
+        let mut map = unsafe { MmapMut::map_mut(&file)? };
+        let header = map.as_mut_ptr() as *mut Header;
+        // SAFETY: the map is page aligned and at least HEADER_SIZE long, and
+        // no reader trusts the header before the magic is set.
+        unsafe {
+            (*header).version = VERSION;
+            (*header).capacity = capacity;
+            (*header).magic = MAGIC;
+        }
+        map.flush()?;
+        Ok(Self {
+            map,
+            capacity,
+            slots: HashMap::new(),
+            len: 0,
+        })
+    }
+
+    fn header(&self) -> &Header {
+        // SAFETY: the map starts with a Header, see `create`.
+        unsafe { &*(self.map.as_ptr() as *const Header) }
+    }
+
+    fn slot(&mut self, index: u32) -> *mut Slot {
+        debug_assert!(index < self.capacity);
+        // SAFETY: `index` is below the capacity the file was sized for.
+        unsafe {
+            self.map
+                .as_mut_ptr()
+                .add(HEADER_SIZE + index as usize * SLOT_SIZE) as *mut Slot
+        }
+    }
+
+    /// Slot of an instrument, assigning the next free one on first use.
+    fn slot_index(&mut self, exchange: &str, symbol: &str) -> Option<u32> {
+        if let Some(&index) = self.slots.get(exchange).and_then(|s| s.get(symbol)) {
+            return Some(index);
+        }
+        let index = self.len;
+        if index >= self.capacity
+            || exchange.len() > MAX_EXCHANGE_LEN
+            || symbol.len() > MAX_SYMBOL_LEN
+        {
+            return None;
+        }
+        let slot = self.slot(index);
+        // SAFETY: slots at or above `len` are not read, so the names can be
+        // written before the slot is published below.
+        unsafe {
+            (&mut (*slot).exchange)[..exchange.len()].copy_from_slice(exchange.as_bytes());
+            (&mut (*slot).symbol)[..symbol.len()].copy_from_slice(symbol.as_bytes());
+        }
+        self.len = index + 1;
+        self.header().len.store(self.len, Ordering::Release);
+        self.slots
+            .entry(exchange.to_string())
+            .or_default()
+            .insert(symbol.to_string(), index);
+        Some(index)
+    }
+
+    /// Publish the latest quote of an instrument. Returns `false` when it has
+    /// no slot because the file is full or a name is too long.
+    pub fn update(&mut self, exchange: &str, symbol: &str, quote: &Quote) -> bool {
+        let Some(index) = self.slot_index(exchange, symbol) else {
+            return false;
+        };
+        // SAFETY: published slots are only accessed through atomics and
+        // immutable names from here on.
+        let slot = unsafe { &*self.slot(index) };
+        let seq = slot.seq.load(Ordering::Relaxed);
+        slot.seq.store(seq.wrapping_add(1), Ordering::Relaxed);
+        fence(Ordering::Release);
+        for (value, v) in slot.values.iter().zip(quote.to_values()) {
+            value.store(v, Ordering::Relaxed);
+        }
+        slot.seq.store(seq.wrapping_add(2), Ordering::Release);
+        true
+    }
+
+    /// Instruments with a slot.
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// Read-only view of a top-of-book file. Cheap to share between threads.
+pub struct Reader {
+    map: Mmap,
+    capacity: u32,
+}
+
+impl Reader {
+    /// Map the file at `path`, checking its header.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = File::open(path)?;
+        // SAFETY: the file is only modified through atomics by the writer,
+        // apart from names of slots that are not yet published.
+        let map = unsafe { Mmap::map(&file)? };
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        if map.len() < HEADER_SIZE {
+            return Err(invalid("file too short for a top-of-book header"));
+        }
+        // SAFETY: checked above that the header fits.
+        let header = unsafe { &*(map.as_ptr() as *const Header) };
+        if header.magic != MAGIC {
+            return Err(invalid("not a top-of-book file"));
+        }
+        if header.version != VERSION {
+            return Err(invalid(&format!(
+                "top-of-book layout version {}, expected {VERSION}",
+                header.version
+            )));
+        }
+        let capacity = header.capacity;
+        if map.len() < file_len(capacity) {
+            return Err(invalid("file too short for its capacity"));
+        }
+        Ok(Self { map, capacity })
+    }
+
+    fn header(&self) -> &Header {
+        // SAFETY: validated in `open`.
+        unsafe { &*(self.map.as_ptr() as *const Header) }
+    }
+
+    fn slot(&self, index: usize) -> Option<&Slot> {
+        if index >= self.len() {
+            return None;
+        }
+        // SAFETY: published slots lie within the validated capacity.
+        Some(unsafe { &*(self.map.as_ptr().add(HEADER_SIZE + index * SLOT_SIZE) as *const Slot) })
+    }
+
+    /// Instruments published so far. Slots keep their index for the lifetime
+    /// of the file, so indexes can be cached.
+    pub fn len(&self) -> usize {
+        (self.header().len.load(Ordering::Acquire)).min(self.capacity) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Exchange and symbol of a slot.
+    pub fn name(&self, index: usize) -> Option<(&str, &str)> {
+        let slot = self.slot(index)?;
+        Some((name(&slot.exchange), name(&slot.symbol)))
+    }
+
+    /// Slot of an instrument, matching the symbol exactly as published.
+    pub fn find(&self, exchange: &str, symbol: &str) -> Option<usize> {
+        (0..self.len()).find(|&i| self.name(i) == Some((exchange, symbol)))
+    }
+
+    /// Sequence of a slot, which changes on every update. Lets pollers skip
+    /// reading quotes that have not changed.
+    pub fn sequence(&self, index: usize) -> Option<u64> {
+        Some(self.slot(index)?.seq.load(Ordering::Acquire))
+    }
+
+    /// Latest quote of a slot, spinning while the writer is mid-update.
+    pub fn read(&self, index: usize) -> Option<Quote> {
+        let slot = self.slot(index)?;
+        loop {
+            let before = slot.seq.load(Ordering::Acquire);
+            if before & 1 == 1 {
+                std::hint::spin_loop();
+                continue;
+            }
+            let values = std::array::from_fn(|i| slot.values[i].load(Ordering::Relaxed));
+            fence(Ordering::Acquire);
+            if slot.seq.load(Ordering::Relaxed) == before {
+                return Some(Quote::from_values(values));
+            }
+        }
+    }
+
+    /// Every published instrument with its latest quote.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str, Quote)> + '_ {
+        (0..self.len()).filter_map(|i| {
+            let (exchange, symbol) = self.name(i)?;
+            Some((exchange, symbol, self.read(i)?))
+        })
+    }
+}
@@ -0,0 +1,104 @@
+use md_shm::{Quote, Reader, Writer, MAX_SYMBOL_LEN};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("md_shm_{name}_{}", std::process::id()))
+}
+
+fn quote(n: u64) -> Quote {
+    let p = n as f64;
+    Quote {
+        bid_price: p,
+        bid_quantity: p,
+        ask_price: p,
+        ask_quantity: p,
+        ts: n,
+        ingest_ts_utc: n,
+        ingest_ts_monotonic: n,
+    }
+}
+
+#[test]
+fn readers_see_the_latest_quote_per_instrument() {
+    let path = temp_path("latest");
+    let mut writer = Writer::create(&path, 2).unwrap();
+    let reader = Reader::open(&path).unwrap();
+    assert!(reader.is_empty());
+
+    assert!(writer.update("binance", "BTCUSDT", &quote(1)));
+    assert!(writer.update("okx", "BTC-USDT", &quote(2)));
+    assert!(writer.update("binance", "BTCUSDT", &quote(3)));
+    // Full, and names that do not fit are rejected.
+    assert!(!writer.update("bybit", "BTCUSDT", &quote(4)));
+    assert!(!writer.update("okx", &"X".repeat(MAX_SYMBOL_LEN + 1), &quote(5)));
+    assert_eq!(writer.len(), 2);
+
+    assert_eq!(reader.len(), 2);
+    let btc = reader.find("binance", "BTCUSDT").unwrap();
+    assert_eq!(reader.name(btc), Some(("binance", "BTCUSDT")));
+    assert_eq!(reader.read(btc), Some(quote(3)));
+    assert_eq!(reader.sequence(btc), Some(4));
+    assert_eq!(reader.find("bybit", "BTCUSDT"), None);
+    assert_eq!(reader.read(2), None);
+    let all: Vec<_> = reader.iter().collect();
+    assert_eq!(
+        all,
+        vec![
+            ("binance", "BTCUSDT", quote(3)),
+            ("okx", "BTC-USDT", quote(2))
+        ]
+    );
+
+    // Recreating the file leaves old readers with their mapping.
+    let _writer = Writer::create(&path, 2).unwrap();
+    assert_eq!(reader.read(btc), Some(quote(3)));
+    assert!(Reader::open(&path).unwrap().is_empty());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn rejects_files_that_are_not_top_of_book() {
+    let path = temp_path("invalid");
+    std::fs::write(&path, vec![0u8; 4096]).unwrap();
+    assert!(Reader::open(&path).is_err());
+    std::fs::write(&path, b"short").unwrap();
+    assert!(Reader::open(&path).is_err());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn concurrent_reads_are_never_torn() {
+    let path = temp_path("torn");
+    let mut writer = Writer::create(&path, 1).unwrap();
+    writer.update("binance", "BTCUSDT", &quote(0));
+    let reader = Arc::new(Reader::open(&path).unwrap());
+    let done = Arc::new(AtomicBool::new(false));
+    let readers: Vec<_> = (0..2)
+        .map(|_| {
+            let reader = reader.clone();
+            let done = done.clone();
+            thread::spawn(move || {
+                let mut last = 0;
+                while !done.load(Ordering::Relaxed) {
+                    let q = reader.read(0).unwrap();
+                    // Every field of a quote is written with the same value.
+                    assert_eq!(q, quote(q.ts));
+                    assert!(q.ts >= last);
+                    last = q.ts;
+                }
+            })
+        })
+        .collect();
+    for n in 1..200_000 {
+        writer.update("binance", "BTCUSDT", &quote(n));
+    }
+    done.store(true, Ordering::Relaxed);
+    for r in readers {
+        r.join().unwrap();
+    }
+    assert_eq!(reader.read(0), Some(quote(199_999)));
+    std::fs::remove_file(&path).unwrap();
+}